@@ -52,7 +52,10 @@ impl<T: PageTable> CowExt<T> {
      **  @retval none
      */
     pub fn map_to_shared(&mut self, addr: VirtAddr, target: PhysAddr, writable: bool) {
-        let entry = self.page_table.map(addr, target);
+        let entry = self
+            .page_table
+            .map(addr, target)
+            .expect("failed to allocate page table");
         entry.set_writable(false);
         entry.set_shared(writable);
         entry.update();
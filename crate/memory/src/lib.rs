@@ -1,10 +1,13 @@
 #![cfg_attr(not(test), no_std)]
 #![feature(alloc)]
 #![feature(nll)]
+#![cfg_attr(test, feature(test))]
 
 // import macros from log
 use log::*;
 extern crate alloc;
+#[cfg(test)]
+extern crate test;
 
 mod addr;
 pub mod cow;
@@ -15,8 +18,11 @@ pub mod swap;
 
 pub use crate::addr::*;
 
+#[derive(Debug, Eq, PartialEq)]
 pub enum VMError {
     InvalidPtr,
+    /// Out of physical frames
+    NoMemory,
 }
 
 pub type VMResult<T> = Result<T, VMError>;
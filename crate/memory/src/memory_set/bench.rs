@@ -0,0 +1,132 @@
+//! Tests of memory set, and benchmarks of area lookup with many areas
+//!
+//! The areas are backed by `MockPageTable`. The benchmarks use a handler mapping nothing,
+//! so that only the bookkeeping of `MemorySet` is measured.
+
+use super::test::{MockInactivePageTable, NoMap};
+use super::*;
+use ::test::{black_box, Bencher};
+
+const AREA_COUNT: usize = 4096;
+
+/// One page areas with one page holes: [0x1000, 0x2000), [0x3000, 0x4000), ...
+fn fragmented() -> MemorySet<MockInactivePageTable> {
+    let mut ms = MemorySet::new().unwrap();
+    for i in 0..AREA_COUNT {
+        let start = (2 * i + 1) * PAGE_SIZE;
+        ms.push(
+            start,
+            start + PAGE_SIZE,
+            MemoryAttr::default(),
+            NoMap,
+            "area",
+        )
+        .unwrap();
+    }
+    ms
+}
+
+#[test]
+fn lookup() {
+    let mut ms = fragmented();
+    assert!(ms.check_read_ptr(0x1800 as *const u8).is_ok());
+    assert!(ms.check_read_ptr(0x2800 as *const u8).is_err());
+    assert!(ms.check_read_array(0x1800 as *const u8, 0x1000).is_err());
+    assert_eq!(ms.handle_page_fault(0x3000, false), Ok(true));
+    assert_eq!(ms.handle_page_fault(0x4000, false), Ok(false));
+    assert!(ms.is_range_mapped(0x1000, 0x2000));
+    assert!(!ms.is_range_mapped(0x1000, 0x4000));
+    let end = 2 * AREA_COUNT * PAGE_SIZE;
+    // the null page is never returned
+    assert_eq!(ms.find_free_area(0, PAGE_SIZE, end), Some(0x2000));
+    assert_eq!(ms.find_free_area(0x1000, PAGE_SIZE, end), Some(0x2000));
+    assert_eq!(ms.find_free_area(end, PAGE_SIZE, end), Some(0x2000));
+    assert_eq!(ms.find_free_area(0x1000, 2 * PAGE_SIZE, end), None);
+    assert_eq!(
+        ms.find_free_area(0x1000, 2 * PAGE_SIZE, end + 2 * PAGE_SIZE),
+        Some(end)
+    );
+}
+
+#[test]
+fn split() {
+    let mut ms = MemorySet::<MockInactivePageTable>::new().unwrap();
+    ms.push(0x1000, 0x5000, MemoryAttr::default(), NoMap, "a")
+        .unwrap();
+    ms.push(0x6000, 0x8000, MemoryAttr::default(), NoMap, "b")
+        .unwrap();
+    ms.pop_with_split(0x2000, 0x3000);
+    ms.pop_with_split(0x4000, 0x7000);
+    let ranges: Vec<_> = ms
+        .iter()
+        .map(|area| (area.start_addr(), area.end_addr()))
+        .collect();
+    assert_eq!(
+        ranges,
+        [(0x1000, 0x2000), (0x3000, 0x4000), (0x7000, 0x8000)]
+    );
+}
+
+#[test]
+fn check_array() {
+    let mut ms = MemorySet::<MockInactivePageTable>::new().unwrap();
+    ms.push(0x1000, 0x2000, MemoryAttr::default(), NoMap, "a")
+        .unwrap();
+    ms.push(0x2000, 0x3000, MemoryAttr::default().readonly(), NoMap, "b")
+        .unwrap();
+    // an array may span adjacent areas, up to the end of the last one
+    assert!(ms.check_read_array(0x1800 as *const u8, 0x1800).is_ok());
+    assert!(ms.check_read_array(0x1800 as *const u8, 0x1801).is_err());
+    assert!(ms.check_write_array(0x1800 as *mut u8, 0x800).is_ok());
+    assert!(ms.check_write_array(0x1800 as *mut u8, 0x801).is_err());
+    assert!(ms
+        .check_read_array(0x1000 as *const u64, usize::max_value())
+        .is_err());
+}
+
+#[bench]
+fn check_read_ptr(b: &mut Bencher) {
+    let ms = fragmented();
+    let mut addr = 0;
+    b.iter(|| {
+        addr = (addr + 0x1234) % (2 * AREA_COUNT * PAGE_SIZE);
+        black_box(ms.check_read_ptr(addr as *const u8).is_ok())
+    });
+}
+
+#[bench]
+fn handle_page_fault(b: &mut Bencher) {
+    let mut ms = fragmented();
+    let mut addr = 0;
+    b.iter(|| {
+        addr = (addr + 0x1234) % (2 * AREA_COUNT * PAGE_SIZE);
+        black_box(ms.handle_page_fault(addr, false))
+    });
+}
+
+#[bench]
+fn find_free_area(b: &mut Bencher) {
+    let ms = fragmented();
+    let mut addr = 0;
+    b.iter(|| {
+        addr = (addr + 0x1234) % (2 * AREA_COUNT * PAGE_SIZE);
+        black_box(ms.find_free_area(addr, PAGE_SIZE, usize::max_value()))
+    });
+}
+
+#[bench]
+fn push_and_pop_with_split(b: &mut Bencher) {
+    let mut ms = fragmented();
+    let start = AREA_COUNT * PAGE_SIZE;
+    b.iter(|| {
+        ms.pop_with_split(start, start + 2 * PAGE_SIZE);
+        ms.push(
+            start + PAGE_SIZE,
+            start + 2 * PAGE_SIZE,
+            MemoryAttr::default(),
+            NoMap,
+            "area",
+        )
+        .unwrap();
+    });
+}
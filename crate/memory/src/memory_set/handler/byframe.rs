@@ -10,10 +10,17 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
         Box::new(self.clone())
     }
 
-    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
-        let target = self.allocator.alloc().expect("failed to allocate frame");
-        let entry = pt.map(addr, target);
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) -> VMResult<()> {
+        let target = self.allocator.alloc().ok_or(VMError::NoMemory)?;
+        let entry = match pt.map(addr, target) {
+            Some(entry) => entry,
+            None => {
+                self.allocator.dealloc(target);
+                return Err(VMError::NoMemory);
+            }
+        };
         attr.apply(entry);
+        Ok(())
     }
 
     fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
@@ -22,8 +29,13 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
         pt.unmap(addr);
     }
 
-    fn handle_page_fault(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
-        false
+    fn handle_page_fault(
+        &self,
+        _pt: &mut PageTable,
+        _addr: VirtAddr,
+        _write: bool,
+    ) -> VMResult<bool> {
+        Ok(false)
     }
 }
 
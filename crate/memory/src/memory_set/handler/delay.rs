@@ -1,5 +1,11 @@
 use super::*;
 
+/// Allocate frames on first access.
+///
+/// If the allocator has a zero frame, pages read before written map it readonly,
+/// and get their own frame on the first write, i.e. copy-on-write.
+/// Present pages may also be remapped to other shared frames by page merging,
+/// their entries are marked shared so that they are released by `dealloc_shared`.
 #[derive(Debug, Clone)]
 pub struct Delay<T: FrameAllocator> {
     allocator: T,
@@ -10,23 +16,31 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         Box::new(self.clone())
     }
 
-    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
-        let entry = pt.map(addr, 0);
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) -> VMResult<()> {
+        let entry = pt.map(addr, 0).ok_or(VMError::NoMemory)?;
         entry.set_present(false);
         attr.apply(entry);
+        Ok(())
     }
 
-    fn map_eager(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
-        let target = self.allocator.alloc().expect("failed to alloc frame");
-        let entry = pt.map(addr, target);
+    fn map_eager(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) -> VMResult<()> {
+        let target = self.allocator.alloc().ok_or(VMError::NoMemory)?;
+        let entry = match pt.map(addr, target) {
+            Some(entry) => entry,
+            None => {
+                self.allocator.dealloc(target);
+                return Err(VMError::NoMemory);
+            }
+        };
         entry.set_present(true);
         attr.apply(entry);
+        Ok(())
     }
 
     fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
         let entry = pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
-            self.allocator.dealloc(entry.target());
+            self.release(entry);
         }
 
         // PageTable::unmap requires page to be present
@@ -34,16 +48,67 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         pt.unmap(addr);
     }
 
-    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr, write: bool) -> VMResult<bool> {
         let entry = pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
-            // not a delay case
-            return false;
+            if !(write && entry.writable_shared()) {
+                // not a delay case
+                return Ok(false);
+            }
+            // copy-on-write
+            let source = entry.target();
+            let frame = self.allocator.alloc().ok_or(VMError::NoMemory)?;
+            let data = if Some(source) == self.allocator.zero_frame() {
+                None
+            } else {
+                Some(pt.get_page_slice_mut(addr).to_vec())
+            };
+            let entry = pt.get_entry(addr).expect("failed to get entry");
+            entry.set_target(frame);
+            entry.clear_shared();
+            entry.set_writable(true);
+            entry.update();
+            self.allocator.dealloc_shared(source);
+            let page = pt.get_page_slice_mut(addr);
+            match data {
+                Some(data) => page.copy_from_slice(&data),
+                None => page.iter_mut().for_each(|x| *x = 0),
+            }
+            return Ok(true);
         }
-        let frame = self.allocator.alloc().expect("failed to alloc frame");
-        entry.set_target(frame);
-        entry.set_present(true);
-        entry.update();
+        match self.allocator.zero_frame() {
+            Some(zero) if !write => {
+                let writable = entry.writable();
+                entry.set_target(zero);
+                entry.set_writable(false);
+                entry.set_shared(writable);
+                entry.set_present(true);
+                entry.update();
+            }
+            _ => {
+                let frame = self.allocator.alloc().ok_or(VMError::NoMemory)?;
+                entry.set_target(frame);
+                entry.set_present(true);
+                entry.update();
+                // zero-fill on demand
+                pt.get_page_slice_mut(addr).iter_mut().for_each(|x| *x = 0);
+            }
+        }
+        Ok(true)
+    }
+
+    fn discard(&self, pt: &mut PageTable, addr: VirtAddr) -> bool {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            // release the frame, a new one is allocated on next access
+            self.release(entry);
+            entry.set_present(false);
+            entry.update();
+        }
+        true
+    }
+
+    fn mergeable(&self) -> bool {
         true
     }
 }
@@ -52,4 +117,72 @@ impl<T: FrameAllocator> Delay<T> {
     pub fn new(allocator: T) -> Self {
         Delay { allocator }
     }
+
+    /// Release the frame of a present entry, and restore its permission if shared
+    fn release(&self, entry: &mut Entry) {
+        if entry.writable_shared() || entry.readonly_shared() {
+            self.allocator.dealloc_shared(entry.target());
+            entry.set_writable(entry.writable_shared());
+            entry.clear_shared();
+        } else {
+            self.allocator.dealloc(entry.target());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_set::test::{LimitedFrames, MockInactivePageTable};
+    use crate::memory_set::{MemoryAttr, MemorySet};
+    use crate::paging::MockPageTable;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn zero_page() {
+        let free = Arc::new(AtomicUsize::new(3));
+        let frames = LimitedFrames(free.clone());
+        let mut ms = MemorySet::<MockInactivePageTable>::new().unwrap();
+        ms.push(
+            0x1000,
+            0x3000,
+            MemoryAttr::default(),
+            Delay::new(frames),
+            "a",
+        )
+        .unwrap();
+        // target, writable and copy-on-write of the entry
+        let target = |ms: &mut MemorySet<MockInactivePageTable>, addr| {
+            let mut target = None;
+            ms.edit(|pt: &mut MockPageTable| {
+                let entry = pt.get_entry(addr).unwrap();
+                target = Some((entry.target(), entry.writable(), entry.writable_shared()));
+            });
+            target.unwrap()
+        };
+
+        // a read maps the zero frame, the following write copies it
+        assert_eq!(ms.handle_page_fault(0x1000, false), Ok(true));
+        assert_eq!(target(&mut ms, 0x1000), (0, false, true));
+        assert_eq!(free.load(Ordering::SeqCst), 3);
+        assert_eq!(ms.handle_page_fault(0x1000, false), Ok(false));
+        assert_eq!(ms.handle_page_fault(0x1000, true), Ok(true));
+        assert_eq!(target(&mut ms, 0x1000), (3 * PAGE_SIZE, true, false));
+        assert_eq!(free.load(Ordering::SeqCst), 2);
+        assert_eq!(ms.handle_page_fault(0x1000, true), Ok(false));
+
+        // a write allocates at once, and so does populate
+        assert_eq!(ms.handle_page_fault(0x2000, true), Ok(true));
+        assert_eq!(free.load(Ordering::SeqCst), 1);
+        assert!(ms.discard(0x2000, 0x3000));
+        assert_eq!(free.load(Ordering::SeqCst), 2);
+        assert_eq!(ms.handle_page_fault(0x2000, false), Ok(true));
+        ms.populate(0x1000, 0x3000).unwrap();
+        assert_eq!(target(&mut ms, 0x2000), (0, false, true));
+
+        // the zero frame is never freed
+        ms.pop(0x1000, 0x3000);
+        assert_eq!(free.load(Ordering::SeqCst), 3);
+    }
 }
@@ -3,6 +3,7 @@ use super::*;
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Linear {
     offset: isize,
+    huge: bool,
 }
 
 impl MemoryHandler for Linear {
@@ -10,23 +11,108 @@ impl MemoryHandler for Linear {
         Box::new(self.clone())
     }
 
-    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) -> VMResult<()> {
         let target = (addr as isize + self.offset) as PhysAddr;
-        let entry = pt.map(addr, target);
+        let entry = pt.map(addr, target).ok_or(VMError::NoMemory)?;
         attr.apply(entry);
+        Ok(())
     }
 
     fn unmap(&self, pt: &mut PageTable, addr: VirtAddr) {
         pt.unmap(addr);
     }
 
-    fn handle_page_fault(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
-        false
+    fn map_range(
+        &self,
+        pt: &mut PageTable,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) -> VMResult<()> {
+        let end_addr = Page::of_addr(end_addr + PAGE_SIZE - 1).start_address();
+        let mut addr = Page::of_addr(start_addr).start_address();
+        while addr < end_addr {
+            let target = (addr as isize + self.offset) as PhysAddr;
+            match self.fit_huge_page(pt, addr, end_addr) {
+                Some(size) => {
+                    attr.apply(pt.map_huge(addr, target, size));
+                    addr += size;
+                }
+                None => match pt.map(addr, target) {
+                    Some(entry) => {
+                        attr.apply(entry);
+                        addr += PAGE_SIZE;
+                    }
+                    None => {
+                        if addr > start_addr {
+                            self.unmap_range(pt, start_addr, addr);
+                        }
+                        return Err(VMError::NoMemory);
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn unmap_range(&self, pt: &mut PageTable, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let end_addr = Page::of_addr(end_addr + PAGE_SIZE - 1).start_address();
+        let mut addr = Page::of_addr(start_addr).start_address();
+        while addr < end_addr {
+            let huge = if self.huge {
+                pt.huge_page_size(addr)
+            } else {
+                None
+            };
+            match huge {
+                Some(size) if addr + size <= end_addr => {
+                    pt.unmap_huge(addr, size);
+                    addr += size;
+                }
+                _ => {
+                    pt.unmap(addr);
+                    addr += PAGE_SIZE;
+                }
+            }
+        }
+    }
+
+    fn handle_page_fault(
+        &self,
+        _pt: &mut PageTable,
+        _addr: VirtAddr,
+        _write: bool,
+    ) -> VMResult<bool> {
+        Ok(false)
     }
 }
 
 impl Linear {
     pub fn new(offset: isize) -> Self {
-        Linear { offset }
+        Linear {
+            offset,
+            huge: false,
+        }
+    }
+
+    /// Map with huge pages where aligned, to cut TLB misses of big areas.
+    /// The area must be unmapped as a whole, since huge pages can not be split.
+    pub fn huge(mut self) -> Self {
+        self.huge = true;
+        self
+    }
+
+    /// The huge page size to map `addr`, with the area ending at `end_addr`
+    fn fit_huge_page(
+        &self,
+        pt: &mut PageTable,
+        addr: VirtAddr,
+        end_addr: VirtAddr,
+    ) -> Option<usize> {
+        if !self.huge {
+            return None;
+        }
+        let target = (addr as isize + self.offset) as PhysAddr;
+        pt.fit_huge_page(addr, target, end_addr - addr)
     }
 }
@@ -6,21 +6,66 @@ pub trait MemoryHandler: Debug + 'static {
 
     /// Map `addr` in the page table
     /// Should set page flags here instead of in page_fault_handler
-    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr);
+    /// Fail with `VMError::NoMemory` if a frame can not be allocated
+    fn map(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) -> VMResult<()>;
 
     /// Map `addr` in the page table eagerly (i.e. no delay allocation)
     /// Should set page flags here instead of in page_fault_handler
-    fn map_eager(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+    fn map_eager(&self, pt: &mut PageTable, addr: VirtAddr, attr: &MemoryAttr) -> VMResult<()> {
         // override this when pages are allocated lazily
-        self.map(pt, addr, attr);
+        self.map(pt, addr, attr)
     }
 
     /// Unmap `addr` in the page table
     fn unmap(&self, pt: &mut PageTable, addr: VirtAddr);
 
-    /// Handle page fault on `addr`
+    /// Map the pages of [`start_addr`, `end_addr`) in the page table.
+    /// On failure, the pages mapped so far are unmapped.
+    fn map_range(
+        &self,
+        pt: &mut PageTable,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) -> VMResult<()> {
+        // override this to map multiple pages at once, e.g. huge pages
+        for page in Page::range_of(start_addr, end_addr) {
+            let addr = page.start_address();
+            if let Err(err) = self.map(pt, addr, attr) {
+                if addr > start_addr {
+                    self.unmap_range(pt, start_addr, addr);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmap the pages of [`start_addr`, `end_addr`) in the page table
+    fn unmap_range(&self, pt: &mut PageTable, start_addr: VirtAddr, end_addr: VirtAddr) {
+        for page in Page::range_of(start_addr, end_addr) {
+            self.unmap(pt, page.start_address());
+        }
+    }
+
+    /// Handle page fault on `addr`, caused by a write if `write`
+    /// The page table is active, so the page can be filled by its virtual address
     /// Return true if success, false if error
-    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr) -> bool;
+    /// Fail with `VMError::NoMemory` if a frame can not be allocated
+    fn handle_page_fault(&self, pt: &mut PageTable, addr: VirtAddr, write: bool) -> VMResult<bool>;
+
+    /// Drop the content of `addr`, so that it reads as zero afterwards
+    /// Return false if it is not supported, e.g. the content comes from a file
+    /// The page table may not be active
+    fn discard(&self, _pt: &mut PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+
+    /// Whether the present pages may be remapped to frames shared copy-on-write,
+    /// so that pages of the same content can be merged
+    fn mergeable(&self) -> bool {
+        false
+    }
 }
 
 impl Clone for Box<MemoryHandler> {
@@ -32,6 +77,21 @@ impl Clone for Box<MemoryHandler> {
 pub trait FrameAllocator: Debug + Clone + 'static {
     fn alloc(&self) -> Option<PhysAddr>;
     fn dealloc(&self, target: PhysAddr);
+
+    /// A frame filled with zero, mapped readonly by pages which are read before written.
+    /// Return None to allocate a frame on first access instead.
+    fn zero_frame(&self) -> Option<PhysAddr> {
+        None
+    }
+
+    /// Release a frame shared copy-on-write by several pages.
+    /// The zero frame is never freed.
+    fn dealloc_shared(&self, target: PhysAddr) {
+        // without reference counting, only the zero frame is shared
+        if Some(target) != self.zero_frame() {
+            self.dealloc(target);
+        }
+    }
 }
 
 mod byframe;
@@ -1,8 +1,9 @@
 //! memory set, area
 //! and the inactive page table
 
-use alloc::{boxed::Box, string::String, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
 use core::fmt::{Debug, Error, Formatter};
+use core::mem::size_of;
 
 use crate::paging::*;
 
@@ -10,6 +11,8 @@ use super::*;
 
 use self::handler::MemoryHandler;
 
+#[cfg(test)]
+mod bench;
 pub mod handler;
 
 /// a continuous memory space when the same attribute
@@ -21,6 +24,7 @@ pub struct MemoryArea {
     attr: MemoryAttr,
     handler: Box<MemoryHandler>,
     name: &'static str,
+    locked: bool,
 }
 
 unsafe impl Send for MemoryArea {}
@@ -54,15 +58,33 @@ impl MemoryArea {
     pub fn contains(&self, addr: VirtAddr) -> bool {
         addr >= self.start_addr && addr < self.end_addr
     }
-    /// Check the array is within the readable memory
-    fn check_read_array<S>(&self, ptr: *const S, count: usize) -> bool {
-        // page align
-        ptr as usize >= Page::of_addr(self.start_addr).start_address()
-            && unsafe { ptr.add(count) as usize } < Page::of_addr(self.end_addr + PAGE_SIZE - 1).start_address()
+    /// Get the start address of the memory area
+    pub fn start_addr(&self) -> VirtAddr {
+        self.start_addr
     }
-    /// Check the array is within the writable memory
-    fn check_write_array<S>(&self, ptr: *mut S, count: usize) -> bool {
-        !self.attr.readonly && self.check_read_array(ptr, count)
+    /// Get the end address of the memory area
+    pub fn end_addr(&self) -> VirtAddr {
+        self.end_addr
+    }
+    /// Get the size of the memory area in bytes
+    pub fn size(&self) -> usize {
+        self.end_addr - self.start_addr
+    }
+    /// Get the name of the memory area
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    /// Get the attributes of the memory area
+    pub fn attr(&self) -> MemoryAttr {
+        self.attr
+    }
+    /// Whether the pages of the memory area are locked in memory
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+    /// Whether the present pages may be remapped to frames shared copy-on-write
+    pub fn is_mergeable(&self) -> bool {
+        self.handler.mergeable()
     }
     /// Check the null-end C string is within the readable memory, and is valid.
     /// If so, clone it to a String.
@@ -89,22 +111,28 @@ impl MemoryArea {
     /*
      **  @brief  map the memory area to the physice address in a page table
      **  @param  pt: &mut T::Active   the page table to use
-     **  @retval none
+     **  @retval VMResult<()>
      */
-    fn map(&self, pt: &mut PageTable) {
-        for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.map(pt, page.start_address(), &self.attr);
-        }
+    fn map(&self, pt: &mut PageTable) -> VMResult<()> {
+        self.handler
+            .map_range(pt, self.start_addr, self.end_addr, &self.attr)
     }
     /*
      **  @brief  map the memory area to the physice address in a page table eagerly
      **  @param  pt: &mut T::Active   the page table to use
-     **  @retval none
+     **  @retval VMResult<()>
      */
-    fn map_eager(&self, pt: &mut PageTable) {
+    fn map_eager(&self, pt: &mut PageTable) -> VMResult<()> {
         for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.map_eager(pt, page.start_address(), &self.attr);
+            let addr = page.start_address();
+            if let Err(err) = self.handler.map_eager(pt, addr, &self.attr) {
+                if addr > self.start_addr {
+                    self.handler.unmap_range(pt, self.start_addr, addr);
+                }
+                return Err(err);
+            }
         }
+        Ok(())
     }
     /*
      **  @brief  unmap the memory area from the physice address in a page table
@@ -112,8 +140,18 @@ impl MemoryArea {
      **  @retval none
      */
     fn unmap(&self, pt: &mut PageTable) {
-        for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.unmap(pt, page.start_address());
+        self.handler.unmap_range(pt, self.start_addr, self.end_addr);
+    }
+    /// A copy of this area covering [`start_addr`, `end_addr`) instead.
+    /// The content is not copied.
+    fn sub_area(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> MemoryArea {
+        MemoryArea {
+            start_addr,
+            end_addr,
+            attr: self.attr,
+            handler: self.handler.box_clone(),
+            name: self.name,
+            locked: self.locked,
         }
     }
 }
@@ -168,11 +206,25 @@ impl MemoryAttr {
         self.mmio = value;
         self
     }
+    /// Whether the memory can not be written
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+    /// Whether the memory can be executed
+    pub fn is_execute(&self) -> bool {
+        self.execute
+    }
     /// Apply the attributes to page table entry, then update it.
+    /// A page shared copy-on-write is kept readonly until copied.
     /// NOTE: You may need to set present manually.
     pub fn apply(&self, entry: &mut Entry) {
         entry.set_user(self.user);
-        entry.set_writable(!self.readonly);
+        if entry.writable_shared() || entry.readonly_shared() {
+            entry.set_writable(false);
+            entry.set_shared(!self.readonly);
+        } else {
+            entry.set_writable(!self.readonly);
+        }
         entry.set_execute(self.execute);
         entry.set_mmio(self.mmio);
         entry.update();
@@ -181,27 +233,32 @@ impl MemoryAttr {
 
 /// set of memory space with multiple memory area with associated page table and stack space
 /// like `mm_struct` in ucore
+///
+/// Areas never share a page, so they are kept ordered by start address,
+/// and the area of an address is the last one starting before it.
 pub struct MemorySet<T: InactivePageTable> {
-    areas: Vec<MemoryArea>,
+    areas: BTreeMap<VirtAddr, MemoryArea>,
     page_table: T,
 }
 
 impl<T: InactivePageTable> MemorySet<T> {
     /*
      **  @brief  create a memory set
-     **  @retval MemorySet<T>         the memory set created
+     **  @retval VMResult<MemorySet<T>>
+     **                               the memory set created,
+     **                               `VMError::NoMemory` if the page table can not be allocated
      */
-    pub fn new() -> Self {
-        MemorySet {
-            areas: Vec::new(),
-            page_table: T::new(),
-        }
+    pub fn new() -> VMResult<Self> {
+        Ok(MemorySet {
+            areas: BTreeMap::new(),
+            page_table: T::new().ok_or(VMError::NoMemory)?,
+        })
     }
-    pub fn new_bare() -> Self {
-        MemorySet {
-            areas: Vec::new(),
-            page_table: T::new_bare(),
-        }
+    pub fn new_bare() -> VMResult<Self> {
+        Ok(MemorySet {
+            areas: BTreeMap::new(),
+            page_table: T::new_bare().ok_or(VMError::NoMemory)?,
+        })
     }
     /// Check the pointer is within the readable memory
     pub fn check_read_ptr<S>(&self, ptr: *const S) -> VMResult<()> {
@@ -211,56 +268,97 @@ impl<T: InactivePageTable> MemorySet<T> {
     pub fn check_write_ptr<S>(&self, ptr: *mut S) -> VMResult<()> {
         self.check_write_array(ptr, 1)
     }
-    /// Check the array is within the readable memory
+    /// Check the array is within the readable memory, which may span adjacent areas
     pub fn check_read_array<S>(&self, ptr: *const S, count: usize) -> VMResult<()> {
-        self.areas
-            .iter()
-            .find(|area| area.check_read_array(ptr, count))
-            .map(|_| ())
-            .ok_or(VMError::InvalidPtr)
+        let len = count
+            .checked_mul(size_of::<S>())
+            .ok_or(VMError::InvalidPtr)?;
+        self.check_range(ptr as usize, len, false)
     }
-    /// Check the array is within the writable memory
+    /// Check the array is within the writable memory, which may span adjacent areas
     pub fn check_write_array<S>(&self, ptr: *mut S, count: usize) -> VMResult<()> {
-        self.areas
-            .iter()
-            .find(|area| area.check_write_array(ptr, count))
-            .map(|_| ())
-            .ok_or(VMError::InvalidPtr)
+        let len = count
+            .checked_mul(size_of::<S>())
+            .ok_or(VMError::InvalidPtr)?;
+        self.check_range(ptr as usize, len, true)
+    }
+    /// Check [`start_addr`, `start_addr + len`) is covered by areas, writable if `write`
+    fn check_range(&self, start_addr: VirtAddr, len: usize, write: bool) -> VMResult<()> {
+        if len == 0 {
+            // like a single element, the pointer must still be in an area
+            return find_area_of_page(&self.areas, start_addr)
+                .filter(|area| area.contains(start_addr) && !(write && area.attr.readonly))
+                .map(|_| ())
+                .ok_or(VMError::InvalidPtr);
+        }
+        let end_addr = start_addr.checked_add(len).ok_or(VMError::InvalidPtr)?;
+        let mut addr = Page::of_addr(start_addr).start_address();
+        for area in overlapping_areas(&self.areas, start_addr, end_addr) {
+            if Page::of_addr(area.start_addr).start_address() > addr
+                || (write && area.attr.readonly)
+            {
+                return Err(VMError::InvalidPtr);
+            }
+            addr = Page::of_addr(area.end_addr - 1).start_address() + PAGE_SIZE;
+        }
+        if addr >= end_addr {
+            Ok(())
+        } else {
+            Err(VMError::InvalidPtr)
+        }
     }
     /// Check the null-end C string is within the readable memory, and is valid.
     /// If so, clone it to a String.
     ///
     /// Unsafe: the page table must be active.
     pub unsafe fn check_and_clone_cstr(&self, ptr: *const u8) -> VMResult<String> {
-        self.areas
-            .iter()
-            .filter_map(|area| area.check_and_clone_cstr(ptr))
-            .next()
+        find_area(&self.areas, ptr as usize)
+            .and_then(|area| area.check_and_clone_cstr(ptr))
             .ok_or(VMError::InvalidPtr)
     }
-    /// Find a free area with hint address `addr_hint` and length `len`.
-    /// Return the start address of found free area.
+    /// Find a free area with hint address `addr_hint` and length `len`, ending by `limit`.
+    /// Return the start address of found free area, which is never in the null page.
     /// Used for mmap.
-    pub fn find_free_area(&self, addr_hint: usize, len: usize) -> VirtAddr {
-        // brute force:
-        // try each area's end address as the start
-        core::iter::once(addr_hint)
-            .chain(self.areas.iter().map(|area| area.end_addr))
-            .map(|addr| (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)) // round up a page
-            .find(|&addr| self.test_free_area(addr, addr + len))
-            .expect("failed to find free area ???")
+    pub fn find_free_area(
+        &self,
+        addr_hint: usize,
+        len: usize,
+        limit: VirtAddr,
+    ) -> Option<VirtAddr> {
+        // first fit above the hint, then anywhere
+        self.find_free_area_from(addr_hint.max(PAGE_SIZE), len, limit)
+            .or_else(|| self.find_free_area_from(PAGE_SIZE, len, limit))
+    }
+    /// Find the first free area of length `len` from `addr` ending by `limit`,
+    /// skipping over the areas in the way.
+    fn find_free_area_from(
+        &self,
+        mut addr: VirtAddr,
+        len: usize,
+        limit: VirtAddr,
+    ) -> Option<VirtAddr> {
+        loop {
+            addr = addr.checked_add(PAGE_SIZE - 1)? & !(PAGE_SIZE - 1); // round up a page
+            let end = addr.checked_add(len)?;
+            if end > limit {
+                return None;
+            }
+            match overlapping_areas(&self.areas, addr, end).next_back() {
+                Some(area) => addr = area.end_addr,
+                None => return Some(addr),
+            }
+        }
     }
     /// Test if [`start_addr`, `end_addr`) is a free area
     fn test_free_area(&self, start_addr: usize, end_addr: usize) -> bool {
-        self.areas
-            .iter()
-            .find(|area| area.is_overlap_with(start_addr, end_addr))
+        overlapping_areas(&self.areas, start_addr, end_addr)
+            .next()
             .is_none()
     }
     /*
      **  @brief  add the memory area to the memory set
      **  @param  area: MemoryArea     the memory area to add
-     **  @retval none
+     **  @retval VMResult<()>         fail if out of memory for the handler
      */
     pub fn push(
         &mut self,
@@ -269,21 +367,214 @@ impl<T: InactivePageTable> MemorySet<T> {
         attr: MemoryAttr,
         handler: impl MemoryHandler,
         name: &'static str,
-    ) {
+    ) -> VMResult<()> {
         assert!(start_addr <= end_addr, "invalid memory area");
         assert!(
             self.test_free_area(start_addr, end_addr),
             "memory area overlap"
         );
+        if start_addr == end_addr {
+            // an empty area has nothing to map, and would share the start with another
+            return Ok(());
+        }
         let area = MemoryArea {
             start_addr,
             end_addr,
             attr,
             handler: Box::new(handler),
             name,
+            locked: false,
+        };
+        self.page_table.edit(|pt| area.map(pt))?;
+        self.areas.insert(start_addr, area);
+        Ok(())
+    }
+
+    /// Add a memory area [`start_addr`, `end_addr`) with the attributes, handler and name
+    /// of the area containing `src_addr`. The content is not copied.
+    /// Return false if there is no such area, the new area is not free, or out of memory.
+    /// Used for moving mappings.
+    pub fn push_like(
+        &mut self,
+        src_addr: VirtAddr,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+    ) -> bool {
+        if start_addr >= end_addr || !self.test_free_area(start_addr, end_addr) {
+            return false;
+        }
+        let area = match find_area(&self.areas, src_addr) {
+            Some(src) => src.sub_area(start_addr, end_addr),
+            None => return false,
         };
-        self.page_table.edit(|pt| area.map(pt));
-        self.areas.push(area);
+        if self.page_table.edit(|pt| area.map(pt)).is_err() {
+            return false;
+        }
+        self.areas.insert(start_addr, area);
+        true
+    }
+
+    /// Extend the area starting at page aligned `start_addr` downwards to `new_start_addr`,
+    /// mapping the new pages with the handler of the area.
+    /// Return false if there is no such area, [`new_start_addr`, `start_addr`) is not free,
+    /// or out of memory.
+    /// Used for stacks growing down.
+    pub fn extend_down(&mut self, start_addr: VirtAddr, new_start_addr: VirtAddr) -> bool {
+        if new_start_addr >= start_addr || !self.test_free_area(new_start_addr, start_addr) {
+            return false;
+        }
+        match self.areas.remove(&start_addr) {
+            Some(mut area) => {
+                let mapped = self.page_table.edit(|pt| {
+                    area.handler
+                        .map_range(pt, new_start_addr, start_addr, &area.attr)
+                });
+                let ok = mapped.is_ok();
+                if ok {
+                    area.start_addr = new_start_addr;
+                }
+                self.areas.insert(area.start_addr, area);
+                ok
+            }
+            None => false,
+        }
+    }
+
+    /// Extend the area ending at `end_addr` upwards to `new_end_addr`,
+    /// mapping the new pages with the handler of the area.
+    /// Return false if there is no such area, the new pages are not free, or out of memory.
+    pub fn extend_up(&mut self, end_addr: VirtAddr, new_end_addr: VirtAddr) -> bool {
+        let page_end = Page::of_addr(end_addr + PAGE_SIZE - 1).start_address();
+        if new_end_addr <= end_addr
+            || (new_end_addr > page_end && !self.test_free_area(page_end, new_end_addr))
+        {
+            return false;
+        }
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+        } = self;
+        match areas
+            .range_mut(..end_addr)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.end_addr == end_addr)
+        {
+            Some(area) => {
+                if new_end_addr > page_end {
+                    let mapped = page_table.edit(|pt| {
+                        area.handler
+                            .map_range(pt, page_end, new_end_addr, &area.attr)
+                    });
+                    if mapped.is_err() {
+                        return false;
+                    }
+                }
+                area.end_addr = new_end_addr;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Test if every page in [`start_addr`, `end_addr`) belongs to some area
+    pub fn is_range_mapped(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        let ranges = overlapping_areas(&self.areas, start_addr, end_addr).map(|area| {
+            (
+                Page::of_addr(area.start_addr).start_address(),
+                Page::of_addr(area.end_addr - 1).start_address() + PAGE_SIZE,
+            )
+        });
+        let mut addr = Page::of_addr(start_addr).start_address();
+        for (start, end) in ranges {
+            if start > addr {
+                return false;
+            }
+            addr = addr.max(end);
+        }
+        addr >= end_addr
+    }
+
+    /// Split the area crossing page aligned `addr` into two
+    fn split_at(&mut self, addr: VirtAddr) {
+        let area = self
+            .areas
+            .range_mut(..addr)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| addr < area.end_addr);
+        if let Some(area) = area {
+            let new_area = area.sub_area(addr, area.end_addr);
+            area.end_addr = addr;
+            self.areas.insert(addr, new_area);
+        }
+    }
+
+    /// Lock or unlock the pages in page aligned [`start_addr`, `end_addr`),
+    /// splitting areas when necessary. Locked areas are never discarded.
+    pub fn set_locked(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, locked: bool) {
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        for (_, area) in self.areas.range_mut(start_addr..) {
+            if area.start_addr >= end_addr {
+                break;
+            }
+            if area.end_addr <= end_addr {
+                area.locked = locked;
+            }
+        }
+    }
+
+    /// Allocate the pages in [`start_addr`, `end_addr`) which are not present yet,
+    /// as if written, so that they get frames of their own.
+    /// Stop at the first failure.
+    pub fn populate(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> VMResult<()> {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        let areas = overlapping_areas(areas, start_addr, end_addr);
+        // the handlers fill the pages by their virtual address
+        let fault_in = |pt: &mut T::Active| {
+            for area in areas {
+                let start = area.start_addr.max(start_addr);
+                let end = area.end_addr.min(end_addr);
+                for page in Page::range_of(start, end) {
+                    let addr = page.start_address();
+                    let present = match pt.get_entry(addr) {
+                        Some(entry) => entry.present(),
+                        None => true,
+                    };
+                    if !present {
+                        area.handler.handle_page_fault(pt, addr, true)?;
+                    }
+                }
+            }
+            Ok(())
+        };
+        unsafe { page_table.edit_activated(fault_in) }
+    }
+
+    /// Drop the content of the pages in [`start_addr`, `end_addr`),
+    /// so that they are zero-filled on next access.
+    /// Return false if some of the areas are locked or do not support it.
+    pub fn discard(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        let mut areas = overlapping_areas(areas, start_addr, end_addr);
+        if areas.clone().any(|area| area.locked) {
+            return false;
+        }
+        page_table.edit(|pt| {
+            areas.all(|area| {
+                let start = area.start_addr.max(start_addr);
+                let end = area.end_addr.min(end_addr);
+                Page::range_of(start, end)
+                    .all(|page| area.handler.discard(pt, page.start_address()))
+            })
+        })
     }
 
     /*
@@ -293,14 +584,13 @@ impl<T: InactivePageTable> MemorySet<T> {
      */
     pub fn pop(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
         assert!(start_addr <= end_addr, "invalid memory area");
-        for i in 0..self.areas.len() {
-            if self.areas[i].start_addr == start_addr && self.areas[i].end_addr == end_addr {
-                let area = self.areas.remove(i);
+        match self.areas.get(&start_addr) {
+            Some(area) if area.end_addr == end_addr => {
+                let area = self.areas.remove(&start_addr).unwrap();
                 self.page_table.edit(|pt| area.unmap(pt));
-                return;
             }
+            _ => panic!("no memory area found"),
         }
-        panic!("no memory area found");
     }
 
     /*
@@ -310,96 +600,41 @@ impl<T: InactivePageTable> MemorySet<T> {
      */
     pub fn pop_with_split(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
         assert!(start_addr <= end_addr, "invalid memory area");
-        let mut i = 0;
-        while i < self.areas.len() {
-            if self.areas[i].is_overlap_with(start_addr, end_addr) {
-                if self.areas[i].start_addr >= start_addr && self.areas[i].end_addr <= end_addr {
-                    // subset
-                    let area = self.areas.remove(i);
-                    self.page_table.edit(|pt| area.unmap(pt));
-                    i -= 1;
-                } else if self.areas[i].start_addr >= start_addr
-                    && self.areas[i].start_addr < end_addr
-                {
-                    // prefix
-                    let area = self.areas.remove(i);
-                    let dead_area = MemoryArea {
-                        start_addr: area.start_addr,
-                        end_addr,
-                        attr: area.attr,
-                        handler: area.handler.box_clone(),
-                        name: area.name,
-                    };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
-                    let new_area = MemoryArea {
-                        start_addr: end_addr,
-                        end_addr: area.end_addr,
-                        attr: area.attr,
-                        handler: area.handler,
-                        name: area.name,
-                    };
-                    self.areas.insert(i, new_area);
-                } else if self.areas[i].end_addr <= end_addr && self.areas[i].end_addr > start_addr
-                {
-                    // postfix
-                    let area = self.areas.remove(i);
-                    let dead_area = MemoryArea {
-                        start_addr: start_addr,
-                        end_addr: area.end_addr,
-                        attr: area.attr,
-                        handler: area.handler.box_clone(),
-                        name: area.name,
-                    };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
-                    let new_area = MemoryArea {
-                        start_addr: area.start_addr,
-                        end_addr: start_addr,
-                        attr: area.attr,
-                        handler: area.handler,
-                        name: area.name,
-                    };
-                    self.areas.insert(i, new_area);
-                } else {
-                    // superset
-                    let area = self.areas.remove(i);
-                    let dead_area = MemoryArea {
-                        start_addr: start_addr,
-                        end_addr: end_addr,
-                        attr: area.attr,
-                        handler: area.handler.box_clone(),
-                        name: area.name,
-                    };
-                    self.page_table.edit(|pt| dead_area.unmap(pt));
-                    let new_area_left = MemoryArea {
-                        start_addr: area.start_addr,
-                        end_addr: start_addr,
-                        attr: area.attr,
-                        handler: area.handler.box_clone(),
-                        name: area.name,
-                    };
-                    self.areas.insert(i, new_area_left);
-                    let new_area_right = MemoryArea {
-                        start_addr: end_addr,
-                        end_addr: area.end_addr,
-                        attr: area.attr,
-                        handler: area.handler,
-                        name: area.name,
-                    };
-                    self.areas.insert(i + 1, new_area_right);
-                    i += 1;
-                }
+        let overlapped: Vec<VirtAddr> = overlapping_areas(&self.areas, start_addr, end_addr)
+            .map(|area| area.start_addr)
+            .collect();
+        for key in overlapped {
+            let area = self.areas.remove(&key).unwrap();
+            // unmap the overlapped part, keep the parts on both sides
+            let dead_start = area.start_addr.max(start_addr);
+            let dead_end = area.end_addr.min(end_addr);
+            if dead_start < dead_end {
+                let dead_area = area.sub_area(dead_start, dead_end);
+                self.page_table.edit(|pt| dead_area.unmap(pt));
+            }
+            if area.start_addr < start_addr {
+                let left = area.sub_area(area.start_addr, start_addr.min(area.end_addr));
+                self.areas.insert(left.start_addr, left);
+            }
+            if area.end_addr > end_addr {
+                let right = area.sub_area(end_addr.max(area.start_addr), area.end_addr);
+                self.areas.insert(right.start_addr, right);
             }
-            i += 1;
         }
     }
 
+    /// Get the total size of all memory areas in bytes
+    pub fn size(&self) -> usize {
+        self.areas.values().map(MemoryArea::size).sum()
+    }
+
     /*
      **  @brief  get iterator of the memory area
      **  @retval impl Iterator<Item=&MemoryArea>
      **                               the memory area iterator
      */
     pub fn iter(&self) -> impl Iterator<Item = &MemoryArea> {
-        self.areas.iter()
+        self.areas.values()
     }
     pub fn edit(&mut self, f: impl FnOnce(&mut T::Active)) {
         self.page_table.edit(f);
@@ -412,6 +647,42 @@ impl<T: InactivePageTable> MemorySet<T> {
     pub unsafe fn with(&self, f: impl FnOnce()) {
         self.page_table.with(f);
     }
+    /// Execute function `f` with the associated page table,
+    /// while present pages in [`start_addr`, `end_addr`) are temporarily writable.
+    /// Used to fill the content of readonly areas.
+    pub unsafe fn with_writable<R>(
+        &mut self,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        f: impl FnOnce() -> R,
+    ) -> R {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        let for_each_entry = |pt: &mut T::Active, g: &Fn(&MemoryArea, &mut Entry)| {
+            for area in overlapping_areas(areas, start_addr, end_addr) {
+                let start = area.start_addr.max(start_addr);
+                let end = area.end_addr.min(end_addr);
+                for page in Page::range_of(start, end) {
+                    if let Some(entry) = pt.get_entry(page.start_address()) {
+                        if entry.present() {
+                            g(area, entry);
+                        }
+                    }
+                }
+            }
+        };
+        page_table.edit(|pt| {
+            for_each_entry(pt, &|_, entry| {
+                entry.set_writable(true);
+                entry.update();
+            })
+        });
+        let ret = page_table.with(f);
+        page_table.edit(|pt| for_each_entry(pt, &|area, entry| area.attr.apply(entry)));
+        ret
+    }
     /*
      **  @brief  activate the associated page table
      **  @retval none
@@ -437,7 +708,7 @@ impl<T: InactivePageTable> MemorySet<T> {
             ..
         } = self;
         page_table.edit(|pt| {
-            for area in areas.iter() {
+            for area in areas.values() {
                 area.unmap(pt);
             }
         });
@@ -465,33 +736,95 @@ impl<T: InactivePageTable> MemorySet<T> {
         &mut self.page_table
     }
 
-    pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
-        let area = self.areas.iter().find(|area| area.contains(addr));
-        match area {
-            Some(area) => self
-                .page_table
-                .edit(|pt| area.handler.handle_page_fault(pt, addr)),
-            None => false,
+    /// Handle page fault at `addr`, caused by a write if `write`, with the handler of its area.
+    /// Return false if there is no such area or the handler can not handle it,
+    /// fail with `VMError::NoMemory` if out of memory.
+    pub fn handle_page_fault(&mut self, addr: VirtAddr, write: bool) -> VMResult<bool> {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        match find_area(areas, addr) {
+            // the handler fills the page by its virtual address
+            Some(area) => unsafe {
+                page_table.edit_activated(|pt| area.handler.handle_page_fault(pt, addr, write))
+            },
+            None => Ok(false),
         }
     }
-}
 
-impl<T: InactivePageTable> Clone for MemorySet<T> {
-    fn clone(&self) -> Self {
-        let mut page_table = T::new();
-        page_table.edit(|pt| {
-            // without CoW, we should allocate the pages eagerly
-            for area in self.areas.iter() {
-                area.map_eager(pt);
-            }
+    /// Get the total size of the present pages in bytes, i.e. the resident set size
+    pub fn resident_size(&mut self) -> usize {
+        let Self {
+            ref mut page_table,
+            ref areas,
+        } = self;
+        let pages = page_table.edit(|pt| {
+            areas
+                .values()
+                .flat_map(|area| Page::range_of(area.start_addr, area.end_addr))
+                .filter(|page| match pt.get_entry(page.start_address()) {
+                    Some(entry) => entry.present(),
+                    None => false,
+                })
+                .count()
         });
-        MemorySet {
-            areas: self.areas.clone(),
-            page_table,
+        pages * PAGE_SIZE
+    }
+
+    /// Make a new memory set with the same areas, allocating the pages eagerly.
+    /// The content is not copied.
+    /// Fail with `VMError::NoMemory` if out of memory, leaving nothing allocated.
+    pub fn try_clone(&self) -> VMResult<Self> {
+        let mut new_set = Self::new()?;
+        for area in self.areas.values() {
+            // without CoW, we should allocate the pages eagerly
+            new_set.page_table.edit(|pt| area.map_eager(pt))?;
+            new_set.areas.insert(area.start_addr, area.clone());
         }
+        Ok(new_set)
     }
 }
 
+/// Find the area containing `addr`
+fn find_area(areas: &BTreeMap<VirtAddr, MemoryArea>, addr: VirtAddr) -> Option<&MemoryArea> {
+    areas
+        .range(..=addr)
+        .next_back()
+        .map(|(_, area)| area)
+        .filter(|area| area.contains(addr))
+}
+
+/// Find the only area which may cover the page of `addr`
+fn find_area_of_page(
+    areas: &BTreeMap<VirtAddr, MemoryArea>,
+    addr: VirtAddr,
+) -> Option<&MemoryArea> {
+    areas
+        .range(..=addr | (PAGE_SIZE - 1))
+        .next_back()
+        .map(|(_, area)| area)
+}
+
+/// Get the areas (page) overlapping with [`start_addr`, `end_addr`), in order
+fn overlapping_areas(
+    areas: &BTreeMap<VirtAddr, MemoryArea>,
+    start_addr: VirtAddr,
+    end_addr: VirtAddr,
+) -> impl DoubleEndedIterator<Item = &MemoryArea> + Clone {
+    // only the last area starting before the first page can reach into it
+    let page_start = Page::of_addr(start_addr).start_address();
+    let first = match areas.range(..page_start).next_back() {
+        Some((&addr, _)) => addr,
+        None => page_start,
+    };
+    let last = end_addr.saturating_sub(1) | (PAGE_SIZE - 1);
+    areas
+        .range(first..=last.max(first))
+        .map(|(_, area)| area)
+        .filter(move |area| area.is_overlap_with(start_addr, end_addr))
+}
+
 impl<T: InactivePageTable> Drop for MemorySet<T> {
     fn drop(&mut self) {
         self.clear();
@@ -500,6 +833,123 @@ impl<T: InactivePageTable> Drop for MemorySet<T> {
 
 impl<T: InactivePageTable> Debug for MemorySet<T> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        f.debug_list().entries(self.areas.iter()).finish()
+        f.debug_list().entries(self.areas.values()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::handler::{ByFrame, FrameAllocator};
+    use super::*;
+    use crate::paging::MockPageTable;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    pub struct MockInactivePageTable(MockPageTable);
+
+    impl InactivePageTable for MockInactivePageTable {
+        type Active = MockPageTable;
+
+        fn new_bare() -> Option<Self> {
+            Some(MockInactivePageTable(MockPageTable::new()))
+        }
+        fn map_kernel(&mut self) {}
+        fn token(&self) -> usize {
+            0
+        }
+        unsafe fn set_token(_token: usize) {}
+        fn active_token() -> usize {
+            0
+        }
+        fn flush_tlb() {}
+        fn edit<T>(&mut self, f: impl FnOnce(&mut Self::Active) -> T) -> T {
+            f(&mut self.0)
+        }
+    }
+
+    /// Maps nothing, so that only the bookkeeping of `MemorySet` is tested
+    #[derive(Debug, Clone)]
+    pub struct NoMap;
+
+    impl MemoryHandler for NoMap {
+        fn box_clone(&self) -> Box<MemoryHandler> {
+            Box::new(self.clone())
+        }
+        fn map(&self, _pt: &mut PageTable, _addr: VirtAddr, _attr: &MemoryAttr) -> VMResult<()> {
+            Ok(())
+        }
+        fn unmap(&self, _pt: &mut PageTable, _addr: VirtAddr) {}
+        fn handle_page_fault(
+            &self,
+            _pt: &mut PageTable,
+            _addr: VirtAddr,
+            _write: bool,
+        ) -> VMResult<bool> {
+            Ok(true)
+        }
+    }
+
+    /// Hands out a limited number of frames, frame 0 is the zero frame
+    #[derive(Debug, Clone)]
+    pub struct LimitedFrames(pub Arc<AtomicUsize>);
+
+    impl FrameAllocator for LimitedFrames {
+        fn alloc(&self) -> Option<PhysAddr> {
+            let free = self.0.load(Ordering::SeqCst);
+            if free == 0 {
+                return None;
+            }
+            self.0.store(free - 1, Ordering::SeqCst);
+            Some(free * PAGE_SIZE)
+        }
+        fn dealloc(&self, _target: PhysAddr) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+        fn zero_frame(&self) -> Option<PhysAddr> {
+            Some(0)
+        }
+    }
+
+    #[test]
+    fn lock_and_extend() {
+        let mut ms = MemorySet::<MockInactivePageTable>::new().unwrap();
+        for &(start, end) in [(0x1000, 0x2000), (0x3000, 0x4000), (0x7000, 0x8000)].iter() {
+            ms.push(start, end, MemoryAttr::default(), NoMap, "a")
+                .unwrap();
+        }
+        // the pages of the range are locked
+        ms.set_locked(0x3000, 0x3800, true);
+        assert_eq!(ms.iter().filter(|area| area.is_locked()).count(), 1);
+        ms.set_locked(0x1000, 0x4000, false);
+        assert_eq!(ms.iter().filter(|area| area.is_locked()).count(), 0);
+
+        // an area grows into the free space next to it only
+        assert!(ms.extend_down(0x7000, 0x6000));
+        assert!(ms.extend_up(0x4000, 0x5000));
+        assert!(!ms.extend_up(0x5000, 0x7000));
+        ms.pop(0x6000, 0x8000);
+        assert_eq!(ms.size(), 0x3000);
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let free = Arc::new(AtomicUsize::new(3));
+        let frames = LimitedFrames(free.clone());
+        let mut ms = MemorySet::<MockInactivePageTable>::new().unwrap();
+        let attr = MemoryAttr::default();
+        ms.push(0x1000, 0x3000, attr, ByFrame::new(frames.clone()), "a")
+            .unwrap();
+        // the pages mapped before failure are released
+        let ret = ms.push(0x4000, 0x6000, attr, ByFrame::new(frames.clone()), "b");
+        assert_eq!(ret, Err(VMError::NoMemory));
+        assert_eq!(free.load(Ordering::SeqCst), 1);
+        assert_eq!(ms.iter().count(), 1);
+        assert_eq!(ms.resident_size(), 2 * PAGE_SIZE);
+
+        assert!(ms.try_clone().is_err());
+        assert_eq!(free.load(Ordering::SeqCst), 1);
+        assert!(!ms.extend_up(0x3000, 0x5000));
+        assert_eq!(free.load(Ordering::SeqCst), 1);
+        assert_eq!(ms.size(), 0x2000);
     }
 }
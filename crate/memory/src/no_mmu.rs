@@ -1,4 +1,13 @@
+//! Memory set for targets without MMU
+//!
+//! There is no address translation: every area is a block from the allocator,
+//! used at the address where it is allocated. So programs have to be position
+//! independent, and areas can be shared but not copied-on-write.
+
+use crate::{PhysAddr, VMError, VMResult, VirtAddr, PAGE_SIZE};
 use alloc::alloc::{GlobalAlloc, Layout};
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::marker::PhantomData;
 
@@ -7,9 +16,9 @@ pub trait NoMMUSupport {
     fn allocator() -> &'static Self::Alloc;
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct MemorySet<S: NoMMUSupport> {
-    areas: Vec<MemoryArea<S>>,
+    areas: Vec<Arc<MemoryArea<S>>>,
     support: PhantomData<S>,
 }
 
@@ -20,12 +29,98 @@ impl<S: NoMMUSupport> MemorySet<S> {
             support: PhantomData,
         }
     }
-    /// Allocate `size` bytes space. Return the slice.
-    pub fn push(&mut self, size: usize) -> &'static mut [u8] {
-        let area = MemoryArea::new(size);
+    /// Allocate `size` bytes space in whole pages. Return the slice.
+    pub fn push(&mut self, size: usize, name: &'static str) -> VMResult<&'static mut [u8]> {
+        let area = MemoryArea::new(size, name)?;
         let slice = unsafe { area.as_buf() };
-        self.areas.push(area);
-        slice
+        self.areas.push(Arc::new(area));
+        Ok(slice)
+    }
+    /// Remove the area of exactly [`start_addr`, `end_addr`).
+    /// Return false if there is no such area, since an area can not be split.
+    pub fn pop(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        match self
+            .areas
+            .iter()
+            .position(|area| area.start_addr() == start_addr && area.end_addr() == end_addr)
+        {
+            Some(i) => {
+                self.areas.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Make a memory set with the same areas, which are freed with the last set using them.
+    /// Used by `vfork`, where the child runs in the memory of its parent.
+    pub fn share(&self) -> Self {
+        Self {
+            areas: self.areas.clone(),
+            support: PhantomData,
+        }
+    }
+    /// Check the pointer is within the memory
+    pub fn check_read_ptr<T>(&self, ptr: *const T) -> VMResult<()> {
+        self.check_read_array(ptr, 1)
+    }
+    /// Check the pointer is within the memory
+    pub fn check_write_ptr<T>(&self, ptr: *mut T) -> VMResult<()> {
+        self.check_write_array(ptr, 1)
+    }
+    /// Check the array is within the memory
+    pub fn check_read_array<T>(&self, ptr: *const T, count: usize) -> VMResult<()> {
+        let start = ptr as usize;
+        core::mem::size_of::<T>()
+            .checked_mul(count)
+            .and_then(|len| start.checked_add(len))
+            .filter(|&end| self.is_range_mapped(start, end))
+            .map(|_| ())
+            .ok_or(VMError::InvalidPtr)
+    }
+    /// Check the array is within the memory.
+    /// Nothing is read only without MMU.
+    pub fn check_write_array<T>(&self, ptr: *mut T, count: usize) -> VMResult<()> {
+        self.check_read_array(ptr, count)
+    }
+    /// Check the null-end C string is within the memory, and is valid.
+    /// If so, clone it to a String.
+    pub unsafe fn check_and_clone_cstr(&self, ptr: *const u8) -> VMResult<String> {
+        let area = self.find_area(ptr as usize).ok_or(VMError::InvalidPtr)?;
+        let max_len = area.end_addr() - ptr as usize;
+        (0..max_len)
+            .find(|&i| ptr.add(i).read() == 0)
+            .and_then(|len| core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).ok())
+            .map(String::from)
+            .ok_or(VMError::InvalidPtr)
+    }
+    /// Test whether [`start_addr`, `end_addr`) lies in a single area
+    pub fn is_range_mapped(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        self.find_area(start_addr)
+            .map_or(false, |area| end_addr <= area.end_addr())
+    }
+    fn find_area(&self, addr: VirtAddr) -> Option<&MemoryArea<S>> {
+        self.areas
+            .iter()
+            .map(|area| &**area)
+            .find(|area| area.contains(addr))
+    }
+    /// Addresses are physical without MMU, if mapped
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.find_area(addr).map(|_| addr)
+    }
+    /// Total size of the areas
+    pub fn size(&self) -> usize {
+        self.areas.iter().map(|area| area.size()).sum()
+    }
+    /// Everything is resident without MMU
+    pub fn resident_size(&self) -> usize {
+        self.size()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryArea<S>> {
+        self.areas.iter().map(|area| &**area)
+    }
+    pub fn clear(&mut self) {
+        self.areas.clear();
     }
     // empty impls
     pub fn with<T>(&self, f: impl FnOnce() -> T) -> T {
@@ -37,31 +132,73 @@ impl<S: NoMMUSupport> MemorySet<S> {
     pub unsafe fn activate(&self) {}
 }
 
+/// Copy the data to new areas
+impl<S: NoMMUSupport> Clone for MemorySet<S> {
+    fn clone(&self) -> Self {
+        Self {
+            areas: self
+                .areas
+                .iter()
+                .map(|area| Arc::new((**area).clone()))
+                .collect(),
+            support: PhantomData,
+        }
+    }
+}
+
 #[derive(Debug)]
-struct MemoryArea<S: NoMMUSupport> {
+pub struct MemoryArea<S: NoMMUSupport> {
     ptr: usize,
     layout: Layout,
+    name: &'static str,
     support: PhantomData<S>,
 }
 
 impl<S: NoMMUSupport> MemoryArea<S> {
-    fn new(size: usize) -> Self {
-        let layout = Layout::from_size_align(size, 1).unwrap();
+    fn new(size: usize, name: &'static str) -> VMResult<Self> {
+        let size = size
+            .max(1)
+            .checked_add(PAGE_SIZE - 1)
+            .ok_or(VMError::NoMemory)?;
+        let layout = Layout::from_size_align(size & !(PAGE_SIZE - 1), PAGE_SIZE).unwrap();
         let ptr = unsafe { S::allocator().alloc(layout) } as usize;
-        MemoryArea {
+        if ptr == 0 {
+            return Err(VMError::NoMemory);
+        }
+        Ok(MemoryArea {
             ptr,
             layout,
+            name,
             support: PhantomData,
-        }
+        })
     }
     unsafe fn as_buf(&self) -> &'static mut [u8] {
         core::slice::from_raw_parts_mut(self.ptr as *mut u8, self.layout.size())
     }
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start_addr() && addr < self.end_addr()
+    }
+    pub fn start_addr(&self) -> VirtAddr {
+        self.ptr
+    }
+    pub fn end_addr(&self) -> VirtAddr {
+        self.ptr + self.layout.size()
+    }
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    /// Test whether this area overlaps with [`start_addr`, `end_addr`)
+    pub fn is_overlap_with(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> bool {
+        start_addr < self.end_addr() && self.start_addr() < end_addr
+    }
 }
 
 impl<S: NoMMUSupport> Clone for MemoryArea<S> {
     fn clone(&self) -> Self {
-        let new_area = MemoryArea::new(self.layout.size());
+        let new_area = MemoryArea::new(self.layout.size(), self.name).expect("out of memory");
         unsafe { new_area.as_buf().copy_from_slice(self.as_buf()) }
         new_area
     }
@@ -72,3 +209,79 @@ impl<S: NoMMUSupport> Drop for MemoryArea<S> {
         unsafe { S::allocator().dealloc(self.ptr as *mut u8, self.layout) }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::alloc::System;
+
+    #[derive(Debug)]
+    struct Support;
+
+    impl NoMMUSupport for Support {
+        type Alloc = System;
+        fn allocator() -> &'static System {
+            &System
+        }
+    }
+
+    #[test]
+    fn push_and_check() {
+        let mut ms = MemorySet::<Support>::new();
+        let buf = ms.push(0x100, "data").unwrap();
+        let start = buf.as_ptr() as usize;
+        assert_eq!(start % PAGE_SIZE, 0);
+        assert_eq!(buf.len(), PAGE_SIZE);
+        assert!(ms.check_read_array(start as *const u8, PAGE_SIZE).is_ok());
+        assert!(ms
+            .check_write_ptr((start + PAGE_SIZE - 4) as *mut u32)
+            .is_ok());
+        assert_eq!(
+            ms.check_read_ptr((start + PAGE_SIZE - 3) as *const u32),
+            Err(VMError::InvalidPtr)
+        );
+        assert_eq!(
+            ms.check_read_ptr((start + PAGE_SIZE) as *const u8),
+            Err(VMError::InvalidPtr)
+        );
+        buf[..4].copy_from_slice(b"abc\0");
+        assert_eq!(
+            unsafe { ms.check_and_clone_cstr(start as *const u8) },
+            Ok(String::from("abc"))
+        );
+        assert_eq!(ms.size(), PAGE_SIZE);
+        assert_eq!(ms.translate(start + 0x10), Some(start + 0x10));
+        assert_eq!(ms.translate(start + PAGE_SIZE), None);
+    }
+
+    #[test]
+    fn pop_whole_area() {
+        let mut ms = MemorySet::<Support>::new();
+        let start = ms.push(0x2000, "mmap_anon").unwrap().as_ptr() as usize;
+        assert!(!ms.pop(start, start + 0x1000));
+        assert!(ms.pop(start, start + 0x2000));
+        assert!(!ms.is_range_mapped(start, start + 1));
+        assert_eq!(ms.size(), 0);
+    }
+
+    #[test]
+    fn share_and_clone() {
+        let mut ms = MemorySet::<Support>::new();
+        let buf = ms.push(0x10, "data").unwrap();
+        buf[0] = 1;
+        let start = buf.as_ptr() as usize;
+
+        let shared = ms.share();
+        assert!(shared.is_range_mapped(start, start + 0x10));
+        let copied = ms.clone();
+        let copy_start = copied.iter().next().unwrap().start_addr();
+        assert_ne!(copy_start, start);
+        assert_eq!(unsafe { *(copy_start as *const u8) }, 1);
+
+        // the shared area stays alive after its creator is gone
+        drop(ms);
+        buf[0] = 2;
+        assert_eq!(unsafe { *(start as *const u8) }, 2);
+        drop(shared);
+    }
+}
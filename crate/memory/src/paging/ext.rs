@@ -13,7 +13,8 @@ pub trait PageTableExt: PageTable {
         target: PhysAddr,
         f: impl FnOnce(&mut Self, &mut D) -> T,
     ) -> T {
-        self.map(Self::TEMP_PAGE_ADDR, target);
+        self.map(Self::TEMP_PAGE_ADDR, target)
+            .expect("failed to map temporary page");
         let data =
             unsafe { &mut *(self.get_page_slice_mut(Self::TEMP_PAGE_ADDR).as_ptr() as *mut D) };
         let ret = f(self, data);
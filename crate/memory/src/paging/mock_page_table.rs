@@ -8,6 +8,8 @@ use alloc::boxed::Box;
 
 const PAGE_COUNT: usize = 16;
 const PAGE_SIZE: usize = 4096;
+/// A tiny huge page to fit in the mock memory
+const HUGE_PAGE_SIZE: usize = PAGE_SIZE * 4;
 
 // a mock page table for test purpose
 pub struct MockPageTable {
@@ -27,6 +29,10 @@ pub struct MockEntry {
     writable_shared: bool,
     readonly_shared: bool,
     swapped: bool,
+    huge: bool,
+    user: bool,
+    execute: bool,
+    mmio: u8,
 }
 
 impl Entry for MockEntry {
@@ -82,22 +88,25 @@ impl Entry for MockEntry {
         self.swapped = value;
     }
     fn user(&self) -> bool {
-        unimplemented!()
+        self.user
     }
-    fn set_user(&mut self, _value: bool) {
-        unimplemented!()
+    fn set_user(&mut self, value: bool) {
+        self.user = value;
     }
     fn execute(&self) -> bool {
-        unimplemented!()
+        self.execute
     }
-    fn set_execute(&mut self, _value: bool) {
-        unimplemented!()
+    fn set_execute(&mut self, value: bool) {
+        self.execute = value;
     }
     fn mmio(&self) -> u8 {
-        unimplemented!()
+        self.mmio
     }
-    fn set_mmio(&mut self, _value: u8) {
-        unimplemented!()
+    fn set_mmio(&mut self, value: u8) {
+        self.mmio = value;
+    }
+    fn huge(&self) -> bool {
+        self.huge
     }
 }
 
@@ -106,13 +115,13 @@ type PageFaultHandler = Box<FnMut(&mut MockPageTable, VirtAddr)>;
 impl PageTable for MockPageTable {
     //    type Entry = MockEntry;
 
-    fn map(&mut self, addr: VirtAddr, target: PhysAddr) -> &mut Entry {
+    fn map(&mut self, addr: VirtAddr, target: PhysAddr) -> Option<&mut Entry> {
         let entry = &mut self.entries[addr / PAGE_SIZE];
         assert!(!entry.present);
         entry.present = true;
         entry.writable = true;
         entry.target = target & !(PAGE_SIZE - 1);
-        entry
+        Some(entry)
     }
     fn unmap(&mut self, addr: VirtAddr) {
         let entry = &mut self.entries[addr / PAGE_SIZE];
@@ -120,8 +129,44 @@ impl PageTable for MockPageTable {
         entry.present = false;
     }
     fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut Entry> {
+        let addr = match self.entries[addr / PAGE_SIZE].huge {
+            true => addr & !(HUGE_PAGE_SIZE - 1),
+            false => addr,
+        };
         Some(&mut self.entries[addr / PAGE_SIZE])
     }
+    fn huge_page_sizes(&self) -> &'static [usize] {
+        &[HUGE_PAGE_SIZE]
+    }
+    fn huge_page_unused(&mut self, addr: VirtAddr, size: usize) -> bool {
+        (addr..addr + size)
+            .step_by(PAGE_SIZE)
+            .all(|addr| !self.entries[addr / PAGE_SIZE].present)
+    }
+    fn map_huge(&mut self, addr: VirtAddr, target: PhysAddr, size: usize) -> &mut Entry {
+        assert_eq!(size, HUGE_PAGE_SIZE);
+        assert_eq!(addr % size, 0);
+        assert_eq!(target % size, 0);
+        // every page of it is filled, so that translation stays simple
+        for i in 0..size / PAGE_SIZE {
+            let entry = &mut self.entries[addr / PAGE_SIZE + i];
+            assert!(!entry.present);
+            entry.present = true;
+            entry.writable = true;
+            entry.huge = true;
+            entry.target = target + i * PAGE_SIZE;
+        }
+        &mut self.entries[addr / PAGE_SIZE]
+    }
+    fn unmap_huge(&mut self, addr: VirtAddr, size: usize) {
+        assert_eq!(size, HUGE_PAGE_SIZE);
+        for i in 0..size / PAGE_SIZE {
+            let entry = &mut self.entries[addr / PAGE_SIZE + i];
+            assert!(entry.present && entry.huge);
+            entry.present = false;
+            entry.huge = false;
+        }
+    }
     fn get_page_slice_mut<'a, 'b>(&'a mut self, addr: VirtAddr) -> &'b mut [u8] {
         self._read(addr);
         let pa = self.translate(addr) & !(PAGE_SIZE - 1);
@@ -295,4 +340,46 @@ mod test {
         pt.read(0);
         assert_eq!(*page_fault_count.borrow(), 2);
     }
+
+    #[test]
+    fn huge_page() {
+        let mut pt = MockPageTable::new();
+        assert_eq!(pt.fit_huge_page(0x4000, 0x8000, 0x4000), Some(0x4000));
+        assert_eq!(pt.fit_huge_page(0x4000, 0x9000, 0x4000), None);
+        assert_eq!(pt.fit_huge_page(0x4000, 0x8000, 0x3000), None);
+
+        pt.map_huge(0x4000, 0x8000, 0x4000);
+        pt.write(0x6001, 1);
+        assert_eq!(pt.read(0x6001), 1);
+        assert_eq!(pt.data[0xa001], 1);
+        let entry = pt.get_entry(0x7000).unwrap();
+        assert!(entry.huge());
+        assert_eq!(entry.target(), 0x8000);
+
+        pt.unmap_huge(0x4000, 0x4000);
+        assert!(!pt.get_entry(0x4000).unwrap().present());
+    }
+
+    #[test]
+    fn map_range() {
+        let mut pt = MockPageTable::new();
+        pt.map(0x1000, 0x1000);
+        // 0x1000 is kept, 0x2000 and 0x3000 are small, [0x4000, 0x8000) is huge
+        pt.map_range(0x1000, 0x5000, 0x8000);
+        assert_eq!(pt.get_entry(0x1000).unwrap().target(), 0x1000);
+        assert!(!pt.get_entry(0x2000).unwrap().huge());
+        assert_eq!(pt.get_entry(0x3000).unwrap().target(), 0x7000);
+        assert!(pt.get_entry(0x5000).unwrap().huge());
+        assert_eq!(pt.get_entry(0x5000).unwrap().target(), 0x8000);
+        assert!(!pt.get_entry(0x8000).unwrap().huge());
+        assert_eq!(pt.get_entry(0x8000).unwrap().target(), 0xc000);
+
+        // a page mapped in the range of a huge page makes it 4K pages
+        pt.map(0xd000, 0);
+        pt.map_range(0xc000, 0x4000, 0x4000);
+        assert!(!pt.get_entry(0xc000).unwrap().huge());
+        assert_eq!(pt.get_entry(0xd000).unwrap().target(), 0);
+        assert_eq!(pt.huge_page_size(0x4000), Some(0x4000));
+        assert_eq!(pt.huge_page_size(0xc000), None);
+    }
 }
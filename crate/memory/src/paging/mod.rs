@@ -16,15 +16,88 @@ pub trait PageTable {
 
     /// Map a page of virual address `addr` to the frame of physics address `target`
     /// Return the page table entry of the mapped virual address
-    fn map(&mut self, addr: VirtAddr, target: PhysAddr) -> &mut Entry;
+    /// Return `None` if a frame for the page tables can not be allocated
+    fn map(&mut self, addr: VirtAddr, target: PhysAddr) -> Option<&mut Entry>;
 
     /// Unmap a page of virual address `addr`
     fn unmap(&mut self, addr: VirtAddr);
 
     /// Get the page table entry of a page of virual address `addr`
     /// If its page do not exist, return `None`
+    /// If `addr` is in a huge page, return the entry of the huge page
     fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut Entry>;
 
+    /// Sizes of the huge pages supported, in ascending order
+    fn huge_page_sizes(&self) -> &'static [usize] {
+        &[]
+    }
+
+    /// Map a huge page of `size` at virtual address `addr` to physics address `target`,
+    /// both aligned to `size`, which must be one of `huge_page_sizes`.
+    /// Return the page table entry of the huge page
+    fn map_huge(&mut self, _addr: VirtAddr, _target: PhysAddr, size: usize) -> &mut Entry {
+        panic!("huge page of size {:#x} is not supported", size)
+    }
+
+    /// Unmap the huge page of `size` at virtual address `addr`
+    fn unmap_huge(&mut self, _addr: VirtAddr, size: usize) {
+        panic!("huge page of size {:#x} is not supported", size)
+    }
+
+    /// Whether a huge page of `size` at `addr` can be mapped without replacing anything:
+    /// no page is mapped in it, and there is no lower level table.
+    fn huge_page_unused(&mut self, _addr: VirtAddr, _size: usize) -> bool {
+        false
+    }
+
+    /// The largest huge page size to map `addr` to `target` with `len` bytes left,
+    /// where nothing is mapped yet
+    fn fit_huge_page(&mut self, addr: VirtAddr, target: PhysAddr, len: usize) -> Option<usize> {
+        self.huge_page_sizes().iter().rev().cloned().find(|&size| {
+            addr % size == 0
+                && target % size == 0
+                && len >= size
+                && self.huge_page_unused(addr, size)
+        })
+    }
+
+    /// The size of the huge page mapped at `addr`, if there is one starting there
+    fn huge_page_size(&mut self, addr: VirtAddr) -> Option<usize> {
+        let target = match self.get_entry(addr) {
+            Some(ref entry) if entry.present() && entry.huge() => entry.target(),
+            _ => return None,
+        };
+        // the last page of a huge page is translated by the same entry
+        self.huge_page_sizes().iter().rev().cloned().find(|&size| {
+            addr % size == 0
+                && match self.get_entry(addr + size - PAGE_SIZE) {
+                    Some(entry) => entry.huge() && entry.target() == target,
+                    None => false,
+                }
+        })
+    }
+
+    /// Map [`addr`, `addr + len`) to [`target`, `target + len`),
+    /// using huge pages where aligned and nothing is mapped in the whole huge page,
+    /// 4K pages otherwise. Pages already mapped are kept.
+    /// Panic if a page table can not be allocated, only used for the kernel memory
+    fn map_range(&mut self, addr: VirtAddr, target: PhysAddr, len: usize) {
+        let mut offset = 0;
+        while offset < len {
+            let (vaddr, paddr) = (addr + offset, target + offset);
+            match self.fit_huge_page(vaddr, paddr, len - offset) {
+                Some(size) => {
+                    self.map_huge(vaddr, paddr, size);
+                    offset += size;
+                }
+                None => {
+                    self.map_if_not_exists(vaddr, paddr);
+                    offset += PAGE_SIZE;
+                }
+            }
+        }
+    }
+
     /// Get a mutable reference of the content of a page of virtual address `addr`
     /// Used for testing with mock
     fn get_page_slice_mut<'a>(&mut self, addr: VirtAddr) -> &'a mut [u8] {
@@ -43,13 +116,15 @@ pub trait PageTable {
     }
 
     /// When `vaddr` is not mapped, map it to `paddr`.
+    /// Panic if a page table can not be allocated, only used for the kernel memory
     fn map_if_not_exists(&mut self, vaddr: VirtAddr, paddr: usize) -> bool {
         if let Some(entry) = self.get_entry(vaddr) {
             if entry.present() {
                 return false;
             }
         }
-        self.map(vaddr, paddr);
+        self.map(vaddr, paddr)
+            .expect("failed to allocate page table");
         true
     }
 }
@@ -97,6 +172,9 @@ pub trait Entry {
     fn set_execute(&mut self, value: bool);
     fn mmio(&self) -> u8;
     fn set_mmio(&mut self, value: u8);
+
+    /// Whether the entry maps a huge page
+    fn huge(&self) -> bool;
 }
 
 /// An inactive page table
@@ -107,14 +185,16 @@ pub trait InactivePageTable: Sized {
     type Active: PageTable;
 
     /// Create a new page table with kernel memory mapped
-    fn new() -> Self {
-        let mut pt = Self::new_bare();
+    /// Return `None` if out of frames
+    fn new() -> Option<Self> {
+        let mut pt = Self::new_bare()?;
         pt.map_kernel();
-        pt
+        Some(pt)
     }
 
     /// Create a new page table without kernel memory mapped
-    fn new_bare() -> Self;
+    /// Return `None` if out of frames
+    fn new_bare() -> Option<Self>;
 
     /// Map kernel segments
     fn map_kernel(&mut self);
@@ -157,4 +237,24 @@ pub trait InactivePageTable: Sized {
         }
         ret
     }
+
+    /// Edit this page table with it activated,
+    /// so that `f` may access the mapped pages by their virtual address.
+    /// The temporary map is taken by `edit`, so it can not be used to access the frames.
+    ///
+    /// Unsafe: the kernel must be mapped in this page table, like `with`
+    unsafe fn edit_activated<T>(&mut self, f: impl FnOnce(&mut Self::Active) -> T) -> T {
+        let old_token = Self::active_token();
+        let new_token = self.token();
+        if old_token != new_token {
+            Self::set_token(new_token);
+            Self::flush_tlb();
+        }
+        let ret = self.edit(f);
+        if old_token != new_token {
+            Self::set_token(old_token);
+            Self::flush_tlb();
+        }
+        ret
+    }
 }
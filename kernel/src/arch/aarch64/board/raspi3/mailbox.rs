@@ -3,12 +3,12 @@
 //! (ref: https://github.com/raspberrypi/firmware/wiki/Mailbox-property-interface)
 
 use super::fb::FramebufferInfo;
+use crate::sync::SpinLock as Mutex;
 use aarch64::asm;
 use alloc::string::String;
 use bcm2837::mailbox::{Mailbox, MailboxChannel};
 use core::mem;
 use lazy_static::lazy_static;
-use spin::Mutex;
 
 lazy_static! {
     static ref MAILBOX: Mutex<Mailbox> = Mutex::new(Mailbox::new());
@@ -1,8 +1,8 @@
+use crate::sync::SpinLock as Mutex;
 use bcm2837::mini_uart::{MiniUart, MiniUartInterruptId};
 use core::fmt;
 use lazy_static::lazy_static;
 use once::*;
-use spin::Mutex;
 
 /// Struct to get a global SerialPort interface
 pub struct SerialPort {
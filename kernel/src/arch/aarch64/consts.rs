@@ -3,5 +3,11 @@ pub const KERNEL_OFFSET: usize = 0xFFFF_0000_0000_0000;
 pub const KERNEL_PML4: usize = 0;
 pub const KERNEL_HEAP_SIZE: usize = 8 * 1024 * 1024;
 pub const MEMORY_OFFSET: usize = 0;
+/// Region of kernel stacks with their guards, see `memory::kstack`.
+/// Must match `CHECK_KSTACK_OVERFLOW` in `vector.S`.
+pub const KERNEL_STACK_REGION: usize = 0xFFFF_0010_0000_0000;
+pub const KERNEL_STACK_REGION_SIZE: usize = 0x400_0000;
 pub const USER_STACK_OFFSET: usize = 0x0000_8000_0000_0000 - USER_STACK_SIZE;
 pub const USER_STACK_SIZE: usize = 1 * 1024 * 1024;
+/// End of the user address space (48-bit TTBR0 region)
+pub const USER_END: usize = 0x0001_0000_0000_0000;
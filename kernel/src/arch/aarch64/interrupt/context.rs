@@ -1,11 +1,11 @@
 //! TrapFrame and context definitions for aarch64.
 
+use crate::sync::SpinLock as Mutex;
 use aarch64::addr::PhysAddr;
 use aarch64::asm::{tlb_invalidate_all, ttbr_el1_read, ttbr_el1_write_asid};
 use aarch64::barrier;
 use aarch64::paging::PhysFrame;
 use lazy_static::lazy_static;
-use spin::Mutex;
 
 #[repr(C)]
 #[derive(Default, Debug, Copy, Clone)]
@@ -40,8 +40,48 @@ impl TrapFrame {
         tf.spsr = 0b1101_00_0000; // To EL 0, enable IRQ
         tf
     }
+    /// Whether the trap comes from EL0
     pub fn is_user(&self) -> bool {
-        unimplemented!()
+        self.spsr & 0b1111 == 0
+    }
+    /// Address of the instruction that trapped
+    pub fn pc(&self) -> usize {
+        self.elr
+    }
+    /// Resume at `pc` when returning from the trap
+    pub fn set_pc(&mut self, pc: usize) {
+        self.elr = pc;
+    }
+    /// General registers in the layout of `elf_gregset_t`, for core dumps
+    pub fn elf_gregs(&self) -> [usize; 34] {
+        let mut regs = [0; 34];
+        regs[0] = self.x0;
+        regs[1..30].copy_from_slice(&self.x1to29);
+        regs[30] = self.x30;
+        regs[31] = self.sp;
+        regs[32] = self.elr;
+        regs[33] = self.spsr;
+        regs
+    }
+    /// Set the general registers from the layout of `elf_gregs`, for `ptrace`.
+    /// Only the condition flags of `spsr` can be changed, so it stays in EL0.
+    /// Return false and change nothing if they can not be set, which never happens here.
+    pub fn set_elf_gregs(&mut self, regs: &[usize; 34]) -> bool {
+        const NZCV: usize = 0xf000_0000;
+        self.x0 = regs[0];
+        self.x1to29.copy_from_slice(&regs[1..30]);
+        self.x30 = regs[30];
+        self.sp = regs[31];
+        self.elr = regs[32];
+        self.spsr = self.spsr & !NZCV | regs[33] & NZCV;
+        true
+    }
+    /// The return value of the syscall, once set
+    pub fn syscall_ret(&self) -> isize {
+        self.x0 as isize
+    }
+    pub fn set_syscall_ret(&mut self, ret: isize) {
+        self.x0 = ret as usize;
     }
 }
 
@@ -184,17 +224,21 @@ impl Context {
     }
     pub unsafe fn new_clone(
         tf: &TrapFrame,
-        ustack_top: usize,
+        ustack_top: Option<usize>,
         kstack_top: usize,
         ttbr: usize,
-        tls: usize,
+        tls: Option<usize>,
     ) -> Self {
         InitStack {
             context: ContextData::new(),
             tf: {
                 let mut tf = tf.clone();
-                tf.sp = ustack_top;
-                tf.tpidr = tls;
+                if let Some(ustack_top) = ustack_top {
+                    tf.sp = ustack_top;
+                }
+                if let Some(tls) = tls {
+                    tf.tpidr = tls;
+                }
                 tf.x0 = 0;
                 tf
             },
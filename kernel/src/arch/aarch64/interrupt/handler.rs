@@ -50,10 +50,15 @@ pub extern "C" fn rust_trap(info: Info, esr: u32, tf: &mut TrapFrame) {
             match syndrome {
                 Syndrome::Brk(brk) => handle_break(brk, tf),
                 Syndrome::Svc(svc) => handle_syscall(svc, tf),
-                Syndrome::DataAbort { kind, level: _ }
-                | Syndrome::InstructionAbort { kind, level: _ } => match kind {
+                Syndrome::DataAbort { kind, write, .. } => match kind {
                     Fault::Translation | Fault::AccessFlag | Fault::Permission => {
-                        handle_page_fault(tf)
+                        handle_page_fault(tf, write)
+                    }
+                    _ => crate::trap::error(tf),
+                },
+                Syndrome::InstructionAbort { kind, level: _ } => match kind {
+                    Fault::Translation | Fault::AccessFlag | Fault::Permission => {
+                        handle_page_fault(tf, false)
                     }
                     _ => crate::trap::error(tf),
                 },
@@ -67,6 +72,11 @@ pub extern "C" fn rust_trap(info: Info, esr: u32, tf: &mut TrapFrame) {
 }
 
 fn handle_break(_num: u16, tf: &mut TrapFrame) {
+    // the debugger of a user process handles its brk instruction
+    if tf.is_user() {
+        crate::trap::breakpoint(tf);
+        return;
+    }
     // Skip the current brk instruction (ref: J1.1.2, page 6147)
     tf.elr += 4;
 }
@@ -92,10 +102,10 @@ fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     tf.x0 = ret as usize;
 }
 
-fn handle_page_fault(tf: &mut TrapFrame) {
+fn handle_page_fault(tf: &mut TrapFrame, write: bool) {
     let addr = FAR_EL1.get() as usize;
-    if !crate::memory::handle_page_fault(addr) {
+    if !crate::memory::handle_page_fault(addr, write) {
         error!("\nEXCEPTION: Page Fault @ {:#x}", addr);
-        crate::trap::error(tf);
+        crate::trap::page_fault_error(tf, addr);
     }
 }
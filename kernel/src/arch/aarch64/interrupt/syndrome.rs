@@ -45,7 +45,7 @@ pub enum Syndrome {
     MsrMrsSystem,
     InstructionAbort { kind: Fault, level: u8 },
     PCAlignmentFault,
-    DataAbort { kind: Fault, level: u8 },
+    DataAbort { kind: Fault, level: u8, write: bool },
     SpAlignmentFault,
     TrappedFpu,
     SError,
@@ -90,6 +90,8 @@ impl From<u32> for Syndrome {
             0b100100 | 0b100101 => DataAbort {
                 kind: Fault::from(iss),
                 level: (iss & 0b11) as u8,
+                // WnR
+                write: iss & (1 << 6) != 0,
             },
             0b100110 => SpAlignmentFault,
             0b101000 => TrappedFpu,
@@ -40,51 +40,57 @@ static mut KERNEL_MEMORY_SET: Option<MemorySet> = None;
 /// remap kernel page table after all initialization.
 fn remap_the_kernel() {
     let offset = -(KERNEL_OFFSET as isize);
-    let mut ms = MemorySet::new_bare();
+    let mut ms = MemorySet::new_bare().expect("failed to allocate page table");
     ms.push(
         stext as usize,
         etext as usize,
         MemoryAttr::default().execute().readonly(),
         Linear::new(offset),
         "text",
-    );
+    )
+    .unwrap();
     ms.push(
         sdata as usize,
         edata as usize,
         MemoryAttr::default(),
         Linear::new(offset),
         "data",
-    );
+    )
+    .unwrap();
     ms.push(
         srodata as usize,
         erodata as usize,
         MemoryAttr::default().readonly(),
         Linear::new(offset),
         "rodata",
-    );
+    )
+    .unwrap();
     ms.push(
         sbss as usize,
         ebss as usize,
         MemoryAttr::default(),
         Linear::new(offset),
         "bss",
-    );
+    )
+    .unwrap();
     ms.push(
         bootstack as usize,
         bootstacktop as usize,
         MemoryAttr::default(),
         Linear::new(offset),
         "kstack",
-    );
+    )
+    .unwrap();
 
     use super::board::{IO_REMAP_BASE, IO_REMAP_END};
     ms.push(
         IO_REMAP_BASE,
         IO_REMAP_END,
         MemoryAttr::default().mmio(MMIOType::Device as u8),
-        Linear::new(offset),
+        Linear::new(offset).huge(),
         "io_remap",
-    );
+    )
+    .unwrap();
 
     info!("{:#x?}", ms);
     unsafe { ms.get_page_table_mut().activate_as_kernel() }
@@ -100,14 +106,88 @@ pub fn ioremap(paddr: usize, len: usize, name: &'static str) -> usize {
             vaddr,
             vaddr + len,
             MemoryAttr::default().mmio(MMIOType::NormalNonCacheable as u8),
-            Linear::new(offset),
+            Linear::new(offset).huge(),
             name,
-        );
+        )
+        .unwrap();
         return vaddr;
     }
     0
 }
 
+/// Map frames [`paddr`, `paddr + len`) for the growing kernel heap,
+/// return the virtual address.
+pub fn map_heap_frames(paddr: usize, len: usize) -> Option<usize> {
+    use rcore_memory::paging::{Entry, PageTable};
+    let vaddr = paddr.wrapping_add(KERNEL_OFFSET);
+    let ms = unsafe { KERNEL_MEMORY_SET.as_mut()? };
+    ms.edit(|pt| {
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            match pt.map(vaddr + offset, paddr + offset) {
+                Some(entry) => entry.update(),
+                None => {
+                    for offset in (0..offset).step_by(PAGE_SIZE) {
+                        pt.unmap(vaddr + offset);
+                    }
+                    return None;
+                }
+            }
+        }
+        Some(vaddr)
+    })
+}
+
+/// Map frames [`paddr`, `paddr + len`) for DMA, return the virtual address.
+pub fn map_dma_frames(paddr: usize, len: usize, uncached: bool) -> Option<usize> {
+    use rcore_memory::paging::PageTable;
+    let vaddr = paddr.wrapping_add(KERNEL_OFFSET);
+    let attr = if uncached {
+        MemoryAttr::default().mmio(MMIOType::NormalNonCacheable as u8)
+    } else {
+        MemoryAttr::default()
+    };
+    let ms = unsafe { KERNEL_MEMORY_SET.as_mut()? };
+    ms.edit(|pt| {
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            match pt.map(vaddr + offset, paddr + offset) {
+                Some(entry) => attr.apply(entry),
+                None => {
+                    for offset in (0..offset).step_by(PAGE_SIZE) {
+                        pt.unmap(vaddr + offset);
+                    }
+                    return None;
+                }
+            }
+        }
+        Some(vaddr)
+    })
+}
+
+pub fn unmap_dma_frames(vaddr: usize, len: usize) {
+    use rcore_memory::paging::PageTable;
+    let ms = unsafe { KERNEL_MEMORY_SET.as_mut().unwrap() };
+    ms.edit(|pt| {
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            pt.unmap(vaddr + offset);
+        }
+    });
+}
+
+/// Map a kernel page at `vaddr` to `paddr`, seen by all page tables.
+/// Used for the kernel stack region.
+/// Return `None` if a page table can not be allocated.
+pub fn map_kernel_page(vaddr: usize, paddr: usize) -> Option<()> {
+    use rcore_memory::paging::{Entry, PageTable};
+    let ms = unsafe { KERNEL_MEMORY_SET.as_mut().unwrap() };
+    ms.edit(|pt| pt.map(vaddr, paddr).map(Entry::update))
+}
+
+pub fn unmap_kernel_page(vaddr: usize) {
+    use rcore_memory::paging::PageTable;
+    let ms = unsafe { KERNEL_MEMORY_SET.as_mut().unwrap() };
+    ms.edit(|pt| pt.unmap(vaddr));
+}
+
 extern "C" {
     fn stext();
     fn etext();
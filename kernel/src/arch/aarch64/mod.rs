@@ -16,6 +16,7 @@ pub mod timer;
 pub mod board;
 
 global_asm!(include_str!("boot/entry.S"));
+global_asm!(include_str!("boot/copy_user.S"));
 
 /// The entry point of kernel
 #[no_mangle] // don't mangle the name of this function
@@ -17,7 +17,7 @@ pub struct ActivePageTable(RecursivePageTable<'static>);
 pub struct PageEntry(PageTableEntry);
 
 impl PageTable for ActivePageTable {
-    fn map(&mut self, addr: usize, target: usize) -> &mut Entry {
+    fn map(&mut self, addr: usize, target: usize) -> Option<&mut Entry> {
         let flags = EF::default();
         let attr = MairNormal::attr_value();
         self.0
@@ -30,7 +30,7 @@ impl PageTable for ActivePageTable {
             )
             .unwrap()
             .flush();
-        self.get_entry(addr).expect("fail to get entry")
+        Some(self.get_entry(addr).expect("fail to get entry"))
     }
 
     fn unmap(&mut self, addr: usize) {
@@ -39,13 +39,103 @@ impl PageTable for ActivePageTable {
     }
 
     fn get_entry(&mut self, vaddr: usize) -> Option<&mut Entry> {
+        // a 1GiB or 2MiB block, if the tables above are there
+        for level in (2..=3).rev() {
+            let entry = unsafe { &mut *entry_ptr(vaddr, level) };
+            if entry.huge() {
+                return Some(entry);
+            }
+            if !entry.present() {
+                break;
+            }
+        }
         // get p1 entry
-        let entry_addr =
-            ((vaddr >> 9) & 0o777_777_777_7770) | (RECURSIVE_INDEX << 39) | (vaddr & KERNEL_OFFSET);
-        Some(unsafe { &mut *(entry_addr as *mut PageEntry) })
+        Some(unsafe { &mut *entry_ptr(vaddr, 1) })
+    }
+
+    fn huge_page_sizes(&self) -> &'static [usize] {
+        &[BLOCK_2M_SIZE, BLOCK_1G_SIZE]
+    }
+
+    fn huge_page_unused(&mut self, addr: usize, size: usize) -> bool {
+        let level = match size {
+            BLOCK_2M_SIZE => 2,
+            BLOCK_1G_SIZE => 3,
+            _ => return false,
+        };
+        // nothing is below a missing table
+        for upper in (level + 1..=3).rev() {
+            let entry = unsafe { &*entry_ptr(addr, upper) };
+            if !entry.present() {
+                return true;
+            }
+            if entry.huge() {
+                return false;
+            }
+        }
+        unsafe { !(*entry_ptr(addr, level)).present() }
+    }
+
+    fn map_huge(&mut self, addr: usize, target: usize, size: usize) -> &mut Entry {
+        let level = match size {
+            BLOCK_2M_SIZE => 2,
+            BLOCK_1G_SIZE => 3,
+            _ => panic!("huge page of size {:#x} is not supported", size),
+        };
+        // create the tables above the block
+        for upper in (level + 1..=3).rev() {
+            let entry = unsafe { &mut *entry_ptr(addr, upper) };
+            if !entry.present() {
+                let frame = alloc_frame().expect("failed to allocate frame");
+                entry.0.set_frame(
+                    Frame::of_addr(frame as u64),
+                    EF::default(),
+                    MairNormal::attr_value(),
+                );
+                // the new table, seen through the recursive mapping
+                let table = entry_ptr(addr, upper - 1) as usize & !0xfff;
+                tlb_invalidate(VirtAddr::new(table as u64));
+                unsafe { (*(table as *mut Aarch64PageTable)).zero() };
+            }
+        }
+        let entry = unsafe { &mut *entry_ptr(addr, level) };
+        assert!(!entry.present(), "huge page overlaps a page table");
+        entry.0.set_frame(
+            Frame::of_addr(target as u64),
+            EF::default() - EF::TABLE_OR_PAGE,
+            MairNormal::attr_value(),
+        );
+        entry.update();
+        entry
+    }
+
+    fn unmap_huge(&mut self, addr: usize, size: usize) {
+        let level = match size {
+            BLOCK_2M_SIZE => 2,
+            BLOCK_1G_SIZE => 3,
+            _ => panic!("huge page of size {:#x} is not supported", size),
+        };
+        let entry = unsafe { &mut *entry_ptr(addr, level) };
+        assert!(entry.huge(), "not a huge page");
+        entry.0.set_unused();
+        entry.update();
     }
 }
 
+const BLOCK_2M_SIZE: usize = 1 << 21;
+const BLOCK_1G_SIZE: usize = 1 << 30;
+
+/// The entry of `level` (1 for P1, 4 for P4) covering `vaddr`,
+/// through the recursive mapping of the user or the kernel page table.
+/// The P4 entry must be present, if `level` is below 3.
+fn entry_ptr(vaddr: usize, level: usize) -> *mut PageEntry {
+    let mut addr = (vaddr >> (9 * level)) & ((1 << (48 - 9 * level)) - 1) & !0x7;
+    for i in 0..level {
+        addr |= RECURSIVE_INDEX << (39 - 9 * i);
+    }
+    (addr | (vaddr & KERNEL_OFFSET)) as *mut PageEntry
+}
+
 impl PageTableExt for ActivePageTable {
     const TEMP_PAGE_ADDR: usize = KERNEL_OFFSET | 0xcafeb000;
 }
@@ -73,6 +163,11 @@ pub enum MMIOType {
 
 impl Entry for PageEntry {
     fn update(&mut self) {
+        if self.huge() {
+            // the mapped address can not be told from a block entry
+            tlb_invalidate_all();
+            return;
+        }
         let addr = VirtAddr::new_unchecked((self as *const _ as u64) << 9);
         tlb_invalidate(addr);
     }
@@ -173,6 +268,11 @@ impl Entry for PageEntry {
         };
         self.0.modify_attr(attr);
     }
+    fn huge(&self) -> bool {
+        // a block of 1GiB or 2MiB, rather than a table or a page
+        let flags = self.0.flags();
+        flags.contains(EF::VALID) && !flags.contains(EF::TABLE_OR_PAGE)
+    }
 }
 
 impl PageEntry {
@@ -198,14 +298,14 @@ pub struct InactivePageTable0 {
 impl InactivePageTable for InactivePageTable0 {
     type Active = ActivePageTable;
 
-    fn new() -> Self {
+    fn new() -> Option<Self> {
         // When the new InactivePageTable is created for the user MemorySet, it's use ttbr1 as the
         // TTBR. And the kernel TTBR ttbr0 will never changed, so we needn't call map_kernel()
         Self::new_bare()
     }
 
-    fn new_bare() -> Self {
-        let target = alloc_frame().expect("failed to allocate frame");
+    fn new_bare() -> Option<Self> {
+        let target = alloc_frame()?;
         let frame = Frame::of_addr(target as u64);
         active_table().with_temporary_map(target, |_, table: &mut Aarch64PageTable| {
             table.zero();
@@ -216,7 +316,7 @@ impl InactivePageTable for InactivePageTable0 {
                 MairNormal::attr_value(),
             );
         });
-        InactivePageTable0 { p4_frame: frame }
+        Some(InactivePageTable0 { p4_frame: frame })
     }
 
     fn map_kernel(&mut self) {
@@ -278,6 +278,7 @@ pub const SYS_PKEY_ALLOC: usize = 289;
 pub const SYS_PKEY_FREE: usize = 290;
 pub const SYS_STATX: usize = 291;
 pub const SYS_IO_PGETEVENTS: usize = 292;
+pub const SYS_CLONE3: usize = 435;
 
 // custom temporary syscall
 pub const SYS_MAP_PCI_DEVICE: usize = 999;
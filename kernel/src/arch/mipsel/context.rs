@@ -87,6 +87,136 @@ impl TrapFrame {
         tf.status.set_exl();
         tf
     }
+
+    /// Whether the trap comes from user mode (KSU = 0b10)
+    pub fn is_user(&self) -> bool {
+        self.status.bits & 0x18 == 0x10
+    }
+    /// Address of the instruction that trapped
+    pub fn pc(&self) -> usize {
+        self.epc
+    }
+    /// Resume at `pc` when returning from the trap
+    pub fn set_pc(&mut self, pc: usize) {
+        self.epc = pc;
+    }
+    /// General registers in the layout of `elf_gregset_t`, for core dumps.
+    /// The first 6 are padding, then r0 to r31, lo, hi, and CP0 registers.
+    pub fn elf_gregs(&self) -> [usize; 45] {
+        [
+            // padding
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            // r0 is always zero
+            0,
+            self.at,
+            self.v0,
+            self.v1,
+            self.a0,
+            self.a1,
+            self.a2,
+            self.a3,
+            self.t0,
+            self.t1,
+            self.t2,
+            self.t3,
+            self.t4,
+            self.t5,
+            self.t6,
+            self.t7,
+            self.s0,
+            self.s1,
+            self.s2,
+            self.s3,
+            self.s4,
+            self.s5,
+            self.s6,
+            self.s7,
+            self.t8,
+            self.t9,
+            self.k0,
+            self.k1,
+            self.gp,
+            self.sp,
+            self.fp,
+            self.ra,
+            self.lo,
+            self.hi,
+            self.epc,
+            self.vaddr,
+            self.status.bits as usize,
+            self.cause.bits as usize,
+            0, // unused
+        ]
+    }
+    /// Set the general registers from the layout of `elf_gregs`, for `ptrace`.
+    /// The CP0 registers are kept.
+    /// Return false and change nothing if they can not be set, which never happens here.
+    pub fn set_elf_gregs(&mut self, regs: &[usize; 45]) -> bool {
+        let gprs = [
+            &mut self.at,
+            &mut self.v0,
+            &mut self.v1,
+            &mut self.a0,
+            &mut self.a1,
+            &mut self.a2,
+            &mut self.a3,
+            &mut self.t0,
+            &mut self.t1,
+            &mut self.t2,
+            &mut self.t3,
+            &mut self.t4,
+            &mut self.t5,
+            &mut self.t6,
+            &mut self.t7,
+            &mut self.s0,
+            &mut self.s1,
+            &mut self.s2,
+            &mut self.s3,
+            &mut self.s4,
+            &mut self.s5,
+            &mut self.s6,
+            &mut self.s7,
+            &mut self.t8,
+            &mut self.t9,
+            &mut self.k0,
+            &mut self.k1,
+            &mut self.gp,
+            &mut self.sp,
+            &mut self.fp,
+            &mut self.ra,
+        ];
+        // r1 to r31 follow the padding and r0
+        for (reg, &value) in gprs.iter_mut().zip(regs[7..38].iter()) {
+            **reg = value;
+        }
+        self.lo = regs[38];
+        self.hi = regs[39];
+        self.epc = regs[40];
+        true
+    }
+    /// The return value of the syscall, once set.
+    /// Errors are positive in `v0` with `a3` set, as the n32 ABI of musl expects.
+    pub fn syscall_ret(&self) -> isize {
+        if self.a3 != 0 {
+            -(self.v0 as isize)
+        } else {
+            self.v0 as isize
+        }
+    }
+    pub fn set_syscall_ret(&mut self, ret: isize) {
+        if ret < 0 {
+            self.v0 = (-ret) as usize;
+            self.a3 = 1;
+        } else {
+            self.v0 = ret as usize;
+            self.a3 = 0;
+        }
+    }
 }
 
 use core::fmt::{Debug, Error, Formatter};
@@ -248,21 +378,24 @@ impl Context {
     ///
     /// The stack pointer in kernel mode will be set to `kstack_top`.
     /// The SATP register will be set to `satp`.
-    /// The new user stack will be set to `ustack_top`.
-    /// The new thread pointer will be set to `tls`.
+    /// The new user stack will be set to `ustack_top` if given.
+    /// The new thread pointer will be set to `tls` if given.
     /// All the other registers are same as the original.
     pub unsafe fn new_clone(
         tf: &TrapFrame,
-        ustack_top: usize,
+        ustack_top: Option<usize>,
         kstack_top: usize,
         satp: usize,
-        tls: usize,
+        tls: Option<usize>,
     ) -> Self {
+        let tls = tls.unwrap_or_else(|| unsafe { *(_cur_tls as *const usize) });
         InitStack {
             context: ContextData::new(satp, tls),
             tf: {
                 let mut tf = tf.clone();
-                tf.sp = ustack_top; // sp
+                if let Some(ustack_top) = ustack_top {
+                    tf.sp = ustack_top; // sp
+                }
                 tf.v0 = 0; // return value
                 tf
             },
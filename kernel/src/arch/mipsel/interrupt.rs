@@ -67,9 +67,10 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
     match tf.cause.cause() {
         E::Interrupt => interrupt_dispatcher(tf),
         E::Syscall => syscall(tf),
-        E::TLBModification => page_fault(tf),
-        E::TLBLoadMiss => page_fault(tf),
-        E::TLBStoreMiss => page_fault(tf),
+        E::Breakpoint => crate::trap::breakpoint(tf),
+        E::TLBModification => page_fault(tf, true),
+        E::TLBLoadMiss => page_fault(tf, false),
+        E::TLBStoreMiss => page_fault(tf, true),
         E::ReservedInstruction => {
             if !reserved_inst(tf) {
                 error!("Unhandled Exception @ CPU{}: {:?} ", 0, tf.cause.cause());
@@ -93,7 +94,7 @@ fn interrupt_dispatcher(tf: &mut TrapFrame) {
     let pint = tf.cause.pending_interrupt();
     trace!("  Interrupt {:08b} ", pint);
     if (pint & 0b100_000_00) != 0 {
-        timer();
+        timer(tf);
     } else if (pint & 0b011_111_00) != 0 {
         external();
     } else {
@@ -138,9 +139,9 @@ fn ipi() {
     cp0::cause::reset_soft_int1();
 }
 
-fn timer() {
+fn timer(tf: &mut TrapFrame) {
     super::timer::set_next();
-    crate::trap::timer();
+    crate::trap::timer(tf);
 }
 
 fn syscall(tf: &mut TrapFrame) {
@@ -151,13 +152,7 @@ fn syscall(tf: &mut TrapFrame) {
     let ret = crate::syscall::syscall(tf.v0, arguments, tf) as isize;
     // comply with mips n32 abi, always return a positive value
     // https://git.musl-libc.org/cgit/musl/tree/arch/mipsn32/syscall_arch.h
-    if (ret < 0) {
-        tf.v0 = (-ret) as usize;
-        tf.a3 = 1;
-    } else {
-        tf.v0 = ret as usize;
-        tf.a3 = 0;
-    }
+    tf.set_syscall_ret(ret);
 }
 
 fn set_trapframe_register(rt: usize, val: usize, tf: &mut TrapFrame) {
@@ -229,7 +224,7 @@ fn reserved_inst(tf: &mut TrapFrame) -> bool {
     false
 }
 
-fn page_fault(tf: &mut TrapFrame) {
+fn page_fault(tf: &mut TrapFrame, write: bool) {
     // TODO: set access/dirty bit
     let addr = tf.vaddr;
     trace!("\nEXCEPTION: Page Fault @ {:#x}", addr);
@@ -252,16 +247,16 @@ fn page_fault(tf: &mut TrapFrame) {
             };
 
             if !tlb_valid {
-                if !crate::memory::handle_page_fault(addr) {
-                    crate::trap::error(tf);
+                if !crate::memory::handle_page_fault(addr, write) {
+                    crate::trap::page_fault_error(tf, addr);
                 }
             }
 
             tlb::write_tlb_random(tlb_entry)
         }
         Err(()) => {
-            if !crate::memory::handle_page_fault(addr) {
-                crate::trap::error(tf);
+            if !crate::memory::handle_page_fault(addr, write) {
+                crate::trap::page_fault_error(tf, addr);
             }
         }
     }
@@ -20,6 +20,29 @@ pub fn init() {
     println!("_root_page_table_ptr {:x}", _root_page_table_ptr as usize);
 }
 
+/// Map frames for the growing kernel heap, return the virtual address.
+///
+/// Frames are allocated in KSEG0, which is always accessible to the kernel.
+pub fn map_heap_frames(paddr: usize, _len: usize) -> Option<usize> {
+    Some(paddr)
+}
+
+/// Map frames for DMA, return the virtual address.
+///
+/// Frames are in KSEG0, and their uncached alias is in KSEG1.
+pub fn map_dma_frames(paddr: usize, _len: usize, uncached: bool) -> Option<usize> {
+    if uncached {
+        Some(paddr + KSEG1_OFFSET)
+    } else {
+        Some(paddr)
+    }
+}
+
+pub fn unmap_dma_frames(_vaddr: usize, _len: usize) {}
+
+/// Distance from KSEG0 to KSEG1
+const KSEG1_OFFSET: usize = 0x2000_0000;
+
 pub fn init_other() {
     // TODO: init other CPU cores
 }
@@ -79,4 +79,5 @@ const BOOT_CPU_ID: u32 = 0;
 global_asm!(include_str!("boot/context.gen.s"));
 global_asm!(include_str!("boot/entry.gen.s"));
 global_asm!(include_str!("boot/trap.gen.s"));
+global_asm!(include_str!("boot/copy_user.gen.s"));
 global_asm!(include_str!("boot/dtb.gen.s"));
@@ -16,7 +16,7 @@ pub struct ActivePageTable(TwoLevelPageTable<'static>, PageEntry);
 pub struct PageEntry(&'static mut PageTableEntry, Page);
 
 impl PageTable for ActivePageTable {
-    fn map(&mut self, addr: usize, target: usize) -> &mut Entry {
+    fn map(&mut self, addr: usize, target: usize) -> Option<&mut Entry> {
         // map the 4K `page` to the 4K `frame` with `flags`
         let flags = EF::VALID | EF::WRITABLE | EF::CACHEABLE;
         let page = Page::of_addr(VirtAddr::new(addr));
@@ -27,7 +27,7 @@ impl PageTable for ActivePageTable {
             .map_to(page, frame, flags, &mut FrameAllocatorForRiscv)
             .unwrap()
             .flush();
-        self.get_entry(addr).expect("fail to get entry")
+        Some(self.get_entry(addr).expect("fail to get entry"))
     }
 
     fn unmap(&mut self, addr: usize) {
@@ -146,6 +146,9 @@ impl Entry for PageEntry {
         0
     }
     fn set_mmio(&mut self, _value: u8) {}
+    fn huge(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -156,14 +159,14 @@ pub struct InactivePageTable0 {
 impl InactivePageTable for InactivePageTable0 {
     type Active = ActivePageTable;
 
-    fn new_bare() -> Self {
-        let target = alloc_frame().expect("failed to allocate frame");
+    fn new_bare() -> Option<Self> {
+        let target = alloc_frame()?;
         let frame = Frame::of_addr(PhysAddr::new(target));
 
         let table = unsafe { &mut *(target as *mut MIPSPageTable) };
 
         table.zero();
-        InactivePageTable0 { root_frame: frame }
+        Some(InactivePageTable0 { root_frame: frame })
     }
 
     fn map_kernel(&mut self) {
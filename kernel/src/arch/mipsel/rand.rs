@@ -1,3 +1,8 @@
+use mips::registers::cp0;
+
 pub fn rand() -> u64 {
-    return 0;
+    // no hardware RNG, and the cycle counter is reset every tick,
+    // so mix it with the tick count
+    let tick = unsafe { crate::trap::TICK } as u64;
+    (tick << 32) | cp0::count::read_u32() as u64
 }
@@ -382,6 +382,7 @@ define_syscall!(PKEY_FREE, 365);
 define_syscall!(STATX, 366);
 define_syscall!(RSEQ, 367);
 define_syscall!(IO_PGETEVENTS, 368);
+define_syscall!(CLONE3, 435);
 
 // non-existent syscalls, will not be called or matched
 pub const SYS_NEWFSTATAT: usize = 0;
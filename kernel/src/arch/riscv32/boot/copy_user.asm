@@ -0,0 +1,33 @@
+# Constants / Macros defined in Rust code:
+#   XLENB
+
+    .section .text
+    .globl __copy_user
+# usize __copy_user(dst: *mut u8, src: *const u8, len: usize)
+# Return the number of bytes NOT copied.
+# A page fault inside is redirected to the fixup by `__ex_table`.
+__copy_user:
+    beqz a2, copy_user_fixup
+copy_user_load:
+    lb t0, 0(a1)
+copy_user_store:
+    sb t0, 0(a0)
+    addi a0, a0, 1
+    addi a1, a1, 1
+    addi a2, a2, -1
+    bnez a2, copy_user_load
+copy_user_fixup:
+    mv a0, a2
+    ret
+
+    .section __ex_table, "a"
+    .balign XLENB
+.if XLENB == 8
+    .dword copy_user_load, copy_user_fixup
+    .dword copy_user_store, copy_user_fixup
+.else
+    .word copy_user_load, copy_user_fixup
+    .word copy_user_store, copy_user_fixup
+.endif
+
+    .section .text
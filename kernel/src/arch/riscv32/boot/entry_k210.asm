@@ -12,10 +12,6 @@ _start:
     lui     sp, %hi(bootstack)
     add     sp, sp, t0
 
-    # 1.1 set device tree paddr
-    # OpenSBI give me 0 ???
-    li      a1, 0x800003b0
-
     # 2. jump to rust_main (absolute address)
     lui     t0, %hi(rust_main)
     addi    t0, t0, %lo(rust_main)
@@ -0,0 +1,29 @@
+    .section .text.entry
+    .globl _start
+_start:
+    # a0 == hartid
+    # pc == 0x80200000, or 0x80010000 on K210
+    # sp == 0x800xxxxx
+
+    # 1. set sp
+    # sp = bootstack + (hartid + 1) * 0x10000
+    add     t0, a0, 1
+    slli    t0, t0, 14
+    la      sp, bootstack
+    add     sp, sp, t0
+
+    # 2. keep paging disabled, the kernel runs at its physical address
+    csrw    satp, zero
+    sfence.vma
+
+    # 3. jump to rust_main, `la` is pc-relative as the kernel is linked above 2G
+    la      t0, rust_main
+    jr      t0
+
+    .section .bss.stack
+    .align 12   # page align
+    .global bootstack
+bootstack:
+    .space 4096 * 4 * 8
+    .global bootstacktop
+bootstacktop:
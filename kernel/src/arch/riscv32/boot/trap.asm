@@ -3,6 +3,10 @@
 #   LOAD
 #   STORE
 #   TEST_BACK_TO_KERNEL
+#   KSTACK_REGION_SHIFT
+#   KSTACK_REGION_TAG
+#   KSTACK_GUARD_SHIFT
+#   MAX_CPU_NUM
 
 .macro SAVE_ALL
     # If coming from userspace, preserve the user stack pointer and load
@@ -11,7 +15,29 @@
     csrrw sp, sscratch, sp
     bnez sp, trap_from_user
 trap_from_kernel:
+    # If sp is in the guard of a kernel stack, the trap frame can not be saved there.
+    # Switch to the overflow stack to report it, sscratch keeps the previous sp.
+    # Only sp is free to use here, reload it from sscratch after each check.
     csrr sp, sscratch
+    srai sp, sp, KSTACK_REGION_SHIFT
+    addi sp, sp, -KSTACK_REGION_TAG
+    bnez sp, 1f
+    csrr sp, sscratch
+    addi sp, sp, -37 * XLENB
+    srli sp, sp, KSTACK_GUARD_SHIFT
+    andi sp, sp, 1
+    bnez sp, 1f
+    # sp = kstack_overflow_stack + (hartid + 1) * 0x4000, gp is restored after
+    la sp, kstack_overflow_stack
+    addi gp, gp, 1
+    slli gp, gp, 14
+    add sp, sp, gp
+    srli gp, gp, 14
+    addi gp, gp, -1
+    j 2f
+1:
+    csrr sp, sscratch
+2:
     STORE gp, -1
     # sscratch = previous-sp, sp = kernel-sp
 trap_from_user:
@@ -127,3 +153,9 @@ trap_return:
     RESTORE_ALL
     # return from supervisor call
     sret
+
+    # Stacks for reporting kernel stack overflow, one per hart indexed by the hart id in gp
+    .section .bss
+    .align 12
+kstack_overflow_stack:
+    .space 0x4000 * MAX_CPU_NUM
@@ -14,8 +14,11 @@ pub const RECURSIVE_INDEX: usize = 0o774;
 
 #[cfg(target_arch = "riscv32")]
 pub const KERNEL_OFFSET: usize = 0xC000_0000;
-#[cfg(target_arch = "riscv64")]
+#[cfg(all(target_arch = "riscv64", not(feature = "nommu")))]
 pub const KERNEL_OFFSET: usize = 0xFFFF_FFFF_C000_0000;
+/// Without MMU the kernel runs at its physical address
+#[cfg(feature = "nommu")]
+pub const KERNEL_OFFSET: usize = MEMORY_OFFSET;
 
 #[cfg(target_arch = "riscv32")]
 pub const KERNEL_P2_INDEX: usize = (KERNEL_OFFSET >> 12 >> 10) & 0x3ff;
@@ -34,8 +37,28 @@ pub const MEMORY_END: usize = 0x8060_0000;
 #[cfg(not(feature = "board_k210"))]
 pub const MEMORY_END: usize = 0x8800_0000;
 
+/// End of the linear map of physical memory on riscv32, starting with the kernel
+#[cfg(target_arch = "riscv32")]
+pub const PHYSICAL_MAP_END: usize = MEMORY_END - MEMORY_OFFSET + KERNEL_OFFSET;
+
 // FIXME: rv64 `sh` and `ls` will crash if stack top > 0x80000000 ???
 pub const USER_STACK_OFFSET: usize = 0x80000000 - USER_STACK_SIZE;
 pub const USER_STACK_SIZE: usize = 0x10000;
+/// End of the user address space
+#[cfg(target_arch = "riscv32")]
+pub const USER_END: usize = 0x8000_0000;
+#[cfg(target_arch = "riscv64")]
+pub const USER_END: usize = 0x40_0000_0000;
 
 pub const MAX_DTB_SIZE: usize = 0x2000;
+
+/// Region of kernel stacks with their guards, see `memory::kstack`.
+/// Must match `KSTACK_*` in `mod.rs`.
+#[cfg(target_arch = "riscv32")]
+pub const KERNEL_STACK_REGION: usize = 0xF800_0000;
+#[cfg(target_arch = "riscv32")]
+pub const KERNEL_STACK_REGION_SIZE: usize = 0x80_0000;
+#[cfg(target_arch = "riscv64")]
+pub const KERNEL_STACK_REGION: usize = 0xFFFF_FFFF_F000_0000;
+#[cfg(target_arch = "riscv64")]
+pub const KERNEL_STACK_REGION_SIZE: usize = 0x400_0000;
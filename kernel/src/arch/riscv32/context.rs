@@ -51,6 +51,40 @@ impl TrapFrame {
         tf.sstatus.set_spp(sstatus::SPP::User);
         tf
     }
+
+    /// Whether the trap comes from U-mode
+    pub fn is_user(&self) -> bool {
+        self.sstatus.spp() == sstatus::SPP::User
+    }
+    /// Address of the instruction that trapped
+    pub fn pc(&self) -> usize {
+        self.sepc
+    }
+    /// Resume at `pc` when returning from the trap
+    pub fn set_pc(&mut self, pc: usize) {
+        self.sepc = pc;
+    }
+    /// General registers in the layout of `elf_gregset_t`, for core dumps
+    pub fn elf_gregs(&self) -> [usize; 32] {
+        let mut regs = self.x;
+        // x0 is always zero, pc is in its place
+        regs[0] = self.sepc;
+        regs
+    }
+    /// Set the general registers from the layout of `elf_gregs`, for `ptrace`.
+    /// Return false and change nothing if they can not be set, which never happens here.
+    pub fn set_elf_gregs(&mut self, regs: &[usize; 32]) -> bool {
+        self.sepc = regs[0];
+        self.x[1..].copy_from_slice(&regs[1..]);
+        true
+    }
+    /// The return value of the syscall, once set
+    pub fn syscall_ret(&self) -> isize {
+        self.x[10] as isize
+    }
+    pub fn set_syscall_ret(&mut self, ret: isize) {
+        self.x[10] = ret as usize;
+    }
 }
 
 use core::fmt::{Debug, Error, Formatter};
@@ -184,6 +218,8 @@ impl Context {
         Load sp, 0(a1)
         Load s11, 1*XLENB(sp)
         csrw satp, s11
+        // writing satp does not flush the TLB
+        sfence.vma
         Load ra, 0*XLENB(sp)
         Load s0, 2*XLENB(sp)
         Load s1, 3*XLENB(sp)
@@ -267,22 +303,26 @@ impl Context {
     ///
     /// The stack pointer in kernel mode will be set to `kstack_top`.
     /// The SATP register will be set to `satp`.
-    /// The new user stack will be set to `ustack_top`.
-    /// The new thread pointer will be set to `tls`.
+    /// The new user stack will be set to `ustack_top` if given.
+    /// The new thread pointer will be set to `tls` if given.
     /// All the other registers are same as the original.
     pub unsafe fn new_clone(
         tf: &TrapFrame,
-        ustack_top: usize,
+        ustack_top: Option<usize>,
         kstack_top: usize,
         satp: usize,
-        tls: usize,
+        tls: Option<usize>,
     ) -> Self {
         InitStack {
             context: ContextData::new(satp),
             tf: {
                 let mut tf = tf.clone();
-                tf.x[2] = ustack_top; // sp
-                tf.x[4] = tls; // tp
+                if let Some(ustack_top) = ustack_top {
+                    tf.x[2] = ustack_top; // sp
+                }
+                if let Some(tls) = tls {
+                    tf.x[4] = tls; // tp
+                }
                 tf.x[10] = 0; // a0
                 tf
             },
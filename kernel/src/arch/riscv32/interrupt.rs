@@ -63,11 +63,12 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
     match tf.scause.cause() {
         Trap::Interrupt(I::SupervisorExternal) => external(),
         Trap::Interrupt(I::SupervisorSoft) => ipi(),
-        Trap::Interrupt(I::SupervisorTimer) => timer(),
+        Trap::Interrupt(I::SupervisorTimer) => timer(tf),
         Trap::Exception(E::UserEnvCall) => syscall(tf),
-        Trap::Exception(E::LoadPageFault) => page_fault(tf),
-        Trap::Exception(E::StorePageFault) => page_fault(tf),
-        Trap::Exception(E::InstructionPageFault) => page_fault(tf),
+        Trap::Exception(E::Breakpoint) => crate::trap::breakpoint(tf),
+        Trap::Exception(E::LoadPageFault) => page_fault(tf, false),
+        Trap::Exception(E::StorePageFault) => page_fault(tf, true),
+        Trap::Exception(E::InstructionPageFault) => page_fault(tf, false),
         _ => crate::trap::error(tf),
     }
     trace!("Interrupt end");
@@ -112,9 +113,9 @@ fn ipi() {
     super::sbi::clear_ipi();
 }
 
-fn timer() {
+fn timer(tf: &mut TrapFrame) {
     super::timer::set_next();
-    crate::trap::timer();
+    crate::trap::timer(tf);
 }
 
 fn syscall(tf: &mut TrapFrame) {
@@ -127,11 +128,11 @@ fn syscall(tf: &mut TrapFrame) {
     tf.x[10] = ret as usize;
 }
 
-fn page_fault(tf: &mut TrapFrame) {
+fn page_fault(tf: &mut TrapFrame, write: bool) {
     let addr = tf.stval;
     trace!("\nEXCEPTION: Page Fault @ {:#x}", addr);
 
-    if !crate::memory::handle_page_fault(addr) {
-        crate::trap::error(tf);
+    if !crate::memory::handle_page_fault(addr, write) {
+        crate::trap::page_fault_error(tf, addr);
     }
 }
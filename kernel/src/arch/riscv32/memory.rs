@@ -1,3 +1,5 @@
+#[cfg(target_arch = "riscv32")]
+use crate::consts::PHYSICAL_MAP_END;
 use crate::consts::{KERNEL_OFFSET, MEMORY_END, MEMORY_OFFSET};
 use crate::memory::{init_heap, Linear, MemoryAttr, MemorySet, FRAME_ALLOCATOR};
 use core::mem;
@@ -17,11 +19,16 @@ pub fn init(dtb: usize) {
     // initialize heap and Frame allocator
     init_frame_allocator();
     init_heap();
-    // remap the kernel use 4K page
-    unsafe {
-        super::paging::setup_recursive_mapping();
+    // remap the kernel use 4K page, nothing to map without MMU
+    #[cfg(not(feature = "nommu"))]
+    {
+        unsafe {
+            super::paging::setup_recursive_mapping();
+        }
+        remap_the_kernel(dtb);
     }
-    remap_the_kernel(dtb);
+    #[cfg(target_arch = "riscv32")]
+    init_kernel_stack_region();
 }
 
 pub fn init_other() {
@@ -53,52 +60,79 @@ fn init_frame_allocator() {
     }
 }
 
-/// Remap the kernel memory address with 4K page recorded in p1 page table
+/// Where the linear map of physical memory after the kernel begins
+#[cfg(not(feature = "nommu"))]
+fn physical_map_start() -> usize {
+    (end as usize + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// Remap the kernel memory address with 4K page recorded in p1 page table,
+/// and on riscv32 the rest of the physical memory with megapages where aligned
+#[cfg(not(feature = "nommu"))]
 fn remap_the_kernel(dtb: usize) {
     let offset = -(KERNEL_OFFSET as isize - MEMORY_OFFSET as isize);
-    let mut ms = MemorySet::new_bare();
+    let mut ms = MemorySet::new_bare().expect("failed to allocate page table");
     ms.push(
         stext as usize,
         etext as usize,
         MemoryAttr::default().execute().readonly(),
         Linear::new(offset),
         "text",
-    );
+    )
+    .unwrap();
     ms.push(
         sdata as usize,
         edata as usize,
         MemoryAttr::default(),
         Linear::new(offset),
         "data",
-    );
+    )
+    .unwrap();
     ms.push(
         srodata as usize,
         erodata as usize,
         MemoryAttr::default().readonly(),
         Linear::new(offset),
         "rodata",
-    );
+    )
+    .unwrap();
     ms.push(
         bootstack as usize,
         bootstacktop as usize,
         MemoryAttr::default(),
         Linear::new(offset),
         "stack",
-    );
+    )
+    .unwrap();
     ms.push(
         sbss as usize,
         ebss as usize,
         MemoryAttr::default(),
         Linear::new(offset),
         "bss",
-    );
+    )
+    .unwrap();
+    // on riscv32 a device tree after the kernel is in the linear map of physical memory
+    if cfg!(target_arch = "riscv64") || dtb < physical_map_start() {
+        ms.push(
+            dtb,
+            dtb + super::consts::MAX_DTB_SIZE,
+            MemoryAttr::default().readonly(),
+            Linear::new(offset),
+            "dts",
+        )
+        .unwrap();
+    }
+    // the rest of the physical memory with megapages, for the frames of the heap and DMA
+    #[cfg(target_arch = "riscv32")]
     ms.push(
-        dtb,
-        dtb + super::consts::MAX_DTB_SIZE,
-        MemoryAttr::default().readonly(),
-        Linear::new(offset),
-        "dts",
-    );
+        physical_map_start(),
+        PHYSICAL_MAP_END,
+        MemoryAttr::default(),
+        Linear::new(offset).huge(),
+        "physical",
+    )
+    .unwrap();
     // map PLIC for HiFiveU & VirtIO
     let offset = -(KERNEL_OFFSET as isize);
     ms.push(
@@ -107,14 +141,16 @@ fn remap_the_kernel(dtb: usize) {
         MemoryAttr::default(),
         Linear::new(offset),
         "plic0",
-    );
+    )
+    .unwrap();
     ms.push(
         KERNEL_OFFSET + 0x0C20_2000,
         KERNEL_OFFSET + 0x0C20_2000 + PAGE_SIZE,
         MemoryAttr::default(),
         Linear::new(offset),
         "plic1",
-    );
+    )
+    .unwrap();
     // map UART for HiFiveU
     ms.push(
         KERNEL_OFFSET + 0x10010000,
@@ -122,7 +158,8 @@ fn remap_the_kernel(dtb: usize) {
         MemoryAttr::default(),
         Linear::new(offset),
         "uart",
-    );
+    )
+    .unwrap();
     // map UART for VirtIO
     ms.push(
         KERNEL_OFFSET + 0x10000000,
@@ -130,7 +167,8 @@ fn remap_the_kernel(dtb: usize) {
         MemoryAttr::default(),
         Linear::new(offset),
         "uart16550",
-    );
+    )
+    .unwrap();
     unsafe {
         ms.activate();
     }
@@ -141,6 +179,95 @@ fn remap_the_kernel(dtb: usize) {
     info!("remap kernel end");
 }
 
+/// Map frames [`paddr`, `paddr + len`) for the growing kernel heap,
+/// return the virtual address.
+///
+/// The kernel space is a single root entry shared by all page tables,
+/// so the linear mapping added here is seen everywhere.
+#[cfg(target_arch = "riscv64")]
+pub fn map_heap_frames(paddr: usize, len: usize) -> Option<usize> {
+    use crate::memory::active_table;
+    use rcore_memory::paging::{Entry, PageTable};
+    let vaddr = paddr - MEMORY_OFFSET + KERNEL_OFFSET;
+    let mut page_table = active_table();
+    for offset in (0..len).step_by(PAGE_SIZE) {
+        match page_table.map(vaddr + offset, paddr + offset) {
+            Some(entry) => entry.update(),
+            None => {
+                for offset in (0..offset).step_by(PAGE_SIZE) {
+                    page_table.unmap(vaddr + offset);
+                }
+                return None;
+            }
+        }
+    }
+    Some(vaddr)
+}
+
+/// The kernel root entries are copied into each page table when it is created,
+/// so new kernel mappings would not reach the existing ones.
+/// The frames are in the linear map of physical memory made by `remap_the_kernel` instead.
+#[cfg(target_arch = "riscv32")]
+pub fn map_heap_frames(paddr: usize, _len: usize) -> Option<usize> {
+    Some(paddr - MEMORY_OFFSET + KERNEL_OFFSET)
+}
+
+/// Map frames [`paddr`, `paddr + len`) for DMA, return the virtual address.
+///
+/// DMA of the virtio devices is cache coherent, so they are mapped like the heap.
+pub fn map_dma_frames(paddr: usize, len: usize, _uncached: bool) -> Option<usize> {
+    map_heap_frames(paddr, len)
+}
+
+/// Unmap the frames mapped by `map_dma_frames`
+#[cfg(target_arch = "riscv64")]
+pub fn unmap_dma_frames(vaddr: usize, len: usize) {
+    use crate::memory::active_table;
+    use rcore_memory::paging::PageTable;
+    let mut page_table = active_table();
+    for offset in (0..len).step_by(PAGE_SIZE) {
+        page_table.unmap(vaddr + offset);
+    }
+}
+
+/// The frames stay in the linear map of physical memory
+#[cfg(target_arch = "riscv32")]
+pub fn unmap_dma_frames(_vaddr: usize, _len: usize) {}
+
+/// Map a kernel page at `vaddr` to `paddr`, seen by all page tables.
+/// Used for the kernel stack region.
+/// Return `None` if a page table can not be allocated.
+pub fn map_kernel_page(vaddr: usize, paddr: usize) -> Option<()> {
+    use crate::memory::active_table;
+    use rcore_memory::paging::{Entry, PageTable};
+    active_table().map(vaddr, paddr)?.update();
+    Some(())
+}
+
+pub fn unmap_kernel_page(vaddr: usize) {
+    use crate::memory::active_table;
+    use rcore_memory::paging::PageTable;
+    active_table().unmap(vaddr);
+}
+
+/// Create the page tables of the kernel stack region,
+/// so that `map_kernel` shares them with every new page table.
+#[cfg(target_arch = "riscv32")]
+fn init_kernel_stack_region() {
+    use crate::consts::{KERNEL_STACK_REGION, KERNEL_STACK_REGION_SIZE};
+    use crate::memory::active_table;
+    use rcore_memory::paging::PageTable;
+    let mut page_table = active_table();
+    let region = KERNEL_STACK_REGION..KERNEL_STACK_REGION + KERNEL_STACK_REGION_SIZE;
+    // each root entry covers 4M
+    for vaddr in region.step_by(1 << 22) {
+        page_table
+            .map(vaddr, 0)
+            .expect("failed to allocate kernel stack page table");
+        page_table.unmap(vaddr);
+    }
+}
+
 // First core stores its SATP here.
 // Other cores load it later.
 static mut SATP: usize = 0;
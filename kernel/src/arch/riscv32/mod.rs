@@ -11,6 +11,10 @@ pub mod cpu;
 pub mod interrupt;
 pub mod io;
 pub mod memory;
+#[cfg(not(feature = "nommu"))]
+pub mod paging;
+#[cfg(feature = "nommu")]
+#[path = "paging_nommu.rs"]
 pub mod paging;
 pub mod rand;
 mod sbi;
@@ -23,6 +27,9 @@ use log::*;
 
 #[no_mangle]
 pub extern "C" fn rust_main(hartid: usize, device_tree_paddr: usize) -> ! {
+    // OpenSBI give me 0 ???
+    #[cfg(feature = "board_k210")]
+    let device_tree_paddr = 0x800003b0;
     let device_tree_vaddr = device_tree_paddr - MEMORY_OFFSET + KERNEL_OFFSET;
 
     unsafe {
@@ -81,11 +88,19 @@ const BOOT_HART_ID: usize = 0;
 const BOOT_HART_ID: usize = 1;
 
 /// Constant & Macro for `trap.asm`
+///
+/// `KSTACK_*` must match `KERNEL_STACK_REGION` in `consts.rs`:
+/// `KERNEL_STACK_REGION >> KSTACK_REGION_SHIFT == KSTACK_REGION_TAG` (arithmetic shift),
+/// and `MAX_CPU_NUM` must match the one in `consts.rs`
 #[cfg(target_arch = "riscv32")]
 global_asm!(
     r"
     .equ XLENB,     4
     .equ XLENb,     32
+    .equ KSTACK_REGION_SHIFT,   23
+    .equ KSTACK_REGION_TAG,     -16
+    .equ KSTACK_GUARD_SHIFT,    14
+    .equ MAX_CPU_NUM,           64
     .macro LOAD a1, a2
         lw \a1, \a2*XLENB(sp)
     .endm
@@ -99,6 +114,10 @@ global_asm!(
     r"
     .equ XLENB,     8
     .equ XLENb,     64
+    .equ KSTACK_REGION_SHIFT,   26
+    .equ KSTACK_REGION_TAG,     -4
+    .equ KSTACK_GUARD_SHIFT,    14
+    .equ MAX_CPU_NUM,           64
     .macro LOAD a1, a2
         ld \a1, \a2*XLENB(sp)
     .endm
@@ -109,8 +128,14 @@ global_asm!(
 );
 #[cfg(target_arch = "riscv32")]
 global_asm!(include_str!("boot/entry32.asm"));
-#[cfg(all(target_arch = "riscv64", not(feature = "board_k210")))]
+#[cfg(all(
+    target_arch = "riscv64",
+    not(any(feature = "board_k210", feature = "nommu"))
+))]
 global_asm!(include_str!("boot/entry64.asm"));
-#[cfg(feature = "board_k210")]
+#[cfg(all(feature = "board_k210", not(feature = "nommu")))]
 global_asm!(include_str!("boot/entry_k210.asm"));
+#[cfg(feature = "nommu")]
+global_asm!(include_str!("boot/entry_nommu.asm"));
 global_asm!(include_str!("boot/trap.asm"));
+global_asm!(include_str!("boot/copy_user.asm"));
@@ -1,29 +1,38 @@
 use crate::consts::RECURSIVE_INDEX;
 // Depends on kernel
-#[cfg(target_arch = "riscv32")]
-use crate::consts::KERNEL_P2_INDEX;
 #[cfg(target_arch = "riscv64")]
 use crate::consts::KERNEL_P4_INDEX;
+#[cfg(target_arch = "riscv32")]
+use crate::consts::{
+    KERNEL_P2_INDEX, KERNEL_STACK_REGION, KERNEL_STACK_REGION_SIZE, PHYSICAL_MAP_END,
+};
 use crate::memory::{active_table, alloc_frame, dealloc_frame};
 use log::*;
 use rcore_memory::paging::*;
+use rcore_memory::PAGE_SIZE;
 use riscv::addr::*;
 use riscv::asm::{sfence_vma, sfence_vma_all};
 use riscv::paging::{FrameAllocator, FrameDeallocator};
 use riscv::paging::{
-    Mapper, PageTable as RvPageTable, PageTableEntry, PageTableFlags as EF, PageTableType,
-    RecursivePageTable,
+    MapToError, Mapper, PageTable as RvPageTable, PageTableEntry, PageTableFlags as EF,
+    PageTableType, RecursivePageTable,
 };
 use riscv::register::satp;
 
 pub struct ActivePageTable(RecursivePageTable<'static>, PageEntry);
 
+/// Root entries of the kernel stack region, shared by all page tables
+#[cfg(target_arch = "riscv32")]
+const KSTACK_ENTRY_START: usize = KERNEL_STACK_REGION >> 22;
+#[cfg(target_arch = "riscv32")]
+const KSTACK_ENTRY_COUNT: usize = KERNEL_STACK_REGION_SIZE >> 22;
+
 /// PageTableEntry: the contents of this entry.
 /// Page: this entry is the pte of page `Page`.
 pub struct PageEntry(&'static mut PageTableEntry, Page);
 
 impl PageTable for ActivePageTable {
-    fn map(&mut self, addr: usize, target: usize) -> &mut Entry {
+    fn map(&mut self, addr: usize, target: usize) -> Option<&mut Entry> {
         // use riscv::paging:Mapper::map_to,
         // map the 4K `page` to the 4K `frame` with `flags`
         let flags = EF::VALID | EF::READABLE | EF::WRITABLE;
@@ -31,11 +40,15 @@ impl PageTable for ActivePageTable {
         let frame = Frame::of_addr(PhysAddr::new(target));
         // map the page to the frame using FrameAllocatorForRiscv
         // we may need frame allocator to alloc frame for new page table(first/second)
-        self.0
+        match self
+            .0
             .map_to(page, frame, flags, &mut FrameAllocatorForRiscv)
-            .unwrap()
-            .flush();
-        self.get_entry(addr).expect("fail to get entry")
+        {
+            Ok(flush) => flush.flush(),
+            Err(MapToError::FrameAllocationFailed) => return None,
+            Err(err) => panic!("failed to map {:#x}: {:?}", addr, err),
+        }
+        Some(self.get_entry(addr).expect("fail to get entry"))
     }
 
     fn unmap(&mut self, addr: usize) {
@@ -46,6 +59,14 @@ impl PageTable for ActivePageTable {
 
     fn get_entry(&mut self, vaddr: usize) -> Option<&mut Entry> {
         let page = Page::of_addr(VirtAddr::new(vaddr));
+        #[cfg(any(target_arch = "riscv32", feature = "sv39"))]
+        {
+            let root = root_entry(vaddr);
+            if is_leaf(root) {
+                self.1 = PageEntry(root, page);
+                return Some(&mut self.1 as &mut Entry);
+            }
+        }
         if let Ok(e) = self.0.ref_entry(page.clone()) {
             let e = unsafe { &mut *(e as *mut PageTableEntry) };
             self.1 = PageEntry(e, page);
@@ -54,10 +75,63 @@ impl PageTable for ActivePageTable {
             None
         }
     }
+
+    #[cfg(any(target_arch = "riscv32", feature = "sv39"))]
+    fn huge_page_sizes(&self) -> &'static [usize] {
+        &[ROOT_HUGE_PAGE_SIZE]
+    }
+
+    #[cfg(any(target_arch = "riscv32", feature = "sv39"))]
+    fn huge_page_unused(&mut self, addr: usize, size: usize) -> bool {
+        size == ROOT_HUGE_PAGE_SIZE && root_entry(addr).is_unused()
+    }
+
+    #[cfg(any(target_arch = "riscv32", feature = "sv39"))]
+    fn map_huge(&mut self, addr: usize, target: usize, size: usize) -> &mut Entry {
+        assert_eq!(size, ROOT_HUGE_PAGE_SIZE, "unsupported huge page size");
+        let entry = root_entry(addr);
+        assert!(entry.is_unused(), "huge page overlaps a page table");
+        let flags = EF::VALID | EF::READABLE | EF::WRITABLE;
+        entry.set(Frame::of_addr(PhysAddr::new(target)), flags);
+        unsafe {
+            sfence_vma(0, addr);
+        }
+        self.1 = PageEntry(entry, Page::of_addr(VirtAddr::new(addr)));
+        &mut self.1
+    }
+
+    #[cfg(any(target_arch = "riscv32", feature = "sv39"))]
+    fn unmap_huge(&mut self, addr: usize, size: usize) {
+        assert_eq!(size, ROOT_HUGE_PAGE_SIZE, "unsupported huge page size");
+        root_entry(addr).set_unused();
+        unsafe {
+            sfence_vma(0, addr);
+        }
+    }
 }
 
 impl PageTableExt for ActivePageTable {}
 
+/// Huge pages are mapped by root entries only:
+/// megapages on Sv32 and gigapages on Sv39.
+#[cfg(target_arch = "riscv32")]
+const ROOT_HUGE_PAGE_SIZE: usize = 1 << 22;
+#[cfg(all(target_arch = "riscv64", feature = "sv39"))]
+const ROOT_HUGE_PAGE_SIZE: usize = 1 << 30;
+
+/// The root entry covering `vaddr`
+#[cfg(any(target_arch = "riscv32", feature = "sv39"))]
+fn root_entry(vaddr: usize) -> &'static mut PageTableEntry {
+    let index = vaddr / ROOT_HUGE_PAGE_SIZE % (PAGE_SIZE / core::mem::size_of::<usize>());
+    unsafe { &mut (*ROOT_PAGE_TABLE)[index] }
+}
+
+/// A valid entry pointing to a page instead of a next level table
+fn is_leaf(entry: &PageTableEntry) -> bool {
+    let flags = entry.flags();
+    flags.contains(EF::VALID) && flags.intersects(EF::READABLE | EF::WRITABLE | EF::EXECUTABLE)
+}
+
 /// The virtual address of root page table
 #[cfg(target_arch = "riscv32")]
 const ROOT_PAGE_TABLE: *mut RvPageTable =
@@ -171,6 +245,11 @@ impl Entry for PageEntry {
         0
     }
     fn set_mmio(&mut self, _value: u8) {}
+    fn huge(&self) -> bool {
+        // only root entries map huge pages
+        let table = &*self.0 as *const PageTableEntry as usize & !(PAGE_SIZE - 1);
+        table == ROOT_PAGE_TABLE as usize && is_leaf(&*self.0)
+    }
 }
 
 #[derive(Debug)]
@@ -181,14 +260,14 @@ pub struct InactivePageTable0 {
 impl InactivePageTable for InactivePageTable0 {
     type Active = ActivePageTable;
 
-    fn new_bare() -> Self {
-        let target = alloc_frame().expect("failed to allocate frame");
+    fn new_bare() -> Option<Self> {
+        let target = alloc_frame()?;
         let frame = Frame::of_addr(PhysAddr::new(target));
         active_table().with_temporary_map(target, |_, table: &mut RvPageTable| {
             table.zero();
             table.set_recursive(RECURSIVE_INDEX, frame.clone());
         });
-        InactivePageTable0 { root_frame: frame }
+        Some(InactivePageTable0 { root_frame: frame })
     }
 
     #[cfg(target_arch = "riscv32")]
@@ -196,21 +275,30 @@ impl InactivePageTable for InactivePageTable0 {
         let table = unsafe { &mut *ROOT_PAGE_TABLE };
         extern "C" {
             fn start();
-            fn end();
         }
+        // the kernel and the linear map of physical memory after it
         let mut entrys: [PageTableEntry; 256] = unsafe { core::mem::uninitialized() };
         let entry_start = start as usize >> 22;
-        let entry_end = (end as usize >> 22) + 1;
+        let entry_end = ((PHYSICAL_MAP_END - 1) >> 22) + 1;
         let entry_count = entry_end - entry_start;
         for i in 0..entry_count {
             entrys[i] = table[entry_start + i];
         }
+        // the kernel stack region, whose page tables are created at boot
+        let mut stack_entrys: [PageTableEntry; KSTACK_ENTRY_COUNT] =
+            unsafe { core::mem::uninitialized() };
+        for i in 0..KSTACK_ENTRY_COUNT {
+            stack_entrys[i] = table[KSTACK_ENTRY_START + i];
+        }
 
         self.edit(|_| {
             // NOTE: 'table' now refers to new page table
             for i in 0..entry_count {
                 table[entry_start + i] = entrys[i];
             }
+            for i in 0..KSTACK_ENTRY_COUNT {
+                table[KSTACK_ENTRY_START + i] = stack_entrys[i];
+            }
         });
     }
 
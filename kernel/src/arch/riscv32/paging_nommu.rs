@@ -0,0 +1,102 @@
+//! Page table without MMU
+//!
+//! Paging is never enabled, so every address is physical. Mapping an address to
+//! itself is a no-op and every page translates to itself, which keeps the kernel
+//! code mapping devices and frames working unchanged. Nothing else can be mapped.
+
+use rcore_memory::paging::*;
+use rcore_memory::PAGE_SIZE;
+
+pub struct ActivePageTable(IdentityEntry);
+
+/// The entry of a page mapped to itself
+pub struct IdentityEntry(usize);
+
+impl PageTable for ActivePageTable {
+    fn map(&mut self, addr: usize, target: usize) -> Option<&mut Entry> {
+        assert_eq!(
+            addr & !(PAGE_SIZE - 1),
+            target & !(PAGE_SIZE - 1),
+            "can not map other than to itself without MMU"
+        );
+        self.get_entry(addr)
+    }
+
+    fn unmap(&mut self, _addr: usize) {}
+
+    fn get_entry(&mut self, addr: usize) -> Option<&mut Entry> {
+        self.0 = IdentityEntry(addr & !(PAGE_SIZE - 1));
+        Some(&mut self.0)
+    }
+}
+
+impl PageTableExt for ActivePageTable {
+    fn with_temporary_map<T, D>(
+        &mut self,
+        target: usize,
+        f: impl FnOnce(&mut Self, &mut D) -> T,
+    ) -> T {
+        let data = unsafe { &mut *(target as *mut D) };
+        f(self, data)
+    }
+}
+
+impl ActivePageTable {
+    pub unsafe fn new() -> Self {
+        ActivePageTable(IdentityEntry(0))
+    }
+}
+
+/// Always present, readable, writable and executable, in user and kernel mode
+impl Entry for IdentityEntry {
+    fn update(&mut self) {}
+    fn accessed(&self) -> bool {
+        true
+    }
+    fn dirty(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    fn present(&self) -> bool {
+        true
+    }
+    fn clear_accessed(&mut self) {}
+    fn clear_dirty(&mut self) {}
+    fn set_writable(&mut self, _value: bool) {}
+    fn set_present(&mut self, _value: bool) {}
+    fn target(&self) -> usize {
+        self.0
+    }
+    fn set_target(&mut self, target: usize) {
+        assert_eq!(target, self.0, "can not remap a page without MMU");
+    }
+    fn writable_shared(&self) -> bool {
+        false
+    }
+    fn readonly_shared(&self) -> bool {
+        false
+    }
+    fn set_shared(&mut self, _writable: bool) {}
+    fn clear_shared(&mut self) {}
+    fn swapped(&self) -> bool {
+        false
+    }
+    fn set_swapped(&mut self, _value: bool) {}
+    fn user(&self) -> bool {
+        true
+    }
+    fn set_user(&mut self, _value: bool) {}
+    fn execute(&self) -> bool {
+        true
+    }
+    fn set_execute(&mut self, _value: bool) {}
+    fn mmio(&self) -> u8 {
+        0
+    }
+    fn set_mmio(&mut self, _value: u8) {}
+    fn huge(&self) -> bool {
+        false
+    }
+}
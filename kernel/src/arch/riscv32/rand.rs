@@ -1,3 +1,4 @@
 pub fn rand() -> u64 {
-    return 0;
+    // no hardware RNG, use the timer counter instead
+    super::timer::get_cycle()
 }
@@ -19,6 +19,11 @@ pub const KERNEL_PML4: usize = (KERNEL_OFFSET & PML4_MASK) / PML4_SIZE;
 
 pub const KERNEL_SIZE: usize = PML4_SIZE;
 
+/// Region of kernel stacks with their guards, see `memory::kstack`.
+/// In the kernel PML4, so it is shared by all page tables.
+pub const KERNEL_STACK_REGION: usize = KERNEL_OFFSET + 0x40_0000_0000;
+pub const KERNEL_STACK_REGION_SIZE: usize = 0x400_0000;
+
 /// Offset to kernel heap
 pub const KERNEL_HEAP_OFFSET: usize = KERNEL_OFFSET - PML4_SIZE;
 pub const KERNEL_HEAP_PML4: usize = (KERNEL_HEAP_OFFSET & PML4_MASK) / PML4_SIZE;
@@ -36,6 +41,8 @@ pub const KERNEL_PERCPU_SIZE: usize = 64 * 1024; // 64 KB
 /// Offset to user image
 pub const USER_OFFSET: usize = 0;
 pub const USER_PML4: usize = (USER_OFFSET & PML4_MASK) / PML4_SIZE;
+/// End of the user address space (lower canonical half)
+pub const USER_END: usize = 0x0000_8000_0000_0000;
 
 /// Offset to user TCB
 pub const USER_TCB_OFFSET: usize = 0xB000_0000;
@@ -0,0 +1,20 @@
+.section .text
+.global __copy_user
+.intel_syntax noprefix
+
+# usize __copy_user(dst: *mut u8, src: *const u8, len: usize)
+# Return the number of bytes NOT copied.
+# A page fault inside is redirected to the fixup by `__ex_table`.
+__copy_user:
+    mov rcx, rdx
+copy_user_movsb:
+    rep movsb
+copy_user_fixup:
+    mov rax, rcx
+    ret
+
+.section __ex_table, "a"
+    .balign 8
+    .quad copy_user_movsb, copy_user_fixup
+
+.section .text
@@ -39,6 +39,8 @@ pub fn init() {
         Cr0::update(|cr0| {
             cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
             cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+            // kernel writes to readonly user pages must fault, for copy-on-write
+            cr0.insert(Cr0Flags::WRITE_PROTECT);
         });
     }
 }
@@ -1,12 +1,14 @@
 // Copy from Redox
 
+use crate::sync::SpinLock as Mutex;
 use log::*;
 use once::*;
-use spin::Mutex;
 use x86_64::instructions::port::Port;
 
-static MASTER: Mutex<Pic> = Mutex::new(Pic::new(0x20));
-static SLAVE: Mutex<Pic> = Mutex::new(Pic::new(0xA0));
+lazy_static! {
+    static ref MASTER: Mutex<Pic> = Mutex::new(Pic::new(0x20));
+    static ref SLAVE: Mutex<Pic> = Mutex::new(Pic::new(0xA0));
+}
 
 pub fn disable() {
     // Mask all interrupts (Copy from xv6 x86_64)
@@ -1,12 +1,14 @@
+use crate::sync::SpinLock as Mutex;
 use once::*;
-use spin::Mutex;
 use uart_16550::SerialPort;
 use x86_64::instructions::port::Port;
 
 use crate::arch::interrupt::{consts, enable_irq};
 
-pub static COM1: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(0x3F8) });
-pub static COM2: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(0x2F8) });
+lazy_static! {
+    pub static ref COM1: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(0x3F8) });
+    pub static ref COM2: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(0x2F8) });
+}
 
 pub fn init() {
     assert_has_not_been_called!("serial::init must be called only once");
@@ -2,11 +2,11 @@ use core::fmt;
 
 use console_traits::*;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
 use x86_64::instructions::port::Port;
 
 use crate::consts::KERNEL_OFFSET;
+use crate::sync::SpinLock as Mutex;
 use crate::util::color::ConsoleColor;
 use crate::util::escape_parser::{EscapeParser, CSI};
 
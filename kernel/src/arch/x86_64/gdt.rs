@@ -35,7 +35,7 @@ static mut CPUS: [Option<Cpu>; MAX_CPU_NUM] = [
 pub struct Cpu {
     gdt: GlobalDescriptorTable,
     tss: TaskStateSegment,
-    double_fault_stack: [u8; 0x100],
+    double_fault_stack: [u8; DOUBLE_FAULT_STACK_SIZE],
 }
 
 impl Cpu {
@@ -47,7 +47,7 @@ impl Cpu {
         Cpu {
             gdt: GlobalDescriptorTable::new(),
             tss: TaskStateSegment::new(),
-            double_fault_stack: [0u8; 0x100],
+            double_fault_stack: [0u8; DOUBLE_FAULT_STACK_SIZE],
         }
     }
 
@@ -56,7 +56,8 @@ impl Cpu {
         use x86_64::instructions::tables::load_tss;
 
         // Set the stack when DoubleFault occurs
-        let stack_top = VirtAddr::new(self.double_fault_stack.as_ptr() as u64 + 0x100);
+        let stack_top =
+            VirtAddr::new(self.double_fault_stack.as_ptr() as u64 + DOUBLE_FAULT_STACK_SIZE as u64);
         self.tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] = stack_top;
 
         // GDT
@@ -88,6 +89,8 @@ impl Cpu {
 }
 
 pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
+/// Big enough to report a kernel stack overflow, which ends up in DoubleFault
+const DOUBLE_FAULT_STACK_SIZE: usize = 0x2000;
 
 // Copied from xv6 x86_64
 const KCODE: Descriptor = Descriptor::UserSegment(0x0020980000000000); // EXECUTABLE | USER_SEGMENT | PRESENT | LONG_MODE
@@ -29,6 +29,10 @@ lazy_static! {
                 opt.set_privilege_level(PrivilegeLevel::Ring3);
                 opt.disable_interrupts(false);
             }
+            // `int3` is allowed in user mode for debuggers
+            if i == Breakpoint as usize {
+                opt.set_privilege_level(PrivilegeLevel::Ring3);
+            }
             if i == DoubleFault as usize {
                 unsafe{ opt.set_stack_index(DOUBLE_FAULT_IST_INDEX as u16); }
             }
@@ -72,6 +72,7 @@ use log::*;
 
 global_asm!(include_str!("trap.asm"));
 global_asm!(include_str!("vector.asm"));
+global_asm!(include_str!("../copy_user.asm"));
 
 #[allow(non_upper_case_globals)]
 #[no_mangle]
@@ -83,14 +84,14 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
     );
     // Dispatch
     match tf.trap_num as u8 {
-        Breakpoint => breakpoint(),
+        Breakpoint | Debug => crate::trap::breakpoint(tf),
         DoubleFault => double_fault(tf),
         PageFault => page_fault(tf),
         IRQ0...63 => {
             let irq = tf.trap_num as u8 - IRQ0;
             super::ack(irq); // must ack before switching
             match irq {
-                Timer => crate::trap::timer(),
+                Timer => crate::trap::timer(tf),
                 Keyboard => keyboard(),
                 COM1 => com1(),
                 COM2 => com2(),
@@ -113,11 +114,13 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
     }
 }
 
-fn breakpoint() {
-    error!("\nEXCEPTION: Breakpoint");
-}
-
 fn double_fault(tf: &TrapFrame) {
+    // a page fault on the guard of a kernel stack can not push its trap frame
+    let addr: usize;
+    unsafe {
+        asm!("mov %cr2, $0" : "=r" (addr));
+    }
+    crate::memory::check_kernel_stack_overflow(addr);
     error!("\nEXCEPTION: Double Fault\n{:#x?}", tf);
     loop {}
 }
@@ -139,11 +142,11 @@ fn page_fault(tf: &mut TrapFrame) {
     }
     let code = PageError::from_bits(tf.error_code as u8).unwrap();
 
-    if crate::memory::handle_page_fault(addr) {
+    if crate::memory::handle_page_fault(addr, code.contains(PageError::WRITE)) {
         return;
     }
     error!("\nEXCEPTION: Page Fault @ {:#x}, code: {:?}", addr, code);
-    error(tf);
+    crate::trap::page_fault_error(tf, addr);
 }
 
 fn keyboard() {
@@ -84,6 +84,90 @@ impl TrapFrame {
         tf.fpstate_offset = 16; // skip restoring for first time
         tf
     }
+    /// Whether the trap comes from ring 3
+    pub fn is_user(&self) -> bool {
+        self.cs & 0x3 == 0x3
+    }
+    /// Address of the instruction that trapped
+    pub fn pc(&self) -> usize {
+        self.rip
+    }
+    /// Resume at `pc` when returning from the trap
+    pub fn set_pc(&mut self, pc: usize) {
+        self.rip = pc;
+    }
+    /// General registers in the layout of `elf_gregset_t`, for core dumps
+    pub fn elf_gregs(&self) -> [usize; 27] {
+        [
+            self.r15,
+            self.r14,
+            self.r13,
+            self.r12,
+            self.rbp,
+            self.rbx,
+            self.r11,
+            self.r10,
+            self.r9,
+            self.r8,
+            self.rax,
+            self.rcx,
+            self.rdx,
+            self.rsi,
+            self.rdi,
+            !0, // orig_rax, not in a syscall
+            self.rip,
+            self.cs,
+            self.rflags,
+            self.rsp,
+            self.ss,
+            self.fsbase,
+            // gs_base, ds, es, fs, gs
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]
+    }
+    /// Set the general registers from the layout of `elf_gregs`, for `ptrace`.
+    /// The segments are kept, and so are the flags user mode can not change.
+    /// Return false and change nothing if an address is not canonical,
+    /// which would fault in the kernel when returning to user mode.
+    pub fn set_elf_gregs(&mut self, regs: &[usize; 27]) -> bool {
+        const USER_END: usize = 0x0000_8000_0000_0000;
+        // CF, PF, AF, ZF, SF, TF, DF, OF, AC and ID, like Linux
+        const USER_FLAGS: usize = 0x0025_4dd5;
+        if regs[16] >= USER_END || regs[19] >= USER_END || regs[21] >= USER_END {
+            return false;
+        }
+        self.r15 = regs[0];
+        self.r14 = regs[1];
+        self.r13 = regs[2];
+        self.r12 = regs[3];
+        self.rbp = regs[4];
+        self.rbx = regs[5];
+        self.r11 = regs[6];
+        self.r10 = regs[7];
+        self.r9 = regs[8];
+        self.r8 = regs[9];
+        self.rax = regs[10];
+        self.rcx = regs[11];
+        self.rdx = regs[12];
+        self.rsi = regs[13];
+        self.rdi = regs[14];
+        self.rip = regs[16];
+        self.rflags = self.rflags & !USER_FLAGS | regs[18] & USER_FLAGS;
+        self.rsp = regs[19];
+        self.fsbase = regs[21];
+        true
+    }
+    /// The return value of the syscall, once set
+    pub fn syscall_ret(&self) -> isize {
+        self.rax as isize
+    }
+    pub fn set_syscall_ret(&mut self, ret: isize) {
+        self.rax = ret as usize;
+    }
 }
 
 #[derive(Debug, Default)]
@@ -217,17 +301,21 @@ impl Context {
     }
     pub unsafe fn new_clone(
         tf: &TrapFrame,
-        ustack_top: usize,
+        ustack_top: Option<usize>,
         kstack_top: usize,
         cr3: usize,
-        tls: usize,
+        tls: Option<usize>,
     ) -> Self {
         InitStack {
             context: ContextData::new(cr3),
             tf: {
                 let mut tf = tf.clone();
-                tf.rsp = ustack_top;
-                tf.fsbase = tls;
+                if let Some(ustack_top) = ustack_top {
+                    tf.rsp = ustack_top;
+                }
+                if let Some(tls) = tls {
+                    tf.fsbase = tls;
+                }
                 tf.rax = 0;
                 tf
             },
@@ -36,17 +36,68 @@ fn init_device_vm_map() {
     // IOAPIC
     page_table
         .map(KERNEL_OFFSET + 0xfec00000, 0xfec00000)
+        .expect("failed to map IOAPIC")
         .update();
     // LocalAPIC
     page_table
         .map(KERNEL_OFFSET + 0xfee00000, 0xfee00000)
+        .expect("failed to map LocalAPIC")
         .update();
 }
 
+/// Where frames given to the heap are mapped, offset by their physical address
+const HEAP_VA_OFFSET: usize = KERNEL_OFFSET + 0xe0000000;
+
+/// Map frames [`paddr`, `paddr + len`) for the growing kernel heap,
+/// return the virtual address.
+///
+/// The mapping lives in the kernel PML4 entry shared by all page tables,
+/// with huge pages where a whole one is given.
+pub fn map_heap_frames(paddr: usize, len: usize) -> Option<usize> {
+    active_table().map_range(HEAP_VA_OFFSET + paddr, paddr, len);
+    Some(HEAP_VA_OFFSET + paddr)
+}
+
+/// Map frames [`paddr`, `paddr + len`) for DMA, return the virtual address.
+///
+/// DMA is cache coherent on x86_64, so they are mapped like the heap.
+pub fn map_dma_frames(paddr: usize, len: usize, _uncached: bool) -> Option<usize> {
+    map_heap_frames(paddr, len)
+}
+
+pub fn unmap_dma_frames(vaddr: usize, len: usize) {
+    let mut page_table = active_table();
+    let mut va = vaddr;
+    while va < vaddr + len {
+        match page_table.huge_page_size(va) {
+            Some(size) => {
+                page_table.unmap_huge(va, size);
+                va += size;
+            }
+            None => {
+                page_table.unmap(va);
+                va += PAGE_SIZE;
+            }
+        }
+    }
+}
+
+/// Map a kernel page at `vaddr` to `paddr`, seen by all page tables.
+/// Used for the kernel stack region.
+/// Return `None` if a page table can not be allocated.
+pub fn map_kernel_page(vaddr: usize, paddr: usize) -> Option<()> {
+    active_table().map(vaddr, paddr)?.update();
+    Some(())
+}
+
+pub fn unmap_kernel_page(vaddr: usize) {
+    active_table().unmap(vaddr);
+}
+
 fn enlarge_heap() {
     let mut page_table = active_table();
     let mut addrs = Vec::new();
-    let va_offset = KERNEL_OFFSET + 0xe0000000;
+    let va_offset = HEAP_VA_OFFSET;
     for i in 0..16384 {
         let page = alloc_frame().unwrap();
         let va = KERNEL_OFFSET + 0xe0000000 + page;
@@ -61,11 +112,14 @@ fn enlarge_heap() {
     }
     for (addr, len) in addrs.into_iter() {
         for va in (addr..(addr + len)).step_by(PAGE_SIZE) {
-            page_table.map(va, va - va_offset).update();
+            page_table
+                .map(va, va - va_offset)
+                .expect("failed to map heap")
+                .update();
         }
         info!("Adding {:#X} {:#X} to heap", addr, len);
         unsafe {
-            HEAP_ALLOCATOR.lock().init(addr, len);
+            HEAP_ALLOCATOR.add_to_heap(addr, len);
         }
     }
 }
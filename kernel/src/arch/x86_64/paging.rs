@@ -2,13 +2,14 @@
 use crate::consts::KERNEL_OFFSET;
 use crate::memory::{active_table, alloc_frame, dealloc_frame};
 use log::*;
+use raw_cpuid::CpuId;
 use rcore_memory::paging::*;
 use x86_64::instructions::tlb;
 use x86_64::registers::control::{Cr3, Cr3Flags};
 use x86_64::structures::paging::{
     frame::PhysFrame as Frame,
-    mapper::{Mapper, RecursivePageTable},
-    page::{Page, PageRange, Size4KiB},
+    mapper::{MapToError, Mapper, RecursivePageTable},
+    page::{Page, PageRange, PageSize, Size1GiB, Size2MiB, Size4KiB},
     page_table::{PageTable as x86PageTable, PageTableEntry, PageTableFlags as EF},
     FrameAllocator, FrameDeallocator,
 };
@@ -44,19 +45,24 @@ pub struct ActivePageTable(RecursivePageTable<'static>);
 pub struct PageEntry(PageTableEntry);
 
 impl PageTable for ActivePageTable {
-    fn map(&mut self, addr: usize, target: usize) -> &mut Entry {
+    fn map(&mut self, addr: usize, target: usize) -> Option<&mut Entry> {
         let flags = EF::PRESENT | EF::WRITABLE | EF::NO_EXECUTE;
-        unsafe {
-            if let Ok(flush) = self.0.map_to(
+        let result = unsafe {
+            self.0.map_to(
                 Page::of_addr(addr),
                 Frame::of_addr(target),
                 flags,
                 &mut FrameAllocatorForX86,
-            ) {
-                flush.flush();
-            }
+            )
+        };
+        match result {
+            Ok(flush) => flush.flush(),
+            // the page tables are missing, there is no entry to return
+            Err(MapToError::FrameAllocationFailed) => return None,
+            // a page already mapped is set up again by the caller through its entry
+            Err(_) => {}
         }
-        unsafe { &mut *(get_entry_ptr(addr, 1)) }
+        Some(unsafe { &mut *(get_entry_ptr(addr, 1)) })
     }
 
     fn unmap(&mut self, addr: usize) {
@@ -72,9 +78,89 @@ impl PageTable for ActivePageTable {
             if unsafe { !(*entry).present() } {
                 return None;
             }
+            // P3 and P2 entries may map a huge page
+            if level > 0 && unsafe { (*entry).huge() } {
+                return unsafe { Some(&mut *entry) };
+            }
         }
         unsafe { Some(&mut *(get_entry_ptr(addr, 1))) }
     }
+
+    fn huge_page_sizes(&self) -> &'static [usize] {
+        const SIZES: [usize; 2] = [Size2MiB::SIZE as usize, Size1GiB::SIZE as usize];
+        let has_1gib_pages = CpuId::new()
+            .get_extended_function_info()
+            .map_or(false, |info| info.has_1gib_pages());
+        if has_1gib_pages {
+            &SIZES
+        } else {
+            &SIZES[..1]
+        }
+    }
+
+    fn huge_page_unused(&mut self, addr: usize, size: usize) -> bool {
+        let level = match size as u64 {
+            Size2MiB::SIZE => 2,
+            Size1GiB::SIZE => 3,
+            _ => return false,
+        };
+        // nothing is below a missing table
+        for upper in (level + 1..=4).rev() {
+            let entry = unsafe { &*get_entry_ptr(addr, upper) };
+            if !entry.present() {
+                return true;
+            }
+            if entry.huge() {
+                return false;
+            }
+        }
+        unsafe { (*get_entry_ptr(addr, level)).0.is_unused() }
+    }
+
+    fn map_huge(&mut self, addr: usize, target: usize, size: usize) -> &mut Entry {
+        let flags = EF::PRESENT | EF::WRITABLE | EF::NO_EXECUTE;
+        let vaddr = x86_64::VirtAddr::new(addr as u64);
+        let paddr = PhysAddr::new(target as u64);
+        let level = match size as u64 {
+            Size2MiB::SIZE => {
+                let page = Page::<Size2MiB>::from_start_address(vaddr).unwrap();
+                let frame = Frame::<Size2MiB>::from_start_address(paddr).unwrap();
+                unsafe { self.0.map_to(page, frame, flags, &mut FrameAllocatorForX86) }
+                    .expect("failed to map huge page")
+                    .flush();
+                2
+            }
+            Size1GiB::SIZE => {
+                let page = Page::<Size1GiB>::from_start_address(vaddr).unwrap();
+                let frame = Frame::<Size1GiB>::from_start_address(paddr).unwrap();
+                unsafe { self.0.map_to(page, frame, flags, &mut FrameAllocatorForX86) }
+                    .expect("failed to map huge page")
+                    .flush();
+                3
+            }
+            _ => panic!("huge page of size {:#x} is not supported", size),
+        };
+        unsafe { &mut *(get_entry_ptr(addr, level)) }
+    }
+
+    fn unmap_huge(&mut self, addr: usize, size: usize) {
+        let vaddr = x86_64::VirtAddr::new(addr as u64);
+        match size as u64 {
+            Size2MiB::SIZE => {
+                let page = Page::<Size2MiB>::from_start_address(vaddr).unwrap();
+                if let Ok((_, flush)) = self.0.unmap(page) {
+                    flush.flush();
+                }
+            }
+            Size1GiB::SIZE => {
+                let page = Page::<Size1GiB>::from_start_address(vaddr).unwrap();
+                if let Ok((_, flush)) = self.0.unmap(page) {
+                    flush.flush();
+                }
+            }
+            _ => panic!("huge page of size {:#x} is not supported", size),
+        }
+    }
 }
 
 impl PageTableExt for ActivePageTable {
@@ -93,6 +179,11 @@ impl ActivePageTable {
 impl Entry for PageEntry {
     fn update(&mut self) {
         use x86_64::{instructions::tlb::flush, VirtAddr};
+        if self.huge() {
+            // the mapped address can not be told from a P3 or P2 entry
+            tlb::flush_all();
+            return;
+        }
         let addr = VirtAddr::new_unchecked((self as *const _ as u64) << 9);
         flush(addr);
     }
@@ -172,6 +263,9 @@ impl Entry for PageEntry {
         0
     }
     fn set_mmio(&mut self, _value: u8) {}
+    fn huge(&self) -> bool {
+        self.0.flags().contains(EF::HUGE_PAGE)
+    }
 }
 
 fn get_entry_ptr(addr: usize, level: u8) -> *mut PageEntry {
@@ -194,15 +288,15 @@ pub struct InactivePageTable0 {
 impl InactivePageTable for InactivePageTable0 {
     type Active = ActivePageTable;
 
-    fn new_bare() -> Self {
-        let target = alloc_frame().expect("failed to allocate frame");
+    fn new_bare() -> Option<Self> {
+        let target = alloc_frame()?;
         let frame = Frame::of_addr(target);
         active_table().with_temporary_map(target, |_, table: &mut x86PageTable| {
             table.zero();
             // set up recursive mapping for the table
             table[511].set_frame(frame.clone(), EF::PRESENT | EF::WRITABLE);
         });
-        InactivePageTable0 { p4_frame: frame }
+        Some(InactivePageTable0 { p4_frame: frame })
     }
 
     fn map_kernel(&mut self) {
@@ -6,3 +6,24 @@ pub const MAX_CPU_NUM: usize = 64;
 pub const MAX_PROCESS_NUM: usize = 128;
 
 pub const USEC_PER_TICK: usize = 10000;
+
+/// Load address of position independent executables
+pub const USER_PIE_BASE: usize = 0x1000_0000;
+/// Load address of the dynamic linker
+pub const USER_INTERP_BASE: usize = 0x4000_0000;
+/// Where mmap starts searching for free space without an address hint
+pub const USER_MMAP_BASE: usize = 0x5000_0000;
+
+/// Range of the random offsets applied to the bases above and the stack top by ASLR
+#[cfg(target_pointer_width = "64")]
+pub const USER_ASLR_RANGE: usize = 0x1000_0000;
+#[cfg(target_pointer_width = "32")]
+pub const USER_ASLR_RANGE: usize = 0x100_0000;
+/// Range of the random gap between the end of the executable and the brk start
+pub const USER_BRK_ASLR_RANGE: usize = 0x200_0000;
+
+/// Size of the user stack mapped at exec besides the arguments, the rest grows on demand
+pub const USER_STACK_INIT_SIZE: usize = 0x10000;
+/// Unmapped gap kept below the user stack when it grows,
+/// so that overflow faults instead of running into other mappings
+pub const USER_STACK_GUARD_GAP: usize = 0x10_0000;
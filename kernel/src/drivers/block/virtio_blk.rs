@@ -15,7 +15,7 @@ use volatile::Volatile;
 use rcore_fs::dev::BlockDevice;
 
 use crate::drivers::BlockDriver;
-use crate::memory::active_table;
+use crate::memory::{active_table, DmaBuffer};
 use crate::sync::SpinNoIrqLock as Mutex;
 
 use super::super::bus::virtio_mmio::*;
@@ -27,6 +27,8 @@ pub struct VirtIOBlk {
     header: usize,
     queue: VirtIOVirtqueue,
     capacity: usize,
+    /// Holds the request at the start and the response at `VIRTIO_BLK_RESP_OFFSET`
+    buffer: DmaBuffer,
 }
 
 pub struct VirtIOBlkDriver(Mutex<VirtIOBlk>);
@@ -74,6 +76,9 @@ const VIRTIO_BLK_S_UNSUPP: u8 = 2;
 
 const VIRTIO_BLK_BLK_SIZE: usize = 512;
 
+/// Offset of the response in the buffer, after the largest request
+const VIRTIO_BLK_RESP_OFFSET: usize = 1024;
+
 bitflags! {
     struct VirtIOBlkFeature : u64 {
         const BARRIER = 1 << 0;
@@ -132,20 +137,20 @@ impl Driver for VirtIOBlkDriver {
         // ensure header page is mapped
         active_table().map_if_not_exists(driver.header as usize, driver.header as usize);
 
-        let mut req = VirtIOBlkReadReq::default();
+        let req_addr = driver.buffer.vaddr();
+        let resp_addr = req_addr + VIRTIO_BLK_RESP_OFFSET;
+        let req = unsafe { &mut *(req_addr as *mut VirtIOBlkReadReq) };
         req.req_type = VIRTIO_BLK_T_IN;
         req.reserved = 0;
         req.sector = block_id as u64;
-        let input = [0; size_of::<VirtIOBlkReadResp>()];
-        let output = unsafe {
-            slice::from_raw_parts(
-                &req as *const VirtIOBlkReadReq as *const u8,
-                size_of::<VirtIOBlkReadReq>(),
-            )
+        let input = unsafe {
+            slice::from_raw_parts(resp_addr as *const u8, size_of::<VirtIOBlkReadResp>())
         };
-        driver.queue.add_and_notify(&[&input], &[output], 0);
+        let output =
+            unsafe { slice::from_raw_parts(req_addr as *const u8, size_of::<VirtIOBlkReadReq>()) };
+        driver.queue.add_and_notify(&[input], &[output], 0);
         driver.queue.get_block();
-        let resp = unsafe { &*(&input as *const u8 as *const VirtIOBlkReadResp) };
+        let resp = unsafe { &*(resp_addr as *const VirtIOBlkReadResp) };
         if resp.status == VIRTIO_BLK_S_OK {
             let len = min(buf.len(), VIRTIO_BLK_BLK_SIZE);
             buf[..len].clone_from_slice(&resp.data[..len]);
@@ -160,22 +165,23 @@ impl Driver for VirtIOBlkDriver {
         // ensure header page is mapped
         active_table().map_if_not_exists(driver.header as usize, driver.header as usize);
 
-        let mut req: VirtIOBlkWriteReq = unsafe { zeroed() };
+        let req_addr = driver.buffer.vaddr();
+        let resp_addr = req_addr + VIRTIO_BLK_RESP_OFFSET;
+        let req = unsafe { &mut *(req_addr as *mut VirtIOBlkWriteReq) };
+        *req = unsafe { zeroed() };
         req.req_type = VIRTIO_BLK_T_OUT;
         req.reserved = 0;
         req.sector = block_id as u64;
         let len = min(buf.len(), VIRTIO_BLK_BLK_SIZE);
         req.data[..len].clone_from_slice(&buf[..len]);
-        let input = [0; size_of::<VirtIOBlkWriteResp>()];
-        let output = unsafe {
-            slice::from_raw_parts(
-                &req as *const VirtIOBlkWriteReq as *const u8,
-                size_of::<VirtIOBlkWriteReq>(),
-            )
+        let input = unsafe {
+            slice::from_raw_parts(resp_addr as *const u8, size_of::<VirtIOBlkWriteResp>())
         };
-        driver.queue.add_and_notify(&[&input], &[output], 0);
+        let output =
+            unsafe { slice::from_raw_parts(req_addr as *const u8, size_of::<VirtIOBlkWriteReq>()) };
+        driver.queue.add_and_notify(&[input], &[output], 0);
         driver.queue.get_block();
-        let resp = unsafe { &*(&input as *const u8 as *const VirtIOBlkWriteResp) };
+        let resp = unsafe { &*(resp_addr as *const VirtIOBlkWriteResp) };
         if resp.status == VIRTIO_BLK_S_OK {
             true
         } else {
@@ -218,6 +224,7 @@ pub fn virtio_blk_init(node: &Node) {
         header: from as usize,
         queue: VirtIOVirtqueue::new(header, 0, 16),
         capacity: config.capacity.read() as usize,
+        buffer: DmaBuffer::new(PAGE_SIZE, false).expect("failed to allocate virtio_blk buffer"),
     }));
 
     header.status.write(VirtIODeviceStatus::DRIVER_OK.bits());
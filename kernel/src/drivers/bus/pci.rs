@@ -8,7 +8,7 @@ use alloc::sync::Arc;
 use core::cmp::Ordering;
 use pci::*;
 use rcore_memory::{paging::PageTable, PAGE_SIZE};
-use spin::Mutex;
+use crate::sync::SpinLock as Mutex;
 
 const PCI_COMMAND: u16 = 0x04;
 const PCI_CAP_PTR: u16 = 0x34;
@@ -155,11 +155,7 @@ pub fn init_driver(dev: &PCIDevice) {
             if let Some(BAR::Memory(addr, len, _, _)) = dev.bars[0] {
                 let irq = unsafe { enable(dev.loc) };
                 let vaddr = KERNEL_OFFSET + addr as usize;
-                let mut current_addr = addr as usize;
-                while current_addr < addr as usize + len as usize {
-                    active_table().map_if_not_exists(KERNEL_OFFSET + current_addr, current_addr);
-                    current_addr = current_addr + PAGE_SIZE;
-                }
+                active_table().map_range(vaddr, addr as usize, len as usize);
                 let index = NET_DRIVERS.read().len();
                 e1000::init(name, irq, vaddr, len as usize, index);
             }
@@ -169,11 +165,7 @@ pub fn init_driver(dev: &PCIDevice) {
             if let Some(BAR::Memory(addr, len, _, _)) = dev.bars[0] {
                 let irq = unsafe { enable(dev.loc) };
                 let vaddr = KERNEL_OFFSET + addr as usize;
-                let mut current_addr = addr as usize;
-                while current_addr < addr as usize + len as usize {
-                    active_table().map_if_not_exists(KERNEL_OFFSET + current_addr, current_addr);
-                    current_addr = current_addr + PAGE_SIZE;
-                }
+                active_table().map_range(vaddr, addr as usize, len as usize);
                 let index = NET_DRIVERS.read().len();
                 PCI_DRIVERS.lock().insert(
                     dev.loc,
@@ -206,7 +198,9 @@ pub fn init_driver(dev: &PCIDevice) {
             let irq = unsafe { enable(dev.loc) };
             assert!(len as usize <= PAGE_SIZE);
             let vaddr = KERNEL_OFFSET + addr as usize;
-            active_table().map(vaddr, addr as usize);
+            active_table()
+                .map(vaddr, addr as usize)
+                .expect("failed to map AHCI");
             PCI_DRIVERS.lock().insert(dev.loc, ahci::init(irq, vaddr, len as usize));
         }
     }
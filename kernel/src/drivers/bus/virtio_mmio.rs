@@ -1,4 +1,3 @@
-use alloc::alloc::{GlobalAlloc, Layout};
 use alloc::{vec, vec::Vec};
 use core::mem::size_of;
 use core::slice;
@@ -12,9 +11,7 @@ use rcore_memory::paging::PageTable;
 use rcore_memory::PAGE_SIZE;
 use volatile::{ReadOnly, Volatile, WriteOnly};
 
-use crate::arch::consts::{KERNEL_OFFSET, MEMORY_OFFSET};
-use crate::memory::active_table;
-use crate::HEAP_ALLOCATOR;
+use crate::memory::{active_table, dma, DmaBuffer};
 
 use super::super::block::virtio_blk;
 use super::super::gpu::virtio_gpu;
@@ -65,13 +62,15 @@ pub struct VirtIOHeader {
 #[repr(C)]
 pub struct VirtIOVirtqueue {
     header: usize,
-    queue_address: usize,
+    queue_buffer: DmaBuffer,
     queue_num: usize,
     queue: usize,
     desc: usize,  // *mut VirtIOVirtqueueDesc,
     avail: usize, // *mut VirtIOVirtqueueAvailableRing,
     used: usize,  // *mut VirtIOVirtqueueUsedRing,
     desc_state: Vec<usize>,
+    /// Virtual address of the buffer in each descriptor
+    desc_vaddr: Vec<usize>,
     num_used: usize,
     free_head: usize,
     avail_idx: u16,
@@ -90,15 +89,12 @@ impl VirtIOVirtqueue {
         let size = virtqueue_size(queue_num, align);
         assert!(size % align == 0);
         // alloc continuous pages
-        let address =
-            unsafe { HEAP_ALLOCATOR.alloc_zeroed(Layout::from_size_align(size, align).unwrap()) }
-                as usize;
+        let queue_buffer = DmaBuffer::new(size, false).expect("failed to allocate virtqueue");
+        let address = queue_buffer.vaddr();
 
         header.queue_num.write(queue_num as u32);
         header.queue_align.write(align as u32);
-        header
-            .queue_pfn
-            .write(((address - KERNEL_OFFSET + MEMORY_OFFSET) as u32) >> 12);
+        header.queue_pfn.write((queue_buffer.paddr() >> 12) as u32);
 
         // link desc together
         let desc =
@@ -109,13 +105,14 @@ impl VirtIOVirtqueue {
 
         VirtIOVirtqueue {
             header: header as *mut VirtIOHeader as usize,
-            queue_address: address,
+            queue_buffer,
             queue_num,
             queue,
             desc: address,
             avail: address + size_of::<VirtIOVirtqueueDesc>() * queue_num,
             used: address + virtqueue_used_elem_offset(queue_num, align),
             desc_state: vec![0; queue_num],
+            desc_vaddr: vec![0; queue_num],
             num_used: 0,
             free_head: 0,
             avail_idx: 0,
@@ -144,9 +141,8 @@ impl VirtIOVirtqueue {
         let mut cur = self.free_head;
         for i in 0..output.len() {
             desc[cur].flags.write(VirtIOVirtqueueFlag::NEXT.bits());
-            desc[cur]
-                .addr
-                .write(output[i].as_ptr() as u64 - KERNEL_OFFSET as u64 + MEMORY_OFFSET as u64);
+            desc[cur].addr.write(dma_address(output[i]) as u64);
+            self.desc_vaddr[cur] = output[i].as_ptr() as usize;
             desc[cur].len.write(output[i].len() as u32);
             prev = cur;
             cur = desc[cur].next.read() as usize;
@@ -155,9 +151,8 @@ impl VirtIOVirtqueue {
             desc[cur]
                 .flags
                 .write((VirtIOVirtqueueFlag::NEXT | VirtIOVirtqueueFlag::WRITE).bits());
-            desc[cur]
-                .addr
-                .write(input[i].as_ptr() as u64 - KERNEL_OFFSET as u64 + MEMORY_OFFSET as u64);
+            desc[cur].addr.write(dma_address(input[i]) as u64);
+            self.desc_vaddr[cur] = input[i].as_ptr() as usize;
             desc[cur].len.write(input[i].len() as u32);
             prev = cur;
             cur = desc[cur].next.read() as usize;
@@ -222,7 +217,7 @@ impl VirtIOVirtqueue {
         let mut output = Vec::new();
         loop {
             let flags = VirtIOVirtqueueFlag::from_bits_truncate(desc[cur].flags.read());
-            let addr = desc[cur].addr.read() as u64 - MEMORY_OFFSET as u64 + KERNEL_OFFSET as u64;
+            let addr = self.desc_vaddr[cur];
             let buffer =
                 unsafe { slice::from_raw_parts(addr as *const u8, desc[cur].len.read() as usize) };
             if flags.contains(VirtIOVirtqueueFlag::WRITE) {
@@ -265,6 +260,11 @@ impl VirtIOVirtqueue {
     }
 }
 
+/// Physical address of `buffer`, which must be in a DMA buffer
+fn dma_address(buffer: &[u8]) -> usize {
+    dma::virt_to_phys(buffer.as_ptr() as usize).expect("virtqueue buffer is not DMA memory")
+}
+
 pub const VIRTIO_CONFIG_SPACE_OFFSET: u64 = 0x100;
 
 impl VirtIOHeader {
@@ -358,7 +358,9 @@ pub fn virtio_probe(node: &Node) {
         let size = reg.as_slice().read_be_u64(8).unwrap();
         // assuming one page
         assert_eq!(size as usize, PAGE_SIZE);
-        active_table().map(from as usize, from as usize);
+        active_table()
+            .map(from as usize, from as usize)
+            .expect("failed to map virtio header");
         let header = unsafe { &mut *(from as *mut VirtIOHeader) };
         let magic = header.magic.read();
         let version = header.version.read();
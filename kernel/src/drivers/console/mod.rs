@@ -6,7 +6,7 @@ use core::marker::PhantomData;
 
 use lazy_static::lazy_static;
 use log::*;
-use spin::Mutex;
+use crate::sync::SpinLock as Mutex;
 
 use crate::util::escape_parser::{CharacterAttribute, EscapeParser};
 
@@ -1,11 +1,11 @@
 //! Framebuffer
 
+use crate::sync::SpinLock as Mutex;
 use alloc::string::String;
 use core::fmt;
 use lazy_static::lazy_static;
 use log::*;
 use once::*;
-use spin::Mutex;
 
 /// Framebuffer information
 #[repr(C)]
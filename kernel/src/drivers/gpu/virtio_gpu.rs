@@ -1,4 +1,3 @@
-use alloc::alloc::{GlobalAlloc, Layout};
 use alloc::string::String;
 use alloc::sync::Arc;
 use core::slice;
@@ -11,11 +10,9 @@ use rcore_memory::paging::PageTable;
 use rcore_memory::PAGE_SIZE;
 use volatile::{ReadOnly, Volatile, WriteOnly};
 
-use crate::arch::consts::{KERNEL_OFFSET, MEMORY_OFFSET};
 use crate::arch::cpu;
-use crate::memory::active_table;
+use crate::memory::{active_table, DmaBuffer};
 use crate::sync::SpinNoIrqLock as Mutex;
-use crate::HEAP_ALLOCATOR;
 
 use super::super::bus::virtio_mmio::*;
 use super::super::{DeviceType, Driver, DRIVERS};
@@ -269,9 +266,11 @@ fn setup_framebuffer(driver: &mut VirtIOGpu) {
 
     // alloc continuous pages for the frame buffer
     let size = response_get_display_info.rect.width * response_get_display_info.rect.height * 4;
-    let frame_buffer = unsafe {
-        HEAP_ALLOCATOR.alloc_zeroed(Layout::from_size_align(size as usize, PAGE_SIZE).unwrap())
-    } as usize;
+    // uncached, so that the device always sees the latest pixels
+    let frame_buffer =
+        DmaBuffer::new(size as usize, true).expect("failed to allocate frame buffer");
+    let frame_buffer_paddr = frame_buffer.paddr();
+    let frame_buffer = frame_buffer.into_raw();
     mandelbrot(
         driver.rect.width,
         driver.rect.height,
@@ -285,7 +284,7 @@ fn setup_framebuffer(driver: &mut VirtIOGpu) {
         header: VirtIOGpuCtrlHdr::with_type(VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING),
         resource_id: VIRTIO_GPU_RESOURCE_ID,
         nr_entries: 1,
-        addr: (frame_buffer - KERNEL_OFFSET + MEMORY_OFFSET) as u64,
+        addr: frame_buffer_paddr as u64,
         length: size,
         padding: 0,
     };
@@ -389,9 +388,9 @@ pub fn virtio_gpu_init(node: &Node) {
 
     for buffer in 0..2 {
         // allocate a page for each buffer
-        let page = unsafe {
-            HEAP_ALLOCATOR.alloc_zeroed(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap())
-        } as usize;
+        let page = DmaBuffer::new(PAGE_SIZE, false)
+            .expect("failed to allocate virtio_gpu buffer")
+            .into_raw();
         driver.queue_buffer[buffer as usize] = page;
         debug!("buffer {} using page address {:#X}", buffer, page as usize);
     }
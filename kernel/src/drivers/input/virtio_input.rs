@@ -1,7 +1,5 @@
-use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::sync::Arc;
-use alloc::vec;
 use core::fmt;
 use core::mem::size_of;
 use core::mem::transmute_copy;
@@ -16,7 +14,7 @@ use rcore_memory::PAGE_SIZE;
 use volatile::Volatile;
 
 use crate::arch::cpu;
-use crate::memory::active_table;
+use crate::memory::{active_table, DmaBuffer};
 use crate::sync::SpinNoIrqLock as Mutex;
 
 use super::super::bus::virtio_mmio::*;
@@ -209,8 +207,11 @@ pub fn virtio_input_init(node: &Node) {
         y: 0,
     };
 
-    let buffer = vec![VirtIOInputEvent::default(); queue_num];
-    let input_buffers: &mut [VirtIOInputEvent] = Box::leak(buffer.into_boxed_slice());
+    let buffer = DmaBuffer::new(size_of::<VirtIOInputEvent>() * queue_num, false)
+        .expect("failed to allocate virtio_input buffer")
+        .into_raw();
+    let input_buffers =
+        unsafe { slice::from_raw_parts(buffer as *const VirtIOInputEvent, queue_num) };
     for i in 0..queue_num {
         let buffer = unsafe {
             slice::from_raw_parts(
@@ -4,9 +4,8 @@ use alloc::vec::Vec;
 
 use lazy_static::lazy_static;
 use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address};
-use spin::RwLock;
 
-use crate::sync::Condvar;
+use crate::sync::{Condvar, RwLock};
 use rcore_fs::dev::{self, DevError, BlockDevice};
 
 #[allow(dead_code)]
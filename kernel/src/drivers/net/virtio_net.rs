@@ -1,4 +1,3 @@
-use alloc::alloc::{GlobalAlloc, Layout};
 use alloc::format;
 use alloc::string::String;
 use alloc::sync::Arc;
@@ -18,9 +17,8 @@ use smoltcp::wire::{EthernetAddress, Ipv4Address};
 use smoltcp::Result;
 use volatile::{ReadOnly, Volatile};
 
-use crate::memory::active_table;
+use crate::memory::{active_table, DmaBuffer};
 use crate::sync::SpinNoIrqLock as Mutex;
-use crate::HEAP_ALLOCATOR;
 
 use super::super::bus::virtio_mmio::*;
 use super::super::{DeviceType, Driver, DRIVERS, NET_DRIVERS};
@@ -168,10 +166,9 @@ impl phy::TxToken for VirtIONetTxToken {
                 unsafe { slice::from_raw_parts_mut(output[0].as_ptr() as *mut u8, output[0].len()) }
             } else {
                 // allocate a page for buffer
-                let page = unsafe {
-                    HEAP_ALLOCATOR
-                        .alloc_zeroed(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap())
-                } as usize;
+                let page = DmaBuffer::new(PAGE_SIZE, false)
+                    .expect("failed to allocate virtio_net buffer")
+                    .into_raw();
                 unsafe { slice::from_raw_parts_mut(page as *mut u8, PAGE_SIZE) }
             }
         };
@@ -290,9 +287,9 @@ pub fn virtio_net_init(node: &Node) {
     };
 
     // allocate a page for buffer
-    let page = unsafe {
-        HEAP_ALLOCATOR.alloc_zeroed(Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap())
-    } as usize;
+    let page = DmaBuffer::new(PAGE_SIZE, false)
+        .expect("failed to allocate virtio_net buffer")
+        .into_raw();
     let input = unsafe { slice::from_raw_parts(page as *const u8, PAGE_SIZE) };
     driver.queues[VIRTIO_QUEUE_RECEIVE].add_and_notify(&[input], &[], 0);
 
@@ -1,10 +1,7 @@
-use alloc::alloc::{alloc_zeroed, dealloc, Layout};
-
 use isomorphic_drivers::provider;
-use rcore_memory::paging::PageTable;
 use rcore_memory::PAGE_SIZE;
 
-use crate::memory::active_table;
+use crate::memory::DmaBuffer;
 
 pub struct Provider;
 
@@ -12,14 +9,12 @@ impl provider::Provider for Provider {
     const PAGE_SIZE: usize = PAGE_SIZE;
 
     fn alloc_dma(size: usize) -> (usize, usize) {
-        let layout = Layout::from_size_align(size, PAGE_SIZE).unwrap();
-        let vaddr = unsafe { alloc_zeroed(layout) } as usize;
-        let paddr = active_table().get_entry(vaddr).unwrap().target();
-        (vaddr, paddr)
+        let buffer = DmaBuffer::new(size, false).expect("failed to allocate DMA buffer");
+        let paddr = buffer.paddr();
+        (buffer.into_raw(), paddr)
     }
 
-    fn dealloc_dma(vaddr: usize, size: usize) {
-        let layout = Layout::from_size_align(size, PAGE_SIZE).unwrap();
-        unsafe { dealloc(vaddr as *mut u8, layout) }
+    fn dealloc_dma(vaddr: usize, _size: usize) {
+        drop(unsafe { DmaBuffer::from_raw(vaddr) });
     }
 }
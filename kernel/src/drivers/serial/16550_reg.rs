@@ -2,9 +2,9 @@
 
 #![allow(dead_code)]
 
+use crate::sync::SpinLock as Mutex;
 use crate::util::{read, write};
 use core::fmt::{Arguments, Result, Write};
-use spin::Mutex;
 
 pub struct SerialPort {
     base: usize,
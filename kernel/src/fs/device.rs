@@ -1,7 +1,8 @@
 //! Implement Device
 
 use rcore_fs::dev::{self, *};
-use spin::RwLock;
+
+use crate::sync::RwLock;
 
 #[cfg(target_arch = "x86_64")]
 use crate::arch::driver::ide;
@@ -9,6 +9,8 @@ pub struct FileHandle {
     inode: Arc<INode>,
     offset: u64,
     options: OpenOptions,
+    /// Closed by `exec`. The flag of the fd, as each fd has a handle of its own.
+    pub fd_cloexec: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -32,6 +34,7 @@ impl FileHandle {
             inode,
             offset: 0,
             options,
+            fd_cloexec: false,
         }
     }
 
@@ -92,6 +95,11 @@ impl FileHandle {
         self.inode.sync_data()
     }
 
+    /// Whether `other` is a handle of the same inode
+    pub fn same_inode(&self, other: &FileHandle) -> bool {
+        Arc::ptr_eq(&self.inode, &other.inode)
+    }
+
     pub fn metadata(&self) -> Result<Metadata> {
         self.inode.metadata()
     }
@@ -9,12 +9,14 @@ use crate::arch::driver::ide;
 pub use self::file::*;
 pub use self::file_like::*;
 pub use self::pipe::Pipe;
+pub use self::proc::lookup_proc;
 pub use self::stdio::{STDIN, STDOUT};
 
 mod device;
 mod file;
 mod file_like;
 mod pipe;
+mod proc;
 mod stdio;
 
 /// Hard link user programs
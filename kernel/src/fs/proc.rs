@@ -0,0 +1,147 @@
+//! Implement INode for the pseudo files under `/proc`
+
+use alloc::{boxed::Box, string::String, sync::Arc};
+use core::any::Any;
+use core::sync::atomic::Ordering;
+
+use rcore_fs::vfs::*;
+
+use crate::memory::oom::{OOM_SCORE_ADJ_MAX, OOM_SCORE_ADJ_MIN};
+use crate::process::Process;
+
+/// Find the pseudo file at absolute `path`, `/proc/self` refers to `proc`
+pub fn lookup_proc(path: &str, proc: &Process) -> Option<Arc<INode>> {
+    let file = match path {
+        "/proc/slabinfo" => ProcFile {
+            inode: 1,
+            read: Box::new(crate::memory::slabinfo),
+            write: None,
+        },
+        "/proc/kthreads" => ProcFile {
+            inode: 2,
+            read: Box::new(crate::process::kthread::list),
+            write: None,
+        },
+        "/proc/self/comm" => {
+            let comm = String::from(proc.comm());
+            ProcFile {
+                inode: 3,
+                read: Box::new(move || format!("{}\n", comm)),
+                write: None,
+            }
+        }
+        "/proc/self/oom_score_adj" => {
+            let value = proc.oom_score_adj.clone();
+            let new_value = value.clone();
+            ProcFile {
+                inode: 4,
+                read: Box::new(move || format!("{}\n", value.load(Ordering::Relaxed))),
+                write: Some(Box::new(move |content| {
+                    let adj: isize = content.trim().parse().map_err(|_| FsError::InvalidParam)?;
+                    if adj < OOM_SCORE_ADJ_MIN || adj > OOM_SCORE_ADJ_MAX {
+                        return Err(FsError::InvalidParam);
+                    }
+                    new_value.store(adj, Ordering::Relaxed);
+                    Ok(())
+                })),
+            }
+        }
+        _ => return None,
+    };
+    Some(Arc::new(file))
+}
+
+/// Block size in the metadata of the files, the same as Linux
+const BLK_SIZE: usize = 1024;
+
+/// A file whose content is generated on every read,
+/// and parsed as a whole on every write if writable
+struct ProcFile {
+    /// Fixed for each path
+    inode: usize,
+    read: Box<Fn() -> String + Send + Sync>,
+    write: Option<Box<Fn(&str) -> Result<()> + Send + Sync>>,
+}
+
+impl INode for ProcFile {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let content = (self.read)();
+        let bytes = content.as_bytes();
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(bytes.len() - offset);
+        buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+        Ok(len)
+    }
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        let write = self.write.as_ref().ok_or(FsError::NotSupported)?;
+        let content = core::str::from_utf8(buf).map_err(|_| FsError::InvalidParam)?;
+        write(content)?;
+        Ok(buf.len())
+    }
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: self.write.is_some(),
+            error: false,
+        })
+    }
+    /// A regular file of size 0 like Linux, as the content is only known when read
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: self.inode,
+            size: 0,
+            blk_size: BLK_SIZE,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::File,
+            mode: if self.write.is_some() { 0o644 } else { 0o444 },
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+    fn set_metadata(&self, _metadata: &Metadata) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> Result<Arc<INode>> {
+        Err(FsError::NotDir)
+    }
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotDir)
+    }
+    fn link(&self, _name: &str, _other: &Arc<INode>) -> Result<()> {
+        Err(FsError::NotDir)
+    }
+    fn move_(&self, _old_name: &str, _target: &Arc<INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotDir)
+    }
+    fn find(&self, _name: &str) -> Result<Arc<INode>> {
+        Err(FsError::NotDir)
+    }
+    fn get_entry(&self, _id: usize) -> Result<String> {
+        Err(FsError::NotDir)
+    }
+    fn io_control(&self, _cmd: u32, _data: usize) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+    fn fs(&self) -> Arc<FileSystem> {
+        unimplemented!()
+    }
+    fn as_any_ref(&self) -> &Any {
+        self
+    }
+}
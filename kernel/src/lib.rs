@@ -6,6 +6,7 @@
 #![feature(optin_builtin_traits)]
 #![feature(panic_info_message)]
 #![feature(global_asm)]
+#![feature(try_reserve)]
 #![deny(unused_must_use)]
 #![no_std]
 
@@ -18,8 +19,6 @@ extern crate log;
 extern crate lazy_static;
 
 pub use crate::process::{new_kernel_context, processor};
-use buddy_system_allocator::LockedHeap;
-use rcore_thread::std_thread as thread;
 
 #[macro_use] // print!
 mod logging;
@@ -36,6 +35,7 @@ mod process;
 mod shell;
 mod sync;
 mod syscall;
+mod thread;
 mod trap;
 
 #[allow(dead_code)]
@@ -61,8 +61,8 @@ pub fn kmain() -> ! {
 
 /// Global heap allocator
 ///
-/// Available after `memory::init()`.
+/// Available after `memory::init()`, and grows on demand after that.
 ///
 /// It should be defined in memory mod, but in Rust `global_allocator` must be in root mod.
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: memory::KernelHeap = memory::KernelHeap::empty();
@@ -0,0 +1,142 @@
+//! Physically contiguous memory for device DMA
+//!
+//! Buffers are taken from the frame allocator in contiguous runs and mapped
+//! into kernel space by the arch, uncached on request where the arch supports it.
+//! Arches which can not map new kernel memory fall back to the kernel heap,
+//! which is physically contiguous there.
+//!
+//! Every live buffer is recorded, so that queues holding raw addresses can
+//! translate them with `virt_to_phys` and give them back with `DmaBuffer::from_raw`.
+
+use super::*;
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use alloc::collections::BTreeMap;
+use core::slice;
+use rcore_memory::paging::PageTable;
+
+/// A zeroed, page aligned and physically contiguous buffer, freed on drop
+pub struct DmaBuffer {
+    vaddr: usize,
+    region: DmaRegion,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DmaRegion {
+    paddr: usize,
+    size: usize,
+    from_heap: bool,
+}
+
+lazy_static! {
+    /// Live buffers indexed by virtual address
+    static ref DMA_REGIONS: SpinNoIrqLock<BTreeMap<usize, DmaRegion>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
+impl DmaBuffer {
+    /// Allocate a buffer of at least `size` bytes.
+    /// The memory is mapped uncached if `uncached` and supported by the arch.
+    pub fn new(size: usize, uncached: bool) -> Option<Self> {
+        let size = (size.max(1) + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let pages = size / PAGE_SIZE;
+        let paddr = alloc_frames(pages)?;
+        let (vaddr, paddr, from_heap) =
+            match crate::arch::memory::map_dma_frames(paddr, size, uncached) {
+                Some(vaddr) => {
+                    unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, size) };
+                    (vaddr, paddr, false)
+                }
+                None => {
+                    dealloc_frames(paddr, pages);
+                    let vaddr = unsafe { alloc_zeroed(heap_layout(size)) } as usize;
+                    if vaddr == 0 {
+                        return None;
+                    }
+                    // the frames above are freed, the device is given the heap memory
+                    let paddr = match active_table().get_entry(vaddr) {
+                        Some(entry) => entry.target(),
+                        None => {
+                            unsafe { dealloc(vaddr as *mut u8, heap_layout(size)) };
+                            return None;
+                        }
+                    };
+                    (vaddr, paddr, true)
+                }
+            };
+        let region = DmaRegion {
+            paddr,
+            size,
+            from_heap,
+        };
+        DMA_REGIONS.lock().insert(vaddr, region);
+        Some(DmaBuffer { vaddr, region })
+    }
+
+    /// Virtual address for the kernel
+    pub fn vaddr(&self) -> usize {
+        self.vaddr
+    }
+
+    /// Physical address for the device
+    pub fn paddr(&self) -> usize {
+        self.region.paddr
+    }
+
+    pub fn size(&self) -> usize {
+        self.region.size
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.vaddr as *const u8, self.region.size) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.vaddr as *mut u8, self.region.size) }
+    }
+
+    /// Give up the ownership, return the virtual address.
+    /// The buffer can be taken back with `from_raw`.
+    pub fn into_raw(self) -> usize {
+        let vaddr = self.vaddr;
+        core::mem::forget(self);
+        vaddr
+    }
+
+    /// Take back the buffer at `vaddr` from `into_raw`
+    pub unsafe fn from_raw(vaddr: usize) -> Self {
+        let region = *DMA_REGIONS.lock().get(&vaddr).expect("not a DMA buffer");
+        DmaBuffer { vaddr, region }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        DMA_REGIONS.lock().remove(&self.vaddr);
+        let DmaRegion {
+            paddr,
+            size,
+            from_heap,
+        } = self.region;
+        if from_heap {
+            unsafe { dealloc(self.vaddr as *mut u8, heap_layout(size)) };
+        } else {
+            crate::arch::memory::unmap_dma_frames(self.vaddr, size);
+            dealloc_frames(paddr, size / PAGE_SIZE);
+        }
+    }
+}
+
+fn heap_layout(size: usize) -> Layout {
+    Layout::from_size_align(size, PAGE_SIZE).unwrap()
+}
+
+/// Physical address of `vaddr` inside a live DMA buffer
+pub fn virt_to_phys(vaddr: usize) -> Option<usize> {
+    let regions = DMA_REGIONS.lock();
+    let (&start, region) = regions.range(..=vaddr).next_back()?;
+    if vaddr - start < region.size {
+        Some(region.paddr + (vaddr - start))
+    } else {
+        None
+    }
+}
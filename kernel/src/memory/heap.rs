@@ -0,0 +1,171 @@
+//! Kernel heap
+//!
+//! The buddy allocator starts with a static area of `KERNEL_HEAP_SIZE`,
+//! and grows with physical frames when an allocation fails.
+//! Small objects are served by the slab caches in front of it.
+
+use super::{alloc_frame, dealloc_frame, slab};
+use crate::sync::{preempt::PreemptGuard, FlagsGuard};
+use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rcore_memory::PAGE_SIZE;
+use spin::Mutex;
+
+/// Minimal size to grow the heap by
+const HEAP_GROW_SIZE: usize = 0x10_0000;
+
+pub struct KernelHeap {
+    buddy: LockedHeap,
+    /// Held while growing, so that one CPU maps new frames at a time
+    grow_lock: Mutex<()>,
+    /// Bytes given to the buddy allocator
+    total: AtomicUsize,
+    /// Bytes requested by the allocations alive
+    used: AtomicUsize,
+    /// Times the heap grew from physical frames
+    grow_count: AtomicUsize,
+}
+
+/// A snapshot of the heap statistics
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub total: usize,
+    pub used: usize,
+    pub grow_count: usize,
+}
+
+impl KernelHeap {
+    pub const fn empty() -> Self {
+        KernelHeap {
+            buddy: LockedHeap::empty(),
+            grow_lock: Mutex::new(()),
+            total: AtomicUsize::new(0),
+            used: AtomicUsize::new(0),
+            grow_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Give memory [`start`, `start + size`) to the heap
+    pub unsafe fn add_to_heap(&self, start: usize, size: usize) {
+        self.buddy.lock().init(start, size);
+        self.total.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            total: self.total.load(Ordering::Relaxed),
+            used: self.used.load(Ordering::Relaxed),
+            grow_count: self.grow_count.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Allocate from the buddy allocator, growing the heap on failure
+    pub(super) fn alloc_buddy(&self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.buddy.lock().alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // the buddy is not locked while growing, which may take a while,
+        // and no interrupt handler may allocate on this CPU meanwhile
+        let _flags = FlagsGuard::no_irq_region();
+        let _grow = self.grow_lock.lock();
+        // another CPU may have grown the heap meanwhile
+        if let Ok(ptr) = self.buddy.lock().alloc(layout) {
+            return ptr.as_ptr();
+        }
+        if !self.grow(&layout) {
+            return ptr::null_mut();
+        }
+        match self.buddy.lock().alloc(layout) {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    pub(super) unsafe fn dealloc_buddy(&self, ptr: *mut u8, layout: Layout) {
+        self.buddy
+            .lock()
+            .dealloc(NonNull::new_unchecked(ptr), layout);
+    }
+
+    /// Grow the heap by enough physical frames for `layout`.
+    ///
+    /// Frames are not guaranteed to be contiguous, so each contiguous run
+    /// is given to the buddy allocator separately.
+    /// Must not allocate from the heap itself.
+    fn grow(&self, layout: &Layout) -> bool {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .next_power_of_two()
+            .max(HEAP_GROW_SIZE);
+        let (mut run_start, mut run_len) = (0, 0);
+        let mut grown = false;
+        for _ in 0..size / PAGE_SIZE {
+            let frame = match alloc_frame() {
+                Some(frame) => frame,
+                None => break,
+            };
+            if run_len != 0 && frame + PAGE_SIZE == run_start {
+                run_start = frame;
+                run_len += PAGE_SIZE;
+            } else if run_len != 0 && frame == run_start + run_len {
+                run_len += PAGE_SIZE;
+            } else {
+                grown |= self.add_frames(run_start, run_len);
+                run_start = frame;
+                run_len = PAGE_SIZE;
+            }
+        }
+        grown |= self.add_frames(run_start, run_len);
+        if grown {
+            self.grow_count.fetch_add(1, Ordering::Relaxed);
+        }
+        grown
+    }
+
+    /// Map the frames [`paddr`, `paddr + len`) and give them to the heap,
+    /// or free them if the arch can not map them.
+    fn add_frames(&self, paddr: usize, len: usize) -> bool {
+        if len == 0 {
+            return false;
+        }
+        match crate::arch::memory::map_heap_frames(paddr, len) {
+            Some(vaddr) => {
+                unsafe { self.add_to_heap(vaddr, len) };
+                true
+            }
+            None => {
+                for frame in (paddr..paddr + len).step_by(PAGE_SIZE) {
+                    dealloc_frame(frame);
+                }
+                false
+            }
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // the buddy and slab locks are `spin` locks, not counted by `sync::preempt`
+        let _preempt = PreemptGuard::new();
+        let ptr = match slab::find_cache(&layout) {
+            Some(cache) => cache.alloc(self),
+            None => self.alloc_buddy(layout),
+        };
+        if !ptr.is_null() {
+            self.used.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _preempt = PreemptGuard::new();
+        self.used.fetch_sub(layout.size(), Ordering::Relaxed);
+        match slab::find_cache(&layout) {
+            Some(cache) => cache.dealloc(self, ptr),
+            None => self.dealloc_buddy(ptr, layout),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Kernel same-page merging
+//!
+//! A background thread scans the present pages of mergeable areas, i.e. anonymous
+//! memory, in all processes, and remaps pages of the same content to one frame
+//! copy-on-write. Pages of zeros are remapped to the zero frame.
+//!
+//! Pages are hashed by content. A page matching a merged frame joins it, otherwise
+//! it waits in the unstable tree for another page of the same hash during the scan.
+//! Before compared, a page is write protected as if it were shared, so that its
+//! content stays the same until remapped, or it is copied by the page fault.
+//!
+//! Merged frames are counted here, and freed with the last page mapping them.
+//! Only the TLB of this CPU is flushed, so the pages of a process are protected
+//! and remapped only while none of its threads is on a CPU, see `OnCpu`.
+
+use super::*;
+use crate::process::{kthread, Process, PROCESSES};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::thread;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::time::Duration;
+use rcore_memory::paging::{Entry, PageTable, PageTableExt};
+
+#[cfg(target_arch = "mips")]
+compile_error!("KSM needs to mark shared pages, which the MIPS page table can not");
+#[cfg(feature = "nommu")]
+compile_error!("KSM remaps pages, which can not be done without MMU");
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Stable {
+    /// Merged frames by the hash of their content
+    frames: BTreeMap<u64, Vec<usize>>,
+    /// Number of pages mapping each shared frame, and its hash
+    refs: BTreeMap<usize, (usize, u64)>,
+}
+
+lazy_static! {
+    static ref STABLE: SpinNoIrqLock<Stable> = SpinNoIrqLock::new(Stable::default());
+}
+
+/// A present page of a process, and its frame when scanned
+struct UserPage {
+    proc: Weak<Mutex<Process>>,
+    addr: usize,
+    frame: usize,
+}
+
+/// Start the merging thread
+pub fn init() {
+    kthread::spawn("ksmd", ksmd);
+}
+
+fn ksmd() {
+    loop {
+        scan();
+        thread::sleep(SCAN_INTERVAL);
+    }
+}
+
+/// Drop a reference to a frame shared by merging, free it with the last one
+pub fn release(frame: usize) {
+    let mut stable = STABLE.lock();
+    let hash = match stable.refs.get_mut(&frame) {
+        Some((refs, _)) if *refs > 1 => {
+            *refs -= 1;
+            return;
+        }
+        Some(&mut (_, hash)) => hash,
+        None => {
+            warn!("ksm: release frame {:#x} which is not shared", frame);
+            return;
+        }
+    };
+    stable.refs.remove(&frame);
+    if let Some(frames) = stable.frames.get_mut(&hash) {
+        frames.retain(|&f| f != frame);
+        if frames.is_empty() {
+            stable.frames.remove(&hash);
+        }
+    }
+    drop(stable);
+    dealloc_frame(frame);
+}
+
+fn scan() {
+    let processes: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    let mut unstable: BTreeMap<u64, UserPage> = BTreeMap::new();
+    let mut merged = 0;
+    for proc in processes {
+        for page in candidates(&proc) {
+            let (hash, zero) = with_frame(page.frame, |data| {
+                (hash_page(data), data.iter().all(|&x| x == 0))
+            });
+            let done = if zero {
+                merge_zero(&page)
+            } else {
+                merge_stable(&page, hash)
+                    || match unstable.remove(&hash) {
+                        Some(other) => merge_pair(&page, &other, hash),
+                        None => false,
+                    }
+            };
+            if done {
+                merged += 1;
+            } else {
+                unstable.insert(hash, page);
+            }
+        }
+    }
+    let shared = STABLE.lock().refs.len();
+    debug!("ksm: merged {} pages, {} frames shared", merged, shared);
+}
+
+/// The present and private pages of the mergeable areas of `proc`
+fn candidates(proc: &Arc<Mutex<Process>>) -> Vec<UserPage> {
+    let mut pages = Vec::new();
+    // a process locked by others is busy in the kernel, leave it alone
+    let mut locked = match proc.try_lock() {
+        Some(locked) => locked,
+        None => return pages,
+    };
+    let ranges: Vec<_> = locked
+        .vm
+        .iter()
+        .filter(|area| area.is_mergeable() && !area.is_locked())
+        .map(|area| (area.start_addr(), area.end_addr()))
+        .collect();
+    locked.vm.edit(|pt| {
+        for (start, end) in ranges {
+            for page in Page::range_of(start, end) {
+                let addr = page.start_address();
+                if let Some(entry) = pt.get_entry(addr) {
+                    if entry.present() && !is_shared(entry) {
+                        pages.push(UserPage {
+                            proc: Arc::downgrade(proc),
+                            addr,
+                            frame: entry.target(),
+                        });
+                    }
+                }
+            }
+        }
+    });
+    pages
+}
+
+/// Remap a page of zeros to the zero frame
+fn merge_zero(page: &UserPage) -> bool {
+    if !protect(page, 0) {
+        return false;
+    }
+    if with_frame(page.frame, |data| data.iter().all(|&x| x == 0)) && remap(page, zero_frame()) {
+        return true;
+    }
+    unprotect(page);
+    false
+}
+
+/// Remap `page` to a merged frame of the same content
+fn merge_stable(page: &UserPage, hash: u64) -> bool {
+    let frames = match STABLE.lock().frames.get(&hash) {
+        Some(frames) => frames.clone(),
+        None => return false,
+    };
+    if !protect(page, hash) {
+        return false;
+    }
+    let content = with_frame(page.frame, |data| data.to_vec());
+    for frame in frames {
+        if with_frame(frame, |data| data[..] == content[..]) && remap(page, frame) {
+            return true;
+        }
+    }
+    unprotect(page);
+    false
+}
+
+/// Merge `page` with `other` from the unstable tree, whose frame is kept
+fn merge_pair(page: &UserPage, other: &UserPage, hash: u64) -> bool {
+    if !protect(other, hash) {
+        return false;
+    }
+    if !protect(page, hash) {
+        unprotect(other);
+        return false;
+    }
+    let content = with_frame(page.frame, |data| data.to_vec());
+    if !with_frame(other.frame, |data| data[..] == content[..]) {
+        unprotect(page);
+        unprotect(other);
+        return false;
+    }
+    {
+        let mut stable = STABLE.lock();
+        if stable.refs.contains_key(&other.frame) {
+            stable.frames.entry(hash).or_default().push(other.frame);
+        }
+    }
+    if remap(page, other.frame) {
+        return true;
+    }
+    unprotect(page);
+    false
+}
+
+/// Write protect `page` as if shared, with its frame counted as shared by itself.
+/// Return false if the page is gone or changed.
+fn protect(page: &UserPage, hash: u64) -> bool {
+    with_entry(page, |entry| {
+        if !entry.present() || entry.target() != page.frame || is_shared(entry) {
+            return false;
+        }
+        STABLE.lock().refs.insert(page.frame, (1, hash));
+        let writable = entry.writable();
+        entry.set_writable(false);
+        entry.set_shared(writable);
+        entry.update();
+        true
+    })
+}
+
+/// Undo `protect` if `page` is not merged
+fn unprotect(page: &UserPage) {
+    with_entry(page, |entry| {
+        if entry.present() && entry.target() == page.frame && is_shared(entry) {
+            let mut stable = STABLE.lock();
+            if let Some(&(1, _)) = stable.refs.get(&page.frame) {
+                stable.refs.remove(&page.frame);
+                entry.set_writable(entry.writable_shared());
+                entry.clear_shared();
+                entry.update();
+            }
+        }
+        true
+    });
+}
+
+/// Remap protected `page` to `frame`, the zero frame or a shared one,
+/// and release its own frame
+fn remap(page: &UserPage, frame: usize) -> bool {
+    with_entry(page, |entry| {
+        if !entry.present() || entry.target() != page.frame || !is_shared(entry) {
+            return false;
+        }
+        if frame != zero_frame() {
+            match STABLE.lock().refs.get_mut(&frame) {
+                Some((refs, _)) => *refs += 1,
+                None => return false,
+            }
+        }
+        entry.set_target(frame);
+        entry.update();
+        release(page.frame);
+        true
+    })
+}
+
+/// Run `f` on the entry of `page` with its process locked and kept off the CPUs.
+/// Return false if the process is busy, running or gone.
+fn with_entry(page: &UserPage, f: impl FnOnce(&mut Entry) -> bool) -> bool {
+    let proc = match page.proc.upgrade() {
+        Some(proc) => proc,
+        None => return false,
+    };
+    let mut locked = match proc.try_lock() {
+        Some(locked) => locked,
+        None => return false,
+    };
+    let on_cpu = locked.on_cpu.clone();
+    on_cpu
+        .if_idle(|| {
+            let mut ret = false;
+            locked.vm.edit(|pt| {
+                if let Some(entry) = pt.get_entry(page.addr) {
+                    ret = f(entry);
+                }
+            });
+            ret
+        })
+        .unwrap_or(false)
+}
+
+/// Run `f` on the content of `frame`, which may be freed meanwhile
+fn with_frame<T>(frame: usize, f: impl FnOnce(&[u8; PAGE_SIZE]) -> T) -> T {
+    active_table().with_temporary_map(frame, |_, data: &mut [u8; PAGE_SIZE]| f(data))
+}
+
+fn is_shared(entry: &Entry) -> bool {
+    entry.writable_shared() || entry.readonly_shared()
+}
+
+/// FNV-1a
+fn hash_page(data: &[u8; PAGE_SIZE]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |h, &x| {
+        (h ^ x as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
@@ -0,0 +1,145 @@
+//! Kernel stacks with guards
+//!
+//! Each stack lives in a slot of `2 * STACK_SIZE` in the region at `KERNEL_STACK_REGION`,
+//! shared by all page tables. Only the upper half of a slot is mapped, the lower half
+//! is the guard, so an overflow faults instead of corrupting the memory below.
+//!
+//! A fault in the guard can not save its trap frame on the overflowed stack.
+//! The trap entry of each arch detects it and switches to an overflow stack
+//! (the double fault stack on x86_64), where `check_kernel_stack_overflow` reports it.
+//!
+//! MIPS kernel runs in the unmapped KSEG0, and nothing is mapped without MMU,
+//! so their stacks come from the heap, with a canary at the bottom checked by
+//! `KernelStack::check_canary`.
+
+use super::*;
+use crate::thread;
+
+pub const STACK_SIZE: usize = 0x4000;
+
+#[cfg(not(any(target_arch = "mips", feature = "nommu")))]
+pub use self::guarded::{check_kernel_stack_overflow, KernelStack};
+#[cfg(any(target_arch = "mips", feature = "nommu"))]
+pub use self::heap::KernelStack;
+
+#[cfg(not(any(target_arch = "mips", feature = "nommu")))]
+mod guarded {
+    use super::*;
+    use crate::arch::memory::{map_kernel_page, unmap_kernel_page};
+    use crate::consts::{KERNEL_STACK_REGION, KERNEL_STACK_REGION_SIZE};
+    use bitmap_allocator::BitAlloc4K;
+
+    /// A stack and its guard below
+    const SLOT_SIZE: usize = STACK_SIZE * 2;
+
+    lazy_static! {
+        static ref SLOTS: SpinNoIrqLock<BitAlloc4K> = {
+            let mut slots = BitAlloc4K::default();
+            slots.insert(0..KERNEL_STACK_REGION_SIZE / SLOT_SIZE);
+            SpinNoIrqLock::new(slots)
+        };
+    }
+
+    /// Panic with the thread overflowed, if `addr` is in the guard of a kernel stack
+    pub fn check_kernel_stack_overflow(addr: usize) {
+        let offset = addr.wrapping_sub(KERNEL_STACK_REGION);
+        if offset < KERNEL_STACK_REGION_SIZE && offset % SLOT_SIZE < STACK_SIZE {
+            panic!(
+                "kernel stack overflow in thread {}, fault @ {:#x}",
+                thread::current().id(),
+                addr
+            );
+        }
+    }
+
+    pub struct KernelStack {
+        slot: usize,
+        /// `None` only while allocating
+        frames: [Option<usize>; STACK_SIZE / PAGE_SIZE],
+    }
+
+    impl KernelStack {
+        /// Return `None` if the region or the memory runs out
+        pub fn new() -> Option<Self> {
+            let slot = SLOTS.lock().alloc()?;
+            // dropped with the frames allocated so far on failure
+            let mut stack = KernelStack {
+                slot,
+                frames: [None; STACK_SIZE / PAGE_SIZE],
+            };
+            let bottom = stack.bottom();
+            for (i, frame) in stack.frames.iter_mut().enumerate() {
+                let target = alloc_frame()?;
+                if map_kernel_page(bottom + i * PAGE_SIZE, target).is_none() {
+                    dealloc_frame(target);
+                    return None;
+                }
+                *frame = Some(target);
+            }
+            Some(stack)
+        }
+        fn bottom(&self) -> usize {
+            KERNEL_STACK_REGION + self.slot * SLOT_SIZE + STACK_SIZE
+        }
+        pub fn top(&self) -> usize {
+            self.bottom() + STACK_SIZE
+        }
+    }
+
+    impl Drop for KernelStack {
+        fn drop(&mut self) {
+            let bottom = self.bottom();
+            for (i, frame) in self.frames.iter().enumerate() {
+                if let Some(frame) = *frame {
+                    unmap_kernel_page(bottom + i * PAGE_SIZE);
+                    dealloc_frame(frame);
+                }
+            }
+            SLOTS.lock().dealloc(self.slot);
+        }
+    }
+}
+
+#[cfg(any(target_arch = "mips", feature = "nommu"))]
+mod heap {
+    use super::*;
+    use alloc::alloc::{alloc, dealloc, Layout};
+
+    /// Written at the bottom of each stack, overwritten on overflow
+    const CANARY: usize = 0x57ac_c0de;
+
+    pub struct KernelStack(usize);
+
+    impl KernelStack {
+        /// Return `None` if the heap runs out
+        pub fn new() -> Option<Self> {
+            let bottom =
+                unsafe { alloc(Layout::from_size_align(STACK_SIZE, STACK_SIZE).unwrap()) } as usize;
+            if bottom == 0 {
+                return None;
+            }
+            unsafe { *(bottom as *mut usize) = CANARY };
+            Some(KernelStack(bottom))
+        }
+        pub fn top(&self) -> usize {
+            self.0 + STACK_SIZE
+        }
+        /// Panic with the thread overflowed, if the canary of the current stack is overwritten
+        pub fn check_canary(&self) {
+            if unsafe { *(self.0 as *const usize) } != CANARY {
+                panic!("kernel stack overflow in thread {}", thread::current().id());
+            }
+        }
+    }
+
+    impl Drop for KernelStack {
+        fn drop(&mut self) {
+            unsafe {
+                dealloc(
+                    self.0 as _,
+                    Layout::from_size_align(STACK_SIZE, STACK_SIZE).unwrap(),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use super::HEAP_ALLOCATOR;
+pub use crate::arch::paging::*;
+use crate::consts::{MEMORY_OFFSET, USER_END};
+use crate::process::{current_thread, process_unsafe};
+use crate::sync::SpinNoIrqLock;
+use bitmap_allocator::BitAlloc;
+use lazy_static::*;
+use log::*;
+pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr};
+use rcore_memory::paging::PageTableExt;
+use rcore_memory::*;
+
+pub use self::dma::DmaBuffer;
+pub use self::heap::KernelHeap;
+pub use self::kstack::*;
+pub use self::slab::slabinfo;
+
+pub mod dma;
+mod heap;
+#[cfg(feature = "ksm")]
+pub mod ksm;
+mod kstack;
+pub mod oom;
+mod slab;
+
+#[cfg(not(feature = "nommu"))]
+pub type MemorySet = rcore_memory::memory_set::MemorySet<InactivePageTable0>;
+#[cfg(feature = "nommu")]
+pub type MemorySet = rcore_memory::no_mmu::MemorySet<NoMMUAlloc>;
+
+#[cfg(all(feature = "nommu", not(target_arch = "riscv64")))]
+compile_error!("running without MMU is only supported on riscv64");
+
+// x86_64 support up to 64G memory
+#[cfg(target_arch = "x86_64")]
+pub type FrameAlloc = bitmap_allocator::BitAlloc16M;
+
+// RISCV, ARM, MIPS has 1G memory
+#[cfg(all(
+    any(
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+        target_arch = "aarch64",
+        target_arch = "mips"
+    ),
+    not(feature = "board_k210")
+))]
+pub type FrameAlloc = bitmap_allocator::BitAlloc1M;
+
+// K210 has 8M memory
+#[cfg(feature = "board_k210")]
+pub type FrameAlloc = bitmap_allocator::BitAlloc4K;
+
+lazy_static! {
+    pub static ref FRAME_ALLOCATOR: SpinNoIrqLock<FrameAlloc> =
+        SpinNoIrqLock::new(FrameAlloc::default());
+    /// The frame of zeros mapped by anonymous pages until written
+    static ref ZERO_FRAME: usize = {
+        let frame = alloc_frame().expect("failed to allocate zero frame");
+        active_table().with_temporary_map(frame, |_, data: &mut [u8; PAGE_SIZE]| {
+            data.iter_mut().for_each(|x| *x = 0)
+        });
+        frame
+    };
+}
+
+/// The only way to get active page table
+///
+/// ## CHANGE LOG
+///
+/// In the past, this function returns a `MutexGuard` of a global
+/// `Mutex<ActiveTable>` object, which means only one CPU core
+/// can access its active table at a time.
+///
+/// But given that a page table is ** process local **, and being active
+/// when and only when a thread of the process is running.
+/// The ownership of this page table is in the `MemorySet` object.
+/// So it's safe to access the active table inside `MemorySet`.
+/// But the shared parts is readonly, e.g. all pages mapped in
+/// `InactivePageTable::map_kernel()`.
+pub fn active_table() -> ActivePageTable {
+    unsafe { ActivePageTable::new() }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GlobalFrameAlloc;
+
+impl FrameAllocator for GlobalFrameAlloc {
+    fn alloc(&self) -> Option<usize> {
+        // get the real address of the alloc frame
+        let ret = FRAME_ALLOCATOR
+            .lock()
+            .alloc()
+            .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        trace!("Allocate frame: {:x?}", ret);
+        ret
+        // TODO: try to swap out when alloc failed
+    }
+    fn dealloc(&self, target: usize) {
+        trace!("Deallocate frame: {:x}", target);
+        FRAME_ALLOCATOR
+            .lock()
+            .dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+    }
+    fn zero_frame(&self) -> Option<usize> {
+        // MIPS page table can not mark the pages shared copy-on-write
+        if cfg!(target_arch = "mips") {
+            None
+        } else {
+            Some(zero_frame())
+        }
+    }
+    fn dealloc_shared(&self, target: usize) {
+        // the zero frame is never freed, and only merging shares others
+        if target != zero_frame() {
+            #[cfg(feature = "ksm")]
+            ksm::release(target);
+        }
+    }
+}
+
+/// Allocate the zero frame, which can not be done in a page fault
+pub fn init_zero_frame() {
+    lazy_static::initialize(&ZERO_FRAME);
+}
+
+pub fn zero_frame() -> usize {
+    *ZERO_FRAME
+}
+
+pub fn alloc_frame() -> Option<usize> {
+    GlobalFrameAlloc.alloc()
+}
+pub fn dealloc_frame(target: usize) {
+    GlobalFrameAlloc.dealloc(target);
+}
+
+/// Allocate `count` physically contiguous frames, return the address of the first one
+pub fn alloc_frames(count: usize) -> Option<usize> {
+    let mut ba = FRAME_ALLOCATOR.lock();
+    let mut key = 0;
+    let start = loop {
+        // skip to the next free frame, then check the run after it
+        let start = ba.next(key)?;
+        if start + count > FrameAlloc::CAP {
+            return None;
+        }
+        match (start..start + count).find(|&i| !ba.test(i)) {
+            Some(used) => key = used + 1,
+            None => break start,
+        }
+    };
+    ba.remove(start..start + count);
+    let ret = start * PAGE_SIZE + MEMORY_OFFSET;
+    trace!("Allocate {} frames: {:x}", count, ret);
+    Some(ret)
+}
+
+pub fn dealloc_frames(target: usize, count: usize) {
+    trace!("Deallocate {} frames: {:x}", count, target);
+    let start = (target - MEMORY_OFFSET) / PAGE_SIZE;
+    FRAME_ALLOCATOR.lock().insert(start..start + count);
+}
+
+/// Handle page fault at `addr`, caused by a write if `write`.
+/// Return true to continue, false to halt.
+/// When out of memory, a process is killed and the access is retried.
+#[cfg(not(feature = "nommu"))]
+pub fn handle_page_fault(addr: usize, write: bool) -> bool {
+    // debug!("page fault @ {:#x}", addr);
+
+    // kernel threads have no user memory
+    if current_thread().is_kernel() {
+        return false;
+    }
+    // This is safe as long as page fault never happens in page fault handler
+    let mut proc = unsafe { process_unsafe() };
+    match proc.vm.handle_page_fault(addr, write) {
+        Ok(true) => true,
+        Ok(false) => proc.grow_stack(addr),
+        Err(_) => {
+            drop(proc);
+            oom::out_of_memory()
+        }
+    }
+}
+
+/// There is no page fault without MMU
+#[cfg(feature = "nommu")]
+pub fn handle_page_fault(_addr: usize, _write: bool) -> bool {
+    false
+}
+
+/// An entry of the exception table, emitted into section `__ex_table`
+/// by the user access routines in `arch`.
+///
+/// A fault at `insn` continues at `fixup` instead of crashing the kernel.
+#[repr(C)]
+struct ExceptionTableEntry {
+    insn: usize,
+    fixup: usize,
+}
+
+extern "C" {
+    fn __ex_table_start();
+    fn __ex_table_end();
+    fn __copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+}
+
+/// Find the fixup address for a kernel fault at `pc`
+pub fn exception_fixup(pc: usize) -> Option<usize> {
+    let start = __ex_table_start as usize;
+    let len = (__ex_table_end as usize - start) / core::mem::size_of::<ExceptionTableEntry>();
+    let table = unsafe { core::slice::from_raw_parts(start as *const ExceptionTableEntry, len) };
+    table
+        .iter()
+        .find(|entry| entry.insn == pc)
+        .map(|entry| entry.fixup)
+}
+
+/// Check `[addr, addr + len)` lies entirely in user space
+fn check_user_range(addr: usize, len: usize) -> VMResult<()> {
+    match addr.checked_add(len) {
+        Some(end) if end <= USER_END => Ok(()),
+        _ => Err(VMError::InvalidPtr),
+    }
+}
+
+/// Copy `dst.len()` bytes from user address `src`.
+///
+/// Unlike dereferencing a checked pointer, a fault on an unmapped or
+/// protected page is caught and reported as `VMError::InvalidPtr`.
+pub fn copy_from_user(dst: &mut [u8], src: *const u8) -> VMResult<()> {
+    check_user_range(src as usize, dst.len())?;
+    match unsafe { __copy_user(dst.as_mut_ptr(), src, dst.len()) } {
+        0 => Ok(()),
+        _ => Err(VMError::InvalidPtr),
+    }
+}
+
+/// Copy `src` to user address `dst`. See `copy_from_user`.
+pub fn copy_to_user(dst: *mut u8, src: &[u8]) -> VMResult<()> {
+    check_user_range(dst as usize, src.len())?;
+    match unsafe { __copy_user(dst, src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(VMError::InvalidPtr),
+    }
+}
+
+/// Check every page of [`src`, `src + len`) can be read by user, before allocating a buffer
+/// of `len` given by user. A byte of each page is read, which maps the page if not yet.
+pub fn check_user_readable(src: *const u8, len: usize) -> VMResult<()> {
+    check_user_range(src as usize, len)?;
+    if len == 0 {
+        return Ok(());
+    }
+    let start = src as usize;
+    let mut byte = 0u8;
+    for page in Page::range_of(start, start + len) {
+        let addr = page.start_address().max(start);
+        if unsafe { __copy_user(&mut byte, addr as *const u8, 1) } != 0 {
+            return Err(VMError::InvalidPtr);
+        }
+    }
+    Ok(())
+}
+
+pub fn init_heap() {
+    use crate::consts::KERNEL_HEAP_SIZE;
+    static mut HEAP: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
+    unsafe {
+        HEAP_ALLOCATOR.add_to_heap(HEAP.as_ptr() as usize, KERNEL_HEAP_SIZE);
+    }
+    info!("heap init end");
+}
+
+/// Allocator of the user memory without MMU.
+///
+/// Areas are made of whole frames, used at their physical address,
+/// so that they are accounted together with the rest of the memory.
+#[cfg(feature = "nommu")]
+#[derive(Debug)]
+pub struct NoMMUAlloc;
+
+#[cfg(feature = "nommu")]
+unsafe impl core::alloc::GlobalAlloc for NoMMUAlloc {
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        // frames are page aligned, which is the most areas ask for
+        let pages = (layout.size() + PAGE_SIZE - 1) / PAGE_SIZE;
+        alloc_frames(pages).unwrap_or(0) as *mut u8
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+        let pages = (layout.size() + PAGE_SIZE - 1) / PAGE_SIZE;
+        dealloc_frames(ptr as usize, pages);
+    }
+}
+
+#[cfg(feature = "nommu")]
+impl rcore_memory::no_mmu::NoMMUSupport for NoMMUAlloc {
+    type Alloc = NoMMUAlloc;
+    fn allocator() -> &'static NoMMUAlloc {
+        &NoMMUAlloc
+    }
+}
@@ -0,0 +1,122 @@
+//! Out of memory killer
+//!
+//! There is no reclaim or swap to fall back on, so when a page fault can not
+//! get a frame, a process is killed by SIGKILL to make room. The victim is the
+//! one with the highest badness: its resident pages, plus its `oom_score_adj`
+//! in thousandths of the pages resident in all candidates.
+//!
+//! The faulting thread then yields and retries the access. No other process is
+//! killed until the memory of the last victim is released, even if it is left as a zombie.
+
+use super::*;
+use crate::process::{Process, PROCESSES};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::thread;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+/// Killed by the OOM killer
+const SIGKILL: usize = 9;
+
+pub const OOM_SCORE_ADJ_MIN: isize = -1000;
+pub const OOM_SCORE_ADJ_MAX: isize = 1000;
+
+lazy_static! {
+    /// The last victim, waited for until its memory is released
+    static ref VICTIM: SpinNoIrqLock<Weak<Mutex<Process>>> = SpinNoIrqLock::new(Weak::new());
+}
+
+struct Candidate {
+    proc: Arc<Mutex<Process>>,
+    pid: usize,
+    rss: usize,
+    oom_score_adj: isize,
+}
+
+/// Kill a process to free memory, and give it a chance to exit.
+/// Return false if there is nothing to kill.
+pub fn out_of_memory() -> bool {
+    if victim_holds_memory() {
+        thread::yield_now();
+        return true;
+    }
+    let candidates = candidates();
+    let total: usize = candidates.iter().map(|c| c.rss).sum();
+    let victim = candidates
+        .into_iter()
+        .map(|c| {
+            let points = c.rss as isize + c.oom_score_adj * total as isize / 1000;
+            (points, c)
+        })
+        .filter(|&(points, _)| points > 0)
+        .max_by_key(|&(points, _)| points);
+    let (points, victim) = match victim {
+        Some(victim) => victim,
+        None => {
+            error!("out of memory, but no process can be killed");
+            return false;
+        }
+    };
+    warn!(
+        "out of memory: kill process {}, {} KiB resident, oom_score_adj {}, points {} of {}",
+        victim.pid,
+        victim.rss * PAGE_SIZE / 1024,
+        victim.oom_score_adj,
+        points,
+        total
+    );
+    *VICTIM.lock() = Arc::downgrade(&victim.proc);
+    crate::syscall::kill_process(&victim.proc, SIGKILL);
+    drop(victim);
+    thread::yield_now();
+    true
+}
+
+/// Whether the last victim has not released its memory yet.
+/// Its areas are all removed when released, while the zombie may stay for long.
+fn victim_holds_memory() -> bool {
+    let victim = VICTIM.lock().upgrade();
+    match victim {
+        // a victim locked by others is still being killed
+        Some(proc) => proc
+            .try_lock()
+            .map_or(true, |proc| proc.vm.iter().next().is_some()),
+        None => false,
+    }
+}
+
+/// Processes which may be killed, with their resident pages
+fn candidates() -> Vec<Candidate> {
+    let processes: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    let mut candidates = Vec::new();
+    for proc in processes {
+        // a process locked by others is busy in the kernel, leave it alone
+        let mut locked = match proc.try_lock() {
+            Some(locked) => locked,
+            None => continue,
+        };
+        let oom_score_adj = locked.oom_score_adj.load(Ordering::Relaxed);
+        if oom_score_adj == OOM_SCORE_ADJ_MIN {
+            continue;
+        }
+        // nothing to free
+        let rss = locked.vm.resident_size() / PAGE_SIZE;
+        if rss == 0 {
+            continue;
+        }
+        let pid = locked.pid.get();
+        drop(locked);
+        candidates.push(Candidate {
+            proc,
+            pid,
+            rss,
+            oom_score_adj,
+        });
+    }
+    candidates
+}
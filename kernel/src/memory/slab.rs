@@ -0,0 +1,281 @@
+//! Slab caches for kernel objects
+//!
+//! An allocation is served by the smallest cache whose objects fit it,
+//! unless more than half of the object would be wasted.
+//! Besides the caches of hot objects, there are general caches of power of two sizes.
+//!
+//! Small objects are carved out of slabs of `SLAB_SIZE` taken from the buddy heap,
+//! which are aligned to their size, so that the slab of an object is found by its address.
+//! A slab goes back to the buddy heap once all its objects are freed.
+//! Objects too big to share a slab are taken from the buddy heap one by one,
+//! the cache only counts them.
+
+use super::heap::KernelHeap;
+use super::STACK_SIZE;
+use crate::fs::FileLike;
+use crate::net::{TCP_SENDBUF, UDP_SENDBUF};
+use crate::process::Thread;
+use crate::sync::preempt::PreemptGuard;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt::Write;
+use core::mem::{align_of, size_of};
+use core::ptr;
+use rcore_memory::PAGE_SIZE;
+use spin::Mutex;
+
+/// Size of a slab of small objects, which is also its alignment
+const SLAB_SIZE: usize = 0x8000;
+
+pub struct SlabCache {
+    name: &'static str,
+    size: usize,
+    align: usize,
+    state: Mutex<SlabState>,
+}
+
+struct SlabState {
+    /// Slabs with free objects, linked through `Slab::next`
+    partial: usize,
+    active_objs: usize,
+    num_slabs: usize,
+}
+
+/// Header at the start of a slab of small objects
+struct Slab {
+    /// Free objects of the slab, linked through their first word
+    free: usize,
+    /// Objects in use
+    inuse: usize,
+    /// The next slab with free objects
+    next: usize,
+}
+
+/// A snapshot of the statistics of a cache
+struct SlabInfo {
+    name: &'static str,
+    active_objs: usize,
+    num_objs: usize,
+    obj_size: usize,
+    objs_per_slab: usize,
+    pages_per_slab: usize,
+    num_slabs: usize,
+}
+
+static CACHES: [SlabCache; 12] = [
+    SlabCache::new("thread", size_of::<Thread>(), align_of::<Thread>()),
+    // only used on mips, where kernel stacks are not guarded
+    SlabCache::new("kernel_stack", STACK_SIZE, STACK_SIZE),
+    SlabCache::new("file_like", size_of::<FileLike>(), align_of::<FileLike>()),
+    SlabCache::new("tcp_buffer", TCP_SENDBUF, 1),
+    SlabCache::new("udp_buffer", UDP_SENDBUF, 1),
+    SlabCache::new("kmalloc-32", 32, 32),
+    SlabCache::new("kmalloc-64", 64, 64),
+    SlabCache::new("kmalloc-128", 128, 128),
+    SlabCache::new("kmalloc-256", 256, 256),
+    SlabCache::new("kmalloc-512", 512, 512),
+    SlabCache::new("kmalloc-1024", 1024, 1024),
+    SlabCache::new("kmalloc-2048", 2048, 2048),
+];
+
+/// Find the cache serving allocations of `layout`.
+/// It must only depend on `layout`, so that the same cache frees the object.
+pub fn find_cache(layout: &Layout) -> Option<&'static SlabCache> {
+    let size = layout.size();
+    CACHES
+        .iter()
+        .filter(|cache| {
+            size <= cache.size && cache.size / 2 < size && layout.align() <= cache.slot_align()
+        })
+        .min_by_key(|cache| cache.size)
+}
+
+impl SlabCache {
+    const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        SlabCache {
+            name,
+            size,
+            align,
+            state: Mutex::new(SlabState {
+                partial: 0,
+                active_objs: 0,
+                num_slabs: 0,
+            }),
+        }
+    }
+
+    /// Size of an object slot, which holds the free list link when free
+    fn slot_size(&self) -> usize {
+        let align = self.slot_align();
+        (self.size.max(size_of::<usize>()) + align - 1) & !(align - 1)
+    }
+
+    fn slot_align(&self) -> usize {
+        self.align.max(align_of::<usize>())
+    }
+
+    /// Offset of the first object in a slab, after the header
+    fn first_offset(&self) -> usize {
+        let align = self.slot_align();
+        (size_of::<Slab>() + align - 1) & !(align - 1)
+    }
+
+    /// Objects in a slab, less than 2 if the objects are big
+    fn objs_per_slab(&self) -> usize {
+        SLAB_SIZE.saturating_sub(self.first_offset()) / self.slot_size()
+    }
+
+    fn is_big(&self) -> bool {
+        self.objs_per_slab() < 2
+    }
+
+    /// Layout of a big object, taken from the buddy heap alone
+    fn big_layout(&self) -> Layout {
+        Layout::from_size_align(self.size, self.slot_align()).unwrap()
+    }
+
+    fn slab_layout() -> Layout {
+        Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap()
+    }
+
+    pub(super) fn alloc(&self, heap: &KernelHeap) -> *mut u8 {
+        let mut state = self.state.lock();
+        if self.is_big() {
+            let obj = heap.alloc_buddy(self.big_layout());
+            if !obj.is_null() {
+                state.active_objs += 1;
+                state.num_slabs += 1;
+            }
+            return obj;
+        }
+        if state.partial == 0 {
+            let slab = heap.alloc_buddy(Self::slab_layout());
+            if slab.is_null() {
+                return ptr::null_mut();
+            }
+            self.init_slab(slab as usize);
+            state.partial = slab as usize;
+            state.num_slabs += 1;
+        }
+        let slab = unsafe { &mut *(state.partial as *mut Slab) };
+        let obj = slab.free;
+        slab.free = unsafe { *(obj as *const usize) };
+        slab.inuse += 1;
+        if slab.free == 0 {
+            // full, no longer partial
+            state.partial = slab.next;
+            slab.next = 0;
+        }
+        state.active_objs += 1;
+        obj as *mut u8
+    }
+
+    pub(super) unsafe fn dealloc(&self, heap: &KernelHeap, ptr: *mut u8) {
+        let mut state = self.state.lock();
+        state.active_objs -= 1;
+        if self.is_big() {
+            state.num_slabs -= 1;
+            heap.dealloc_buddy(ptr, self.big_layout());
+            return;
+        }
+        let slab_addr = ptr as usize & !(SLAB_SIZE - 1);
+        let slab = &mut *(slab_addr as *mut Slab);
+        let was_full = slab.free == 0;
+        *(ptr as *mut usize) = slab.free;
+        slab.free = ptr as usize;
+        slab.inuse -= 1;
+        if slab.inuse == 0 {
+            // a slab has several objects, so it was not full and is on the partial list
+            Self::unlink(&mut state, slab_addr);
+            state.num_slabs -= 1;
+            heap.dealloc_buddy(slab_addr as *mut u8, Self::slab_layout());
+        } else if was_full {
+            slab.next = state.partial;
+            state.partial = slab_addr;
+        }
+    }
+
+    /// Link the objects of a new slab at `slab` into its free list
+    fn init_slab(&self, slab: usize) {
+        let (first, slot_size) = (self.first_offset(), self.slot_size());
+        let mut free = 0;
+        for i in (0..self.objs_per_slab()).rev() {
+            let obj = slab + first + i * slot_size;
+            unsafe { *(obj as *mut usize) = free };
+            free = obj;
+        }
+        let header = Slab {
+            free,
+            inuse: 0,
+            next: 0,
+        };
+        unsafe { ptr::write(slab as *mut Slab, header) };
+    }
+
+    /// Remove `slab` from the partial list
+    fn unlink(state: &mut SlabState, slab: usize) {
+        let mut link = &mut state.partial as *mut usize;
+        unsafe {
+            while *link != slab {
+                link = &mut (*(*link as *mut Slab)).next;
+            }
+            *link = (*(slab as *mut Slab)).next;
+        }
+    }
+
+    fn info(&self) -> SlabInfo {
+        // not counted by `sync::preempt` like the allocations, which lock it too
+        let _preempt = PreemptGuard::new();
+        let state = self.state.lock();
+        let (objs_per_slab, pages_per_slab) = if self.is_big() {
+            (1, (self.size + PAGE_SIZE - 1) / PAGE_SIZE)
+        } else {
+            (self.objs_per_slab(), SLAB_SIZE / PAGE_SIZE)
+        };
+        SlabInfo {
+            name: self.name,
+            active_objs: state.active_objs,
+            num_objs: state.num_slabs * objs_per_slab,
+            obj_size: self.slot_size(),
+            objs_per_slab,
+            pages_per_slab,
+            num_slabs: state.num_slabs,
+        }
+    }
+}
+
+/// Heap and slab statistics in the format of `/proc/slabinfo`
+pub fn slabinfo() -> String {
+    // take the snapshots first, formatting allocates
+    let infos: Vec<SlabInfo> = CACHES.iter().map(SlabCache::info).collect();
+    let heap = crate::HEAP_ALLOCATOR.stats();
+    let mut s = String::new();
+    writeln!(s, "slabinfo - version: 2.1").unwrap();
+    writeln!(
+        s,
+        "# name            <active_objs> <num_objs> <objsize> <objperslab> <pagesperslab> : slabdata <num_slabs>"
+    )
+    .unwrap();
+    for info in infos.iter() {
+        writeln!(
+            s,
+            "{:<17} {:>6} {:>6} {:>6} {:>4} {:>4} : slabdata {:>6}",
+            info.name,
+            info.active_objs,
+            info.num_objs,
+            info.obj_size,
+            info.objs_per_slab,
+            info.pages_per_slab,
+            info.num_slabs
+        )
+        .unwrap();
+    }
+    writeln!(
+        s,
+        "# heap: total {} bytes, used {} bytes, grown {} times",
+        heap.total, heap.used, heap.grow_count
+    )
+    .unwrap();
+    s
+}
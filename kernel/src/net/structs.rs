@@ -1103,7 +1103,7 @@ pub const TCP_SENDBUF: usize = 512 * 1024; // 512K
 pub const TCP_RECVBUF: usize = 512 * 1024; // 512K
 
 const UDP_METADATA_BUF: usize = 1024;
-const UDP_SENDBUF: usize = 64 * 1024; // 64K
+pub const UDP_SENDBUF: usize = 64 * 1024; // 64K
 const UDP_RECVBUF: usize = 64 * 1024; // 64K
 
 const RAW_METADATA_BUF: usize = 1024;
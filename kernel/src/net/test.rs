@@ -5,7 +5,7 @@ use alloc::vec;
 use core::fmt::Write;
 use smoltcp::socket::*;
 
-pub extern "C" fn server(_arg: usize) -> ! {
+pub fn server() {
     if NET_DRIVERS.read().len() < 1 {
         loop {
             thread::yield_now();
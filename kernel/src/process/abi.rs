@@ -3,18 +3,27 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use core::ptr::null;
 
+use crate::arch::rand::rand;
+
 pub struct ProcInitInfo {
     pub args: Vec<String>,
     pub envs: Vec<String>,
     pub auxv: BTreeMap<u8, usize>,
+    /// The path passed to execve
+    pub execfn: String,
 }
 
 impl ProcInitInfo {
     pub unsafe fn push_at(&self, stack_top: usize) -> usize {
         let mut writer = StackWriter { sp: stack_top };
+        let mut auxv = self.auxv.clone();
         // from stack_top:
         // program name
-        writer.push_str(&self.args[0]);
+        writer.push_str(&self.execfn);
+        auxv.insert(AT_EXECFN, writer.sp);
+        // random bytes for libc to seed stack protector and pointer guard
+        writer.push_slice(&[rand(), rand()]);
+        auxv.insert(AT_RANDOM, writer.sp);
         // environment strings
         let envs: Vec<_> = self
             .envs
@@ -35,7 +44,7 @@ impl ProcInitInfo {
             .collect();
         // auxiliary vector entries
         writer.push_slice(&[null::<u8>(), null::<u8>()]);
-        for (&type_, &value) in self.auxv.iter() {
+        for (&type_, &value) in auxv.iter() {
             writer.push_slice(&[type_ as usize, value]);
         }
         // envionment pointers
@@ -48,6 +57,22 @@ impl ProcInitInfo {
         writer.push_slice(&[argv.len()]);
         writer.sp
     }
+
+    /// Upper bound of the stack space taken by `push_at`
+    pub fn size(&self) -> usize {
+        use core::mem::size_of;
+        let strings: usize = self
+            .args
+            .iter()
+            .chain(self.envs.iter())
+            .chain(Some(&self.execfn))
+            .map(|s| s.len() + 1)
+            .sum();
+        // pointers, auxv with AT_EXECFN and AT_RANDOM, terminators and argc
+        let words = self.args.len() + self.envs.len() + (self.auxv.len() + 3) * 2 + 3;
+        // random bytes, and padding for alignment
+        strings + 16 + (words + 2) * size_of::<usize>()
+    }
 }
 
 struct StackWriter {
@@ -74,3 +99,15 @@ pub const AT_PHDR: u8 = 3;
 pub const AT_PHENT: u8 = 4;
 pub const AT_PHNUM: u8 = 5;
 pub const AT_PAGESZ: u8 = 6;
+pub const AT_BASE: u8 = 7;
+pub const AT_FLAGS: u8 = 8;
+pub const AT_ENTRY: u8 = 9;
+pub const AT_UID: u8 = 11;
+pub const AT_EUID: u8 = 12;
+pub const AT_GID: u8 = 13;
+pub const AT_EGID: u8 = 14;
+pub const AT_HWCAP: u8 = 16;
+pub const AT_CLKTCK: u8 = 17;
+pub const AT_SECURE: u8 = 23;
+pub const AT_RANDOM: u8 = 25;
+pub const AT_EXECFN: u8 = 31;
@@ -0,0 +1,51 @@
+//! Address space layout randomization
+//!
+//! The PIE load bias, dynamic linker base, stack top, mmap base and brk start
+//! of every new user process are shifted by random page aligned offsets.
+//! Boot with `norandmaps` in the kernel cmdline to get a fixed layout.
+
+use crate::arch::rand::rand;
+use crate::drivers::CMDLINE;
+use crate::sync::SpinNoIrqLock;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::*;
+use log::*;
+use rcore_memory::PAGE_SIZE;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+lazy_static! {
+    /// State of the xorshift generator, reseeded from the arch entropy on every use
+    static ref STATE: SpinNoIrqLock<u64> = SpinNoIrqLock::new(0x853c_49e6_748f_ea9b);
+}
+
+pub fn init() {
+    if CMDLINE.read().split(' ').any(|arg| arg == "norandmaps") {
+        ENABLED.store(false, Ordering::Relaxed);
+    }
+    info!("aslr: {}", if enabled() { "enabled" } else { "disabled" });
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// A random page aligned offset in [0, `range`), or 0 if ASLR is disabled
+pub fn random_offset(range: usize) -> usize {
+    let pages = range / PAGE_SIZE;
+    if !enabled() || pages == 0 {
+        return 0;
+    }
+    let mut state = STATE.lock();
+    // the arch counters are weak on their own, so stir them into a xorshift64*
+    let mut x = *state ^ rand();
+    if x == 0 {
+        x = 1;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    *state = x;
+    let r = x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32;
+    (r as usize % pages) * PAGE_SIZE
+}
@@ -0,0 +1,328 @@
+//! ELF core dumps of processes killed by fatal traps
+//!
+//! Like Linux, the file `core` is written in the working directory of the process,
+//! and cut at RLIMIT_CORE, which is 0 by default so nothing is dumped.
+//! A `PT_NOTE` segment holds the registers of each thread in `NT_PRSTATUS`, the faulting
+//! one first, and each memory area has a `PT_LOAD` segment, so the core can be read by gdb
+//! on the host. The other threads are quit before dumping, and leave the registers saved
+//! at their last entry to the kernel. A thread that has never run is left out.
+
+use super::rlimit::RLIMIT_CORE;
+use super::*;
+use crate::arch::interrupt::TrapFrame;
+use crate::fs::{FOLLOW_MAX_DEPTH, ROOT_INODE};
+use crate::memory::active_table;
+use crate::syscall::SysError;
+use alloc::vec::Vec;
+use core::mem::{size_of, size_of_val};
+use core::slice;
+use rcore_fs::vfs::{FileType, FsError, INode};
+use rcore_memory::paging::{PageTable, PageTableExt};
+use rcore_memory::PAGE_SIZE;
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+/// Name of the notes of Linux, with the trailing zero padded to 4 bytes
+const NOTE_NAME: &[u8; 8] = b"CORE\0\0\0\0";
+
+#[cfg(target_pointer_width = "64")]
+const ELF_CLASS: u8 = 2;
+#[cfg(target_pointer_width = "32")]
+const ELF_CLASS: u8 = 1;
+
+#[cfg(target_arch = "x86_64")]
+const ELF_MACHINE: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const ELF_MACHINE: u16 = 183;
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+const ELF_MACHINE: u16 = 243;
+#[cfg(target_arch = "mips")]
+const ELF_MACHINE: u16 = 8;
+
+#[repr(C)]
+struct ElfHeader {
+    ident: [u8; 16],
+    type_: u16,
+    machine: u16,
+    version: u32,
+    entry: usize,
+    phoff: usize,
+    shoff: usize,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[cfg(target_pointer_width = "64")]
+#[repr(C)]
+struct ProgramHeader {
+    type_: u32,
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    paddr: usize,
+    filesz: usize,
+    memsz: usize,
+    align: usize,
+}
+
+#[cfg(target_pointer_width = "32")]
+#[repr(C)]
+struct ProgramHeader {
+    type_: u32,
+    offset: usize,
+    vaddr: usize,
+    paddr: usize,
+    filesz: usize,
+    memsz: usize,
+    flags: u32,
+    align: usize,
+}
+
+#[repr(C)]
+struct NoteHeader {
+    namesz: u32,
+    descsz: u32,
+    type_: u32,
+}
+
+/// `elf_prstatus` of Linux before the registers, which are followed by `pr_fpvalid`
+#[repr(C)]
+struct PrStatus {
+    signo: i32,
+    code: i32,
+    errno: i32,
+    cursig: u16,
+    _pad: u16,
+    sigpend: usize,
+    sighold: usize,
+    pid: i32,
+    ppid: i32,
+    pgrp: i32,
+    sid: i32,
+    /// User and system time of the process and of its children
+    times: [usize; 8],
+}
+
+/// Dump the core of the current process killed by `sig` at the trap `tf`.
+/// Return whether it is dumped.
+pub fn dump(tf: &TrapFrame, sig: usize) -> bool {
+    match write_core(tf, sig) {
+        Ok(dumped) => dumped,
+        Err(err) => {
+            warn!("failed to dump core: {:?}", err);
+            false
+        }
+    }
+}
+
+fn write_core(tf: &TrapFrame, sig: usize) -> Result<bool, SysError> {
+    // never lock the parent while holding current process
+    let parent = process().parent.clone();
+    let ppid = parent
+        .and_then(|weak| weak.upgrade())
+        .map_or(0, |parent| parent.lock().pid.get());
+    let (limit, pid, cwd) = {
+        let proc = process();
+        let cwd = proc.cwd.lock().clone();
+        (proc.rlimits.get(RLIMIT_CORE).cur, proc.pid.get(), cwd)
+    };
+    if limit == 0 {
+        return Ok(false);
+    }
+
+    // the other threads are quit as the process is killed next,
+    // and leave their registers when switched out for good
+    let tid = processor().tid();
+    let live_threads = {
+        let mut proc = process();
+        proc.live_threads.collect_regs();
+        proc.quit_threads_except(tid, sig);
+        proc.live_threads.clone()
+    };
+    live_threads.wait_until(1);
+    let mut threads = vec![(tid, tf.clone())];
+    threads.extend(live_threads.take_regs());
+
+    // the process is not locked while writing the file
+    let dir = ROOT_INODE.lookup_follow(&cwd, FOLLOW_MAX_DEPTH)?;
+    let file = match dir.find("core") {
+        Ok(file) => file,
+        Err(FsError::EntryNotFound) => dir.create("core", FileType::File, 0o600)?,
+        Err(err) => return Err(err.into()),
+    };
+    file.resize(0)?;
+    let mut writer = CoreWriter {
+        file,
+        offset: 0,
+        limit: limit.min(usize::max_value() as u64) as usize,
+    };
+
+    // the layout: headers, notes of the faulting thread first, then the memory
+    // from a page boundary
+    let regs_size = size_of_val(&tf.elf_gregs());
+    let prstatus_size = size_of::<PrStatus>() + regs_size + size_of::<i32>();
+    let desc_size = align_up(prstatus_size, size_of::<usize>());
+    let note_size = size_of::<NoteHeader>() + NOTE_NAME.len() + desc_size;
+    let notes_size = note_size * threads.len();
+    let areas: Vec<(usize, usize, u32)> = process()
+        .vm
+        .iter()
+        .map(|area| {
+            #[cfg(not(feature = "nommu"))]
+            let flags = {
+                let attr = area.attr();
+                let write = if attr.is_readonly() { 0 } else { PF_W };
+                let execute = if attr.is_execute() { PF_X } else { 0 };
+                PF_R | write | execute
+            };
+            #[cfg(feature = "nommu")]
+            let flags = PF_R | PF_W | PF_X;
+            (area.start_addr(), area.end_addr(), flags)
+        })
+        .collect();
+    let phnum = 1 + areas.len();
+    let note_offset = size_of::<ElfHeader>() + phnum * size_of::<ProgramHeader>();
+    let data_offset = align_up(note_offset + notes_size, PAGE_SIZE);
+
+    let mut ident = [0u8; 16];
+    ident[..7].copy_from_slice(&[0x7f, b'E', b'L', b'F', ELF_CLASS, 1, 1]);
+    writer.write_value(&ElfHeader {
+        ident,
+        type_: ET_CORE,
+        machine: ELF_MACHINE,
+        version: 1,
+        entry: 0,
+        phoff: size_of::<ElfHeader>(),
+        shoff: 0,
+        flags: 0,
+        ehsize: size_of::<ElfHeader>() as u16,
+        phentsize: size_of::<ProgramHeader>() as u16,
+        phnum: phnum as u16,
+        shentsize: 0,
+        shnum: 0,
+        shstrndx: 0,
+    })?;
+    writer.write_value(&ProgramHeader {
+        type_: PT_NOTE,
+        flags: 0,
+        offset: note_offset,
+        vaddr: 0,
+        paddr: 0,
+        filesz: notes_size,
+        memsz: 0,
+        align: 4,
+    })?;
+    let mut offset = data_offset;
+    for &(start, end, flags) in areas.iter() {
+        writer.write_value(&ProgramHeader {
+            type_: PT_LOAD,
+            flags,
+            offset,
+            vaddr: start,
+            paddr: 0,
+            filesz: end - start,
+            memsz: end - start,
+            align: PAGE_SIZE,
+        })?;
+        offset += end - start;
+    }
+
+    for (tid, tf) in threads.iter() {
+        let note_start = writer.offset;
+        writer.write_value(&NoteHeader {
+            namesz: 5,
+            descsz: desc_size as u32,
+            type_: NT_PRSTATUS,
+        })?;
+        writer.write(NOTE_NAME)?;
+        writer.write_value(&PrStatus {
+            signo: sig as i32,
+            code: 0,
+            errno: 0,
+            cursig: sig as u16,
+            _pad: 0,
+            sigpend: 0,
+            sighold: 0,
+            pid: *tid as i32,
+            ppid: ppid as i32,
+            pgrp: pid as i32,
+            sid: pid as i32,
+            times: [0; 8],
+        })?;
+        writer.write_value(&tf.elf_gregs())?;
+        // no floating point registers
+        writer.write_value(&0i32)?;
+        writer.pad_to(note_start + note_size)?;
+    }
+    writer.pad_to(data_offset)?;
+
+    // pages never touched are not allocated, and dumped as zeros.
+    // Each page is copied with the process locked, and written after.
+    let mut page = vec![0u8; PAGE_SIZE];
+    for &(start, end, _) in areas.iter() {
+        for addr in (start..end).step_by(PAGE_SIZE) {
+            {
+                let mut proc = process();
+                // the entry of a page not allocated yet has no valid target
+                let mut present = false;
+                proc.vm
+                    .edit(|pt| present = pt.get_entry(addr).map_or(false, |entry| entry.present()));
+                match proc.vm.translate(addr).filter(|_| present) {
+                    Some(frame) => {
+                        active_table()
+                            .with_temporary_map(frame, |_, data: &mut [u8; PAGE_SIZE]| {
+                                page.copy_from_slice(&data[..])
+                            });
+                    }
+                    None => page.iter_mut().for_each(|x| *x = 0),
+                }
+            }
+            writer.write(&page[..PAGE_SIZE.min(end - addr)])?;
+        }
+    }
+    info!("dumped core of {}, {} bytes", pid, writer.offset);
+    Ok(true)
+}
+
+/// Writes the core file in order, dropping the part beyond the limit
+struct CoreWriter {
+    file: Arc<INode>,
+    offset: usize,
+    limit: usize,
+}
+
+impl CoreWriter {
+    fn write(&mut self, data: &[u8]) -> Result<(), SysError> {
+        let len = data.len().min(self.limit.saturating_sub(self.offset));
+        if len != 0 {
+            self.file.write_at(self.offset, &data[..len])?;
+        }
+        self.offset += data.len();
+        Ok(())
+    }
+
+    fn write_value<T>(&mut self, value: &T) -> Result<(), SysError> {
+        let data = unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+        self.write(data)
+    }
+
+    /// Fill zeros up to `offset`
+    fn pad_to(&mut self, offset: usize) -> Result<(), SysError> {
+        let zeros = vec![0u8; offset - self.offset];
+        self.write(&zeros)
+    }
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) / align * align
+}
@@ -0,0 +1,135 @@
+//! Kernel threads
+//!
+//! A kernel thread runs a closure on the kernel page table and belongs to no process,
+//! so syscalls never take it for a user process. Running ones are listed with their names
+//! in `/proc/kthreads`.
+//!
+//! Like Linux, stopping a kernel thread only asks it to stop:
+//! the closure should check `should_stop` when woken up and return.
+
+use super::*;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+lazy_static! {
+    /// Running kernel threads made by `spawn`
+    static ref KTHREADS: Mutex<BTreeMap<Tid, Arc<KThread>>> = Mutex::new(BTreeMap::new());
+}
+
+struct KThread {
+    name: String,
+    should_stop: AtomicBool,
+}
+
+/// The return value of a kernel thread, set when it finishes
+struct Packet<T> {
+    result: Mutex<Option<T>>,
+    finished: Condvar,
+}
+
+/// An owned permission to join on or stop a kernel thread
+pub struct JoinHandle<T> {
+    tid: Tid,
+    kthread: Arc<KThread>,
+    packet: Arc<Packet<T>>,
+}
+
+/// Start a kernel thread named `name` running `f`
+pub fn spawn<F, T>(name: &str, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let kthread = Arc::new(KThread {
+        name: String::from(name),
+        should_stop: AtomicBool::new(false),
+    });
+    let packet = Arc::new(Packet {
+        result: Mutex::new(None),
+        finished: Condvar::new(),
+    });
+    let their_packet = packet.clone();
+    let thread = new_thread(move || {
+        let result = f();
+        *their_packet.result.lock() = Some(result);
+        their_packet.finished.notify_all();
+    });
+    // hold the table, so the thread can not remove itself before it is added
+    let mut kthreads = KTHREADS.lock();
+    let tid = manager().add(thread);
+    kthreads.insert(tid, kthread.clone());
+    drop(kthreads);
+    info!("kthread: spawn {} {}", tid, name);
+    JoinHandle {
+        tid,
+        kthread,
+        packet,
+    }
+}
+
+fn new_thread<M: FnOnce() + Send + 'static>(main: M) -> Box<Thread> {
+    let arg = Box::into_raw(Box::new(main)) as usize;
+    Thread::new_kernel(kthread_entry::<M>, arg)
+}
+
+extern "C" fn kthread_entry<M: FnOnce() + Send + 'static>(arg: usize) -> ! {
+    let main = unsafe { *Box::from_raw(arg as *mut M) };
+    main();
+    let tid = processor().tid();
+    KTHREADS.lock().remove(&tid);
+    manager().exit(tid, 0);
+    processor().yield_now();
+    unreachable!();
+}
+
+impl<T> JoinHandle<T> {
+    pub fn tid(&self) -> Tid {
+        self.tid
+    }
+
+    /// Wait for the thread to finish, and get its return value
+    pub fn join(self) -> T {
+        let mut result = self.packet.result.lock();
+        loop {
+            if let Some(value) = result.take() {
+                return value;
+            }
+            result = self.packet.finished.wait(result);
+        }
+    }
+
+    /// Ask the thread to stop, wake it up, and wait for it to finish
+    pub fn stop(self) -> T {
+        self.kthread.should_stop.store(true, Ordering::SeqCst);
+        // the tid may be reused once the thread removed itself from the table
+        let kthreads = KTHREADS.lock();
+        let running = kthreads
+            .get(&self.tid)
+            .map_or(false, |kthread| Arc::ptr_eq(kthread, &self.kthread));
+        if running {
+            manager().wakeup(self.tid);
+        }
+        drop(kthreads);
+        self.join()
+    }
+}
+
+/// Whether the current kernel thread is asked to stop
+pub fn should_stop() -> bool {
+    let tid = processor().tid();
+    KTHREADS
+        .lock()
+        .get(&tid)
+        .map_or(false, |kthread| kthread.should_stop.load(Ordering::SeqCst))
+}
+
+/// List the running kernel threads, one `tid name` on each line
+pub fn list() -> String {
+    let mut output = String::new();
+    for (tid, kthread) in KTHREADS.lock().iter() {
+        output += &format!("{} {}\n", tid, kthread.name);
+    }
+    output
+}
@@ -1,18 +1,25 @@
 pub use self::structs::*;
 use crate::arch::cpu;
 use crate::consts::{MAX_CPU_NUM, MAX_PROCESS_NUM};
-use crate::sync::{MutexGuard, SpinNoIrq};
+use crate::sync::{preempt::PreemptGuard, MutexGuard, SpinNoIrq};
 use alloc::{boxed::Box, sync::Arc};
 use log::*;
 pub use rcore_thread::*;
 
 mod abi;
+pub mod aslr;
+pub mod coredump;
+pub mod kthread;
+pub mod ptrace;
+pub mod rlimit;
+pub mod sched;
 pub mod structs;
 
 pub fn init() {
-    // NOTE: max_time_slice <= 5 to ensure 'priority' test pass
-    let scheduler = scheduler::RRScheduler::new(5);
-    let manager = Arc::new(ThreadPool::new(scheduler, MAX_PROCESS_NUM));
+    aslr::init();
+    crate::memory::init_zero_frame();
+
+    let manager = Arc::new(ThreadPool::new(&*sched::SCHEDULER, MAX_PROCESS_NUM));
 
     unsafe {
         for cpu_id in 0..MAX_CPU_NUM {
@@ -20,6 +27,9 @@ pub fn init() {
         }
     }
 
+    #[cfg(feature = "ksm")]
+    crate::memory::ksm::init();
+
     crate::shell::run_user_shell();
 
     info!("process: init end");
@@ -111,15 +121,15 @@ static PROCESSORS: [Processor; MAX_CPU_NUM] = [
 
 /// Get current process
 pub fn process() -> MutexGuard<'static, Process, SpinNoIrq> {
-    current_thread().proc.lock()
+    current_thread().proc().lock()
 }
 
 /// Get current process, ignoring its lock
 /// Only use this when necessary
 pub unsafe fn process_unsafe() -> MutexGuard<'static, Process, SpinNoIrq> {
     let thread = current_thread();
-    thread.proc.force_unlock();
-    thread.proc.lock()
+    thread.proc().force_unlock();
+    thread.proc().lock()
 }
 
 /// Get current thread
@@ -131,6 +141,22 @@ pub fn current_thread() -> &'static mut Thread {
     process
 }
 
+/// The thread manager, not preempted until the returned guard is dropped,
+/// as it holds spin locks of `rcore_thread` not counted by `sync::preempt`.
+/// Use it within one statement, e.g. `manager().exit(tid, code)`.
+pub fn manager() -> Manager {
+    Manager(PreemptGuard::new())
+}
+
+pub struct Manager(PreemptGuard);
+
+impl core::ops::Deref for Manager {
+    type Target = ThreadPool;
+    fn deref(&self) -> &ThreadPool {
+        processor().manager()
+    }
+}
+
 // Implement dependencies for std::thread
 
 #[no_mangle]
@@ -0,0 +1,283 @@
+//! Tracing of processes by `ptrace`
+//!
+//! A traced process stops in the kernel at breakpoints and single steps, after `exec`,
+//! at the next trap from user mode after `PTRACE_ATTACH`, and at the entry and exit
+//! of syscalls while resumed by `PTRACE_SYSCALL`. Its tracer learns of the stop by `wait4`,
+//! then reads and writes its memory and the trap frame of the stopped thread,
+//! which stays on the kernel stack of the thread until the tracer resumes it.
+//!
+//! There are no signal handlers yet. A signal sent to a tracee by `kill`, except SIGKILL,
+//! makes it stop with the signal instead, and the tracer may pass it on to kill the tracee
+//! when resuming it.
+//! The other threads of the process are held as well at their next return to user mode,
+//! e.g. the next timer interrupt, until resumed. Only the registers of the thread
+//! which stops are seen by the tracer.
+
+use super::*;
+use crate::arch::interrupt::TrapFrame;
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::syscall::SysError;
+use alloc::sync::Weak;
+
+/// Trace or breakpoint trap
+pub const SIGTRAP: usize = 5;
+/// Stop the process
+#[cfg(not(target_arch = "mips"))]
+pub const SIGSTOP: usize = 19;
+#[cfg(target_arch = "mips")]
+pub const SIGSTOP: usize = 23;
+
+/// Set in the signal of syscall stops with `PTRACE_O_TRACESYSGOOD`
+const SYSCALL_STOP_BIT: usize = 0x80;
+
+/// The state of a traced process
+pub struct Tracee {
+    /// Never locked while holding the tracee, as the tracer locks its tracees
+    pub tracer: Weak<Mutex<Process>>,
+    /// Stop at the next syscall entry or exit, set by `PTRACE_SYSCALL`
+    pub stop_at_syscall: bool,
+    /// Report syscall stops with `SIGTRAP | 0x80`, set by `PTRACE_O_TRACESYSGOOD`
+    pub syscall_good: bool,
+    /// Stop with this signal at the next syscall or timer interrupt from user mode
+    pub pending_stop: Option<usize>,
+    /// The signal of the current stop, until reported to the tracer by `wait4`
+    pub unreported: Option<usize>,
+    /// The stopped thread, set while the process is `Stopped`
+    pub stopped: Option<Stop>,
+    resumed: Arc<Condvar>,
+}
+
+/// A thread stopped for its tracer
+pub struct Stop {
+    pub tid: Tid,
+    /// The syscall at whose entry or exit it stops
+    pub syscall: Option<usize>,
+    /// Address of the trap frame, on the kernel stack of the stopped thread
+    tf: usize,
+}
+
+impl Tracee {
+    pub fn new(tracer: Weak<Mutex<Process>>) -> Self {
+        Tracee {
+            tracer,
+            stop_at_syscall: false,
+            syscall_good: false,
+            pending_stop: None,
+            unreported: None,
+            stopped: None,
+            resumed: Arc::new(Condvar::new()),
+        }
+    }
+}
+
+impl Stop {
+    /// The trap frame of the stopped thread, to be returned to user mode when resumed.
+    /// The tracee must be locked, so it is not resumed meanwhile.
+    pub fn tf(&mut self) -> &mut TrapFrame {
+        unsafe { &mut *(self.tf as *mut TrapFrame) }
+    }
+}
+
+/// Stop the current thread at the trap `tf` with `sig` until the tracer resumes it.
+/// Nothing is done if the process is not traced.
+pub fn stop(tf: &mut TrapFrame, sig: usize, syscall: Option<usize>) {
+    let mut proc = process();
+    let resumed = match &proc.ptrace {
+        Some(tracee) => tracee.resumed.clone(),
+        None => return,
+    };
+    // one thread stops at a time
+    while proc.state == ProcessState::Stopped {
+        proc = resumed.wait(proc);
+    }
+    if proc.state != ProcessState::Running {
+        return;
+    }
+    let tracer = match proc.ptrace.as_mut() {
+        Some(tracee) => {
+            tracee.pending_stop = None;
+            tracee.unreported = Some(sig);
+            tracee.stopped = Some(Stop {
+                tid: processor().tid(),
+                syscall,
+                tf: tf as *mut TrapFrame as usize,
+            });
+            tracee.tracer.upgrade()
+        }
+        // detached meanwhile
+        None => return,
+    };
+    proc.state = ProcessState::Stopped;
+    info!("ptrace: {} stopped with sig {}", proc.pid, sig);
+    drop(proc);
+
+    // wake up `wait4` of the tracer, as SIGCHLD would do
+    if let Some(tracer) = tracer {
+        tracer.lock().child_exit.notify_all();
+    }
+    let mut proc = process();
+    while proc.state == ProcessState::Stopped {
+        proc = resumed.wait(proc);
+    }
+}
+
+/// Hold the current thread while another thread of its process is stopped
+pub fn wait_if_stopped() {
+    let mut proc = process();
+    let resumed = match &proc.ptrace {
+        Some(tracee) => tracee.resumed.clone(),
+        None => return,
+    };
+    while proc.state == ProcessState::Stopped {
+        proc = resumed.wait(proc);
+    }
+}
+
+/// Stop for `PTRACE_ATTACH`, `kill` or after `exec`, if pending.
+/// Otherwise wait while another thread is stopped.
+pub fn stop_if_pending(tf: &mut TrapFrame) {
+    wait_if_stopped();
+    let pending = process()
+        .ptrace
+        .as_ref()
+        .and_then(|tracee| tracee.pending_stop);
+    if let Some(sig) = pending {
+        stop(tf, sig, None);
+    }
+}
+
+/// Stop at the entry of syscall `id`, if the tracer asks to
+pub fn syscall_enter(tf: &mut TrapFrame, id: usize) {
+    stop_if_pending(tf);
+    if let Some(sig) = syscall_stop_signal() {
+        stop(tf, sig, Some(id));
+    }
+}
+
+/// Stop at the exit of syscall `id` returning `ret`, if the tracer asks to.
+/// Return the value to return, which may be changed by the tracer.
+pub fn syscall_exit(tf: &mut TrapFrame, id: usize, ret: isize) -> isize {
+    let mut ret = ret;
+    if let Some(sig) = syscall_stop_signal() {
+        tf.set_syscall_ret(ret);
+        stop(tf, sig, Some(id));
+        ret = tf.syscall_ret();
+    }
+    stop_if_pending(tf);
+    ret
+}
+
+fn syscall_stop_signal() -> Option<usize> {
+    let proc = process();
+    let tracee = proc.ptrace.as_ref()?;
+    if !tracee.stop_at_syscall {
+        return None;
+    }
+    if tracee.syscall_good {
+        Some(SIGTRAP | SYSCALL_STOP_BIT)
+    } else {
+        Some(SIGTRAP)
+    }
+}
+
+/// Resume the stopped `tracee`. It stops again at the next syscall entry or exit
+/// if `syscall`, or after one instruction if `single_step` where the arch supports it.
+pub fn resume(tracee: &mut Process, syscall: bool, single_step: bool) -> Result<(), SysError> {
+    if tracee.state != ProcessState::Stopped {
+        return Err(SysError::ESRCH);
+    }
+    let state = tracee.ptrace.as_mut().ok_or(SysError::ESRCH)?;
+    let stop = state.stopped.as_mut().ok_or(SysError::ESRCH)?;
+    set_single_step(stop.tf(), single_step)?;
+    state.stopped = None;
+    state.stop_at_syscall = syscall;
+    state.unreported = None;
+    tracee.state = ProcessState::Running;
+    state.resumed.notify_all();
+    Ok(())
+}
+
+/// Let the trap flag raise a debug exception after the next instruction
+#[cfg(target_arch = "x86_64")]
+fn set_single_step(tf: &mut TrapFrame, single_step: bool) -> Result<(), SysError> {
+    const TRAP_FLAG: usize = 1 << 8;
+    if single_step {
+        tf.rflags |= TRAP_FLAG;
+    } else {
+        tf.rflags &= !TRAP_FLAG;
+    }
+    Ok(())
+}
+
+/// There is no single step without debug registers set up on other archs
+#[cfg(not(target_arch = "x86_64"))]
+fn set_single_step(_tf: &mut TrapFrame, single_step: bool) -> Result<(), SysError> {
+    if single_step {
+        return Err(SysError::EIO);
+    }
+    Ok(())
+}
+
+/// Stop tracing `tracee`, and resume it if stopped
+pub fn detach(tracee: &mut Process) {
+    let mut state = match tracee.ptrace.take() {
+        Some(state) => state,
+        None => return,
+    };
+    if let Some(stop) = state.stopped.as_mut() {
+        set_single_step(stop.tf(), false).ok();
+    }
+    if tracee.state == ProcessState::Stopped {
+        tracee.state = ProcessState::Running;
+    }
+    state.resumed.notify_all();
+}
+
+/// Take a stop or an exit of the tracees of `tracer` not reported yet,
+/// from the process `pid`, or any if `None`.
+/// Return whether there is such a tracee, and the pid and wait status taken.
+/// The exit of a child is left to be reaped as usual.
+pub fn wait_tracees(tracer: &mut Process, pid: Option<usize>) -> (bool, Option<(usize, i32)>) {
+    let mut found = false;
+    let mut exited = None;
+    let mut result = None;
+    for (index, weak) in tracer.tracees.iter().enumerate() {
+        let tracee = match weak.upgrade() {
+            Some(tracee) => tracee,
+            None => continue,
+        };
+        let mut tracee_lock = tracee.lock();
+        let tracee_pid = tracee_lock.pid.get();
+        if pid.map_or(false, |pid| pid != tracee_pid) {
+            continue;
+        }
+        if let ProcessState::Zombie(exit_code) = tracee_lock.state {
+            let child = tracer
+                .children
+                .iter()
+                .any(|child| Arc::ptr_eq(child, &tracee));
+            exited = Some(index);
+            if !child {
+                found = true;
+                result = Some((tracee_pid, exit_code as i32));
+            }
+            break;
+        }
+        let state = match tracee_lock.ptrace.as_mut() {
+            Some(state) => state,
+            None => continue,
+        };
+        found = true;
+        if let Some(sig) = state.unreported.take() {
+            // like WIFSTOPPED
+            result = Some((tracee_pid, ((sig << 8) | 0x7f) as i32));
+            break;
+        }
+    }
+    if let Some(index) = exited {
+        tracer.tracees.remove(index);
+    }
+    // forget the tracees reaped
+    tracer.tracees.retain(|weak| weak.upgrade().is_some());
+    (found, result)
+}
@@ -0,0 +1,91 @@
+//! Per-process resource limits
+//!
+//! Ref: [http://man7.org/linux/man-pages/man2/getrlimit.2.html]
+
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_FSIZE: usize = 1;
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_RSS: usize = 5;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIMIT_LOCKS: usize = 10;
+pub const RLIMIT_SIGPENDING: usize = 11;
+pub const RLIMIT_MSGQUEUE: usize = 12;
+pub const RLIMIT_NICE: usize = 13;
+pub const RLIMIT_RTPRIO: usize = 14;
+pub const RLIMIT_RTTIME: usize = 15;
+pub const RLIM_NLIMITS: usize = 16;
+
+pub const RLIM_INFINITY: u64 = !0;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RLimit {
+    pub cur: u64, // soft limit
+    pub max: u64, // hard limit
+}
+
+impl RLimit {
+    const fn new(cur: u64, max: u64) -> Self {
+        RLimit { cur, max }
+    }
+
+    const fn infinity() -> Self {
+        RLimit::new(RLIM_INFINITY, RLIM_INFINITY)
+    }
+
+    /// Whether `value` goes beyond the soft limit
+    pub fn exceeded_by(&self, value: usize) -> bool {
+        self.cur != RLIM_INFINITY && value as u64 > self.cur
+    }
+}
+
+/// The limits table of a process, inherited by its children and kept across exec
+#[derive(Debug, Copy, Clone)]
+pub struct ResourceLimits([RLimit; RLIM_NLIMITS]);
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        let mut limits = [RLimit::infinity(); RLIM_NLIMITS];
+        limits[RLIMIT_STACK] = RLimit::new(USER_STACK_DEFAULT_SIZE as u64, RLIM_INFINITY);
+        limits[RLIMIT_CORE] = RLimit::new(0, RLIM_INFINITY);
+        limits[RLIMIT_NPROC] = RLimit::new(1024, 1024);
+        limits[RLIMIT_NOFILE] = RLimit::new(1024, 4096);
+        limits[RLIMIT_MEMLOCK] = RLimit::new(64 * 1024, 64 * 1024);
+        limits[RLIMIT_NICE] = RLimit::new(0, 0);
+        limits[RLIMIT_RTPRIO] = RLimit::new(0, 0);
+        ResourceLimits(limits)
+    }
+}
+
+impl ResourceLimits {
+    /// Get the limit of `resource`, which must be less than `RLIM_NLIMITS`
+    pub fn get(&self, resource: usize) -> RLimit {
+        self.0[resource]
+    }
+
+    /// Set the limit of `resource`, which must be less than `RLIM_NLIMITS`
+    pub fn set(&mut self, resource: usize, limit: RLimit) {
+        self.0[resource] = limit;
+    }
+
+    /// The maximum size the user stack can grow to
+    pub fn stack_size(&self) -> usize {
+        use rcore_memory::PAGE_SIZE;
+        // leave the address space below the stack to other mappings
+        // even if RLIMIT_STACK is unlimited
+        let cur = self.get(RLIMIT_STACK).cur;
+        if cur == RLIM_INFINITY || cur as usize > USER_STACK_MAX_SIZE {
+            USER_STACK_MAX_SIZE
+        } else {
+            (cur as usize).max(PAGE_SIZE) & !(PAGE_SIZE - 1)
+        }
+    }
+}
+
+const USER_STACK_DEFAULT_SIZE: usize = 8 * 1024 * 1024;
+const USER_STACK_MAX_SIZE: usize = 64 * 1024 * 1024;
@@ -0,0 +1,313 @@
+//! Schedulers of threads
+//!
+//! Real-time threads run before others, see `rt`, and each CPU has its own run queue, see `smp`.
+//! Boot with `sched=stride` or `sched=cfs` in the kernel cmdline to schedule others fairly,
+//! weighted by nice values, instead of by round robin which ignores priorities.
+//!
+//! The priority of a thread given to the scheduler is its nice value plus 20,
+//! so 0 is the highest and 39 the lowest. Each nice level changes the share
+//! of CPU time by about 10%, by the weights from Linux.
+
+pub use self::rt::*;
+pub use self::smp::*;
+use super::{scheduler::RRScheduler, Scheduler, Tid};
+use crate::drivers::CMDLINE;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use log::*;
+
+mod rt;
+mod smp;
+
+pub const NICE_MIN: isize = -20;
+pub const NICE_MAX: isize = 19;
+
+/// The weight of nice 0
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Weight of each priority, i.e. nice -20..=19
+const PRIORITY_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// The priority of a thread with `nice`, clamped into the valid range
+pub fn nice_to_priority(nice: isize) -> u8 {
+    (nice.max(NICE_MIN).min(NICE_MAX) - NICE_MIN) as u8
+}
+
+fn weight(priority: u8) -> u64 {
+    PRIORITY_TO_WEIGHT[(priority as usize).min(PRIORITY_TO_WEIGHT.len() - 1)]
+}
+
+lazy_static! {
+    /// The scheduler of the thread pool.
+    /// Real-time threads run first, then others by the scheduler chosen on the cmdline.
+    pub static ref SCHEDULER: SmpScheduler = SmpScheduler::new(fair_scheduler());
+}
+
+fn fair_scheduler() -> fn() -> Box<dyn Scheduler + Send + Sync> {
+    let cmdline = CMDLINE.read();
+    match cmdline.split(' ').find(|arg| arg.starts_with("sched=")) {
+        Some("sched=stride") => {
+            info!("process: use stride scheduler");
+            || Box::new(StrideScheduler::new(5))
+        }
+        Some("sched=cfs") => {
+            info!("process: use CFS scheduler");
+            || Box::new(CfsScheduler::new())
+        }
+        arg => {
+            if let Some(arg) = arg.filter(|&arg| arg != "sched=rr") {
+                warn!("unknown scheduler {}, use round robin", arg);
+            }
+            // NOTE: max_time_slice <= 5 to ensure 'priority' test pass
+            || Box::new(RRScheduler::new(5))
+        }
+    }
+}
+
+/// Stride scheduling: the ready thread with the least pass runs,
+/// and its pass then advances by its stride, inversely proportional to its weight.
+pub struct StrideScheduler {
+    inner: Mutex<StrideInner>,
+}
+
+struct StrideInner {
+    max_time_slice: usize,
+    infos: Vec<StrideInfo>,
+    /// Ready threads ordered by pass
+    queue: BTreeSet<(u64, Tid)>,
+    /// Pass of the last thread to run, where a new thread starts
+    current_pass: u64,
+}
+
+#[derive(Debug, Clone)]
+struct StrideInfo {
+    queued: bool,
+    rest_slice: usize,
+    pass: u64,
+    priority: u8,
+}
+
+impl Default for StrideInfo {
+    fn default() -> Self {
+        StrideInfo {
+            queued: false,
+            rest_slice: 0,
+            pass: 0,
+            priority: nice_to_priority(0),
+        }
+    }
+}
+
+/// Stride of the heaviest thread is still large enough to be precise
+const BIG_STRIDE: u64 = 1 << 30;
+
+impl StrideScheduler {
+    pub fn new(max_time_slice: usize) -> Self {
+        StrideScheduler {
+            inner: Mutex::new(StrideInner {
+                max_time_slice,
+                infos: Vec::new(),
+                queue: BTreeSet::new(),
+                current_pass: 0,
+            }),
+        }
+    }
+}
+
+impl StrideInner {
+    fn info(&mut self, tid: Tid) -> &mut StrideInfo {
+        if tid >= self.infos.len() {
+            self.infos.resize(tid + 1, StrideInfo::default());
+        }
+        &mut self.infos[tid]
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn push(&self, tid: Tid) {
+        let mut inner = self.inner.lock();
+        let (current_pass, max_time_slice) = (inner.current_pass, inner.max_time_slice);
+        let info = inner.info(tid);
+        if info.queued {
+            return;
+        }
+        // a thread which slept long can not take over the CPU
+        info.pass = info.pass.max(current_pass);
+        info.queued = true;
+        if info.rest_slice == 0 {
+            info.rest_slice = max_time_slice;
+        }
+        let pass = info.pass;
+        inner.queue.insert((pass, tid));
+    }
+
+    fn pop(&self, _cpu_id: usize) -> Option<Tid> {
+        let mut inner = self.inner.lock();
+        let &(pass, tid) = inner.queue.iter().next()?;
+        inner.queue.remove(&(pass, tid));
+        inner.current_pass = pass;
+        let info = inner.info(tid);
+        info.queued = false;
+        info.pass += BIG_STRIDE / weight(info.priority);
+        Some(tid)
+    }
+
+    fn tick(&self, current_tid: Tid) -> bool {
+        let mut inner = self.inner.lock();
+        let info = inner.info(current_tid);
+        info.rest_slice = info.rest_slice.saturating_sub(1);
+        info.rest_slice == 0
+    }
+
+    fn set_priority(&self, tid: Tid, priority: u8) {
+        self.inner.lock().info(tid).priority = priority;
+    }
+
+    fn remove(&self, tid: Tid) {
+        let mut inner = self.inner.lock();
+        let info = inner.info(tid);
+        if info.queued {
+            info.queued = false;
+            let pass = info.pass;
+            inner.queue.remove(&(pass, tid));
+        }
+    }
+}
+
+/// Like the CFS of Linux: the ready thread with the least virtual runtime runs.
+/// The virtual runtime grows slower for a heavier thread, so it runs longer,
+/// and every ready thread runs once in a scheduling period.
+pub struct CfsScheduler {
+    inner: Mutex<CfsInner>,
+}
+
+struct CfsInner {
+    infos: Vec<CfsInfo>,
+    /// Ready threads ordered by virtual runtime
+    queue: BTreeSet<(u64, Tid)>,
+    /// Never decreases, where new and waking threads are placed
+    min_vruntime: u64,
+    /// Total weight of the ready threads
+    load: u64,
+}
+
+#[derive(Debug, Clone)]
+struct CfsInfo {
+    queued: bool,
+    vruntime: u64,
+    /// Ticks since the thread was picked
+    ran: usize,
+    priority: u8,
+}
+
+impl Default for CfsInfo {
+    fn default() -> Self {
+        CfsInfo {
+            queued: false,
+            vruntime: 0,
+            ran: 0,
+            priority: nice_to_priority(0),
+        }
+    }
+}
+
+/// Ticks in which every ready thread runs once, if there are not too many
+const SCHED_PERIOD: u64 = 20;
+/// A thread runs at least this many ticks once picked
+const MIN_GRANULARITY: u64 = 1;
+/// Virtual runtime of a tick at nice 0
+const TICK_VRUNTIME: u64 = 1 << 20;
+
+impl CfsScheduler {
+    pub fn new() -> Self {
+        CfsScheduler {
+            inner: Mutex::new(CfsInner {
+                infos: Vec::new(),
+                queue: BTreeSet::new(),
+                min_vruntime: 0,
+                load: 0,
+            }),
+        }
+    }
+}
+
+impl CfsInner {
+    fn info(&mut self, tid: Tid) -> &mut CfsInfo {
+        if tid >= self.infos.len() {
+            self.infos.resize(tid + 1, CfsInfo::default());
+        }
+        &mut self.infos[tid]
+    }
+}
+
+impl Scheduler for CfsScheduler {
+    fn push(&self, tid: Tid) {
+        let mut inner = self.inner.lock();
+        // credit a waking thread half a period, but no more
+        let start = inner
+            .min_vruntime
+            .saturating_sub(SCHED_PERIOD * TICK_VRUNTIME / 2);
+        let info = inner.info(tid);
+        if info.queued {
+            return;
+        }
+        info.vruntime = info.vruntime.max(start);
+        info.queued = true;
+        let (vruntime, weight) = (info.vruntime, weight(info.priority));
+        inner.queue.insert((vruntime, tid));
+        inner.load += weight;
+    }
+
+    fn pop(&self, _cpu_id: usize) -> Option<Tid> {
+        let mut inner = self.inner.lock();
+        let &(vruntime, tid) = inner.queue.iter().next()?;
+        inner.queue.remove(&(vruntime, tid));
+        inner.min_vruntime = inner.min_vruntime.max(vruntime);
+        let info = inner.info(tid);
+        info.queued = false;
+        info.ran = 0;
+        let weight = weight(info.priority);
+        inner.load -= weight;
+        Some(tid)
+    }
+
+    fn tick(&self, current_tid: Tid) -> bool {
+        let mut inner = self.inner.lock();
+        let load = inner.load;
+        let info = inner.info(current_tid);
+        let weight = weight(info.priority);
+        info.vruntime += TICK_VRUNTIME * NICE_0_WEIGHT / weight;
+        info.ran += 1;
+        // the share of the period for this thread
+        let slice = (SCHED_PERIOD * weight / (load + weight)).max(MIN_GRANULARITY);
+        info.ran as u64 >= slice
+    }
+
+    fn set_priority(&self, tid: Tid, priority: u8) {
+        let mut inner = self.inner.lock();
+        let info = inner.info(tid);
+        let (queued, old_weight) = (info.queued, weight(info.priority));
+        info.priority = priority;
+        if queued {
+            inner.load = inner.load - old_weight + weight(priority);
+        }
+    }
+
+    fn remove(&self, tid: Tid) {
+        let mut inner = self.inner.lock();
+        let info = inner.info(tid);
+        if info.queued {
+            info.queued = false;
+            let (vruntime, weight) = (info.vruntime, weight(info.priority));
+            inner.queue.remove(&(vruntime, tid));
+            inner.load -= weight;
+        }
+    }
+}
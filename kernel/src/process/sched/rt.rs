@@ -0,0 +1,168 @@
+//! Real-time scheduling policies `SCHED_FIFO` and `SCHED_RR`
+//!
+//! A ready real-time thread always runs before other threads, and before real-time threads
+//! of lower priority. Within a priority, a `SCHED_FIFO` thread runs until it blocks or yields,
+//! while `SCHED_RR` threads take turns every `RR_TIMESLICE`.
+//!
+//! Like Linux, real-time threads may only use `RT_RUNTIME` ticks of every `RT_PERIOD`
+//! on each CPU, so a busy loop can not starve the others for ever.
+
+use crate::consts::USEC_PER_TICK;
+use crate::process::{Scheduler, Tid};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+pub const SCHED_NORMAL: usize = 0;
+pub const SCHED_FIFO: usize = 1;
+pub const SCHED_RR: usize = 2;
+pub const SCHED_BATCH: usize = 3;
+pub const SCHED_IDLE: usize = 5;
+
+pub const RT_PRIO_MIN: usize = 1;
+pub const RT_PRIO_MAX: usize = 99;
+
+/// Ticks a `SCHED_RR` thread runs before the next one of its priority, 100ms like Linux
+pub const RR_TIMESLICE: usize = 100_000 / USEC_PER_TICK;
+/// Length of the period of RT throttling, 1s like Linux
+const RT_PERIOD: usize = 1_000_000 / USEC_PER_TICK;
+/// Ticks real-time threads may run in a period, the rest is left for the others
+const RT_RUNTIME: usize = RT_PERIOD * 95 / 100;
+
+/// The scheduling policy of a thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Normal,
+    Batch,
+    Idle,
+    Fifo(u8),
+    RoundRobin(u8),
+}
+
+impl Policy {
+    /// Make a policy from the arguments of `sched_setscheduler`.
+    /// Real-time policies need a priority in `RT_PRIO_MIN..=RT_PRIO_MAX`, others 0.
+    pub fn new(policy: usize, priority: usize) -> Option<Self> {
+        let rt_priority = priority as u8;
+        match policy {
+            SCHED_FIFO | SCHED_RR if priority < RT_PRIO_MIN || priority > RT_PRIO_MAX => None,
+            SCHED_FIFO => Some(Policy::Fifo(rt_priority)),
+            SCHED_RR => Some(Policy::RoundRobin(rt_priority)),
+            _ if priority != 0 => None,
+            SCHED_NORMAL => Some(Policy::Normal),
+            SCHED_BATCH => Some(Policy::Batch),
+            SCHED_IDLE => Some(Policy::Idle),
+            _ => None,
+        }
+    }
+
+    /// The policy number for `sched_getscheduler`
+    pub fn policy(&self) -> usize {
+        match self {
+            Policy::Normal => SCHED_NORMAL,
+            Policy::Batch => SCHED_BATCH,
+            Policy::Idle => SCHED_IDLE,
+            Policy::Fifo(_) => SCHED_FIFO,
+            Policy::RoundRobin(_) => SCHED_RR,
+        }
+    }
+
+    /// The real-time priority, 0 for other policies
+    pub fn rt_priority(&self) -> usize {
+        match *self {
+            Policy::Fifo(priority) | Policy::RoundRobin(priority) => priority as usize,
+            _ => 0,
+        }
+    }
+
+    pub fn is_rt(&self) -> bool {
+        self.rt_priority() != 0
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Normal
+    }
+}
+
+/// Ready real-time threads of all CPUs
+pub(super) struct RtQueue {
+    /// Threads of each priority
+    queues: Vec<VecDeque<Tid>>,
+    /// Number of threads in `queues`
+    ready: usize,
+    /// Ticks of all CPUs passed in the current period of RT throttling
+    period_ticks: usize,
+    /// Ticks spent by real-time threads in the current period
+    rt_ticks: usize,
+}
+
+impl RtQueue {
+    pub fn new() -> Self {
+        RtQueue {
+            queues: (0..=RT_PRIO_MAX).map(|_| VecDeque::new()).collect(),
+            ready: 0,
+            period_ticks: 0,
+            rt_ticks: 0,
+        }
+    }
+
+    /// Add a thread to the tail of its priority, or to the head if it was preempted
+    pub fn push(&mut self, tid: Tid, priority: usize, preempted: bool) {
+        let queue = &mut self.queues[priority];
+        if preempted {
+            queue.push_front(tid);
+        } else {
+            queue.push_back(tid);
+        }
+        self.ready += 1;
+    }
+
+    pub fn remove(&mut self, tid: Tid, priority: usize) {
+        let queue = &mut self.queues[priority];
+        if let Some(index) = queue.iter().position(|&t| t == tid) {
+            queue.remove(index);
+            self.ready -= 1;
+        }
+    }
+
+    /// The highest priority of the ready threads which are `allowed`
+    pub fn highest_ready(&self, allowed: impl Fn(Tid) -> bool) -> Option<usize> {
+        if self.ready == 0 {
+            return None;
+        }
+        (RT_PRIO_MIN..=RT_PRIO_MAX)
+            .rev()
+            .find(|&priority| self.queues[priority].iter().any(|&tid| allowed(tid)))
+    }
+
+    /// Take the first thread of the highest priority which is `allowed`
+    pub fn pop(&mut self, allowed: impl Fn(Tid) -> bool) -> Option<Tid> {
+        let priority = self.highest_ready(&allowed)?;
+        let queue = &mut self.queues[priority];
+        let index = queue.iter().position(|&tid| allowed(tid)).unwrap();
+        self.ready -= 1;
+        queue.remove(index)
+    }
+
+    pub fn has_ready(&self, priority: usize) -> bool {
+        !self.queues[priority].is_empty()
+    }
+
+    /// Account a tick of one of `cpus` CPUs, which runs a real-time thread if `rt`
+    pub fn tick(&mut self, rt: bool, cpus: usize) {
+        self.period_ticks += 1;
+        if self.period_ticks >= RT_PERIOD * cpus {
+            self.period_ticks = 0;
+            self.rt_ticks = 0;
+        }
+        if rt {
+            self.rt_ticks += 1;
+        }
+    }
+
+    /// Whether real-time threads used up their time of `cpus` CPUs in this period
+    pub fn throttled(&self, cpus: usize) -> bool {
+        self.rt_ticks >= RT_RUNTIME * cpus
+    }
+}
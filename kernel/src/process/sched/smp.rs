@@ -0,0 +1,342 @@
+//! Per-CPU run queues
+//!
+//! Each CPU has its own instance of the fair scheduler. A thread getting ready is put
+//! on the least loaded CPU it is allowed to run on, preferring the one it ran on last.
+//! An idle CPU steals a thread from the busiest one, and every `BALANCE_INTERVAL` ticks
+//! a CPU pulls a thread from the busiest one if that has at least two more.
+//!
+//! Real-time threads are in one queue shared by all CPUs, see `rt`.
+
+use super::rt::*;
+use crate::consts::MAX_CPU_NUM;
+use crate::process::{Scheduler, Tid};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Bit `i` is set for CPU `i`
+pub type CpuMask = u64;
+
+/// All CPUs the kernel supports
+pub const CPU_MASK_ALL: CpuMask = !0 >> (64 - MAX_CPU_NUM);
+
+/// Ticks between two load balancing on each CPU
+const BALANCE_INTERVAL: usize = 10;
+
+/// Runs ready real-time threads by priority,
+/// and the other threads by the fair scheduler of each CPU.
+pub struct SmpScheduler {
+    inner: Mutex<SmpInner>,
+    fair: Vec<Box<dyn Scheduler + Send + Sync>>,
+}
+
+struct SmpInner {
+    infos: Vec<ThreadInfo>,
+    cpus: Vec<CpuInfo>,
+    rt: RtQueue,
+}
+
+#[derive(Debug, Clone)]
+struct ThreadInfo {
+    policy: Policy,
+    affinity: CpuMask,
+    /// The CPU it runs on, or whose fair scheduler it is queued in
+    cpu: usize,
+    /// In the real-time queue for a real-time policy, otherwise in the fair scheduler
+    queued: bool,
+    /// Taken from the fair scheduler of `cpu` when it ran last
+    from_fair: bool,
+    /// Preempted by a thread of higher priority, so it runs first in its priority again
+    preempted: bool,
+    rest_slice: usize,
+}
+
+impl Default for ThreadInfo {
+    fn default() -> Self {
+        ThreadInfo {
+            policy: Policy::Normal,
+            affinity: CPU_MASK_ALL,
+            cpu: 0,
+            queued: false,
+            from_fair: false,
+            preempted: false,
+            rest_slice: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct CpuInfo {
+    /// Started to run threads
+    online: bool,
+    /// Number of threads in its fair scheduler
+    ready: usize,
+    ticks: usize,
+}
+
+impl SmpScheduler {
+    /// Make a scheduler with a fair scheduler from `new_fair` for each CPU
+    pub fn new(new_fair: fn() -> Box<dyn Scheduler + Send + Sync>) -> Self {
+        SmpScheduler {
+            inner: Mutex::new(SmpInner {
+                infos: Vec::new(),
+                cpus: vec![CpuInfo::default(); MAX_CPU_NUM],
+                rt: RtQueue::new(),
+            }),
+            fair: (0..MAX_CPU_NUM).map(|_| new_fair()).collect(),
+        }
+    }
+
+    /// Get the policy of thread `tid`
+    pub fn policy(&self, tid: Tid) -> Policy {
+        self.inner.lock().info(tid).policy
+    }
+
+    /// Set the policy of thread `tid`, moving it between the queues if ready
+    pub fn set_policy(&self, tid: Tid, policy: Policy) {
+        let mut inner = self.inner.lock();
+        let queued = inner.info(tid).queued;
+        if queued {
+            self.dequeue(&mut inner, tid);
+        }
+        let info = inner.info(tid);
+        info.policy = policy;
+        info.rest_slice = 0;
+        info.preempted = false;
+        if queued {
+            self.enqueue(&mut inner, tid);
+        }
+    }
+
+    /// Get the CPUs thread `tid` may run on
+    pub fn affinity(&self, tid: Tid) -> CpuMask {
+        self.inner.lock().info(tid).affinity
+    }
+
+    /// Set the CPUs thread `tid` may run on, moving it if ready on another CPU.
+    /// A running thread moves at the next tick.
+    pub fn set_affinity(&self, tid: Tid, affinity: CpuMask) {
+        let mut inner = self.inner.lock();
+        let info = inner.info(tid);
+        let moved = info.queued && !info.policy.is_rt() && affinity & (1 << info.cpu) == 0;
+        if moved {
+            self.dequeue(&mut inner, tid);
+        }
+        inner.info(tid).affinity = affinity & CPU_MASK_ALL;
+        if moved {
+            self.enqueue(&mut inner, tid);
+        }
+    }
+
+    /// The CPUs running threads
+    pub fn online_cpus(&self) -> CpuMask {
+        let inner = self.inner.lock();
+        (0..MAX_CPU_NUM)
+            .filter(|&cpu| inner.cpus[cpu].online)
+            .fold(0, |mask, cpu| mask | 1 << cpu)
+    }
+
+    fn enqueue(&self, inner: &mut SmpInner, tid: Tid) {
+        let info = inner.info(tid);
+        info.queued = true;
+        let policy = info.policy;
+        if policy.is_rt() {
+            if info.rest_slice == 0 {
+                info.rest_slice = RR_TIMESLICE;
+            }
+            let preempted = info.preempted;
+            info.preempted = false;
+            inner.rt.push(tid, policy.rt_priority(), preempted);
+            return;
+        }
+        let cpu = inner.select_cpu(tid);
+        inner.info(tid).cpu = cpu;
+        inner.cpus[cpu].ready += 1;
+        self.fair[cpu].push(tid);
+    }
+
+    fn dequeue(&self, inner: &mut SmpInner, tid: Tid) {
+        let info = inner.info(tid);
+        info.queued = false;
+        let (policy, cpu) = (info.policy, info.cpu);
+        if policy.is_rt() {
+            inner.rt.remove(tid, policy.rt_priority());
+        } else {
+            inner.cpus[cpu].ready -= 1;
+            self.fair[cpu].remove(tid);
+        }
+    }
+
+    /// Move a ready thread from the fair scheduler of CPU `from` to CPU `to`,
+    /// if the thread is allowed to run there
+    fn migrate(&self, inner: &mut SmpInner, from: usize, to: usize) {
+        let tid = match self.fair[from].pop(from) {
+            Some(tid) => tid,
+            None => return,
+        };
+        inner.cpus[from].ready -= 1;
+        let info = inner.info(tid);
+        let cpu = if info.affinity & (1 << to) != 0 {
+            to
+        } else {
+            from
+        };
+        info.cpu = cpu;
+        inner.cpus[cpu].ready += 1;
+        self.fair[cpu].push(tid);
+    }
+}
+
+impl SmpInner {
+    fn info(&mut self, tid: Tid) -> &mut ThreadInfo {
+        if tid >= self.infos.len() {
+            self.infos.resize(tid + 1, ThreadInfo::default());
+        }
+        &mut self.infos[tid]
+    }
+
+    fn online_count(&self) -> usize {
+        self.cpus.iter().filter(|cpu| cpu.online).count().max(1)
+    }
+
+    /// The least loaded online CPU thread `tid` may run on, preferring the last one
+    fn select_cpu(&self, tid: Tid) -> usize {
+        let info = &self.infos[tid];
+        (0..MAX_CPU_NUM)
+            .filter(|&cpu| info.affinity & (1 << cpu) != 0 && self.cpus[cpu].online)
+            .min_by_key(|&cpu| (self.cpus[cpu].ready, cpu != info.cpu))
+            .unwrap_or_else(|| {
+                // no allowed CPU is online yet
+                if info.affinity & (1 << info.cpu) != 0 {
+                    info.cpu
+                } else {
+                    info.affinity.trailing_zeros() as usize
+                }
+            })
+    }
+
+    /// The other CPU with the most ready threads, if any
+    fn busiest(&self, cpu: usize) -> Option<usize> {
+        (0..MAX_CPU_NUM)
+            .filter(|&other| other != cpu && self.cpus[other].ready != 0)
+            .max_by_key(|&other| self.cpus[other].ready)
+    }
+
+    /// Take the real-time thread to run on `cpu`
+    fn pop_rt(&mut self, cpu: usize) -> Option<Tid> {
+        let infos = &self.infos;
+        let tid = self.rt.pop(|tid| infos[tid].affinity & (1 << cpu) != 0)?;
+        let info = &mut self.infos[tid];
+        info.queued = false;
+        info.from_fair = false;
+        info.cpu = cpu;
+        Some(tid)
+    }
+
+    /// The highest priority of the ready real-time threads allowed on `cpu`
+    fn highest_rt(&self, cpu: usize) -> Option<usize> {
+        let infos = &self.infos;
+        self.rt
+            .highest_ready(|tid| infos[tid].affinity & (1 << cpu) != 0)
+    }
+}
+
+impl Scheduler for &'static SmpScheduler {
+    fn push(&self, tid: Tid) {
+        let mut inner = self.inner.lock();
+        if !inner.info(tid).queued {
+            self.enqueue(&mut inner, tid);
+        }
+    }
+
+    fn pop(&self, cpu_id: usize) -> Option<Tid> {
+        let mut inner = self.inner.lock();
+        inner.cpus[cpu_id].online = true;
+        let cpus = inner.online_count();
+        if !inner.rt.throttled(cpus) {
+            if let Some(tid) = inner.pop_rt(cpu_id) {
+                return Some(tid);
+            }
+        }
+        if inner.cpus[cpu_id].ready == 0 {
+            if let Some(busiest) = inner.busiest(cpu_id) {
+                self.migrate(&mut inner, busiest, cpu_id);
+            }
+        }
+        if let Some(tid) = self.fair[cpu_id].pop(cpu_id) {
+            inner.cpus[cpu_id].ready -= 1;
+            let info = inner.info(tid);
+            info.queued = false;
+            info.from_fair = true;
+            info.cpu = cpu_id;
+            return Some(tid);
+        }
+        // nothing else to run, the throttling does not matter
+        inner.pop_rt(cpu_id)
+    }
+
+    fn tick(&self, current_tid: Tid) -> bool {
+        let mut inner = self.inner.lock();
+        let cpus = inner.online_count();
+        let info = inner.info(current_tid).clone();
+        let cpu = info.cpu;
+        inner.rt.tick(info.policy.is_rt(), cpus);
+
+        inner.cpus[cpu].ticks += 1;
+        if inner.cpus[cpu].ticks % BALANCE_INTERVAL == 0 {
+            if let Some(busiest) = inner.busiest(cpu) {
+                if inner.cpus[busiest].ready > inner.cpus[cpu].ready + 1 {
+                    self.migrate(&mut inner, busiest, cpu);
+                }
+            }
+        }
+
+        if info.affinity & (1 << cpu) == 0 {
+            // not allowed here any more
+            return true;
+        }
+        let highest_rt = if inner.rt.throttled(cpus) {
+            None
+        } else {
+            inner.highest_rt(cpu)
+        };
+        if !info.policy.is_rt() {
+            // a real-time thread which became normal is put into a fair scheduler first
+            return !info.from_fair || self.fair[cpu].tick(current_tid) || highest_rt.is_some();
+        }
+        if inner.rt.throttled(cpus) {
+            return true;
+        }
+        if highest_rt.unwrap_or(0) > info.policy.rt_priority() {
+            inner.info(current_tid).preempted = true;
+            return true;
+        }
+        if let Policy::RoundRobin(priority) = info.policy {
+            let info = inner.info(current_tid);
+            info.rest_slice = info.rest_slice.saturating_sub(1);
+            if info.rest_slice == 0 {
+                // go on if there is no other thread of this priority
+                if !inner.rt.has_ready(priority as usize) {
+                    inner.info(current_tid).rest_slice = RR_TIMESLICE;
+                    return false;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    fn set_priority(&self, tid: Tid, priority: u8) {
+        // the nice value, used on any CPU and after the thread leaves real-time policies
+        for fair in self.fair.iter() {
+            fair.set_priority(tid, priority);
+        }
+    }
+
+    fn remove(&self, tid: Tid) {
+        let mut inner = self.inner.lock();
+        if inner.info(tid).queued {
+            self.dequeue(&mut inner, tid);
+        }
+    }
+}
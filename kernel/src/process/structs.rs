@@ -1,11 +1,12 @@
 use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, sync::Weak, vec::Vec};
 use core::fmt;
 
-use core::str;
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+use core::{ptr, slice, str};
 use log::*;
+use rcore_memory::paging::{PageTable, PageTableExt};
 use rcore_memory::PAGE_SIZE;
 use rcore_thread::Tid;
-use spin::RwLock;
 use xmas_elf::{
     header,
     program::{Flags, SegmentData, Type},
@@ -13,21 +14,52 @@ use xmas_elf::{
 };
 
 use crate::arch::interrupt::{Context, TrapFrame};
+use crate::consts::{
+    USEC_PER_TICK, USER_ASLR_RANGE, USER_BRK_ASLR_RANGE, USER_INTERP_BASE, USER_MMAP_BASE,
+    USER_PIE_BASE,
+};
 use crate::fs::{FileHandle, FileLike, INodeExt, OpenOptions, FOLLOW_MAX_DEPTH};
-use crate::memory::{ByFrame, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet};
+use crate::memory::{active_table, ByFrame, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet};
 use crate::net::SOCKETS;
-use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::sync::{preempt, Condvar, RwLock, Semaphore, SpinNoIrqLock as Mutex};
+use crate::syscall::{CloneFlags, SysError};
 
 use super::abi::{self, ProcInitInfo};
+use super::aslr::random_offset;
+use super::ptrace::Tracee;
+use super::rlimit::{ResourceLimits, RLIMIT_AS, RLIMIT_DATA, RLIMIT_NOFILE};
+use super::sched::{nice_to_priority, CpuMask, Policy, CPU_MASK_ALL, SCHEDULER};
+use super::{manager, processor};
 
 // TODO: avoid pub
 pub struct Thread {
     pub context: Context,
     pub kstack: KernelStack,
+    /// Kernel writes the tid here when the thread is added, for `CLONE_CHILD_SETTID`.
+    set_child_tid: usize,
+    /// The process of the parent thread and the address in it to write the tid,
+    /// for `CLONE_PARENT_SETTID`. Written when added, before the thread may run.
+    set_parent_tid: Option<(Arc<Mutex<Process>>, usize)>,
+    /// Scheduling policy given to the scheduler when the thread is added
+    policy: Policy,
+    /// CPUs allowed to run on, given to the scheduler when the thread is added
+    affinity: CpuMask,
+    /// Spin locks held when switched out, see `sync::preempt`
+    preempt_count: usize,
     /// Kernel performs futex wake when thread exits.
     /// Ref: [http://man7.org/linux/man-pages/man2/set_tid_address.2.html]
     pub clear_child_tid: usize,
-    pub proc: Arc<Mutex<Process>>,
+    /// The process of a user thread, kernel threads have none
+    proc: Option<Arc<Mutex<Process>>>,
+    /// `Process::on_cpu` of its process, counted without locking the process
+    on_cpu: Option<Arc<OnCpu>>,
+    /// `Process::live_threads` of its process, counted down when dropped
+    live_threads: Option<Arc<LiveThreads>>,
+    /// Set when added
+    tid: Tid,
+    /// Address of the trap frame saved when it last entered the kernel from user mode,
+    /// on its kernel stack. 0 if it has never run.
+    user_tf: usize,
 }
 
 /// Pid type
@@ -73,25 +105,160 @@ impl fmt::Display for Pid {
 pub struct Process {
     // resources
     pub vm: MemorySet,
-    pub files: BTreeMap<usize, FileLike>,
-    pub cwd: String,
+    /// The file table, shared by the processes made by `clone` with `CLONE_FILES`
+    pub files: Arc<Mutex<BTreeMap<usize, FileLike>>>,
+    /// The working directory, shared by the processes made by `clone` with `CLONE_FS`
+    pub cwd: Arc<Mutex<String>>,
     futexes: BTreeMap<usize, Arc<Condvar>>,
+    pub rlimits: ResourceLimits,
+    /// Timer ticks spent in user mode, checked against RLIMIT_CPU
+    pub cpu_ticks: usize,
+    /// Where mmap starts searching for free space without an address hint
+    pub mmap_base: usize,
+    /// Start of the heap, right after the executable
+    pub brk_start: usize,
+    /// Current program break
+    pub brk: usize,
+    /// Added to the badness of the process for the OOM killer,
+    /// `OOM_SCORE_ADJ_MIN` makes it unkillable.
+    /// Shared with `/proc/self/oom_score_adj`, which is written while the process is locked.
+    pub oom_score_adj: Arc<AtomicIsize>,
+    /// Threads switched in on some CPU, shared with the threads
+    pub on_cpu: Arc<OnCpu>,
+    /// Threads not dropped yet, shared with the threads
+    pub live_threads: Arc<LiveThreads>,
+    /// Released when the child of `vfork` execs or exits, for its waiting parent
+    pub vfork_done: Option<Arc<Semaphore>>,
+    /// The nice value, -20..=19, shared by its threads
+    pub nice: isize,
+    /// Path of the executable, the target of `/proc/self/exe`
+    pub exec_path: String,
 
     // relationship
     pub pid: Pid, // i.e. tgid, usually the tid of first thread
-    pub parent: Option<Arc<Mutex<Process>>>,
-    pub children: Vec<Weak<Mutex<Process>>>,
+    pub state: ProcessState,
+    /// Never locked while holding the lock of this process, as `wait4` locks the children
+    pub parent: Option<Weak<Mutex<Process>>>,
+    /// Zombie children are kept here until reaped by `wait4`
+    pub children: Vec<Arc<Mutex<Process>>>,
     pub threads: Vec<Tid>, // threads in the same process
+    /// Set while traced by `ptrace`
+    pub ptrace: Option<Tracee>,
+    /// Processes traced by this one, waited for by `wait4` like the children
+    pub tracees: Vec<Weak<Mutex<Process>>>,
 
     // for waiting child
     pub child_exit: Arc<Condvar>, // notified when the a child process is going to terminate
-    pub child_exit_code: BTreeMap<usize, usize>, // child process store its exit code here
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    /// Stopped until resumed, e.g. by its tracer
+    Stopped,
+    /// All threads exited with the exit code, and the memory and files are released.
+    /// Only the record is left for its parent.
+    Zombie(usize),
+}
+
+/// The number of threads of a process switched in on CPUs.
+///
+/// There is no TLB shootdown, so the page table of a process is edited by others
+/// only while none of its threads is on a CPU. Switching in reloads the page table,
+/// which flushes the TLB of the CPU.
+#[derive(Default)]
+pub struct OnCpu(AtomicUsize);
+
+/// Held in `OnCpu` while the page table is edited, keeping the threads off
+const ON_CPU_LOCKED: usize = !(usize::max_value() >> 1);
+
+impl OnCpu {
+    /// Count a thread switched in, waiting while the page table is edited
+    fn enter(&self) {
+        loop {
+            let count = self.0.load(Ordering::SeqCst);
+            if count & ON_CPU_LOCKED == 0
+                && self.0.compare_and_swap(count, count + 1, Ordering::SeqCst) == count
+            {
+                return;
+            }
+            core::sync::atomic::spin_loop_hint();
+        }
+    }
+
+    fn leave(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Run `f` if no thread is on a CPU, keeping them off until it returns.
+    /// Return `None` if some thread is running.
+    ///
+    /// `f` must not be preempted, e.g. by holding the lock of the process.
+    pub fn if_idle<T>(&self, f: impl FnOnce() -> T) -> Option<T> {
+        if self.0.compare_and_swap(0, ON_CPU_LOCKED, Ordering::SeqCst) != 0 {
+            return None;
+        }
+        let ret = f();
+        self.0.store(0, Ordering::SeqCst);
+        Some(ret)
+    }
+}
+
+/// The threads of a process not dropped yet, which may still run in its memory
+/// even after exited. Counted down by `Drop for Thread` without locking the process.
+#[derive(Default)]
+pub struct LiveThreads {
+    count: AtomicUsize,
+    /// Registers of the threads dropped while collected for a core dump
+    regs: Mutex<Option<Vec<(Tid, TrapFrame)>>>,
+}
+
+impl LiveThreads {
+    fn add(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn remove(&self, tid: Tid, tf: Option<&TrapFrame>) {
+        if let (Some(regs), Some(tf)) = (self.regs.lock().as_mut(), tf) {
+            regs.push((tid, tf.clone()));
+        }
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wait until at most `count` threads are left, e.g. only the current one
+    pub fn wait_until(&self, count: usize) {
+        while self.count.load(Ordering::SeqCst) > count {
+            crate::thread::yield_now();
+        }
+    }
+
+    /// Collect the registers of the threads dropped from now on
+    pub fn collect_regs(&self) {
+        *self.regs.lock() = Some(Vec::new());
+    }
+
+    /// Stop collecting, and return the registers collected
+    pub fn take_regs(&self) -> Vec<(Tid, TrapFrame)> {
+        self.regs.lock().take().unwrap_or_default()
+    }
 }
 
 /// Records the mapping between pid and Process struct.
 lazy_static! {
     pub static ref PROCESSES: RwLock<BTreeMap<usize, Weak<Mutex<Process>>>> =
         RwLock::new(BTreeMap::new());
+    /// The first user process, adopting the orphans
+    pub static ref INIT_PROCESS: RwLock<Weak<Mutex<Process>>> = RwLock::new(Weak::new());
+    /// The page table of kernel threads, with only the kernel mapped
+    static ref KERNEL_TOKEN: usize = {
+        let vm = MemorySet::new();
+        #[cfg(not(feature = "nommu"))]
+        let vm = vm.expect("failed to allocate kernel page table");
+        let token = vm.token();
+        // used until shutdown
+        core::mem::forget(vm);
+        token
+    };
 }
 
 /// Let `rcore_thread` can switch between our `Thread`
@@ -99,130 +266,226 @@ impl rcore_thread::Context for Thread {
     unsafe fn switch_to(&mut self, target: &mut rcore_thread::Context) {
         use core::mem::transmute;
         let (target, _): (&mut Thread, *const ()) = transmute(target);
+        // count the target first, so that a process switching to itself never looks idle
+        if let Some(on_cpu) = &target.on_cpu {
+            on_cpu.enter();
+        }
+        if let Some(on_cpu) = &self.on_cpu {
+            on_cpu.leave();
+        }
+        self.preempt_count = preempt::switch_count(target.preempt_count);
         self.context.switch(&mut target.context);
     }
 
     fn set_tid(&mut self, tid: Tid) {
+        self.tid = tid;
+        // the tid may be reused, reset the scheduling left by the last thread
+        SCHEDULER.set_policy(tid, self.policy);
+        SCHEDULER.set_affinity(tid, self.affinity);
+        let proc_arc = match &self.proc {
+            Some(proc) => proc.clone(),
+            None => {
+                // kernel thread
+                manager().set_priority(tid, nice_to_priority(0));
+                return;
+            }
+        };
         // set pid=tid if unspecified
-        let mut proc = self.proc.lock();
+        let mut proc = proc_arc.lock();
         if proc.pid.set_if_uninitialized(tid) {
             // first thread in the process
             // link to its ppid
-            if let Some(parent) = &proc.parent {
+            if let Some(parent) = proc.parent.as_ref().and_then(Weak::upgrade) {
                 let mut parent = parent.lock();
-                parent.children.push(Arc::downgrade(&self.proc));
+                parent.children.push(proc_arc.clone());
             }
         }
         // add it to threads
         proc.threads.push(tid);
         PROCESSES
             .write()
-            .insert(proc.pid.get(), Arc::downgrade(&self.proc));
+            .insert(proc.pid.get(), Arc::downgrade(&proc_arc));
+        manager().set_priority(tid, nice_to_priority(proc.nice));
+        // the child may not run in the current memory, so write to its frame
+        if self.set_child_tid != 0 {
+            if let Err(err) = proc.write_user_u32(self.set_child_tid, tid as u32) {
+                warn!(
+                    "failed to set child tid at {:#x}: {:?}",
+                    self.set_child_tid, err
+                );
+            }
+        }
+        if let Some((parent, addr)) = self.set_parent_tid.take() {
+            // a thread is added by its own process, which is locked already
+            let ret = if Arc::ptr_eq(&parent, &proc_arc) {
+                proc.write_user_u32(addr, tid as u32)
+            } else {
+                drop(proc);
+                parent.lock().write_user_u32(addr, tid as u32)
+            };
+            if let Err(err) = ret {
+                warn!("failed to set parent tid at {:#x}: {:?}", addr, err);
+            }
+        }
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        // the thread manager may be locked, so never lock the process here
+        if let Some(live_threads) = &self.live_threads {
+            // the kernel stack is still there
+            let tf = match self.user_tf {
+                0 => None,
+                addr => Some(unsafe { &*(addr as *const TrapFrame) }),
+            };
+            live_threads.remove(self.tid, tf);
+        }
     }
 }
 
 impl Thread {
     /// Make a struct for the init thread
     pub unsafe fn new_init() -> Box<Thread> {
-        Box::new(Thread {
-            context: Context::null(),
-            // safety: other fields will never be used
-            ..core::mem::uninitialized()
-        })
+        // safety: other fields will never be used, and the init thread is never dropped
+        let mut thread: Box<Thread> = Box::new(core::mem::uninitialized());
+        ptr::write(&mut thread.context, Context::null());
+        thread.preempt_count = 0;
+        ptr::write(&mut thread.proc, None);
+        ptr::write(&mut thread.on_cpu, None);
+        ptr::write(&mut thread.live_threads, None);
+        thread
+    }
+
+    /// Get the process of a user thread, panic on a kernel thread
+    pub fn proc(&self) -> &Arc<Mutex<Process>> {
+        self.proc.as_ref().expect("kernel thread has no process")
+    }
+
+    pub fn is_kernel(&self) -> bool {
+        self.proc.is_none()
     }
 
-    /// Make a new kernel thread starting from `entry` with `arg`
+    /// Remember the trap frame `tf` on entering the kernel from user mode, see `Drop`.
+    /// Every entry from user mode saves its frame at the same place on the kernel stack,
+    /// so the address is still right for later entries.
+    pub fn enter_from_user(&mut self, tf: &TrapFrame) {
+        self.user_tf = tf as *const TrapFrame as usize;
+    }
+
+    /// Whether this is a thread of `proc`, even if it is removed from the threads by `exit`
+    pub fn belongs_to(&self, proc: &Arc<Mutex<Process>>) -> bool {
+        self.proc
+            .as_ref()
+            .map_or(false, |own| Arc::ptr_eq(own, proc))
+    }
+
+    /// Make a new kernel thread starting from `entry` with `arg`.
+    /// It runs on the kernel page table, and belongs to no process.
     pub fn new_kernel(entry: extern "C" fn(usize) -> !, arg: usize) -> Box<Thread> {
-        let vm = MemorySet::new();
-        let kstack = KernelStack::new();
+        let kstack = KernelStack::new().expect("failed to allocate kernel stack");
         Box::new(Thread {
-            context: unsafe { Context::new_kernel_thread(entry, arg, kstack.top(), vm.token()) },
+            context: unsafe { Context::new_kernel_thread(entry, arg, kstack.top(), *KERNEL_TOKEN) },
             kstack,
+            set_child_tid: 0,
+            set_parent_tid: None,
+            policy: Policy::Normal,
+            affinity: CPU_MASK_ALL,
+            preempt_count: 0,
             clear_child_tid: 0,
-            // TODO: kernel thread should not have a process
-            proc: Arc::new(Mutex::new(Process {
-                vm,
-                files: BTreeMap::default(),
-                cwd: String::from("/"),
-                futexes: BTreeMap::default(),
-                pid: Pid::uninitialized(),
-                parent: None,
-                children: Vec::new(),
-                threads: Vec::new(),
-                child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
-            })),
+            proc: None,
+            on_cpu: None,
+            live_threads: None,
+            tid: 0,
+            user_tf: 0,
         })
     }
 
-    /// Make a new user process from ELF `data`
+    /// Make a new user process from ELF `data`, or from a `#!` script
     pub fn new_user(
         data: &[u8],
         exec_path: &str,
-        mut args: Vec<String>,
+        args: Vec<String>,
         envs: Vec<String>,
-    ) -> Box<Thread> {
+        rlimits: ResourceLimits,
+    ) -> Result<Box<Thread>, SysError> {
+        if data.starts_with(b"#!") {
+            return Thread::new_user_script(data, exec_path, args, envs, rlimits);
+        }
+
         // Parse ELF
-        let elf = ElfFile::new(data).expect("failed to read elf");
+        let elf = ElfFile::new(data).map_err(|err| {
+            warn!("failed to read elf {}: {}", exec_path, err);
+            SysError::ENOEXEC
+        })?;
+        elf.check_header().map_err(|err| {
+            warn!("invalid elf {}: {}", exec_path, err);
+            SysError::ENOEXEC
+        })?;
 
-        // Check ELF type
-        match elf.header.pt2.type_().as_type() {
-            header::Type::Executable => {}
-            header::Type::SharedObject => {}
-            _ => panic!("ELF is not executable or shared object"),
-        }
+        // Make page table
+        #[cfg(not(feature = "nommu"))]
+        let mut vm = MemorySet::new()?;
+        #[cfg(feature = "nommu")]
+        let mut vm = MemorySet::new();
+        #[cfg(not(feature = "nommu"))]
+        let loaded = {
+            // Position independent executables are loaded with a bias
+            let bias = match elf.header.pt2.type_().as_type() {
+                header::Type::SharedObject => USER_PIE_BASE + random_offset(USER_ASLR_RANGE),
+                _ => 0,
+            };
+            elf.map_segments(&mut vm, bias).map(|_| bias)
+        };
+        // Without MMU, the executable is loaded wherever its memory is allocated
+        #[cfg(feature = "nommu")]
+        let loaded = elf.load_segments(&mut vm);
+        let bias = loaded.map_err(|err| {
+            warn!("failed to load elf {}: {}", exec_path, err);
+            elf_load_error(err)
+        })?;
+        let mut entry_addr = elf.header.pt2.entry_point() as usize + bias;
 
-        // Check ELF arch
-        match elf.header.pt2.machine().as_machine() {
-            #[cfg(target_arch = "x86_64")]
-            header::Machine::X86_64 => {}
-            #[cfg(target_arch = "aarch64")]
-            header::Machine::AArch64 => {}
-            #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
-            header::Machine::Other(243) => {}
-            #[cfg(target_arch = "mips")]
-            header::Machine::Mips => {}
-            machine @ _ => panic!("invalid elf arch: {:?}", machine),
-        }
+        // The heap starts after the executable, with a random gap
+        let elf_end = vm.iter().map(|area| area.end_addr()).max().unwrap_or(0);
+        let brk_start =
+            ((elf_end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)) + random_offset(USER_BRK_ASLR_RANGE);
 
-        // Check interpreter (for dynamic link)
+        // Load interpreter (for dynamic link) at another base
+        let mut interp_base = 0;
         if let Ok(loader_path) = elf.get_interpreter() {
             // assuming absolute path
-            if let Ok(inode) = crate::fs::ROOT_INODE.lookup_follow(loader_path, FOLLOW_MAX_DEPTH) {
-                if let Ok(buf) = inode.read_as_vec() {
-                    // Elf loader should not have INTERP
-                    // No infinite loop
-                    args.insert(0, loader_path.into());
-                    args.insert(1, exec_path.into());
-                    args.remove(2);
-                    info!("loader args: {:?}", args);
-                    return Thread::new_user(buf.as_slice(), exec_path, args, envs);
-                } else {
-                    warn!("loader specified as {} but failed to read", &loader_path);
-                }
-            } else {
-                warn!("loader specified as {} but not found", &loader_path);
-            }
+            let inode = crate::fs::ROOT_INODE
+                .lookup_follow(loader_path, FOLLOW_MAX_DEPTH)
+                .map_err(|_| {
+                    warn!("loader specified as {} but not found", loader_path);
+                    SysError::ENOENT
+                })?;
+            let buf = inode.read_as_vec()?;
+            let loader = ElfFile::new(&buf).map_err(|err| {
+                warn!("failed to read loader {}: {}", loader_path, err);
+                SysError::ENOEXEC
+            })?;
+            #[cfg(not(feature = "nommu"))]
+            let loaded = {
+                let base = USER_INTERP_BASE + random_offset(USER_ASLR_RANGE);
+                loader
+                    .check_header()
+                    .and_then(|_| loader.map_segments(&mut vm, base))
+                    .map(|_| base)
+            };
+            #[cfg(feature = "nommu")]
+            let loaded = loader
+                .check_header()
+                .and_then(|_| loader.load_segments(&mut vm));
+            interp_base = loaded.map_err(|err| {
+                warn!("failed to load loader {}: {}", loader_path, err);
+                elf_load_error(err)
+            })?;
+            entry_addr = loader.header.pt2.entry_point() as usize + interp_base;
+            info!("loader {} loaded at {:#x}", loader_path, interp_base);
         }
 
-        // Make page table
-        let mut vm = elf.make_memory_set();
-
-        // User stack
-        use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE};
-        let mut ustack_top = {
-            let ustack_buttom = USER_STACK_OFFSET;
-            let ustack_top = USER_STACK_OFFSET + USER_STACK_SIZE;
-            vm.push(
-                ustack_buttom,
-                ustack_top,
-                MemoryAttr::default().user(),
-                ByFrame::new(GlobalFrameAlloc),
-                "user_stack",
-            );
-            ustack_top
-        };
-
         // Make init info
         let init_info = ProcInitInfo {
             args,
@@ -230,13 +493,61 @@ impl Thread {
             auxv: {
                 let mut map = BTreeMap::new();
                 if let Some(phdr_vaddr) = elf.get_phdr_vaddr() {
-                    map.insert(abi::AT_PHDR, phdr_vaddr as usize);
+                    map.insert(abi::AT_PHDR, phdr_vaddr as usize + bias);
                 }
                 map.insert(abi::AT_PHENT, elf.header.pt2.ph_entry_size() as usize);
                 map.insert(abi::AT_PHNUM, elf.header.pt2.ph_count() as usize);
                 map.insert(abi::AT_PAGESZ, PAGE_SIZE);
+                map.insert(abi::AT_BASE, interp_base);
+                map.insert(abi::AT_FLAGS, 0);
+                map.insert(abi::AT_ENTRY, elf.header.pt2.entry_point() as usize + bias);
+                map.insert(abi::AT_UID, 0);
+                map.insert(abi::AT_EUID, 0);
+                map.insert(abi::AT_GID, 0);
+                map.insert(abi::AT_EGID, 0);
+                // no optional hardware capabilities are advertised
+                map.insert(abi::AT_HWCAP, 0);
+                map.insert(abi::AT_CLKTCK, 1_000_000 / USEC_PER_TICK);
+                map.insert(abi::AT_SECURE, 0);
                 map
             },
+            execfn: exec_path.into(),
+        };
+
+        // User stack, grows down on demand up to RLIMIT_STACK
+        use crate::consts::{USER_STACK_INIT_SIZE, USER_STACK_OFFSET, USER_STACK_SIZE};
+        let ustack_limit = rlimits.stack_size();
+        if init_info.size() > ustack_limit {
+            return Err(SysError::E2BIG);
+        }
+        #[cfg(not(feature = "nommu"))]
+        let mut ustack_top = {
+            let ustack_top = USER_STACK_OFFSET + USER_STACK_SIZE - random_offset(USER_ASLR_RANGE);
+            if vm
+                .iter()
+                .any(|area| area.is_overlap_with(ustack_top - ustack_limit, ustack_top))
+            {
+                warn!("elf {} overlaps with user stack", exec_path);
+                return Err(SysError::ENOEXEC);
+            }
+            let ustack_size =
+                (init_info.size() + USER_STACK_INIT_SIZE + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+            vm.push(
+                ustack_top - ustack_size.min(ustack_limit),
+                ustack_top,
+                MemoryAttr::default().user(),
+                ByFrame::new(GlobalFrameAlloc),
+                "user_stack",
+            )?;
+            ustack_top
+        };
+        // Without MMU the stack can not grow, so `USER_STACK_SIZE` is allocated to spare
+        #[cfg(feature = "nommu")]
+        let mut ustack_top = {
+            let ustack_size =
+                (init_info.size() + USER_STACK_SIZE + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+            let ustack = vm.push(ustack_size, "user_stack")?;
+            ustack.as_ptr() as usize + ustack_size
         };
         unsafe {
             vm.with(|| ustack_top = init_info.push_at(ustack_top));
@@ -244,7 +555,7 @@ impl Thread {
 
         trace!("{:#x?}", vm);
 
-        let kstack = KernelStack::new();
+        let kstack = KernelStack::new().ok_or(SysError::ENOMEM)?;
 
         let mut files = BTreeMap::new();
         files.insert(
@@ -281,91 +592,293 @@ impl Thread {
             )),
         );
 
-        let entry_addr = elf.header.pt2.entry_point() as usize;
-
-        Box::new(Thread {
+        let on_cpu = Arc::new(OnCpu::default());
+        let live_threads = Arc::new(LiveThreads::default());
+        live_threads.add();
+        Ok(Box::new(Thread {
             context: unsafe {
                 Context::new_user_thread(entry_addr, ustack_top, kstack.top(), vm.token())
             },
             kstack,
+            set_child_tid: 0,
+            set_parent_tid: None,
+            policy: Policy::Normal,
+            affinity: CPU_MASK_ALL,
+            preempt_count: 0,
             clear_child_tid: 0,
-            proc: Arc::new(Mutex::new(Process {
+            on_cpu: Some(on_cpu.clone()),
+            live_threads: Some(live_threads.clone()),
+            tid: 0,
+            user_tf: 0,
+            proc: Some(Arc::new(Mutex::new(Process {
                 vm,
-                files,
-                cwd: String::from("/"),
+                files: Arc::new(Mutex::new(files)),
+                cwd: Arc::new(Mutex::new(String::from("/"))),
                 futexes: BTreeMap::default(),
+                rlimits,
+                cpu_ticks: 0,
+                mmap_base: USER_MMAP_BASE + random_offset(USER_ASLR_RANGE),
+                brk_start,
+                brk: brk_start,
+                oom_score_adj: Arc::new(AtomicIsize::new(0)),
+                on_cpu,
+                live_threads,
+                vfork_done: None,
+                nice: 0,
+                exec_path: String::from(exec_path),
                 pid: Pid::uninitialized(),
+                state: ProcessState::Running,
                 parent: None,
                 children: Vec::new(),
                 threads: Vec::new(),
+                ptrace: None,
+                tracees: Vec::new(),
                 child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
-            })),
-        })
+            }))),
+        }))
     }
 
-    /// Fork a new process from current one
-    pub fn fork(&self, tf: &TrapFrame) -> Box<Thread> {
-        // Clone memory set, make a new page table
-        let proc = self.proc.lock();
-        let vm = proc.vm.clone();
-        let files = proc.files.clone();
-        let cwd = proc.cwd.clone();
-        drop(proc);
-        let parent = Some(self.proc.clone());
-        debug!("fork: finish clone MemorySet");
+    /// Run the script `data` by the interpreter named in its `#!` line.
+    /// The interpreter may be a script itself, up to `MAX_SCRIPT_DEPTH` levels.
+    fn new_user_script(
+        data: &[u8],
+        exec_path: &str,
+        mut args: Vec<String>,
+        envs: Vec<String>,
+        rlimits: ResourceLimits,
+    ) -> Result<Box<Thread>, SysError> {
+        let mut script_path = String::from(exec_path);
+        let mut line = parse_interpreter_line(data)?;
+        for _ in 0..MAX_SCRIPT_DEPTH {
+            let (interp_path, interp_arg) = line;
+            // argv[0] is replaced by the interpreter, its argument and the script path
+            let mut new_args = vec![interp_path.clone()];
+            new_args.extend(interp_arg);
+            new_args.push(script_path);
+            new_args.extend(args.into_iter().skip(1));
+            args = new_args;
+            info!("script interpreter args: {:?}", args);
 
-        // MMU:   copy data to the new space
-        // NoMMU: coping data has been done in `vm.clone()`
-        for area in vm.iter() {
-            let data = Vec::<u8>::from(unsafe { area.as_slice() });
-            unsafe { vm.with(|| area.as_slice_mut().copy_from_slice(data.as_slice())) }
+            // assuming absolute path
+            let inode = crate::fs::ROOT_INODE.lookup_follow(&interp_path, FOLLOW_MAX_DEPTH)?;
+            let buf = inode.read_as_vec()?;
+            if !buf.starts_with(b"#!") {
+                return Thread::new_user(buf.as_slice(), exec_path, args, envs, rlimits);
+            }
+            line = parse_interpreter_line(&buf)?;
+            script_path = interp_path;
         }
-
-        debug!("fork: temporary copy data!");
-        let kstack = KernelStack::new();
-
-        Box::new(Thread {
-            context: unsafe { Context::new_fork(tf, kstack.top(), vm.token()) },
-            kstack,
-            clear_child_tid: 0,
-            proc: Arc::new(Mutex::new(Process {
-                vm,
-                files,
-                cwd,
-                futexes: BTreeMap::default(),
-                pid: Pid::uninitialized(),
-                parent,
-                children: Vec::new(),
-                threads: Vec::new(),
-                child_exit: Arc::new(Condvar::new()),
-                child_exit_code: BTreeMap::new(),
-            })),
-        })
+        warn!("too many levels of script interpreters for {}", exec_path);
+        Err(SysError::ELOOP)
     }
 
-    /// Create a new thread in the same process.
+    /// Create a new thread from current one, in the same process with `CLONE_THREAD`,
+    /// otherwise in a new process sharing the resources `flags` asks for.
+    /// The user stack and thread pointer are set to `stack_top` and `tls` if given.
+    /// The tid is written at `set_parent_tid` of the current process if not 0.
     pub fn clone(
         &self,
         tf: &TrapFrame,
-        stack_top: usize,
-        tls: usize,
+        flags: CloneFlags,
+        stack_top: Option<usize>,
+        tls: Option<usize>,
+        set_child_tid: usize,
+        set_parent_tid: usize,
         clear_child_tid: usize,
-    ) -> Box<Thread> {
-        let kstack = KernelStack::new();
-        let token = self.proc.lock().vm.token();
-        Box::new(Thread {
+    ) -> Result<Box<Thread>, SysError> {
+        // before copying the memory for a new process
+        let kstack = KernelStack::new().ok_or(SysError::ENOMEM)?;
+        let proc = if flags.contains(CloneFlags::THREAD) {
+            self.proc().clone()
+        } else {
+            Arc::new(Mutex::new(self.fork_process(flags)?))
+        };
+        let (token, on_cpu, live_threads) = {
+            let proc = proc.lock();
+            (
+                proc.vm.token(),
+                proc.on_cpu.clone(),
+                proc.live_threads.clone(),
+            )
+        };
+        live_threads.add();
+        Ok(Box::new(Thread {
             context: unsafe { Context::new_clone(tf, stack_top, kstack.top(), token, tls) },
             kstack,
+            set_child_tid,
+            set_parent_tid: match set_parent_tid {
+                0 => None,
+                addr => Some((self.proc().clone(), addr)),
+            },
             clear_child_tid,
-            proc: self.proc.clone(),
+            // inherited from the current thread like Linux
+            policy: SCHEDULER.policy(processor().tid()),
+            affinity: SCHEDULER.affinity(processor().tid()),
+            preempt_count: 0,
+            proc: Some(proc),
+            on_cpu: Some(on_cpu),
+            live_threads: Some(live_threads),
+            tid: 0,
+            user_tf: 0,
+        }))
+    }
+
+    /// Make a new process from the current one.
+    /// The file table and working directory are shared with `CLONE_FILES` and `CLONE_FS`,
+    /// and the parent is the parent of current one with `CLONE_PARENT`.
+    ///
+    /// The memory set belongs to a process, so it can not be shared by another one.
+    /// `CLONE_VM` is only allowed with `CLONE_VFORK`, where the parent waits until the child
+    /// execs or exits, and the child runs in a copy of the memory like `fork`:
+    /// its writes are not seen by the parent.
+    fn fork_process(&self, flags: CloneFlags) -> Result<Process, SysError> {
+        // Clone memory set, make a new page table
+        let proc = self.proc().lock();
+        #[cfg(not(feature = "nommu"))]
+        let mut vm = proc.vm.try_clone()?;
+        // NoMMU: the child runs in the memory of the parent, which waits like `vfork`
+        #[cfg(feature = "nommu")]
+        let vm = proc.vm.share();
+        let files = if flags.contains(CloneFlags::FILES) {
+            proc.files.clone()
+        } else {
+            Arc::new(Mutex::new(proc.files.lock().clone()))
+        };
+        let cwd = if flags.contains(CloneFlags::FS) {
+            proc.cwd.clone()
+        } else {
+            Arc::new(Mutex::new(proc.cwd.lock().clone()))
+        };
+        let rlimits = proc.rlimits;
+        let (mmap_base, brk_start, brk) = (proc.mmap_base, proc.brk_start, proc.brk);
+        let oom_score_adj = proc.oom_score_adj.load(Ordering::Relaxed);
+        let nice = proc.nice;
+        let exec_path = proc.exec_path.clone();
+        let parent = if flags.contains(CloneFlags::PARENT) {
+            proc.parent.clone()
+        } else {
+            Some(Arc::downgrade(self.proc()))
+        };
+        drop(proc);
+        debug!("fork: finish clone MemorySet");
+
+        // MMU:   copy data to the new space
+        // NoMMU: nothing to copy, the memory is shared
+        #[cfg(not(feature = "nommu"))]
+        {
+            let ranges: Vec<_> = vm
+                .iter()
+                .map(|area| (area.start_addr(), area.end_addr()))
+                .collect();
+            for (start, end) in ranges {
+                let data = Vec::<u8>::from(unsafe {
+                    slice::from_raw_parts(start as *const u8, end - start)
+                });
+                unsafe {
+                    vm.with_writable(start, end, || {
+                        slice::from_raw_parts_mut(start as *mut u8, end - start)
+                            .copy_from_slice(&data)
+                    })
+                }
+            }
+        }
+
+        debug!("fork: temporary copy data!");
+
+        Ok(Process {
+            vm,
+            files,
+            cwd,
+            futexes: BTreeMap::default(),
+            rlimits,
+            cpu_ticks: 0,
+            mmap_base,
+            brk_start,
+            brk,
+            oom_score_adj: Arc::new(AtomicIsize::new(oom_score_adj)),
+            on_cpu: Arc::default(),
+            live_threads: Arc::default(),
+            vfork_done: if cfg!(feature = "nommu") || flags.contains(CloneFlags::VFORK) {
+                Some(Arc::new(Semaphore::new(0)))
+            } else {
+                None
+            },
+            nice,
+            exec_path,
+            pid: Pid::uninitialized(),
+            state: ProcessState::Running,
+            parent,
+            children: Vec::new(),
+            threads: Vec::new(),
+            ptrace: None,
+            tracees: Vec::new(),
+            child_exit: Arc::new(Condvar::new()),
         })
     }
 }
 
 impl Process {
-    pub fn get_free_fd(&self) -> usize {
-        (0..).find(|i| !self.files.contains_key(i)).unwrap()
+    /// Add a file at the lowest free fd, limited by RLIMIT_NOFILE. Return the fd.
+    pub fn add_file(&self, file_like: FileLike) -> Result<usize, SysError> {
+        let mut files = self.files.lock();
+        let fd = (0..).find(|i| !files.contains_key(i)).unwrap();
+        if fd as u64 >= self.rlimits.get(RLIMIT_NOFILE).cur {
+            return Err(SysError::EMFILE);
+        }
+        files.insert(fd, file_like);
+        Ok(fd)
+    }
+    /// Grow the user stack downwards to cover `addr` on page fault.
+    /// Fail if the stack would exceed RLIMIT_STACK,
+    /// or come within the guard gap of the area below it.
+    #[cfg(not(feature = "nommu"))]
+    pub fn grow_stack(&mut self, addr: usize) -> bool {
+        use crate::consts::USER_STACK_GUARD_GAP;
+        let (start, end) = match self.vm.iter().find(|area| area.name() == "user_stack") {
+            Some(area) => (area.start_addr(), area.end_addr()),
+            None => return false,
+        };
+        if addr >= start {
+            return false;
+        }
+        let new_start = addr & !(PAGE_SIZE - 1);
+        if end - new_start > self.rlimits.stack_size()
+            || self.check_vm_limit(start - new_start, false).is_err()
+        {
+            return false;
+        }
+        let gap_start = new_start.saturating_sub(USER_STACK_GUARD_GAP);
+        if self
+            .vm
+            .iter()
+            .any(|area| area.is_overlap_with(gap_start, new_start))
+        {
+            return false;
+        }
+        self.vm.extend_down(start, new_start)
+    }
+    /// Check that mapping `len` more bytes keeps the address space within RLIMIT_AS,
+    /// and the private anonymous memory within RLIMIT_DATA if `is_data`
+    pub fn check_vm_limit(&self, len: usize, is_data: bool) -> Result<(), SysError> {
+        if self
+            .rlimits
+            .get(RLIMIT_AS)
+            .exceeded_by(self.vm.size() + len)
+        {
+            return Err(SysError::ENOMEM);
+        }
+        if is_data {
+            let data_size: usize = self
+                .vm
+                .iter()
+                .filter(|area| area.name() == "mmap_anon" || area.name() == "heap")
+                .map(|area| area.size())
+                .sum();
+            if self.rlimits.get(RLIMIT_DATA).exceeded_by(data_size + len) {
+                return Err(SysError::ENOMEM);
+            }
+        }
+        Ok(())
     }
     pub fn get_futex(&mut self, uaddr: usize) -> Arc<Condvar> {
         if !self.futexes.contains_key(&uaddr) {
@@ -373,13 +886,149 @@ impl Process {
         }
         self.futexes.get(&uaddr).unwrap().clone()
     }
-    pub fn clone_for_exec(&mut self, other: &Self) {
-        self.files = other.files.clone();
-        self.cwd = other.cwd.clone();
-        self.pid = other.pid.clone();
-        self.parent = other.parent.clone();
-        self.threads = other.threads.clone();
+    /// Write `value` at `addr` of the user memory, which may not be active
+    pub fn write_user_u32(&mut self, addr: usize, value: u32) -> Result<(), SysError> {
+        self.vm.check_write_ptr(addr as *mut u32)?;
+        if addr % core::mem::align_of::<u32>() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        self.write_user_bytes(addr, &value.to_ne_bytes())
+    }
+    /// Write `value` at `addr` of the user memory, which may not be active.
+    /// Readonly memory is written too, like breakpoints in the code by `ptrace`.
+    pub fn write_user_word(&mut self, addr: usize, value: usize) -> Result<(), SysError> {
+        self.vm.check_read_ptr(addr as *const usize)?;
+        if addr % core::mem::align_of::<usize>() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        // a readonly page may share its frame, e.g. the zero frame or a merged one
+        #[cfg(not(feature = "nommu"))]
+        {
+            let page = addr & !(PAGE_SIZE - 1);
+            let mut shared = false;
+            self.vm.edit(|pt| {
+                shared = pt
+                    .get_entry(page)
+                    .map_or(false, |entry| entry.present() && entry.readonly_shared())
+            });
+            if shared {
+                return Err(SysError::EIO);
+            }
+        }
+        self.write_user_bytes(addr, &value.to_ne_bytes())
+    }
+    /// Write `data` at `addr` within a page of the user memory, whose range is checked
+    fn write_user_bytes(&mut self, addr: usize, data: &[u8]) -> Result<(), SysError> {
+        let page = addr & !(PAGE_SIZE - 1);
+        // give the page a frame of its own, as if written by the user
+        #[cfg(not(feature = "nommu"))]
+        self.vm.handle_page_fault(page, true)?;
+        let frame = self.vm.translate(page).ok_or(SysError::EFAULT)?;
+        active_table().with_temporary_map(frame, |_, page_data: &mut [u8; PAGE_SIZE]| {
+            let offset = addr - page;
+            page_data[offset..offset + data.len()].copy_from_slice(data);
+        });
+        Ok(())
+    }
+    /// Read a word at `addr` of the user memory, which may not be active
+    pub fn read_user_word(&mut self, addr: usize) -> Result<usize, SysError> {
+        self.vm.check_read_ptr(addr as *const usize)?;
+        if addr % core::mem::align_of::<usize>() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let page = addr & !(PAGE_SIZE - 1);
+        // give the page a frame, as if read by the user
+        #[cfg(not(feature = "nommu"))]
+        self.vm.handle_page_fault(page, false)?;
+        let frame = self.vm.translate(page).ok_or(SysError::EFAULT)?;
+        let mut bytes = [0u8; core::mem::size_of::<usize>()];
+        active_table().with_temporary_map(frame, |_, data: &mut [u8; PAGE_SIZE]| {
+            let offset = addr - page;
+            bytes.copy_from_slice(&data[offset..offset + bytes.len()]);
+        });
+        Ok(usize::from_ne_bytes(bytes))
+    }
+    /// Take the memory and executable of process `new` loaded by `exec`, keeping the others.
+    /// Like Linux, the file table is no longer shared after `exec`,
+    /// and the files with close-on-exec flag are closed.
+    pub fn exec_from(&mut self, new: &mut Process) {
+        core::mem::swap(&mut self.vm, &mut new.vm);
+        core::mem::swap(&mut self.exec_path, &mut new.exec_path);
+        let files = self
+            .files
+            .lock()
+            .iter()
+            .filter(|(_, file_like)| match file_like {
+                FileLike::File(file) => !file.fd_cloexec,
+                _ => true,
+            })
+            .map(|(&fd, file_like)| (fd, file_like.clone()))
+            .collect();
+        self.files = Arc::new(Mutex::new(files));
+        self.futexes.clear();
+        self.mmap_base = new.mmap_base;
+        self.brk_start = new.brk_start;
+        self.brk = new.brk;
+    }
+
+    /// Quit all threads except `tid` with exit code `code`, keeping only `tid` in the threads.
+    /// They may run until switched out for good, see `LiveThreads::wait_until`.
+    pub fn quit_threads_except(&mut self, tid: Tid, code: usize) {
+        for &other in self.threads.iter().filter(|&&other| other != tid) {
+            manager().exit(other, code);
+        }
+        self.threads.retain(|&other| other == tid);
+    }
+
+    /// Name of the executable without its directory, up to 15 bytes like Linux
+    pub fn comm(&self) -> &str {
+        let name = self.exec_path.rsplit('/').next().unwrap();
+        let mut end = name.len().min(TASK_COMM_LEN - 1);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        &name[..end]
+    }
+}
+
+/// Size of the name of a process in Linux, including the trailing zero
+const TASK_COMM_LEN: usize = 16;
+
+/// Maximum nesting depth of `#!` script interpreters
+const MAX_SCRIPT_DEPTH: usize = 4;
+/// Maximum length of the `#!` line, including the trailing newline
+const MAX_INTERPRETER_LINE: usize = 256;
+
+/// Parse the `#!` line of a script into the interpreter path and its optional argument.
+/// Like Linux, everything after the interpreter path is passed as a single argument.
+fn parse_interpreter_line(data: &[u8]) -> Result<(String, Option<String>), SysError> {
+    let data = &data[..data.len().min(MAX_INTERPRETER_LINE)];
+    let end = data
+        .iter()
+        .position(|&c| c == b'\n')
+        .unwrap_or_else(|| data.len());
+    if end == MAX_INTERPRETER_LINE {
+        // no newline found in the limit
+        return Err(SysError::ENOEXEC);
+    }
+    let line = str::from_utf8(&data[2..end]).map_err(|_| SysError::ENOEXEC)?;
+    let line = line.trim_matches(|c| c == ' ' || c == '\t' || c == '\r');
+    let (path, arg) = match line.find(|c| c == ' ' || c == '\t') {
+        Some(pos) => (
+            &line[..pos],
+            line[pos..].trim_matches(|c| c == ' ' || c == '\t'),
+        ),
+        None => (line, ""),
+    };
+    if path.is_empty() {
+        return Err(SysError::ENOEXEC);
     }
+    let arg = if arg.is_empty() {
+        None
+    } else {
+        Some(String::from(arg))
+    };
+    Ok((String::from(path), arg))
 }
 
 trait ToMemoryAttr {
@@ -389,7 +1038,9 @@ trait ToMemoryAttr {
 impl ToMemoryAttr for Flags {
     fn to_attr(&self) -> MemoryAttr {
         let mut flags = MemoryAttr::default().user();
-        // FIXME: handle readonly
+        if !self.is_write() {
+            flags = flags.readonly();
+        }
         if self.is_execute() {
             flags = flags.execute();
         }
@@ -397,10 +1048,31 @@ impl ToMemoryAttr for Flags {
     }
 }
 
+/// Error of `ElfExt::map_segments` when out of memory
+const NO_MEMORY: &str = "out of memory";
+
+/// Errno for an error of `ElfExt::map_segments`
+fn elf_load_error(err: &str) -> SysError {
+    if err == NO_MEMORY {
+        SysError::ENOMEM
+    } else {
+        SysError::ENOEXEC
+    }
+}
+
 /// Helper functions to process ELF file
 trait ElfExt {
-    /// Generate a MemorySet according to the ELF file.
-    fn make_memory_set(&self) -> MemorySet;
+    /// Map the loadable segments into `ms` at `bias`, and fill their content.
+    #[cfg(not(feature = "nommu"))]
+    fn map_segments(&self, ms: &mut MemorySet, bias: usize) -> Result<(), &'static str>;
+
+    /// Load the segments of a position independent file into a new area of `ms`.
+    /// Return the bias, i.e. where the segment at address 0 would be.
+    #[cfg(feature = "nommu")]
+    fn load_segments(&self, ms: &mut MemorySet) -> Result<usize, &'static str>;
+
+    /// Check whether the ELF file can be run on this machine.
+    fn check_header(&self) -> Result<(), &'static str>;
 
     /// Get interpreter string if it has.
     fn get_interpreter(&self) -> Result<&str, &str>;
@@ -410,43 +1082,114 @@ trait ElfExt {
 }
 
 impl ElfExt for ElfFile<'_> {
-    fn make_memory_set(&self) -> MemorySet {
-        debug!("creating MemorySet from ELF");
-        let mut ms = MemorySet::new();
-
+    #[cfg(not(feature = "nommu"))]
+    fn map_segments(&self, ms: &mut MemorySet, bias: usize) -> Result<(), &'static str> {
+        debug!("mapping ELF segments at {:#x}", bias);
         for ph in self.program_iter() {
             if ph.get_type() != Ok(Type::Load) {
                 continue;
             }
-            let virt_addr = ph.virtual_addr() as usize;
             let mem_size = ph.mem_size() as usize;
-            let data = match ph.get_data(self).unwrap() {
+            if mem_size == 0 {
+                continue;
+            }
+            let data = match ph.get_data(self)? {
                 SegmentData::Undefined(data) => data,
-                _ => unreachable!(),
-            };
-
-            // Get target slice
-            let target = {
-                ms.push(
-                    virt_addr,
-                    virt_addr + mem_size,
-                    ph.flags().to_attr(),
-                    ByFrame::new(GlobalFrameAlloc),
-                    "elf",
-                );
-                unsafe { ::core::slice::from_raw_parts_mut(virt_addr as *mut u8, mem_size) }
+                _ => return Err("invalid segment data"),
             };
-            // Copy data
+            if data.len() > mem_size {
+                return Err("segment file size is larger than memory size");
+            }
+            let start_addr = (ph.virtual_addr() as usize)
+                .checked_add(bias)
+                .ok_or("segment address overflow")?;
+            let end_addr = start_addr
+                .checked_add(mem_size)
+                .ok_or("segment address overflow")?;
+            if ms
+                .iter()
+                .any(|area| area.is_overlap_with(start_addr, end_addr))
+            {
+                return Err("overlapped segments");
+            }
+            ms.push(
+                start_addr,
+                end_addr,
+                ph.flags().to_attr(),
+                ByFrame::new(GlobalFrameAlloc),
+                "elf",
+            )
+            .map_err(|_| NO_MEMORY)?;
+            // Copy data, the segment may be readonly
             unsafe {
-                ms.with(|| {
-                    if data.len() != 0 {
-                        target[..data.len()].copy_from_slice(data);
-                    }
+                ms.with_writable(start_addr, end_addr, || {
+                    let target = slice::from_raw_parts_mut(start_addr as *mut u8, mem_size);
+                    target[..data.len()].copy_from_slice(data);
                     target[data.len()..].iter_mut().for_each(|x| *x = 0);
                 });
             }
         }
-        ms
+        Ok(())
+    }
+
+    #[cfg(feature = "nommu")]
+    fn load_segments(&self, ms: &mut MemorySet) -> Result<usize, &'static str> {
+        match self.header.pt2.type_().as_type() {
+            header::Type::SharedObject => {}
+            _ => return Err("not position independent"),
+        }
+        let segments: Vec<_> = self
+            .program_iter()
+            .filter(|ph| ph.get_type() == Ok(Type::Load) && ph.mem_size() != 0)
+            .collect();
+        let start = segments
+            .iter()
+            .map(|ph| ph.virtual_addr() as usize & !(PAGE_SIZE - 1))
+            .min()
+            .ok_or("no loadable segment")?;
+        let mut end = start;
+        for ph in segments.iter() {
+            let seg_end = (ph.virtual_addr() as usize)
+                .checked_add(ph.mem_size() as usize)
+                .ok_or("segment address overflow")?;
+            end = end.max(seg_end);
+        }
+        let area = ms.push(end - start, "elf").map_err(|_| NO_MEMORY)?;
+        // gaps between the segments are zeroed as well
+        area.iter_mut().for_each(|x| *x = 0);
+        for ph in segments {
+            let data = match ph.get_data(self)? {
+                SegmentData::Undefined(data) => data,
+                _ => return Err("invalid segment data"),
+            };
+            if data.len() > ph.mem_size() as usize {
+                return Err("segment file size is larger than memory size");
+            }
+            let offset = ph.virtual_addr() as usize - start;
+            area[offset..offset + data.len()].copy_from_slice(data);
+        }
+        let bias = (area.as_ptr() as usize).wrapping_sub(start);
+        debug!("loaded ELF segments at {:#x}", bias);
+        Ok(bias)
+    }
+
+    fn check_header(&self) -> Result<(), &'static str> {
+        match self.header.pt2.type_().as_type() {
+            header::Type::Executable | header::Type::SharedObject => {}
+            _ => return Err("not an executable or shared object"),
+        }
+        match self.header.pt2.machine().as_machine() {
+            #[cfg(target_arch = "x86_64")]
+            header::Machine::X86_64 => {}
+            #[cfg(target_arch = "aarch64")]
+            header::Machine::AArch64 => {}
+            #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+            header::Machine::Other(243) => {}
+            #[cfg(target_arch = "mips")]
+            header::Machine::Mips => {}
+            _ => return Err("invalid elf arch"),
+        }
+        Ok(())
     }
 
     fn get_interpreter(&self) -> Result<&str, &str> {
@@ -2,38 +2,50 @@
 
 use crate::drivers::CMDLINE;
 use crate::fs::{INodeExt, ROOT_INODE};
+use crate::process::rlimit::ResourceLimits;
 use crate::process::*;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 #[cfg(not(feature = "run_cmdline"))]
 pub fn run_user_shell() {
     if let Ok(inode) = ROOT_INODE.lookup("busybox") {
         let data = inode.read_as_vec().unwrap();
-        processor().manager().add(Thread::new_user(
+        let thread = Thread::new_user(
             data.as_slice(),
             "busybox",
             vec!["busybox".into(), "sh".into()],
             Vec::new(),
-        ));
+            ResourceLimits::default(),
+        )
+        .expect("failed to load busybox");
+        *INIT_PROCESS.write() = Arc::downgrade(thread.proc());
+        manager().add(thread);
     } else {
-        processor().manager().add(Thread::new_kernel(shell, 0));
+        kthread::spawn("shell", shell);
     }
 }
 
 #[cfg(feature = "run_cmdline")]
 pub fn run_user_shell() {
     let cmdline = CMDLINE.read();
-    let inode = ROOT_INODE.lookup(&cmdline).unwrap();
+    let name = cmdline.split(' ').next().unwrap();
+    let inode = ROOT_INODE.lookup(name).unwrap();
     let data = inode.read_as_vec().unwrap();
-    processor().manager().add(Thread::new_user(
+    let thread = Thread::new_user(
         data.as_slice(),
+        name,
         cmdline.split(' ').map(|s| s.into()).collect(),
         Vec::new(),
-    ));
+        ResourceLimits::default(),
+    )
+    .expect("failed to load program");
+    *INIT_PROCESS.write() = Arc::downgrade(thread.proc());
+    manager().add(thread);
 }
 
-pub extern "C" fn shell(_arg: usize) -> ! {
+pub fn shell() {
     let files = ROOT_INODE.list().unwrap();
     println!("Available programs: {:?}", files);
     let mut history = Vec::new();
@@ -47,12 +59,18 @@ pub extern "C" fn shell(_arg: usize) -> ! {
         let name = cmd.trim().split(' ').next().unwrap();
         if let Ok(file) = ROOT_INODE.lookup(name) {
             let data = file.read_as_vec().unwrap();
-            let _pid = processor().manager().add(Thread::new_user(
+            match Thread::new_user(
                 data.as_slice(),
-                &cmd,
+                name,
                 cmd.split(' ').map(|s| s.into()).collect(),
                 Vec::new(),
-            ));
+                ResourceLimits::default(),
+            ) {
+                Ok(thread) => {
+                    let _pid = manager().add(thread);
+                }
+                Err(err) => println!("Failed to run {}: {}", name, err),
+            }
         // TODO: wait until process exits, or use user land shell completely
         //unsafe { thread::JoinHandle::<()>::_of(pid) }.join().unwrap();
         } else {
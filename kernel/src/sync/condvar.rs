@@ -51,8 +51,13 @@ impl Condvar {
         S: MutexSupport,
     {
         let mutex = guard.mutex;
-        drop(guard);
-        self._wait();
+        // Unlock only after added to the wait queue, like `_wait`,
+        // so a notifier that changed the data under the lock never misses us.
+        let lock = self.add_to_wait_queue();
+        thread::park_action(move || {
+            drop(lock);
+            drop(guard);
+        });
         mutex.lock()
     }
 
@@ -10,6 +10,12 @@
 //!     参考`spin::Mutex`实现了一套可替换底层支持的锁框架，在此基础上实现了三种锁：
 //!     自旋锁，禁用中断自旋锁，线程调度锁
 //!
+//! * `rwlock`: 读写锁。
+//!     包装`spin::RwLock`，持有期间禁用内核抢占。
+//!
+//! * `preempt`: 内核抢占。
+//!     每个CPU记录持有的自旋锁数量，为0时才允许时钟中断抢占内核态的线程。
+//!
 //! * `condvar`: 条件变量。
 //!     依赖`thread`，为其它工具提供线程调度支持。
 //!
@@ -52,10 +58,13 @@
 
 pub use self::condvar::*;
 pub use self::mutex::*;
+pub use self::rwlock::*;
 pub use self::semaphore::*;
 
 mod condvar;
 pub mod mpsc;
 mod mutex;
+pub mod preempt;
+mod rwlock;
 mod semaphore;
 pub mod test;
@@ -10,6 +10,7 @@
 //!     等价于`spin::Mutex`，相当于Linux中的`spin_lock`。
 //!     当获取锁失败时，忙等待。
 //!     由于没有禁用内核抢占和中断，在单处理器上使用可能发生死锁。
+//!     开启`preempt`特性时，持有期间禁用内核抢占。
 //!
 //! * `SpinNoIrqLock`: 禁止中断的自旋锁。
 //!     相当于Linux中的`spin_lock_irqsave`。
@@ -26,6 +27,7 @@
 //! `MutexSupport`提供了若干接口，它们会在操作锁的不同时间点被调用。
 //! 注意这个接口实际是取了几种实现的并集，并不是很通用。
 
+use super::preempt::PreemptGuard;
 use super::Condvar;
 use crate::arch::interrupt;
 use core::cell::UnsafeCell;
@@ -207,7 +209,7 @@ pub trait MutexSupport {
 pub struct Spin;
 
 impl MutexSupport for Spin {
-    type GuardData = ();
+    type GuardData = PreemptGuard;
 
     fn new() -> Self {
         Spin
@@ -222,7 +224,9 @@ impl MutexSupport for Spin {
             asm!("yield" :::: "volatile");
         }
     }
-    fn before_lock() -> Self::GuardData {}
+    fn before_lock() -> Self::GuardData {
+        PreemptGuard::new()
+    }
     fn after_unlock(&self) {}
 }
 
@@ -246,7 +250,8 @@ impl FlagsGuard {
 }
 
 impl MutexSupport for SpinNoIrq {
-    type GuardData = FlagsGuard;
+    /// Preemption is enabled again before interrupts
+    type GuardData = (PreemptGuard, FlagsGuard);
     fn new() -> Self {
         SpinNoIrq
     }
@@ -261,7 +266,7 @@ impl MutexSupport for SpinNoIrq {
         }
     }
     fn before_lock() -> Self::GuardData {
-        FlagsGuard(unsafe { interrupt::disable_and_store() })
+        (PreemptGuard::new(), FlagsGuard::no_irq_region())
     }
     fn after_unlock(&self) {}
 }
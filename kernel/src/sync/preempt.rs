@@ -0,0 +1,81 @@
+//! Kernel preemption
+//!
+//! With feature `preempt`, syscalls run with interrupts enabled,
+//! so the timer may switch to another thread in the middle of a long syscall.
+//! A thread holding a `SpinLock`, `SpinNoIrqLock` or `RwLock` must not be switched out,
+//! or other threads would spin on the lock for ever,
+//! so each CPU counts the spin locks held by its current thread and is only preempted at zero.
+//! A thread may still sleep holding locks, so the count moves with it on context switches.
+//!
+//! Spin locks of other crates are counted by a `PreemptGuard` held around their use,
+//! e.g. by the kernel heap, and for `rcore_thread` by `process::manager` and `thread`.
+//!
+//! Without `preempt` nothing is counted, and only the code running
+//! with interrupts enabled, i.e. user threads and kernel threads, is preempted.
+
+#[cfg(feature = "preempt")]
+use crate::arch::{cpu, interrupt};
+#[cfg(feature = "preempt")]
+use crate::consts::MAX_CPU_NUM;
+
+/// Number of spin locks held on each CPU, only changed by the CPU with interrupts disabled
+#[cfg(feature = "preempt")]
+static mut PREEMPT_COUNT: [usize; MAX_CPU_NUM] = [0; MAX_CPU_NUM];
+
+/// Disable preemption on current CPU until the guard is dropped
+pub struct PreemptGuard(());
+
+impl PreemptGuard {
+    pub fn new() -> Self {
+        #[cfg(feature = "preempt")]
+        unsafe {
+            let flags = interrupt::disable_and_store();
+            PREEMPT_COUNT[cpu::id()] += 1;
+            interrupt::restore(flags);
+        }
+        PreemptGuard(())
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "preempt")]
+        unsafe {
+            let flags = interrupt::disable_and_store();
+            PREEMPT_COUNT[cpu::id()] -= 1;
+            interrupt::restore(flags);
+        }
+    }
+}
+
+/// Switch the count of current CPU to `count` of the next thread, return the count of last one
+#[cfg(feature = "preempt")]
+pub unsafe fn switch_count(count: usize) -> usize {
+    let flags = interrupt::disable_and_store();
+    let last = core::mem::replace(&mut PREEMPT_COUNT[cpu::id()], count);
+    interrupt::restore(flags);
+    last
+}
+
+/// Switch the count of current CPU to `count` of the next thread, return the count of last one
+#[cfg(not(feature = "preempt"))]
+pub unsafe fn switch_count(_count: usize) -> usize {
+    0
+}
+
+/// Whether the timer may switch out the code it interrupted in kernel mode
+#[cfg(feature = "preempt")]
+pub fn preemptible() -> bool {
+    unsafe {
+        let flags = interrupt::disable_and_store();
+        let count = PREEMPT_COUNT[cpu::id()];
+        interrupt::restore(flags);
+        count == 0
+    }
+}
+
+/// Whether the timer may switch out the code it interrupted in kernel mode
+#[cfg(not(feature = "preempt"))]
+pub fn preemptible() -> bool {
+    true
+}
@@ -0,0 +1,69 @@
+//! Reader-writer spin lock
+//!
+//! `spin::RwLock` with kernel preemption disabled while held, like `SpinLock`.
+
+use super::preempt::PreemptGuard;
+use core::ops::{Deref, DerefMut};
+
+pub struct RwLock<T: ?Sized>(spin::RwLock<T>);
+
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    // unlocked before preemption is enabled again
+    guard: spin::RwLockReadGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    guard: spin::RwLockWriteGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+impl<T> RwLock<T> {
+    pub fn new(data: T) -> Self {
+        RwLock(spin::RwLock::new(data))
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        let _preempt = PreemptGuard::new();
+        RwLockReadGuard {
+            guard: self.0.read(),
+            _preempt,
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let _preempt = PreemptGuard::new();
+        RwLockWriteGuard {
+            guard: self.0.write(),
+            _preempt,
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
@@ -7,6 +7,7 @@ use rcore_memory::memory_set::MemoryAttr;
 /// The kernel driver using the PCI device will be unloaded
 #[cfg(target_arch = "x86_64")]
 pub fn sys_map_pci_device(vendor: usize, product: usize) -> SysResult {
+    use crate::consts::USER_END;
     use crate::drivers::bus::pci;
     info!(
         "map_pci_device: vendor: {:x}, product: {:x}",
@@ -22,7 +23,10 @@ pub fn sys_map_pci_device(vendor: usize, product: usize) -> SysResult {
     let (base, len) = pci::get_bar0_mem(tag).ok_or(SysError::ENOENT)?;
 
     let mut proc = process();
-    let virt_addr = proc.vm.find_free_area(0, len);
+    let virt_addr = proc
+        .vm
+        .find_free_area(0, len, USER_END)
+        .ok_or(SysError::ENOMEM)?;
     let attr = MemoryAttr::default().user();
     proc.vm.push(
         virt_addr,
@@ -30,7 +34,7 @@ pub fn sys_map_pci_device(vendor: usize, product: usize) -> SysResult {
         attr,
         Linear::new(base as isize - virt_addr as isize),
         "pci",
-    );
+    )?;
     Ok(virt_addr)
 }
 
@@ -1,86 +1,163 @@
 //! Syscalls for file system
 
-use core::cell::UnsafeCell;
+use alloc::collections::BTreeMap;
 use core::cmp::min;
 use core::mem::size_of;
+use core::ops::{Deref, DerefMut};
 #[cfg(not(target_arch = "mips"))]
 use rcore_fs::vfs::Timespec;
 
 use crate::drivers::SOCKET_ACTIVITY;
 use crate::fs::*;
-use crate::memory::MemorySet;
-use crate::sync::Condvar;
+use crate::process::rlimit::RLIMIT_NOFILE;
+use crate::sync::{Condvar, MutexGuard, SpinNoIrq, SpinNoIrqLock};
 
 use bitvec::prelude::{BitSlice, BitVec, LittleEndian};
 
 use super::*;
 
-pub fn sys_read(fd: usize, base: *mut u8, len: usize) -> SysResult {
-    let mut proc = process();
-    if !proc.pid.is_init() {
-        // we trust pid 0 process
-        info!("read: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
-    }
-    proc.vm.check_write_array(base, len)?;
-    let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.read(slice)?;
-    Ok(len)
+/// Max size of the kernel buffer of a read or write, whose length is given by user.
+/// Regular files are read and written in chunks of it, other files at most once,
+/// as another read or write may block. It holds the largest UDP datagram.
+pub const MAX_CHUNK_SIZE: usize = 0x10000;
+
+pub fn sys_read(fd: usize, base: UserOutPtr<u8>, len: usize) -> SysResult {
+    let mut file_like = {
+        let proc = process();
+        if !proc.pid.is_init() {
+            // we trust pid 0 process
+            info!("read: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
+        }
+        // check the buffer first, so that no data read is lost
+        proc.vm.check_write_array(base.as_ptr(), len)?;
+        proc.copy_file_like(fd)?
+    };
+    let once = !is_regular(&file_like);
+    read_chunks(base, len, once, |_, buf| file_like.read(buf))
 }
 
-pub fn sys_write(fd: usize, base: *const u8, len: usize) -> SysResult {
-    let mut proc = process();
-    if !proc.pid.is_init() {
-        // we trust pid 0 process
-        info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
-    }
-    proc.vm.check_read_array(base, len)?;
-    let slice = unsafe { slice::from_raw_parts(base, len) };
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.write(slice)?;
-    Ok(len)
+pub fn sys_write(fd: usize, base: UserInPtr<u8>, len: usize) -> SysResult {
+    let mut file_like = {
+        let proc = process();
+        if !proc.pid.is_init() {
+            // we trust pid 0 process
+            info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
+        }
+        proc.vm.check_read_array(base.as_ptr(), len)?;
+        proc.copy_file_like(fd)?
+    };
+    let once = !is_regular(&file_like);
+    write_chunks(base, len, once, |_, buf| file_like.write(buf))
 }
 
-pub fn sys_pread(fd: usize, base: *mut u8, len: usize, offset: usize) -> SysResult {
+pub fn sys_pread(fd: usize, base: UserOutPtr<u8>, len: usize, offset: usize) -> SysResult {
     info!(
         "pread: fd: {}, base: {:?}, len: {}, offset: {}",
         fd, base, len, offset
     );
-    let mut proc = process();
-    proc.vm.check_write_array(base, len)?;
-
-    let slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let len = proc.get_file(fd)?.read_at(offset, slice)?;
-    Ok(len)
+    let mut file_like = {
+        let proc = process();
+        proc.vm.check_write_array(base.as_ptr(), len)?;
+        proc.copy_file_like(fd)?
+    };
+    let file = file_like.file()?;
+    read_chunks(base, len, false, |pos, buf| {
+        Ok(file.read_at(offset + pos, buf)?)
+    })
 }
 
-pub fn sys_pwrite(fd: usize, base: *const u8, len: usize, offset: usize) -> SysResult {
+pub fn sys_pwrite(fd: usize, base: UserInPtr<u8>, len: usize, offset: usize) -> SysResult {
     info!(
         "pwrite: fd: {}, base: {:?}, len: {}, offset: {}",
         fd, base, len, offset
     );
-    let mut proc = process();
-    proc.vm.check_read_array(base, len)?;
+    let mut file_like = {
+        let proc = process();
+        proc.vm.check_read_array(base.as_ptr(), len)?;
+        proc.copy_file_like(fd)?
+    };
+    let file = file_like.file()?;
+    write_chunks(base, len, false, |pos, buf| {
+        Ok(file.write_at(offset + pos, buf)?)
+    })
+}
+
+/// Whether `file_like` is a regular file, which can be read and written in chunks
+fn is_regular(file_like: &FileLike) -> bool {
+    match file_like {
+        FileLike::File(file) => file
+            .metadata()
+            .map_or(false, |metadata| metadata.type_ == FileType::File),
+        FileLike::Socket(_) => false,
+    }
+}
 
-    let slice = unsafe { slice::from_raw_parts(base, len) };
-    let len = proc.get_file(fd)?.write_at(offset, slice)?;
-    Ok(len)
+/// Read `len` bytes to `base` in chunks by `read(pos, buf)`, where `pos` is the bytes read so far.
+/// Stop at a short read, or after the first one if `once`.
+/// An error after some bytes are read is dropped, like a short read.
+fn read_chunks(
+    base: UserOutPtr<u8>,
+    len: usize,
+    once: bool,
+    mut read: impl FnMut(usize, &mut [u8]) -> SysResult,
+) -> SysResult {
+    let mut buf = vec![0u8; len.min(MAX_CHUNK_SIZE)];
+    let mut pos = 0;
+    while pos < len {
+        let chunk_len = (len - pos).min(MAX_CHUNK_SIZE);
+        let read_len = match read(pos, &mut buf[..chunk_len]) {
+            Ok(read_len) => read_len,
+            Err(err) if pos == 0 => return Err(err),
+            Err(_) => break,
+        };
+        base.add(pos).write_array(&buf[..read_len])?;
+        pos += read_len;
+        if once || read_len < chunk_len {
+            break;
+        }
+    }
+    Ok(pos)
 }
 
-pub fn sys_ppoll(ufds: *mut PollFd, nfds: usize, timeout: *const TimeSpec) -> SysResult {
-    let proc = process();
+/// Write `len` bytes from `base` in chunks by `write(pos, buf)`. See `read_chunks`.
+fn write_chunks(
+    base: UserInPtr<u8>,
+    len: usize,
+    once: bool,
+    mut write: impl FnMut(usize, &[u8]) -> SysResult,
+) -> SysResult {
+    let mut pos = 0;
+    while pos < len {
+        let chunk_len = (len - pos).min(MAX_CHUNK_SIZE);
+        let buf = base.add(pos).read_array(chunk_len)?;
+        let written_len = match write(pos, &buf) {
+            Ok(written_len) => written_len,
+            Err(err) if pos == 0 => return Err(err),
+            Err(_) => break,
+        };
+        pos += written_len;
+        if once || written_len < chunk_len {
+            break;
+        }
+    }
+    Ok(pos)
+}
+
+pub fn sys_ppoll(
+    ufds: UserInOutPtr<PollFd>,
+    nfds: usize,
+    timeout: UserInPtr<TimeSpec>,
+) -> SysResult {
     let timeout_msecs = if timeout.is_null() {
         1 << 31 // infinity
     } else {
-        proc.vm.check_read_ptr(timeout)?;
-        unsafe { (*timeout).to_msec() }
+        timeout.read()?.to_msec()
     };
-    drop(proc);
 
     sys_poll(ufds, nfds, timeout_msecs as usize)
 }
 
-pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResult {
+pub fn sys_poll(mut ufds: UserInOutPtr<PollFd>, nfds: usize, timeout_msecs: usize) -> SysResult {
     let proc = process();
     if !proc.pid.is_init() {
         // we trust pid 0 process
@@ -89,11 +166,10 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
             ufds, nfds, timeout_msecs
         );
     }
-    proc.vm.check_write_array(ufds, nfds)?;
 
-    let polls = unsafe { slice::from_raw_parts_mut(ufds, nfds) };
+    let mut polls = ufds.read_array(nfds)?;
     for poll in polls.iter() {
-        if proc.files.get(&(poll.fd as usize)).is_none() {
+        if proc.files.lock().get(&(poll.fd as usize)).is_none() {
             return Err(SysError::EINVAL);
         }
     }
@@ -106,7 +182,7 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
         let mut events = 0;
         for poll in polls.iter_mut() {
             poll.revents = PE::empty();
-            if let Some(file_like) = proc.files.get(&(poll.fd as usize)) {
+            if let Some(file_like) = proc.files.lock().get(&(poll.fd as usize)) {
                 let status = file_like.poll()?;
                 if status.error {
                     poll.revents |= PE::HUP;
@@ -128,11 +204,13 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
         drop(proc);
 
         if events > 0 {
+            ufds.write_array(&polls)?;
             return Ok(events);
         }
 
         let current_time_ms = crate::trap::uptime_msec();
         if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs {
+            ufds.write_array(&polls)?;
             return Ok(0);
         }
 
@@ -142,34 +220,31 @@ pub fn sys_poll(ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResu
 
 pub fn sys_select(
     nfds: usize,
-    read: *mut u32,
-    write: *mut u32,
-    err: *mut u32,
-    timeout: *const TimeVal,
+    read: UserInOutPtr<u32>,
+    write: UserInOutPtr<u32>,
+    err: UserInOutPtr<u32>,
+    timeout: UserInPtr<TimeVal>,
 ) -> SysResult {
     info!(
         "select: nfds: {}, read: {:?}, write: {:?}, err: {:?}, timeout: {:?}",
         nfds, read, write, err, timeout
     );
 
-    let proc = process();
-    let mut read_fds = FdSet::new(&proc.vm, read, nfds)?;
-    let mut write_fds = FdSet::new(&proc.vm, write, nfds)?;
-    let mut err_fds = FdSet::new(&proc.vm, err, nfds)?;
-    let timeout_msecs = if timeout as usize != 0 {
-        proc.vm.check_read_ptr(timeout)?;
-        unsafe { *timeout }.to_msec()
+    let mut read_fds = FdSet::new(read, nfds)?;
+    let mut write_fds = FdSet::new(write, nfds)?;
+    let mut err_fds = FdSet::new(err, nfds)?;
+    let timeout_msecs = if !timeout.is_null() {
+        timeout.read()?.to_msec()
     } else {
         // infinity
         1 << 31
     };
-    drop(proc);
 
     let begin_time_ms = crate::trap::uptime_msec();
     loop {
         let proc = process();
         let mut events = 0;
-        for (&fd, file_like) in proc.files.iter() {
+        for (&fd, file_like) in proc.files.lock().iter() {
             if fd >= nfds {
                 continue;
             }
@@ -189,68 +264,86 @@ pub fn sys_select(
         }
         drop(proc);
 
-        if events > 0 {
-            return Ok(events);
-        }
-
-        if timeout_msecs == 0 {
-            // no timeout, return now;
-            return Ok(0);
-        }
-
         let current_time_ms = crate::trap::uptime_msec();
         // infinity check
-        if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs as usize {
-            return Ok(0);
+        let timed_out =
+            timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs as usize;
+        // no timeout, return now
+        if events > 0 || timeout_msecs == 0 || timed_out {
+            read_fds.write_back()?;
+            write_fds.write_back()?;
+            err_fds.write_back()?;
+            return Ok(events);
         }
 
         Condvar::wait_any(&[&STDIN.pushed, &(*SOCKET_ACTIVITY)]);
     }
 }
 
-pub fn sys_readv(fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResult {
+pub fn sys_readv(fd: usize, iov_ptr: UserInPtr<IoVec>, iov_count: usize) -> SysResult {
     info!(
         "readv: fd: {}, iov: {:?}, count: {}",
         fd, iov_ptr, iov_count
     );
-    let mut proc = process();
-    let mut iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, true)?;
-
-    // read all data to a buf
-    let file_like = proc.get_file_like(fd)?;
-    let mut buf = iovs.new_buf(true);
-    let len = file_like.read(buf.as_mut_slice())?;
-    // copy data to user
-    iovs.write_all_from_slice(&buf[..len]);
-    Ok(len)
+    let iovs = IoVecs::new(iov_ptr, iov_count)?;
+    let mut file_like = {
+        let proc = process();
+        iovs.check_writable(&proc.vm)?;
+        proc.copy_file_like(fd)?
+    };
+    let once = !is_regular(&file_like);
+    let mut total = 0;
+    for iov in iovs.0.iter() {
+        let base = UserOutPtr::from(iov.base.as_ptr() as usize);
+        let len = read_chunks(base, iov.len, once, |_, buf| file_like.read(buf))?;
+        total += len;
+        if once || len < iov.len {
+            break;
+        }
+    }
+    Ok(total)
 }
 
-pub fn sys_writev(fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResult {
-    let mut proc = process();
-    if !proc.pid.is_init() {
+pub fn sys_writev(fd: usize, iov_ptr: UserInPtr<IoVec>, iov_count: usize) -> SysResult {
+    if !process().pid.is_init() {
         // we trust pid 0 process
         info!(
             "writev: fd: {}, iov: {:?}, count: {}",
             fd, iov_ptr, iov_count
         );
     }
-    let iovs = IoVecs::check_and_new(iov_ptr, iov_count, &proc.vm, false)?;
-
-    let buf = iovs.read_all_to_vec();
-    let len = buf.len();
-
-    let file_like = proc.get_file_like(fd)?;
-    let len = file_like.write(buf.as_slice())?;
-    Ok(len)
+    let iovs = IoVecs::new(iov_ptr, iov_count)?;
+    let mut file_like = {
+        let proc = process();
+        for iov in iovs.0.iter() {
+            proc.vm.check_read_array(iov.base.as_ptr(), iov.len)?;
+        }
+        proc.copy_file_like(fd)?
+    };
+    // a socket sends the data at once, e.g. as a datagram
+    if !is_regular(&file_like) {
+        let buf = iovs.read_to_vec(MAX_CHUNK_SIZE)?;
+        return file_like.write(&buf);
+    }
+    let mut total = 0;
+    for iov in iovs.0.iter() {
+        let base = UserInPtr::from(iov.base.as_ptr() as usize);
+        let len = write_chunks(base, iov.len, false, |_, buf| file_like.write(buf))?;
+        total += len;
+        if len < iov.len {
+            break;
+        }
+    }
+    Ok(total)
 }
 
-pub fn sys_open(path: *const u8, flags: usize, mode: usize) -> SysResult {
+pub fn sys_open(path: UserInPtr<u8>, flags: usize, mode: usize) -> SysResult {
     sys_openat(AT_FDCWD, path, flags, mode)
 }
 
-pub fn sys_openat(dir_fd: usize, path: *const u8, flags: usize, mode: usize) -> SysResult {
-    let mut proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+pub fn sys_openat(dir_fd: usize, path: UserInPtr<u8>, flags: usize, mode: usize) -> SysResult {
+    let proc = process();
+    let path = path.read_cstring()?;
     let flags = OpenFlags::from_bits_truncate(flags);
     info!(
         "openat: dir_fd: {}, path: {:?}, flags: {:?}, mode: {:#o}",
@@ -277,28 +370,26 @@ pub fn sys_openat(dir_fd: usize, path: *const u8, flags: usize, mode: usize) ->
         proc.lookup_inode_at(dir_fd, &path, true)?
     };
 
-    let fd = proc.get_free_fd();
-
-    let file = FileHandle::new(inode, flags.to_options());
-    proc.files.insert(fd, FileLike::File(file));
-    Ok(fd)
+    let mut file = FileHandle::new(inode, flags.to_options());
+    file.fd_cloexec = flags.contains(OpenFlags::CLOEXEC);
+    proc.add_file(FileLike::File(file))
 }
 
 pub fn sys_close(fd: usize) -> SysResult {
     info!("close: fd: {:?}", fd);
-    let mut proc = process();
-    proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+    let proc = process();
+    proc.files.lock().remove(&fd).ok_or(SysError::EBADF)?;
     Ok(0)
 }
 
-pub fn sys_access(path: *const u8, mode: usize) -> SysResult {
+pub fn sys_access(path: UserInPtr<u8>, mode: usize) -> SysResult {
     sys_faccessat(AT_FDCWD, path, mode, 0)
 }
 
-pub fn sys_faccessat(dirfd: usize, path: *const u8, mode: usize, flags: usize) -> SysResult {
+pub fn sys_faccessat(dirfd: usize, path: UserInPtr<u8>, mode: usize, flags: usize) -> SysResult {
     // TODO: check permissions based on uid/gid
     let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let path = path.read_cstring()?;
     let flags = AtFlags::from_bits_truncate(flags);
     if !proc.pid.is_init() {
         // we trust pid 0 process
@@ -311,40 +402,41 @@ pub fn sys_faccessat(dirfd: usize, path: *const u8, mode: usize, flags: usize) -
     Ok(0)
 }
 
-pub fn sys_getcwd(buf: *mut u8, len: usize) -> SysResult {
+pub fn sys_getcwd(mut buf: UserOutPtr<u8>, len: usize) -> SysResult {
     let proc = process();
     if !proc.pid.is_init() {
         // we trust pid 0 process
         info!("getcwd: buf: {:?}, len: {:#x}", buf, len);
     }
-    proc.vm.check_write_array(buf, len)?;
-    if proc.cwd.len() + 1 > len {
+    let cwd = proc.cwd.lock();
+    if cwd.len() + 1 > len {
         return Err(SysError::ERANGE);
     }
-    unsafe { util::write_cstr(buf, &proc.cwd) }
-    Ok(buf as usize)
+    buf.write_cstring(&cwd)?;
+    Ok(buf.as_ptr() as usize)
 }
 
-pub fn sys_lstat(path: *const u8, stat_ptr: *mut Stat) -> SysResult {
+pub fn sys_lstat(path: UserInPtr<u8>, stat_ptr: UserOutPtr<Stat>) -> SysResult {
     sys_fstatat(AT_FDCWD, path, stat_ptr, AtFlags::SYMLINK_NOFOLLOW.bits())
 }
 
-pub fn sys_fstat(fd: usize, stat_ptr: *mut Stat) -> SysResult {
+pub fn sys_fstat(fd: usize, mut stat_ptr: UserOutPtr<Stat>) -> SysResult {
     info!("fstat: fd: {}, stat_ptr: {:?}", fd, stat_ptr);
     let mut proc = process();
-    proc.vm.check_write_ptr(stat_ptr)?;
     let file = proc.get_file(fd)?;
     let stat = Stat::from(file.metadata()?);
-    unsafe {
-        stat_ptr.write(stat);
-    }
+    stat_ptr.write(stat)?;
     Ok(0)
 }
 
-pub fn sys_fstatat(dirfd: usize, path: *const u8, stat_ptr: *mut Stat, flags: usize) -> SysResult {
+pub fn sys_fstatat(
+    dirfd: usize,
+    path: UserInPtr<u8>,
+    mut stat_ptr: UserOutPtr<Stat>,
+    flags: usize,
+) -> SysResult {
     let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
-    proc.vm.check_write_ptr(stat_ptr)?;
+    let path = path.read_cstring()?;
     let flags = AtFlags::from_bits_truncate(flags);
     info!(
         "fstatat: dirfd: {}, path: {:?}, stat_ptr: {:?}, flags: {:?}",
@@ -353,31 +445,40 @@ pub fn sys_fstatat(dirfd: usize, path: *const u8, stat_ptr: *mut Stat, flags: us
 
     let inode = proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
     let stat = Stat::from(inode.metadata()?);
-    unsafe {
-        stat_ptr.write(stat);
-    }
+    stat_ptr.write(stat)?;
     Ok(0)
 }
 
-pub fn sys_stat(path: *const u8, stat_ptr: *mut Stat) -> SysResult {
+pub fn sys_stat(path: UserInPtr<u8>, stat_ptr: UserOutPtr<Stat>) -> SysResult {
     sys_fstatat(AT_FDCWD, path, stat_ptr, 0)
 }
 
-pub fn sys_readlink(path: *const u8, base: *mut u8, len: usize) -> SysResult {
+pub fn sys_readlink(path: UserInPtr<u8>, base: UserOutPtr<u8>, len: usize) -> SysResult {
     sys_readlinkat(AT_FDCWD, path, base, len)
 }
 
-pub fn sys_readlinkat(dirfd: usize, path: *const u8, base: *mut u8, len: usize) -> SysResult {
+pub fn sys_readlinkat(
+    dirfd: usize,
+    path: UserInPtr<u8>,
+    mut base: UserOutPtr<u8>,
+    len: usize,
+) -> SysResult {
     let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
-    proc.vm.check_write_array(base, len)?;
+    let path = path.read_cstring()?;
     info!("readlink: path: {:?}, base: {:?}, len: {}", path, base, len);
 
+    if path == "/proc/self/exe" {
+        let exe = proc.exec_path.as_bytes();
+        let len = exe.len().min(len);
+        base.write_array(&exe[..len])?;
+        return Ok(len);
+    }
     let inode = proc.lookup_inode_at(dirfd, &path, false)?;
     if inode.metadata()?.type_ == FileType::SymLink {
         // TODO: recursive link resolution and loop detection
-        let mut slice = unsafe { slice::from_raw_parts_mut(base, len) };
-        let len = inode.read_at(0, &mut slice)?;
+        let mut buf = vec![0u8; len.min(MAX_CHUNK_SIZE)];
+        let len = inode.read_at(0, &mut buf)?;
+        base.write_array(&buf[..len])?;
         Ok(len)
     } else {
         Err(SysError::EINVAL)
@@ -394,7 +495,7 @@ pub fn sys_lseek(fd: usize, offset: i64, whence: u8) -> SysResult {
     info!("lseek: fd: {}, pos: {:?}", fd, pos);
 
     let mut proc = process();
-    let file = proc.get_file(fd)?;
+    let mut file = proc.get_file(fd)?;
     let offset = file.seek(pos)?;
     Ok(offset as usize)
 }
@@ -411,9 +512,9 @@ pub fn sys_fdatasync(fd: usize) -> SysResult {
     Ok(0)
 }
 
-pub fn sys_truncate(path: *const u8, len: usize) -> SysResult {
+pub fn sys_truncate(path: UserInPtr<u8>, len: usize) -> SysResult {
     let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let path = path.read_cstring()?;
     info!("truncate: path: {:?}, len: {}", path, len);
     proc.lookup_inode(&path)?.resize(len)?;
     Ok(0)
@@ -425,19 +526,18 @@ pub fn sys_ftruncate(fd: usize, len: usize) -> SysResult {
     Ok(0)
 }
 
-pub fn sys_getdents64(fd: usize, buf: *mut LinuxDirent64, buf_size: usize) -> SysResult {
+pub fn sys_getdents64(fd: usize, mut buf: UserOutPtr<u8>, buf_size: usize) -> SysResult {
     info!(
         "getdents64: fd: {}, ptr: {:?}, buf_size: {}",
         fd, buf, buf_size
     );
     let mut proc = process();
-    proc.vm.check_write_array(buf as *mut u8, buf_size)?;
-    let file = proc.get_file(fd)?;
+    let mut file = proc.get_file(fd)?;
     let info = file.metadata()?;
     if info.type_ != FileType::Dir {
         return Err(SysError::ENOTDIR);
     }
-    let mut writer = unsafe { DirentBufWriter::new(buf, buf_size) };
+    let mut writer = DirentBufWriter::new(buf_size);
     loop {
         let name = match file.read_entry() {
             Err(FsError::EntryNotFound) => break,
@@ -449,33 +549,76 @@ pub fn sys_getdents64(fd: usize, buf: *mut LinuxDirent64, buf_size: usize) -> Sy
             break;
         }
     }
-    Ok(writer.written_size)
+    buf.write_array(&writer.buf)?;
+    Ok(writer.buf.len())
 }
 
 pub fn sys_dup2(fd1: usize, fd2: usize) -> SysResult {
     info!("dup2: from {} to {}", fd1, fd2);
-    let mut proc = process();
-    // close fd2 first if it is opened
-    proc.files.remove(&fd2);
+    let proc = process();
+    if fd2 as u64 >= proc.rlimits.get(RLIMIT_NOFILE).cur {
+        return Err(SysError::EBADF);
+    }
+    let mut files = proc.files.lock();
+    let mut file_like = files.get(&fd1).ok_or(SysError::EBADF)?.clone();
+    // the new fd is kept by `exec`
+    if let FileLike::File(file) = &mut file_like {
+        file.fd_cloexec = false;
+    }
+    // close fd2 if it is opened
+    files.insert(fd2, file_like);
+    Ok(fd2)
+}
 
-    let file_like = proc.get_file_like(fd1)?.clone();
-    proc.files.insert(fd2, file_like);
+/// Like `dup2`, but `fd1` must differ from `fd2`, and `flags` may have `O_CLOEXEC`
+pub fn sys_dup3(fd1: usize, fd2: usize, flags: usize) -> SysResult {
+    info!("dup3: from {} to {}, flags: {:#x}", fd1, fd2, flags);
+    let flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if fd1 == fd2 || !OpenFlags::CLOEXEC.contains(flags) {
+        return Err(SysError::EINVAL);
+    }
+    sys_dup2(fd1, fd2)?;
+    if let Some(FileLike::File(file)) = process().files.lock().get_mut(&fd2) {
+        file.fd_cloexec = flags.contains(OpenFlags::CLOEXEC);
+    }
     Ok(fd2)
 }
 
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const FD_CLOEXEC: usize = 1;
+
+/// Only the close-on-exec flag of files is supported
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> SysResult {
+    info!("fcntl: fd: {}, cmd: {}, arg: {:#x}", fd, cmd, arg);
+    let proc = process();
+    let mut files = proc.files.lock();
+    match (cmd, files.get_mut(&fd).ok_or(SysError::EBADF)?) {
+        (F_GETFD, FileLike::File(file)) => Ok(if file.fd_cloexec { FD_CLOEXEC } else { 0 }),
+        (F_SETFD, FileLike::File(file)) => {
+            file.fd_cloexec = arg & FD_CLOEXEC != 0;
+            Ok(0)
+        }
+        _ => {
+            warn!("sys_fcntl: cmd {} is unimplemented", cmd);
+            Ok(0)
+        }
+    }
+}
+
 pub fn sys_ioctl(fd: usize, request: usize, arg1: usize, arg2: usize, arg3: usize) -> SysResult {
     info!(
         "ioctl: fd: {}, request: {:x}, args: {} {} {}",
         fd, request, arg1, arg2, arg3
     );
     let mut proc = process();
-    let file_like = proc.get_file_like(fd)?;
+    let mut file_like = proc.get_file_like(fd)?;
     file_like.ioctl(request, arg1, arg2, arg3)
 }
 
-pub fn sys_chdir(path: *const u8) -> SysResult {
-    let mut proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+pub fn sys_chdir(path: UserInPtr<u8>) -> SysResult {
+    let proc = process();
+    let path = path.read_cstring()?;
     if !proc.pid.is_init() {
         // we trust pid 0 process
         info!("chdir: path: {:?}", path);
@@ -491,7 +634,7 @@ pub fn sys_chdir(path: *const u8) -> SysResult {
     if path.len() > 0 {
         let cwd = match path.as_bytes()[0] {
             b'/' => String::from("/"),
-            _ => proc.cwd.clone(),
+            _ => proc.cwd.lock().clone(),
         };
         let mut cwd_vec: Vec<_> = cwd.split("/").filter(|&x| x != "").collect();
         let path_split = path.split("/").filter(|&x| x != "");
@@ -504,31 +647,32 @@ pub fn sys_chdir(path: *const u8) -> SysResult {
                 cwd_vec.push(seg);
             }
         }
-        proc.cwd = String::from("");
+        let mut cwd = proc.cwd.lock();
+        *cwd = String::from("");
         for seg in cwd_vec {
-            proc.cwd.push_str("/");
-            proc.cwd.push_str(seg);
+            cwd.push_str("/");
+            cwd.push_str(seg);
         }
-        if proc.cwd == "" {
-            proc.cwd = String::from("/");
+        if *cwd == "" {
+            *cwd = String::from("/");
         }
     }
     Ok(0)
 }
 
-pub fn sys_rename(oldpath: *const u8, newpath: *const u8) -> SysResult {
+pub fn sys_rename(oldpath: UserInPtr<u8>, newpath: UserInPtr<u8>) -> SysResult {
     sys_renameat(AT_FDCWD, oldpath, AT_FDCWD, newpath)
 }
 
 pub fn sys_renameat(
     olddirfd: usize,
-    oldpath: *const u8,
+    oldpath: UserInPtr<u8>,
     newdirfd: usize,
-    newpath: *const u8,
+    newpath: UserInPtr<u8>,
 ) -> SysResult {
     let mut proc = process();
-    let oldpath = unsafe { proc.vm.check_and_clone_cstr(oldpath)? };
-    let newpath = unsafe { proc.vm.check_and_clone_cstr(newpath)? };
+    let oldpath = oldpath.read_cstring()?;
+    let newpath = newpath.read_cstring()?;
     info!(
         "renameat: olddirfd: {}, oldpath: {:?}, newdirfd: {}, newpath: {:?}",
         olddirfd as isize, oldpath, newdirfd as isize, newpath
@@ -542,13 +686,13 @@ pub fn sys_renameat(
     Ok(0)
 }
 
-pub fn sys_mkdir(path: *const u8, mode: usize) -> SysResult {
+pub fn sys_mkdir(path: UserInPtr<u8>, mode: usize) -> SysResult {
     sys_mkdirat(AT_FDCWD, path, mode)
 }
 
-pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: usize) -> SysResult {
+pub fn sys_mkdirat(dirfd: usize, path: UserInPtr<u8>, mode: usize) -> SysResult {
     let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let path = path.read_cstring()?;
     // TODO: check pathname
     info!(
         "mkdirat: dirfd: {}, path: {:?}, mode: {:#o}",
@@ -564,9 +708,9 @@ pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: usize) -> SysResult {
     Ok(0)
 }
 
-pub fn sys_rmdir(path: *const u8) -> SysResult {
+pub fn sys_rmdir(path: UserInPtr<u8>) -> SysResult {
     let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let path = path.read_cstring()?;
     info!("rmdir: path: {:?}", path);
 
     let (dir_path, file_name) = split_path(&path);
@@ -579,20 +723,20 @@ pub fn sys_rmdir(path: *const u8) -> SysResult {
     Ok(0)
 }
 
-pub fn sys_link(oldpath: *const u8, newpath: *const u8) -> SysResult {
+pub fn sys_link(oldpath: UserInPtr<u8>, newpath: UserInPtr<u8>) -> SysResult {
     sys_linkat(AT_FDCWD, oldpath, AT_FDCWD, newpath, 0)
 }
 
 pub fn sys_linkat(
     olddirfd: usize,
-    oldpath: *const u8,
+    oldpath: UserInPtr<u8>,
     newdirfd: usize,
-    newpath: *const u8,
+    newpath: UserInPtr<u8>,
     flags: usize,
 ) -> SysResult {
     let proc = process();
-    let oldpath = unsafe { proc.vm.check_and_clone_cstr(oldpath)? };
-    let newpath = unsafe { proc.vm.check_and_clone_cstr(newpath)? };
+    let oldpath = oldpath.read_cstring()?;
+    let newpath = newpath.read_cstring()?;
     let flags = AtFlags::from_bits_truncate(flags);
     info!(
         "linkat: olddirfd: {}, oldpath: {:?}, newdirfd: {}, newpath: {:?}, flags: {:?}",
@@ -606,13 +750,13 @@ pub fn sys_linkat(
     Ok(0)
 }
 
-pub fn sys_unlink(path: *const u8) -> SysResult {
+pub fn sys_unlink(path: UserInPtr<u8>) -> SysResult {
     sys_unlinkat(AT_FDCWD, path, 0)
 }
 
-pub fn sys_unlinkat(dirfd: usize, path: *const u8, flags: usize) -> SysResult {
+pub fn sys_unlinkat(dirfd: usize, path: UserInPtr<u8>, flags: usize) -> SysResult {
     let proc = process();
-    let path = unsafe { proc.vm.check_and_clone_cstr(path)? };
+    let path = path.read_cstring()?;
     let flags = AtFlags::from_bits_truncate(flags);
     info!(
         "unlinkat: dirfd: {}, path: {:?}, flags: {:?}",
@@ -629,42 +773,51 @@ pub fn sys_unlinkat(dirfd: usize, path: *const u8, flags: usize) -> SysResult {
     Ok(0)
 }
 
-pub fn sys_pipe(fds: *mut u32) -> SysResult {
-    info!("pipe: fds: {:?}", fds);
+pub fn sys_pipe(fds: UserOutPtr<u32>) -> SysResult {
+    sys_pipe2(fds, 0)
+}
 
-    let mut proc = process();
-    proc.vm.check_write_array(fds, 2)?;
+/// Like `pipe`, and `flags` may have `O_CLOEXEC`
+pub fn sys_pipe2(mut fds: UserOutPtr<u32>, flags: usize) -> SysResult {
+    info!("pipe: fds: {:?}, flags: {:#x}", fds, flags);
+    let cloexec = OpenFlags::from_bits_truncate(flags).contains(OpenFlags::CLOEXEC);
+
+    let proc = process();
     let (read, write) = Pipe::create_pair();
 
-    let read_fd = proc.get_free_fd();
-    proc.files.insert(
-        read_fd,
-        FileLike::File(FileHandle::new(
-            Arc::new(read),
-            OpenOptions {
-                read: true,
-                write: false,
-                append: false,
-            },
-        )),
+    let mut read_file = FileHandle::new(
+        Arc::new(read),
+        OpenOptions {
+            read: true,
+            write: false,
+            append: false,
+        },
     );
+    read_file.fd_cloexec = cloexec;
+    let read_fd = proc.add_file(FileLike::File(read_file))?;
 
-    let write_fd = proc.get_free_fd();
-    proc.files.insert(
-        write_fd,
-        FileLike::File(FileHandle::new(
-            Arc::new(write),
-            OpenOptions {
-                read: false,
-                write: true,
-                append: false,
-            },
-        )),
+    let mut write_file = FileHandle::new(
+        Arc::new(write),
+        OpenOptions {
+            read: false,
+            write: true,
+            append: false,
+        },
     );
+    write_file.fd_cloexec = cloexec;
+    let write_fd = match proc.add_file(FileLike::File(write_file)) {
+        Ok(fd) => fd,
+        Err(err) => {
+            proc.files.lock().remove(&read_fd);
+            return Err(err);
+        }
+    };
 
-    unsafe {
-        fds.write(read_fd as u32);
-        fds.add(1).write(write_fd as u32);
+    if let Err(err) = fds.write_array(&[read_fd as u32, write_fd as u32]) {
+        let mut files = proc.files.lock();
+        files.remove(&read_fd);
+        files.remove(&write_fd);
+        return Err(err);
     }
 
     info!("pipe: created rfd: {} wfd: {}", read_fd, write_fd);
@@ -680,25 +833,23 @@ pub fn sys_sync() -> SysResult {
 pub fn sys_sendfile(
     out_fd: usize,
     in_fd: usize,
-    offset_ptr: *mut usize,
+    mut offset_ptr: UserInOutPtr<usize>,
     count: usize,
 ) -> SysResult {
     info!(
         "sendfile: out: {}, in: {}, offset_ptr: {:?}, count: {}",
         out_fd, in_fd, offset_ptr, count
     );
-    let proc = process();
-    // We know it's save, pacify the borrow checker
-    let proc_cell = UnsafeCell::new(proc);
-    let in_file = unsafe { (*proc_cell.get()).get_file(in_fd)? };
-    let out_file = unsafe { (*proc_cell.get()).get_file(out_fd)? };
+    let (mut in_copy, mut out_copy) = {
+        let proc = process();
+        (proc.copy_file_like(in_fd)?, proc.copy_file_like(out_fd)?)
+    };
+    let in_file = in_copy.file()?;
+    let out_file = out_copy.file()?;
     let mut buffer = [0u8; 1024];
 
     let mut read_offset = if !offset_ptr.is_null() {
-        unsafe {
-            (*proc_cell.get()).vm.check_read_ptr(offset_ptr)?;
-            offset_ptr.read()
-        }
+        offset_ptr.read()?
     } else {
         in_file.seek(SeekFrom::Current(0))? as usize
     };
@@ -724,25 +875,125 @@ pub fn sys_sendfile(
     }
 
     if !offset_ptr.is_null() {
-        unsafe {
-            offset_ptr.write(read_offset);
-        }
+        offset_ptr.write(read_offset)?;
     } else {
         in_file.seek(SeekFrom::Current(bytes_read as i64))?;
     }
     return Ok(bytes_read);
 }
 
-impl Process {
-    pub fn get_file_like(&mut self, fd: usize) -> Result<&mut FileLike, SysError> {
-        self.files.get_mut(&fd).ok_or(SysError::EBADF)
+/// A file of the process, which keeps the file table locked
+pub struct FileGuard<'a, T: ?Sized> {
+    files: MutexGuard<'a, BTreeMap<usize, FileLike>, SpinNoIrq>,
+    file: *mut T,
+}
+
+impl<'a, T: ?Sized> FileGuard<'a, T> {
+    /// Narrow down to a part of the file, or fail with `EBADF` if `f` finds none
+    pub fn map<U: ?Sized>(
+        self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<FileGuard<'a, U>, SysError> {
+        let file = f(unsafe { &mut *self.file }).ok_or(SysError::EBADF)? as *mut U;
+        Ok(FileGuard {
+            files: self.files,
+            file,
+        })
+    }
+}
+
+impl<'a, T: ?Sized> Deref for FileGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // the table is locked, so the file stays
+        unsafe { &*self.file }
     }
-    pub fn get_file(&mut self, fd: usize) -> Result<&mut FileHandle, SysError> {
-        match self.get_file_like(fd)? {
+}
+
+impl<'a, T: ?Sized> DerefMut for FileGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.file }
+    }
+}
+
+/// A copy of a file of the process, so that I/O is done without the file table
+/// or the process locked. The offset moved by it is stored back when dropped.
+pub struct FileCopy {
+    files: Arc<SpinNoIrqLock<BTreeMap<usize, FileLike>>>,
+    fd: usize,
+    file_like: FileLike,
+    /// The offset when copied
+    offset: u64,
+}
+
+impl FileCopy {
+    /// The file handle, or `EBADF` for a socket
+    pub fn file(&mut self) -> Result<&mut FileHandle, SysError> {
+        match &mut self.file_like {
             FileLike::File(file) => Ok(file),
             _ => Err(SysError::EBADF),
         }
     }
+}
+
+impl Deref for FileCopy {
+    type Target = FileLike;
+    fn deref(&self) -> &FileLike {
+        &self.file_like
+    }
+}
+
+impl DerefMut for FileCopy {
+    fn deref_mut(&mut self) -> &mut FileLike {
+        &mut self.file_like
+    }
+}
+
+impl Drop for FileCopy {
+    fn drop(&mut self) {
+        let copy = match &mut self.file_like {
+            FileLike::File(file) => file,
+            _ => return,
+        };
+        let offset = copy.seek(SeekFrom::Current(0)).unwrap_or(self.offset);
+        if offset == self.offset {
+            return;
+        }
+        // the fd may be closed or reused meanwhile
+        if let Some(FileLike::File(file)) = self.files.lock().get_mut(&self.fd) {
+            if file.same_inode(copy) {
+                file.seek(SeekFrom::Start(offset)).ok();
+            }
+        }
+    }
+}
+
+impl Process {
+    /// Copy the file at `fd` for I/O, see `FileCopy`
+    pub fn copy_file_like(&self, fd: usize) -> Result<FileCopy, SysError> {
+        let mut file_like = self.files.lock().get(&fd).ok_or(SysError::EBADF)?.clone();
+        let offset = match &mut file_like {
+            FileLike::File(file) => file.seek(SeekFrom::Current(0))?,
+            _ => 0,
+        };
+        Ok(FileCopy {
+            files: self.files.clone(),
+            fd,
+            file_like,
+            offset,
+        })
+    }
+    pub fn get_file_like(&mut self, fd: usize) -> Result<FileGuard<FileLike>, SysError> {
+        let mut files = self.files.lock();
+        let file = files.get_mut(&fd).ok_or(SysError::EBADF)? as *mut FileLike;
+        Ok(FileGuard { files, file })
+    }
+    pub fn get_file(&mut self, fd: usize) -> Result<FileGuard<FileHandle>, SysError> {
+        self.get_file_like(fd)?.map(|file_like| match file_like {
+            FileLike::File(file) => Some(file),
+            _ => None,
+        })
+    }
     /// Lookup INode from the process.
     ///
     /// - If `path` is relative, then it is interpreted relative to the directory
@@ -760,17 +1011,22 @@ impl Process {
         path: &str,
         follow: bool,
     ) -> Result<Arc<INode>, SysError> {
+        let cwd = self.cwd.lock().clone();
         debug!(
             "lookup_inode_at: dirfd: {:?}, cwd: {:?}, path: {:?}, follow: {:?}",
-            dirfd as isize, self.cwd, path, follow
+            dirfd as isize, cwd, path, follow
         );
+        if let Some(inode) = lookup_proc(path, self) {
+            return Ok(inode);
+        }
         let follow_max_depth = if follow { FOLLOW_MAX_DEPTH } else { 0 };
         if dirfd == AT_FDCWD {
             Ok(ROOT_INODE
-                .lookup(&self.cwd)?
+                .lookup(&cwd)?
                 .lookup_follow(path, follow_max_depth)?)
         } else {
-            let file = match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
+            let files = self.files.lock();
+            let file = match files.get(&dirfd).ok_or(SysError::EBADF)? {
                 FileLike::File(file) => file,
                 _ => return Err(SysError::EBADF),
             };
@@ -843,6 +1099,8 @@ bitflags! {
         const TRUNCATE = 1 << 9;
         /// append on each write
         const APPEND = 1 << 10;
+        /// close on exec
+        const CLOEXEC = 1 << 19;
     }
 }
 
@@ -879,18 +1137,17 @@ pub struct LinuxDirent64 {
     name: [u8; 0],
 }
 
+/// Collects dirents in a kernel buffer, to be copied to user at once
 struct DirentBufWriter {
-    ptr: *mut LinuxDirent64,
+    buf: Vec<u8>,
     rest_size: usize,
-    written_size: usize,
 }
 
 impl DirentBufWriter {
-    unsafe fn new(buf: *mut LinuxDirent64, size: usize) -> Self {
+    fn new(size: usize) -> Self {
         DirentBufWriter {
-            ptr: buf,
+            buf: Vec::new(),
             rest_size: size,
-            written_size: 0,
         }
     }
     fn try_write(&mut self, inode: u64, type_: u8, name: &str) -> bool {
@@ -906,14 +1163,15 @@ impl DirentBufWriter {
             type_,
             name: [],
         };
-        unsafe {
-            self.ptr.write(dent);
-            let name_ptr = self.ptr.add(1) as _;
-            util::write_cstr(name_ptr, name);
-            self.ptr = (self.ptr as *const u8).add(len) as _;
-        }
+        let dent_bytes = unsafe {
+            slice::from_raw_parts(&dent as *const _ as *const u8, size_of::<LinuxDirent64>())
+        };
+        let end = self.buf.len() + len;
+        self.buf.extend_from_slice(dent_bytes);
+        self.buf.extend_from_slice(name.as_bytes());
+        // nul terminator and padding
+        self.buf.resize(end, 0);
         self.rest_size -= len;
-        self.written_size += len;
         true
     }
 }
@@ -1228,75 +1486,62 @@ const SEEK_END: u8 = 2;
 #[repr(C)]
 pub struct IoVec {
     /// Starting address
-    base: *mut u8,
+    base: UserInOutPtr<u8>,
     /// Number of bytes to transfer
     len: usize,
 }
 
-/// A valid IoVecs request from user
+/// An IoVecs request from user
 #[derive(Debug)]
-pub struct IoVecs(Vec<&'static mut [u8]>);
+pub struct IoVecs(Vec<IoVec>);
 
 impl IoVecs {
-    pub fn check_and_new(
-        iov_ptr: *const IoVec,
-        iov_count: usize,
-        vm: &MemorySet,
-        readv: bool,
-    ) -> Result<Self, SysError> {
-        vm.check_read_array(iov_ptr, iov_count)?;
-        let iovs = unsafe { slice::from_raw_parts(iov_ptr, iov_count) }.to_vec();
-        // check all bufs in iov
-        for iov in iovs.iter() {
-            if iov.len > 0 {
-                // skip empty iov
-                if readv {
-                    vm.check_write_array(iov.base, iov.len)?;
-                } else {
-                    vm.check_read_array(iov.base, iov.len)?;
-                }
-            }
-        }
-        let slices = iovs
-            .iter()
-            .map(|iov| unsafe { slice::from_raw_parts_mut(iov.base, iov.len) })
-            .collect();
-        Ok(IoVecs(slices))
+    pub fn new(iov_ptr: UserInPtr<IoVec>, iov_count: usize) -> Result<Self, SysError> {
+        let iovs = iov_ptr.read_array(iov_count)?;
+        // the total length must not overflow
+        iovs.iter()
+            .try_fold(0usize, |total, iov| total.checked_add(iov.len))
+            .ok_or(SysError::EINVAL)?;
+        Ok(IoVecs(iovs))
     }
 
-    pub fn read_all_to_vec(&self) -> Vec<u8> {
-        let mut buf = self.new_buf(false);
-        for slice in self.0.iter() {
-            buf.extend(slice.iter());
+    /// Read the data up to `max_len` bytes to a buffer
+    pub fn read_to_vec(&self, max_len: usize) -> Result<Vec<u8>, SysError> {
+        let mut buf = Vec::new();
+        for iov in self.0.iter() {
+            let len = iov.len.min(max_len - buf.len());
+            buf.extend(iov.base.read_array(len)?);
         }
-        buf
+        Ok(buf)
     }
 
-    pub fn write_all_from_slice(&mut self, buf: &[u8]) {
+    pub fn write_all_from_slice(&mut self, buf: &[u8]) -> Result<(), SysError> {
         let mut copied_len = 0;
-        for slice in self.0.iter_mut() {
-            let copy_len = min(slice.len(), buf.len() - copied_len);
+        for iov in self.0.iter_mut() {
+            let copy_len = min(iov.len, buf.len() - copied_len);
             if copy_len == 0 {
                 continue;
             }
 
-            slice[..copy_len].copy_from_slice(&buf[copied_len..copied_len + copy_len]);
+            iov.base
+                .write_array(&buf[copied_len..copied_len + copy_len])?;
             copied_len += copy_len;
         }
+        Ok(())
     }
 
-    /// Create a new Vec buffer from IoVecs
-    /// For readv:  `set_len` is true,  Vec.len = total_len.
-    /// For writev: `set_len` is false, Vec.cap = total_len.
-    pub fn new_buf(&self, set_len: bool) -> Vec<u8> {
-        let total_len = self.0.iter().map(|slice| slice.len()).sum::<usize>();
-        let mut buf = Vec::with_capacity(total_len);
-        if set_len {
-            unsafe {
-                buf.set_len(total_len);
-            }
+    /// Create a zeroed buffer to read into at once, of the total length up to `MAX_CHUNK_SIZE`
+    pub fn new_buf(&self) -> Vec<u8> {
+        let total_len = self.0.iter().map(|iov| iov.len).sum::<usize>();
+        vec![0u8; total_len.min(MAX_CHUNK_SIZE)]
+    }
+
+    /// Check the buffers are writable, before reading data which would be lost otherwise
+    pub fn check_writable(&self, vm: &crate::memory::MemorySet) -> Result<(), SysError> {
+        for iov in self.0.iter() {
+            vm.check_write_array(iov.base.as_ptr(), iov.len)?;
         }
-        buf
+        Ok(())
     }
 }
 
@@ -1326,33 +1571,34 @@ const FD_PER_ITEM: usize = 8 * size_of::<u32>();
 const MAX_FDSET_SIZE: usize = 1024 / FD_PER_ITEM;
 
 struct FdSet {
-    bitset: &'static mut BitSlice<LittleEndian, u32>,
+    addr: UserInOutPtr<u32>,
+    bits: Vec<u32>,
     origin: BitVec<LittleEndian, u32>,
 }
 
 impl FdSet {
     /// Initialize a `FdSet` from pointer and number of fds
     /// Check if the array is large enough
-    fn new(vm: &MemorySet, addr: *mut u32, nfds: usize) -> Result<FdSet, SysError> {
+    fn new(addr: UserInOutPtr<u32>, nfds: usize) -> Result<FdSet, SysError> {
         if addr.is_null() {
             Ok(FdSet {
-                bitset: BitSlice::empty_mut(),
+                addr,
+                bits: Vec::new(),
                 origin: BitVec::new(),
             })
         } else {
             let len = (nfds + FD_PER_ITEM - 1) / FD_PER_ITEM;
-            vm.check_write_array(addr, len)?;
             if len > MAX_FDSET_SIZE {
                 return Err(SysError::EINVAL);
             }
-            let slice = unsafe { slice::from_raw_parts_mut(addr, len) };
-            let bitset: &'static mut BitSlice<LittleEndian, u32> = slice.into();
+            let mut bits = addr.read_array(len)?;
+            let bitset: &mut BitSlice<LittleEndian, u32> = bits.as_mut_slice().into();
 
             // save the fdset, and clear it
             use alloc::prelude::ToOwned;
             let origin = bitset.to_owned();
             bitset.set_all(false);
-            Ok(FdSet { bitset, origin })
+            Ok(FdSet { addr, bits, origin })
         }
     }
 
@@ -1360,13 +1606,22 @@ impl FdSet {
     /// Return true when `FdSet` is valid, and false when `FdSet` is bad (i.e. null pointer)
     /// Fd should be less than nfds
     fn set(&mut self, fd: usize) -> bool {
-        if self.bitset.is_empty() {
+        if self.bits.is_empty() {
             return false;
         }
-        self.bitset.set(fd, true);
+        let bitset: &mut BitSlice<LittleEndian, u32> = self.bits.as_mut_slice().into();
+        bitset.set(fd, true);
         true
     }
 
+    /// Copy the result back to user
+    fn write_back(&mut self) -> Result<(), SysError> {
+        if !self.bits.is_empty() {
+            self.addr.write_array(&self.bits)?;
+        }
+        Ok(())
+    }
+
     /// Check to see whether `fd` is in original `FdSet`
     /// Fd should be less than nfds
     fn contains(&self, fd: usize) -> bool {
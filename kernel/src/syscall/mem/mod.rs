@@ -0,0 +1,572 @@
+#[cfg(not(feature = "nommu"))]
+use core::ptr;
+
+#[cfg(not(feature = "nommu"))]
+use rcore_memory::memory_set::handler::{ByFrame, Delay};
+#[cfg(not(feature = "nommu"))]
+use rcore_memory::memory_set::MemoryAttr;
+#[cfg(not(feature = "nommu"))]
+use rcore_memory::paging::PageTable;
+#[cfg(not(feature = "nommu"))]
+use rcore_memory::Page;
+use rcore_memory::PAGE_SIZE;
+
+#[cfg(not(feature = "nommu"))]
+use crate::consts::USER_END;
+#[cfg(not(feature = "nommu"))]
+use crate::memory::GlobalFrameAlloc;
+#[cfg(not(feature = "nommu"))]
+use crate::process::rlimit::RLIMIT_MEMLOCK;
+
+use super::*;
+
+#[cfg(feature = "nommu")]
+pub use self::nommu::*;
+
+#[cfg(feature = "nommu")]
+mod nommu;
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_mmap(
+    mut addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> SysResult {
+    let prot = MmapProt::from_bits_truncate(prot);
+    let flags = MmapFlags::from_bits_truncate(flags);
+    info!(
+        "mmap: addr={:#x}, size={:#x}, prot={:?}, flags={:?}, fd={}, offset={:#x}",
+        addr, len, prot, flags, fd, offset
+    );
+
+    let mut proc = process();
+    if flags.contains(MmapFlags::FIXED) {
+        // we have to map it to addr, so remove the old mapping first
+        proc.vm.pop_with_split(addr, addr + len);
+    } else {
+        if addr == 0 {
+            // although NULL can be a valid address
+            // but in C, NULL is regarded as allocation failure
+            // so search from the (randomized) mmap base instead
+            addr = proc.mmap_base;
+        }
+        addr = proc
+            .vm
+            .find_free_area(addr, len, USER_END)
+            .ok_or(SysError::ENOMEM)?;
+    }
+
+    if flags.contains(MmapFlags::ANONYMOUS) {
+        if flags.contains(MmapFlags::SHARED) {
+            return Err(SysError::EINVAL);
+        }
+        proc.check_vm_limit(len, true)?;
+        proc.vm.push(
+            addr,
+            addr + len,
+            prot.to_attr(),
+            Delay::new(GlobalFrameAlloc),
+            "mmap_anon",
+        )?;
+        return Ok(addr);
+    } else {
+        // only check
+        let _ = proc.get_file(fd)?;
+        proc.check_vm_limit(len, false)?;
+
+        // TODO: delay mmap file
+        proc.vm.push(
+            addr,
+            addr + len,
+            prot.to_attr(),
+            ByFrame::new(GlobalFrameAlloc),
+            "mmap_file",
+        )?;
+        let data = unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) };
+        let read_len = proc.get_file(fd)?.read_at(offset, data)?;
+        if read_len != data.len() {
+            // use count() to consume the iterator
+            data[read_len..].iter_mut().map(|x| *x = 0).count();
+        }
+        return Ok(addr);
+    }
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_brk(addr: usize) -> SysResult {
+    info!("brk: addr={:#x}", addr);
+    let mut proc = process();
+    let (brk_start, brk) = (proc.brk_start, proc.brk);
+    // brk(0) queries the current break, and failure also returns it
+    if addr < brk_start {
+        return Ok(brk);
+    }
+    let old_end = page_round_up(brk).unwrap();
+    let new_end = match page_round_up(addr) {
+        Some(end) => end,
+        None => return Ok(brk),
+    };
+    if new_end > old_end {
+        if proc.check_vm_limit(new_end - old_end, true).is_err() {
+            return Ok(brk);
+        }
+        let ok = if old_end == brk_start {
+            let free = !proc
+                .vm
+                .iter()
+                .any(|area| area.is_overlap_with(brk_start, new_end));
+            free && proc
+                .vm
+                .push(
+                    brk_start,
+                    new_end,
+                    MemoryAttr::default().user(),
+                    Delay::new(GlobalFrameAlloc),
+                    "heap",
+                )
+                .is_ok()
+        } else {
+            proc.vm.extend_up(old_end, new_end)
+        };
+        if !ok {
+            return Ok(brk);
+        }
+    } else if new_end < old_end {
+        proc.vm.pop_with_split(new_end, old_end);
+    }
+    proc.brk = addr;
+    Ok(addr)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
+    let prot = MmapProt::from_bits_truncate(prot);
+    info!(
+        "mprotect: addr={:#x}, size={:#x}, prot={:?}",
+        addr, len, prot
+    );
+
+    let mut proc = process();
+    let attr = prot.to_attr();
+
+    // FIXME: properly set the attribute of the area
+    //        now some mut ptr check is fault
+    let memory_area = proc
+        .vm
+        .iter()
+        .find(|area| area.is_overlap_with(addr, addr + len));
+    if memory_area.is_none() {
+        return Err(SysError::ENOMEM);
+    }
+    proc.vm.edit(|pt| {
+        for page in Page::range_of(addr, addr + len) {
+            let entry = pt
+                .get_entry(page.start_address())
+                .expect("failed to get entry");
+            attr.apply(entry);
+        }
+    });
+    Ok(0)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
+    info!("munmap addr={:#x}, size={:#x}", addr, len);
+    let mut proc = process();
+    proc.vm.pop_with_split(addr, addr + len);
+    Ok(0)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_mremap(
+    old_addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: usize,
+    new_addr: usize,
+) -> SysResult {
+    let flags = MremapFlags::from_bits_truncate(flags);
+    info!(
+        "mremap: old_addr={:#x}, old_size={:#x}, new_size={:#x}, flags={:?}, new_addr={:#x}",
+        old_addr, old_size, new_size, flags, new_addr
+    );
+    // duplicating a mapping with `old_size` 0 only works for shared mappings
+    if old_addr % PAGE_SIZE != 0
+        || old_size == 0
+        || new_size == 0
+        || (flags.contains(MremapFlags::FIXED) && !flags.contains(MremapFlags::MAYMOVE))
+    {
+        return Err(SysError::EINVAL);
+    }
+    let old_size = page_round_up(old_size).ok_or(SysError::EINVAL)?;
+    let new_size = page_round_up(new_size).ok_or(SysError::ENOMEM)?;
+    let old_end = old_addr.checked_add(old_size).ok_or(SysError::EFAULT)?;
+
+    let mut proc = process();
+    // the old range must be inside a single area
+    let (area_end, is_anon) = match proc.vm.iter().find(|area| area.contains(old_addr)) {
+        Some(area) => (
+            page_round_up(area.end_addr()).unwrap(),
+            area.name() == "mmap_anon",
+        ),
+        None => return Err(SysError::EFAULT),
+    };
+    if area_end < old_end {
+        return Err(SysError::EFAULT);
+    }
+
+    if !flags.contains(MremapFlags::FIXED) {
+        if new_size <= old_size {
+            // shrink in place
+            if new_size < old_size {
+                proc.vm.pop_with_split(old_addr + new_size, old_end);
+            }
+            return Ok(old_addr);
+        }
+        proc.check_vm_limit(new_size - old_size, is_anon)?;
+        // try to grow in place
+        if area_end == old_end && proc.vm.extend_up(old_end, old_addr + new_size) {
+            return Ok(old_addr);
+        }
+        if !flags.contains(MremapFlags::MAYMOVE) {
+            return Err(SysError::ENOMEM);
+        }
+    } else if new_size > old_size {
+        proc.check_vm_limit(new_size - old_size, is_anon)?;
+    }
+
+    // move to a new place
+    let target = if flags.contains(MremapFlags::FIXED) {
+        if new_addr % PAGE_SIZE != 0 || (new_addr < old_end && old_addr < new_addr + new_size) {
+            return Err(SysError::EINVAL);
+        }
+        proc.vm.pop_with_split(new_addr, new_addr + new_size);
+        new_addr
+    } else {
+        let mmap_base = proc.mmap_base;
+        proc.vm
+            .find_free_area(mmap_base, new_size, USER_END)
+            .ok_or(SysError::ENOMEM)?
+    };
+    if !proc.vm.push_like(old_addr, target, target + new_size) {
+        return Err(SysError::ENOMEM);
+    }
+    // fault in both sides here, instead of in the page fault handler
+    let copy_len = old_size.min(new_size);
+    let populated = proc
+        .vm
+        .populate(old_addr, old_addr + copy_len)
+        .and_then(|_| proc.vm.populate(target, target + copy_len));
+    if let Err(err) = populated {
+        proc.vm.pop(target, target + new_size);
+        return Err(err.into());
+    }
+    unsafe {
+        proc.vm.with_writable(target, target + copy_len, || {
+            ptr::copy_nonoverlapping(old_addr as *const u8, target as *mut u8, copy_len)
+        });
+    }
+    proc.vm.pop_with_split(old_addr, old_end);
+    Ok(target)
+}
+
+pub fn sys_msync(addr: usize, len: usize, flags: usize) -> SysResult {
+    info!(
+        "msync: addr={:#x}, size={:#x}, flags={:#x}",
+        addr, len, flags
+    );
+    let flags = MsyncFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if addr % PAGE_SIZE != 0 || flags.contains(MsyncFlags::ASYNC | MsyncFlags::SYNC) {
+        return Err(SysError::EINVAL);
+    }
+    let end = page_range_end(addr, len)?;
+    if !process().vm.is_range_mapped(addr, end) {
+        return Err(SysError::ENOMEM);
+    }
+    // file mappings are private copies for now, nothing to write back
+    Ok(0)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_mincore(addr: usize, len: usize, vec: *mut u8) -> SysResult {
+    info!("mincore: addr={:#x}, size={:#x}, vec={:?}", addr, len, vec);
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = page_range_end(addr, len)?;
+    let mut proc = process();
+    if !proc.vm.is_range_mapped(addr, end) {
+        return Err(SysError::ENOMEM);
+    }
+    let pages = (end - addr) / PAGE_SIZE;
+    proc.vm.check_write_array(vec, pages)?;
+
+    let mut residency = Vec::with_capacity(pages);
+    proc.vm.edit(|pt| {
+        for page in Page::range_of(addr, end) {
+            let present = pt
+                .get_entry(page.start_address())
+                .map_or(false, |entry| entry.present());
+            residency.push(present as u8);
+        }
+    });
+    unsafe { slice::from_raw_parts_mut(vec, pages) }.copy_from_slice(&residency);
+    Ok(0)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_madvise(addr: usize, len: usize, advice: usize) -> SysResult {
+    info!(
+        "madvise: addr={:#x}, size={:#x}, advice={}",
+        addr, len, advice
+    );
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = page_range_end(addr, len)?;
+    let mut proc = process();
+    if !proc.vm.is_range_mapped(addr, end) {
+        return Err(SysError::ENOMEM);
+    }
+    match advice {
+        // there is no read-ahead to tune
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL => {}
+        MADV_WILLNEED => {
+            // only a hint, it is fine to fault them in later
+            let _ = proc.vm.populate(addr, end);
+        }
+        MADV_DONTNEED | MADV_FREE => {
+            // only anonymous memory can be dropped, file mappings are not reread
+            if !proc.vm.discard(addr, end) {
+                return Err(SysError::EINVAL);
+            }
+        }
+        _ => return Err(SysError::EINVAL),
+    }
+    Ok(0)
+}
+
+pub fn sys_mlock(addr: usize, len: usize) -> SysResult {
+    sys_mlock2(addr, len, 0)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_mlock2(addr: usize, len: usize, flags: usize) -> SysResult {
+    info!(
+        "mlock2: addr={:#x}, size={:#x}, flags={:#x}",
+        addr, len, flags
+    );
+    if flags & !MLOCK_ONFAULT != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let start = addr & !(PAGE_SIZE - 1);
+    let end = page_range_end(addr, len)?;
+    if start == end {
+        return Ok(0);
+    }
+    let mut proc = process();
+    if !proc.vm.is_range_mapped(start, end) {
+        return Err(SysError::ENOMEM);
+    }
+    // the bytes locked already out of the range, the range is counted as a whole
+    let locked: usize = proc
+        .vm
+        .iter()
+        .filter(|area| area.is_locked())
+        .map(|area| {
+            let overlap = area
+                .end_addr()
+                .min(end)
+                .saturating_sub(area.start_addr().max(start));
+            area.size() - overlap
+        })
+        .sum();
+    if proc
+        .rlimits
+        .get(RLIMIT_MEMLOCK)
+        .exceeded_by(locked + end - start)
+    {
+        return Err(SysError::ENOMEM);
+    }
+    // populated before locked, so a failure leaves nothing locked
+    if flags & MLOCK_ONFAULT == 0 {
+        proc.vm.populate(start, end).map_err(|_| SysError::EAGAIN)?;
+    }
+    proc.vm.set_locked(start, end, true);
+    Ok(0)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_munlock(addr: usize, len: usize) -> SysResult {
+    info!("munlock: addr={:#x}, size={:#x}", addr, len);
+    let start = addr & !(PAGE_SIZE - 1);
+    let end = page_range_end(addr, len)?;
+    if start == end {
+        return Ok(0);
+    }
+    let mut proc = process();
+    if !proc.vm.is_range_mapped(start, end) {
+        return Err(SysError::ENOMEM);
+    }
+    proc.vm.set_locked(start, end, false);
+    Ok(0)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_mlockall(flags: usize) -> SysResult {
+    info!("mlockall: flags={:#x}", flags);
+    let flags = MlockallFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if !flags.intersects(MlockallFlags::CURRENT | MlockallFlags::FUTURE) {
+        return Err(SysError::EINVAL);
+    }
+    if flags.contains(MlockallFlags::FUTURE) {
+        warn!("mlockall: MCL_FUTURE is unimplemented");
+    }
+    if !flags.contains(MlockallFlags::CURRENT) {
+        return Ok(0);
+    }
+    // RLIMIT_MEMLOCK is not checked, which the stack and heap alone would exceed:
+    // every process runs as root, which Linux lets lock all memory by CAP_IPC_LOCK
+    let mut proc = process();
+    let ranges: Vec<_> = proc
+        .vm
+        .iter()
+        .map(|area| (area.start_addr(), area.end_addr()))
+        .collect();
+    // populated before locked, so a failure leaves nothing locked
+    if !flags.contains(MlockallFlags::ONFAULT) {
+        for &(start, end) in ranges.iter() {
+            proc.vm.populate(start, end).map_err(|_| SysError::EAGAIN)?;
+        }
+    }
+    for (start, end) in ranges {
+        proc.vm.set_locked(start, end, true);
+    }
+    Ok(0)
+}
+
+#[cfg(not(feature = "nommu"))]
+pub fn sys_munlockall() -> SysResult {
+    info!("munlockall");
+    let mut proc = process();
+    let ranges: Vec<_> = proc
+        .vm
+        .iter()
+        .map(|area| (area.start_addr(), area.end_addr()))
+        .collect();
+    for (start, end) in ranges {
+        proc.vm.set_locked(start, end, false);
+    }
+    Ok(0)
+}
+
+/// Round `size` up to a multiple of page size
+fn page_round_up(size: usize) -> Option<usize> {
+    size.checked_add(PAGE_SIZE - 1)
+        .map(|x| x & !(PAGE_SIZE - 1))
+}
+
+/// The page aligned end of [`addr`, `addr + len`)
+fn page_range_end(addr: usize, len: usize) -> Result<usize, SysError> {
+    addr.checked_add(len)
+        .and_then(page_round_up)
+        .ok_or(SysError::ENOMEM)
+}
+
+bitflags! {
+    pub struct MmapProt: usize {
+        /// Data cannot be accessed
+        const NONE = 0;
+        /// Data can be read
+        const READ = 1 << 0;
+        /// Data can be written
+        const WRITE = 1 << 1;
+        /// Data can be executed
+        const EXEC = 1 << 2;
+    }
+}
+
+#[cfg(target_arch = "mips")]
+bitflags! {
+    pub struct MmapFlags: usize {
+        /// Changes are shared.
+        const SHARED = 1 << 0;
+        /// Changes are private.
+        const PRIVATE = 1 << 1;
+        /// Place the mapping at the exact address
+        const FIXED = 1 << 4;
+        /// The mapping is not backed by any file. (non-POSIX)
+        const ANONYMOUS = 0x800;
+    }
+}
+
+#[cfg(not(target_arch = "mips"))]
+bitflags! {
+    pub struct MmapFlags: usize {
+        /// Changes are shared.
+        const SHARED = 1 << 0;
+        /// Changes are private.
+        const PRIVATE = 1 << 1;
+        /// Place the mapping at the exact address
+        const FIXED = 1 << 4;
+        /// The mapping is not backed by any file. (non-POSIX)
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+bitflags! {
+    pub struct MremapFlags: usize {
+        /// The mapping can be moved to a new address
+        const MAYMOVE = 1 << 0;
+        /// Move the mapping to the given new address
+        const FIXED = 1 << 1;
+    }
+}
+
+bitflags! {
+    pub struct MsyncFlags: usize {
+        /// Schedule the write back and return
+        const ASYNC = 1 << 0;
+        /// Invalidate other mappings of the same file
+        const INVALIDATE = 1 << 1;
+        /// Write back and wait for it to complete
+        const SYNC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MlockallFlags: usize {
+        /// Lock all pages currently mapped
+        const CURRENT = 1 << 0;
+        /// Lock all pages mapped in the future
+        const FUTURE = 1 << 1;
+        /// Lock pages when they are faulted in
+        const ONFAULT = 1 << 2;
+    }
+}
+
+/// Lock pages when they are faulted in
+const MLOCK_ONFAULT: usize = 1;
+
+const MADV_NORMAL: usize = 0;
+const MADV_RANDOM: usize = 1;
+const MADV_SEQUENTIAL: usize = 2;
+const MADV_WILLNEED: usize = 3;
+const MADV_DONTNEED: usize = 4;
+const MADV_FREE: usize = 8;
+
+#[cfg(not(feature = "nommu"))]
+impl MmapProt {
+    fn to_attr(self) -> MemoryAttr {
+        let mut attr = MemoryAttr::default().user();
+        if self.contains(MmapProt::EXEC) {
+            attr = attr.execute();
+        }
+        // FIXME: see sys_mprotect
+        //        if !self.contains(MmapProt::WRITE) { attr = attr.readonly(); }
+        attr
+    }
+}
@@ -0,0 +1,194 @@
+//! Memory syscalls without MMU
+//!
+//! Memory is allocated in whole areas, used at their physical address.
+//! They can not be moved, split, grown or protected, and are always resident.
+
+use super::*;
+
+pub fn sys_mmap(
+    _addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> SysResult {
+    let prot = MmapProt::from_bits_truncate(prot);
+    let flags = MmapFlags::from_bits_truncate(flags);
+    info!(
+        "mmap: size={:#x}, prot={:?}, flags={:?}, fd={}, offset={:#x}",
+        len, prot, flags, fd, offset
+    );
+    // the address hint is ignored, the area is where it is allocated
+    if len == 0 || flags.contains(MmapFlags::FIXED) {
+        return Err(SysError::EINVAL);
+    }
+
+    let mut proc = process();
+    if flags.contains(MmapFlags::ANONYMOUS) {
+        if flags.contains(MmapFlags::SHARED) {
+            return Err(SysError::EINVAL);
+        }
+        proc.check_vm_limit(len, true)?;
+        let data = proc.vm.push(len, "mmap_anon")?;
+        data.iter_mut().for_each(|x| *x = 0);
+        Ok(data.as_ptr() as usize)
+    } else {
+        // only check
+        let _ = proc.get_file(fd)?;
+        proc.check_vm_limit(len, false)?;
+
+        // a private copy of the file
+        let data = proc.vm.push(len, "mmap_file")?;
+        let addr = data.as_ptr() as usize;
+        let read = proc.get_file(fd)?.read_at(offset, data);
+        let read_len = match read {
+            Ok(read_len) => read_len,
+            Err(err) => {
+                proc.vm.pop(addr, addr + data.len());
+                return Err(err.into());
+            }
+        };
+        data[read_len..].iter_mut().for_each(|x| *x = 0);
+        Ok(addr)
+    }
+}
+
+/// The heap can not grow, so the break never moves.
+/// `malloc` of musl falls back to `mmap` then.
+pub fn sys_brk(addr: usize) -> SysResult {
+    info!("brk: addr={:#x}", addr);
+    Ok(process().brk)
+}
+
+/// Nothing can be protected, only check the range
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult {
+    let prot = MmapProt::from_bits_truncate(prot);
+    info!(
+        "mprotect: addr={:#x}, size={:#x}, prot={:?}",
+        addr, len, prot
+    );
+    let end = page_range_end(addr, len)?;
+    if !process().vm.is_range_mapped(addr, end) {
+        return Err(SysError::ENOMEM);
+    }
+    Ok(0)
+}
+
+/// An area can only be unmapped as a whole
+pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
+    info!("munmap addr={:#x}, size={:#x}", addr, len);
+    let end = page_range_end(addr, len)?;
+    if !process().vm.pop(addr, end) {
+        return Err(SysError::EINVAL);
+    }
+    Ok(0)
+}
+
+/// An area can not be moved or resized, but its head can be used as the new mapping
+pub fn sys_mremap(
+    old_addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: usize,
+    new_addr: usize,
+) -> SysResult {
+    let flags = MremapFlags::from_bits_truncate(flags);
+    info!(
+        "mremap: old_addr={:#x}, old_size={:#x}, new_size={:#x}, flags={:?}, new_addr={:#x}",
+        old_addr, old_size, new_size, flags, new_addr
+    );
+    if old_addr % PAGE_SIZE != 0
+        || old_size == 0
+        || new_size == 0
+        || flags.contains(MremapFlags::FIXED)
+    {
+        return Err(SysError::EINVAL);
+    }
+    let old_end = page_range_end(old_addr, old_size).map_err(|_| SysError::EFAULT)?;
+    let new_end = page_range_end(old_addr, new_size)?;
+    let proc = process();
+    if !proc.vm.is_range_mapped(old_addr, old_end) {
+        return Err(SysError::EFAULT);
+    }
+    if !proc.vm.is_range_mapped(old_addr, new_end) {
+        return Err(SysError::ENOMEM);
+    }
+    Ok(old_addr)
+}
+
+pub fn sys_mincore(addr: usize, len: usize, vec: *mut u8) -> SysResult {
+    info!("mincore: addr={:#x}, size={:#x}, vec={:?}", addr, len, vec);
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = page_range_end(addr, len)?;
+    let proc = process();
+    if !proc.vm.is_range_mapped(addr, end) {
+        return Err(SysError::ENOMEM);
+    }
+    let pages = (end - addr) / PAGE_SIZE;
+    proc.vm.check_write_array(vec, pages)?;
+    // every page is resident
+    unsafe { slice::from_raw_parts_mut(vec, pages) }
+        .iter_mut()
+        .for_each(|x| *x = 1);
+    Ok(0)
+}
+
+/// There is nothing to reclaim or read ahead, all advices are ignored like Linux
+pub fn sys_madvise(addr: usize, len: usize, advice: usize) -> SysResult {
+    info!(
+        "madvise: addr={:#x}, size={:#x}, advice={}",
+        addr, len, advice
+    );
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let end = page_range_end(addr, len)?;
+    if !process().vm.is_range_mapped(addr, end) {
+        return Err(SysError::ENOMEM);
+    }
+    match advice {
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_WILLNEED | MADV_DONTNEED | MADV_FREE => {
+            Ok(0)
+        }
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+/// Memory is always resident, locking only checks the range
+pub fn sys_mlock2(addr: usize, len: usize, flags: usize) -> SysResult {
+    info!(
+        "mlock2: addr={:#x}, size={:#x}, flags={:#x}",
+        addr, len, flags
+    );
+    if flags & !MLOCK_ONFAULT != 0 {
+        return Err(SysError::EINVAL);
+    }
+    sys_munlock(addr, len)
+}
+
+pub fn sys_munlock(addr: usize, len: usize) -> SysResult {
+    info!("munlock: addr={:#x}, size={:#x}", addr, len);
+    let start = addr & !(PAGE_SIZE - 1);
+    let end = page_range_end(addr, len)?;
+    if start != end && !process().vm.is_range_mapped(start, end) {
+        return Err(SysError::ENOMEM);
+    }
+    Ok(0)
+}
+
+pub fn sys_mlockall(flags: usize) -> SysResult {
+    info!("mlockall: flags={:#x}", flags);
+    let flags = MlockallFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if !flags.intersects(MlockallFlags::CURRENT | MlockallFlags::FUTURE) {
+        return Err(SysError::EINVAL);
+    }
+    Ok(0)
+}
+
+pub fn sys_munlockall() -> SysResult {
+    info!("munlockall");
+    Ok(0)
+}
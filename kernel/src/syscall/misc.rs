@@ -1,7 +1,8 @@
 use super::*;
 use crate::arch::cpu;
-use crate::consts::USER_STACK_SIZE;
+use crate::process::rlimit::*;
 use core::mem::size_of;
+use core::ptr::{null, null_mut};
 use core::sync::atomic::{AtomicI32, Ordering};
 
 pub fn sys_arch_prctl(code: i32, addr: usize, tf: &mut TrapFrame) -> SysResult {
@@ -33,22 +34,6 @@ pub fn sys_uname(buf: *mut u8) -> SysResult {
     Ok(0)
 }
 
-pub fn sys_sched_getaffinity(pid: usize, size: usize, mask: *mut u32) -> SysResult {
-    info!(
-        "sched_getaffinity: pid: {}, size: {}, mask: {:?}",
-        pid, size, mask
-    );
-    let proc = process();
-    proc.vm.check_write_array(mask, size / size_of::<u32>())?;
-
-    // we only have 4 cpu at most.
-    // so just set it.
-    unsafe {
-        *mask = 0b1111;
-    }
-    Ok(0)
-}
-
 pub fn sys_sysinfo(sys_info: *mut SysInfo) -> SysResult {
     let proc = process();
     proc.vm.check_write_ptr(sys_info)?;
@@ -137,67 +122,66 @@ pub struct SysInfo {
     mem_unit: u32,
 }
 
-const RLIMIT_STACK: usize = 3;
-const RLIMIT_RSS: usize = 5;
-const RLIMIT_NOFILE: usize = 7;
-const RLIMIT_AS: usize = 9;
-
 pub fn sys_prlimit64(
     pid: usize,
     resource: usize,
     new_limit: *const RLimit,
     old_limit: *mut RLimit,
 ) -> SysResult {
-    let proc = process();
     info!(
         "prlimit64: pid: {}, resource: {}, new_limit: {:x?}, old_limit: {:x?}",
         pid, resource, new_limit, old_limit
     );
-    match resource {
-        RLIMIT_STACK => {
-            if !old_limit.is_null() {
-                proc.vm.check_write_ptr(old_limit)?;
-                unsafe {
-                    *old_limit = RLimit {
-                        cur: USER_STACK_SIZE as u64,
-                        max: USER_STACK_SIZE as u64,
-                    };
-                }
-            }
-            Ok(0)
+    if resource >= RLIM_NLIMITS {
+        return Err(SysError::EINVAL);
+    }
+    let new_limit = {
+        let proc = process();
+        if !old_limit.is_null() {
+            proc.vm.check_write_ptr(old_limit)?;
         }
-        RLIMIT_NOFILE => {
-            if !old_limit.is_null() {
-                proc.vm.check_write_ptr(old_limit)?;
-                unsafe {
-                    *old_limit = RLimit {
-                        cur: 1024,
-                        max: 1024,
-                    };
-                }
+        if new_limit.is_null() {
+            None
+        } else {
+            proc.vm.check_read_ptr(new_limit)?;
+            let limit = unsafe { new_limit.read() };
+            if limit.cur > limit.max {
+                return Err(SysError::EINVAL);
             }
-            Ok(0)
+            Some(limit)
         }
-        RLIMIT_RSS | RLIMIT_AS => {
-            if !old_limit.is_null() {
-                proc.vm.check_write_ptr(old_limit)?;
-                unsafe {
-                    // 1GB
-                    *old_limit = RLimit {
-                        cur: 1024 * 1024 * 1024,
-                        max: 1024 * 1024 * 1024,
-                    };
-                }
-            }
-            Ok(0)
+    };
+
+    let target = if pid == 0 {
+        current_thread().proc().clone()
+    } else {
+        PROCESSES
+            .read()
+            .get(&pid)
+            .and_then(|weak| weak.upgrade())
+            .ok_or(SysError::ESRCH)?
+    };
+    let old = {
+        let mut target = target.lock();
+        let old = target.rlimits.get(resource);
+        if let Some(limit) = new_limit {
+            target.rlimits.set(resource, limit);
+        }
+        old
+    };
+
+    if !old_limit.is_null() {
+        unsafe {
+            *old_limit = old;
         }
-        _ => Err(SysError::ENOSYS),
     }
+    Ok(0)
 }
 
-#[repr(C)]
-#[derive(Debug, Default)]
-pub struct RLimit {
-    cur: u64, // soft limit
-    max: u64, // hard limit
+pub fn sys_getrlimit(resource: usize, rlim: *mut RLimit) -> SysResult {
+    sys_prlimit64(0, resource, null(), rlim)
+}
+
+pub fn sys_setrlimit(resource: usize, rlim: *const RLimit) -> SysResult {
+    sys_prlimit64(0, resource, rlim, null_mut())
 }
@@ -10,6 +10,7 @@ use rcore_memory::VMError;
 use crate::arch::cpu;
 use crate::arch::interrupt::TrapFrame;
 use crate::arch::syscall::*;
+use crate::process::rlimit::RLimit;
 use crate::process::*;
 use crate::sync::Condvar;
 use crate::thread;
@@ -21,7 +22,11 @@ use self::mem::*;
 use self::misc::*;
 pub use self::net::*;
 use self::proc::*;
+use self::ptrace::*;
+use self::sched::*;
+pub use self::proc::{kill_process, sys_exit_group, CloneFlags};
 use self::time::*;
+use self::user::{UserInOutPtr, UserInPtr, UserOutPtr};
 
 mod custom;
 mod fs;
@@ -29,39 +34,53 @@ mod mem;
 mod misc;
 mod net;
 mod proc;
+mod ptrace;
+mod sched;
 mod time;
+mod user;
 
 /// System call dispatcher
 // This #[deny(unreachable_patterns)] checks if each match arm is defined
 // See discussion in https://github.com/oscourse-tsinghua/rcore_plus/commit/17e644e54e494835f1a49b34b80c2c4f15ed0dbe.
 #[deny(unreachable_patterns)]
 pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
+    current_thread().enter_from_user(tf);
     let cid = cpu::id();
-    let pid = { process().pid.clone() };
+    let (pid, traced) = {
+        let proc = process();
+        (proc.pid.clone(), proc.ptrace.is_some())
+    };
     let tid = processor().tid();
     if !pid.is_init() {
         // we trust pid 0 process
         debug!("{}:{}:{} syscall id {} begin", cid, pid, tid, id);
     }
+    // the number and arguments are taken before the tracer may change them
+    if traced {
+        crate::process::ptrace::syscall_enter(tf, id);
+    }
+
+    // let the timer preempt long syscalls, see `sync::preempt`
+    #[cfg(feature = "preempt")]
+    unsafe {
+        crate::arch::interrupt::enable();
+    }
 
     // use platform-specific syscal numbers
     // See https://filippo.io/linux-syscall-table/
     // And https://fedora.juszkiewicz.com.pl/syscalls.html.
     let ret = match id {
         // 0
-        SYS_READ => sys_read(args[0], args[1] as *mut u8, args[2]),
-        SYS_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+        SYS_READ => sys_read(args[0], args[1].into(), args[2]),
+        SYS_WRITE => sys_write(args[0], args[1].into(), args[2]),
         SYS_CLOSE => sys_close(args[0]),
-        SYS_FSTAT => sys_fstat(args[0], args[1] as *mut Stat),
+        SYS_FSTAT => sys_fstat(args[0], args[1].into()),
         SYS_LSEEK => sys_lseek(args[0], args[1] as i64, args[2] as u8),
         SYS_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
         // 10
         SYS_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
         SYS_MUNMAP => sys_munmap(args[0], args[1]),
-        SYS_BRK => {
-            warn!("sys_brk is unimplemented, return -1");
-            Err(SysError::ENOMEM)
-        }
+        SYS_BRK => sys_brk(args[0]),
         SYS_RT_SIGACTION => {
             warn!("sys_sigaction is unimplemented");
             Ok(0)
@@ -71,93 +90,103 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             Ok(0)
         }
         SYS_IOCTL => sys_ioctl(args[0], args[1], args[2], args[3], args[4]),
-        SYS_PREAD64 => sys_pread(args[0], args[1] as *mut u8, args[2], args[3]),
-        SYS_PWRITE64 => sys_pwrite(args[0], args[1] as *const u8, args[2], args[3]),
-        SYS_READV => sys_readv(args[0], args[1] as *const IoVec, args[2]),
+        SYS_PREAD64 => sys_pread(args[0], args[1].into(), args[2], args[3]),
+        SYS_PWRITE64 => sys_pwrite(args[0], args[1].into(), args[2], args[3]),
+        SYS_READV => sys_readv(args[0], args[1].into(), args[2]),
         // 20
-        SYS_WRITEV => sys_writev(args[0], args[1] as *const IoVec, args[2]),
+        SYS_WRITEV => sys_writev(args[0], args[1].into(), args[2]),
         SYS_SCHED_YIELD => sys_yield(),
-        SYS_MADVISE => {
-            warn!("sys_madvise is unimplemented");
-            Ok(0)
-        }
-        SYS_NANOSLEEP => sys_nanosleep(args[0] as *const TimeSpec),
+        SYS_MREMAP => sys_mremap(args[0], args[1], args[2], args[3], args[4]),
+        SYS_MSYNC => sys_msync(args[0], args[1], args[2]),
+        SYS_MINCORE => sys_mincore(args[0], args[1], args[2] as *mut u8),
+        SYS_MADVISE => sys_madvise(args[0], args[1], args[2]),
+        SYS_NANOSLEEP => sys_nanosleep(args[0].into()),
         SYS_SETITIMER => {
             warn!("sys_setitimer is unimplemented");
             Ok(0)
         }
         SYS_GETPID => sys_getpid(),
         // 40
-        SYS_SENDFILE => sys_sendfile(args[0], args[1], args[2] as *mut usize, args[3]),
+        SYS_SENDFILE => sys_sendfile(args[0], args[1], args[2].into(), args[3]),
         SYS_SOCKET => sys_socket(args[0], args[1], args[2]),
-        SYS_CONNECT => sys_connect(args[0], args[1] as *const SockAddr, args[2]),
-        SYS_ACCEPT => sys_accept(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
+        SYS_CONNECT => sys_connect(args[0], args[1].into(), args[2]),
+        SYS_ACCEPT => sys_accept(args[0], args[1].into(), args[2].into()),
         SYS_SENDTO => sys_sendto(
             args[0],
-            args[1] as *const u8,
+            args[1].into(),
             args[2],
             args[3],
-            args[4] as *const SockAddr,
+            args[4].into(),
             args[5],
         ),
         SYS_RECVFROM => sys_recvfrom(
             args[0],
-            args[1] as *mut u8,
+            args[1].into(),
             args[2],
             args[3],
-            args[4] as *mut SockAddr,
-            args[5] as *mut u32,
+            args[4].into(),
+            args[5].into(),
         ),
         //        SYS_SENDMSG => sys_sendmsg(),
-        SYS_RECVMSG => sys_recvmsg(args[0], args[1] as *mut MsgHdr, args[2]),
+        SYS_RECVMSG => sys_recvmsg(args[0], args[1].into(), args[2]),
         SYS_SHUTDOWN => sys_shutdown(args[0], args[1]),
-        SYS_BIND => sys_bind(args[0], args[1] as *const SockAddr, args[2]),
+        SYS_BIND => sys_bind(args[0], args[1].into(), args[2]),
         // 50
         SYS_LISTEN => sys_listen(args[0], args[1]),
-        SYS_GETSOCKNAME => sys_getsockname(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
-        SYS_GETPEERNAME => sys_getpeername(args[0], args[1] as *mut SockAddr, args[2] as *mut u32),
-        SYS_SETSOCKOPT => sys_setsockopt(args[0], args[1], args[2], args[3] as *const u8, args[4]),
+        SYS_GETSOCKNAME => sys_getsockname(args[0], args[1].into(), args[2].into()),
+        SYS_GETPEERNAME => sys_getpeername(args[0], args[1].into(), args[2].into()),
+        SYS_SETSOCKOPT => sys_setsockopt(args[0], args[1], args[2], args[3].into(), args[4]),
         SYS_GETSOCKOPT => sys_getsockopt(
             args[0],
             args[1],
             args[2],
-            args[3] as *mut u8,
-            args[4] as *mut u32,
+            args[3].into(),
+            args[4].into(),
         ),
+        #[cfg(target_arch = "x86_64")]
         SYS_CLONE => sys_clone(
             args[0],
             args[1],
-            args[2] as *mut u32,
-            args[3] as *mut u32,
+            args[2].into(),
+            args[3].into(),
             args[4],
             tf,
         ),
+        // tls comes before child_tid on other architectures
+        #[cfg(not(target_arch = "x86_64"))]
+        SYS_CLONE => sys_clone(
+            args[0],
+            args[1],
+            args[2].into(),
+            args[4].into(),
+            args[3],
+            tf,
+        ),
+        SYS_CLONE3 => sys_clone3(args[0].into(), args[1], tf),
         SYS_EXECVE => sys_exec(
-            args[0] as *const u8,
-            args[1] as *const *const u8,
-            args[2] as *const *const u8,
+            args[0].into(),
+            args[1].into(),
+            args[2].into(),
             tf,
         ),
         // 60
         SYS_EXIT => sys_exit(args[0] as usize),
-        SYS_WAIT4 => sys_wait4(args[0] as isize, args[1] as *mut i32), // TODO: wait4
+        SYS_WAIT4 => sys_wait4(args[0] as isize, args[1].into(), args[2]),
         SYS_KILL => sys_kill(args[0], args[1]),
+        SYS_PTRACE => sys_ptrace(args[0], args[1], args[2], args[3]),
         SYS_UNAME => sys_uname(args[0] as *mut u8),
-        SYS_FCNTL => {
-            warn!("sys_fcntl is unimplemented");
-            Ok(0)
-        }
+        SYS_FCNTL => sys_fcntl(args[0], args[1], args[2]),
         SYS_FLOCK => {
             warn!("sys_flock is unimplemented");
             Ok(0)
         }
         SYS_FSYNC => sys_fsync(args[0]),
         SYS_FDATASYNC => sys_fdatasync(args[0]),
-        SYS_TRUNCATE => sys_truncate(args[0] as *const u8, args[1]),
+        SYS_TRUNCATE => sys_truncate(args[0].into(), args[1]),
         SYS_FTRUNCATE => sys_ftruncate(args[0], args[1]),
-        SYS_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
+        SYS_GETCWD => sys_getcwd(args[0].into(), args[1]),
         // 80
-        SYS_CHDIR => sys_chdir(args[0] as *const u8),
+        SYS_CHDIR => sys_chdir(args[0].into()),
         SYS_FCHMOD => {
             warn!("sys_fchmod is unimplemented");
             Ok(0)
@@ -171,7 +200,7 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             Ok(0o777)
         }
         SYS_GETTIMEOFDAY => sys_gettimeofday(args[0] as *mut TimeVal, args[1] as *const u8),
-        //        SYS_GETRLIMIT => sys_getrlimit(),
+        SYS_GETRLIMIT => sys_getrlimit(args[0], args[1] as *mut RLimit),
         SYS_GETRUSAGE => sys_getrusage(args[0], args[1] as *mut RUsage),
         SYS_SYSINFO => sys_sysinfo(args[0] as *mut SysInfo),
         SYS_TIMES => sys_times(args[0] as *mut Tms),
@@ -229,12 +258,17 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             warn!("fstatfs is unimplemented");
             Err(SysError::EACCES)
         }
-        SYS_SETPRIORITY => sys_set_priority(args[0]),
+        SYS_GETPRIORITY => sys_getpriority(args[0], args[1]),
+        SYS_SETPRIORITY => sys_setpriority(args[0], args[1], args[2]),
+        SYS_MLOCK => sys_mlock(args[0], args[1]),
+        SYS_MUNLOCK => sys_munlock(args[0], args[1]),
+        SYS_MLOCKALL => sys_mlockall(args[0]),
+        SYS_MUNLOCKALL => sys_munlockall(),
         SYS_PRCTL => {
             warn!("prctl is unimplemented");
             Ok(0)
         }
-        //        SYS_SETRLIMIT => sys_setrlimit(),
+        SYS_SETRLIMIT => sys_setrlimit(args[0], args[1] as *const RLimit),
         SYS_SYNC => sys_sync(),
         SYS_MOUNT => {
             warn!("mount is unimplemented");
@@ -257,60 +291,69 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             args[2] as i32,
             args[3] as *const TimeSpec,
         ),
-        SYS_SCHED_GETAFFINITY => sys_sched_getaffinity(args[0], args[1], args[2] as *mut u32),
-        SYS_GETDENTS64 => sys_getdents64(args[0], args[1] as *mut LinuxDirent64, args[2]),
+        SYS_SCHED_SETPARAM => sys_sched_setparam(args[0], args[1].into()),
+        SYS_SCHED_GETPARAM => sys_sched_getparam(args[0], args[1].into()),
+        SYS_SCHED_SETSCHEDULER => sys_sched_setscheduler(args[0], args[1], args[2].into()),
+        SYS_SCHED_GETSCHEDULER => sys_sched_getscheduler(args[0]),
+        SYS_SCHED_GET_PRIORITY_MAX => sys_sched_get_priority_max(args[0]),
+        SYS_SCHED_GET_PRIORITY_MIN => sys_sched_get_priority_min(args[0]),
+        SYS_SCHED_RR_GET_INTERVAL => sys_sched_rr_get_interval(args[0], args[1].into()),
+        SYS_SCHED_SETAFFINITY => sys_sched_setaffinity(args[0], args[1], args[2].into()),
+        SYS_SCHED_GETAFFINITY => sys_sched_getaffinity(args[0], args[1], args[2].into()),
+        SYS_GETDENTS64 => sys_getdents64(args[0], args[1].into(), args[2]),
         SYS_SET_TID_ADDRESS => {
             warn!("sys_set_tid_address is unimplemented");
             Ok(thread::current().id())
         }
         SYS_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
         SYS_EXIT_GROUP => sys_exit_group(args[0]),
-        SYS_OPENAT => sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
-        SYS_MKDIRAT => sys_mkdirat(args[0], args[1] as *const u8, args[2]),
+        SYS_OPENAT => sys_openat(args[0], args[1].into(), args[2], args[3]),
+        SYS_MKDIRAT => sys_mkdirat(args[0], args[1].into(), args[2]),
         //        SYS_MKNODAT => sys_mknod(),
         // 260
         SYS_FCHOWNAT => {
             warn!("sys_fchownat is unimplemented");
             Ok(0)
         }
-        SYS_NEWFSTATAT => sys_fstatat(args[0], args[1] as *const u8, args[2] as *mut Stat, args[3]),
-        SYS_UNLINKAT => sys_unlinkat(args[0], args[1] as *const u8, args[2]),
-        SYS_RENAMEAT => sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8),
+        SYS_NEWFSTATAT => sys_fstatat(args[0], args[1].into(), args[2].into(), args[3]),
+        SYS_UNLINKAT => sys_unlinkat(args[0], args[1].into(), args[2]),
+        SYS_RENAMEAT => sys_renameat(args[0], args[1].into(), args[2], args[3].into()),
         SYS_LINKAT => sys_linkat(
             args[0],
-            args[1] as *const u8,
+            args[1].into(),
             args[2],
-            args[3] as *const u8,
+            args[3].into(),
             args[4],
         ),
         SYS_SYMLINKAT => Err(SysError::EACCES),
         SYS_READLINKAT => {
-            sys_readlinkat(args[0], args[1] as *const u8, args[2] as *mut u8, args[3])
+            sys_readlinkat(args[0], args[1].into(), args[2].into(), args[3])
         }
         SYS_FCHMODAT => {
             warn!("sys_fchmodat is unimplemented");
             Ok(0)
         }
-        SYS_FACCESSAT => sys_faccessat(args[0], args[1] as *const u8, args[2], args[3]),
-        SYS_PPOLL => sys_ppoll(args[0] as *mut PollFd, args[1], args[2] as *const TimeSpec), // ignore sigmask
+        SYS_FACCESSAT => sys_faccessat(args[0], args[1].into(), args[2], args[3]),
+        SYS_PPOLL => sys_ppoll(args[0].into(), args[1], args[2].into()), // ignore sigmask
         // 280
         SYS_UTIMENSAT => {
             warn!("sys_utimensat is unimplemented");
             Ok(0)
         }
-        SYS_ACCEPT4 => sys_accept(args[0], args[1] as *mut SockAddr, args[2] as *mut u32), // use accept for accept4
+        SYS_ACCEPT4 => sys_accept(args[0], args[1].into(), args[2].into()), // use accept for accept4
         SYS_EPOLL_CREATE1 => {
             warn!("sys_epoll_create1 is unimplemented");
             Err(SysError::ENOSYS)
         }
-        SYS_DUP3 => sys_dup2(args[0], args[1]), // TODO: handle `flags`
-        SYS_PIPE2 => sys_pipe(args[0] as *mut u32), // TODO: handle `flags`
+        SYS_DUP3 => sys_dup3(args[0], args[1], args[2]),
+        SYS_PIPE2 => sys_pipe2(args[0].into(), args[1]),
         SYS_PRLIMIT64 => sys_prlimit64(
             args[0],
             args[1],
             args[2] as *const RLimit,
             args[3] as *mut RLimit,
         ),
+        SYS_MLOCK2 => sys_mlock2(args[0], args[1], args[2]),
         // custom temporary syscall
         SYS_MAP_PCI_DEVICE => sys_map_pci_device(args[0], args[1]),
         SYS_GET_PADDR => sys_get_paddr(args[0] as *const u64, args[1] as *mut u64, args[2]),
@@ -335,6 +378,13 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             }
         }
     };
+    // a thread killed during the syscall never returns to user mode
+    crate::trap::exit_if_killed();
+    // return to user with interrupts disabled, like the syscall began
+    #[cfg(feature = "preempt")]
+    unsafe {
+        crate::arch::interrupt::disable_and_store();
+    }
     if !pid.is_init() {
         // we trust pid 0 process
         debug!(
@@ -342,36 +392,35 @@ pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
             cid, pid, tid, id, ret
         );
     }
-    match ret {
+    let ret = match ret {
         Ok(code) => code as isize,
         Err(err) => -(err as isize),
+    };
+    if traced {
+        return crate::process::ptrace::syscall_exit(tf, id, ret);
     }
+    // traced meanwhile, another thread may be stopped
+    crate::process::ptrace::wait_if_stopped();
+    ret
 }
 
 #[cfg(target_arch = "mips")]
 fn mips_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<SysResult> {
     let ret = match id {
-        SYS_OPEN => sys_open(args[0] as *const u8, args[1], args[2]),
-        SYS_POLL => sys_poll(args[0] as *mut PollFd, args[1], args[2]),
+        SYS_OPEN => sys_open(args[0].into(), args[1], args[2]),
+        SYS_POLL => sys_poll(args[0].into(), args[1], args[2]),
         SYS_DUP2 => sys_dup2(args[0], args[1]),
         SYS_FORK => sys_fork(tf),
         SYS_MMAP2 => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5] * 4096),
-        SYS_FSTAT64 => sys_fstat(args[0], args[1] as *mut Stat),
-        SYS_LSTAT64 => sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
-        SYS_STAT64 => sys_stat(args[0] as *const u8, args[1] as *mut Stat),
-        SYS_PIPE => {
-            let fd_ptr = args[0] as *mut u32;
-            match sys_pipe(fd_ptr) {
-                Ok(code) => {
-                    unsafe {
-                        tf.v0 = *fd_ptr as usize;
-                        tf.v1 = *(fd_ptr.add(1)) as usize;
-                    }
-                    Ok(tf.v0)
-                }
-                Err(err) => Err(err),
-            }
-        }
+        SYS_FSTAT64 => sys_fstat(args[0], args[1].into()),
+        SYS_LSTAT64 => sys_lstat(args[0].into(), args[1].into()),
+        SYS_STAT64 => sys_stat(args[0].into(), args[1].into()),
+        SYS_PIPE => sys_pipe(args[0].into()).and_then(|_| {
+            let fds = UserInPtr::<u32>::from(args[0]).read_array(2)?;
+            tf.v0 = fds[0] as usize;
+            tf.v1 = fds[1] as usize;
+            Ok(tf.v0)
+        }),
         SYS_GETPGID => {
             warn!("sys_getpgid is unimplemented");
             Ok(0)
@@ -380,10 +429,7 @@ fn mips_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<SysRe
             warn!("sys_setpgid is unimplemented");
             Ok(0)
         }
-        SYS_FCNTL64 => {
-            warn!("sys_fcntl64 is unimplemented");
-            Ok(0)
-        }
+        SYS_FCNTL64 => sys_fcntl(args[0], args[1], args[2]),
         SYS_SET_THREAD_AREA => {
             info!("set_thread_area: tls: 0x{:x}", args[0]);
             extern "C" {
@@ -406,18 +452,18 @@ fn mips_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<SysRe
 #[cfg(target_arch = "x86_64")]
 fn x86_64_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<SysResult> {
     let ret = match id {
-        SYS_OPEN => sys_open(args[0] as *const u8, args[1], args[2]),
-        SYS_STAT => sys_stat(args[0] as *const u8, args[1] as *mut Stat),
-        SYS_LSTAT => sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
-        SYS_POLL => sys_poll(args[0] as *mut PollFd, args[1], args[2]),
-        SYS_ACCESS => sys_access(args[0] as *const u8, args[1]),
-        SYS_PIPE => sys_pipe(args[0] as *mut u32),
+        SYS_OPEN => sys_open(args[0].into(), args[1], args[2]),
+        SYS_STAT => sys_stat(args[0].into(), args[1].into()),
+        SYS_LSTAT => sys_lstat(args[0].into(), args[1].into()),
+        SYS_POLL => sys_poll(args[0].into(), args[1], args[2]),
+        SYS_ACCESS => sys_access(args[0].into(), args[1]),
+        SYS_PIPE => sys_pipe(args[0].into()),
         SYS_SELECT => sys_select(
             args[0],
-            args[1] as *mut u32,
-            args[2] as *mut u32,
-            args[3] as *mut u32,
-            args[4] as *const TimeVal,
+            args[1].into(),
+            args[2].into(),
+            args[3].into(),
+            args[4].into(),
         ),
         SYS_DUP2 => sys_dup2(args[0], args[1]),
         SYS_ALARM => {
@@ -427,12 +473,12 @@ fn x86_64_syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> Option<Sys
         SYS_FORK => sys_fork(tf),
         // use fork for vfork
         SYS_VFORK => sys_fork(tf),
-        SYS_RENAME => sys_rename(args[0] as *const u8, args[1] as *const u8),
-        SYS_MKDIR => sys_mkdir(args[0] as *const u8, args[1]),
-        SYS_RMDIR => sys_rmdir(args[0] as *const u8),
-        SYS_LINK => sys_link(args[0] as *const u8, args[1] as *const u8),
-        SYS_UNLINK => sys_unlink(args[0] as *const u8),
-        SYS_READLINK => sys_readlink(args[0] as *const u8, args[1] as *mut u8, args[2]),
+        SYS_RENAME => sys_rename(args[0].into(), args[1].into()),
+        SYS_MKDIR => sys_mkdir(args[0].into(), args[1]),
+        SYS_RMDIR => sys_rmdir(args[0].into()),
+        SYS_LINK => sys_link(args[0].into(), args[1].into()),
+        SYS_UNLINK => sys_unlink(args[0].into()),
+        SYS_READLINK => sys_readlink(args[0].into(), args[1].into(), args[2]),
         // 90
         SYS_CHMOD => {
             warn!("sys_chmod is unimplemented");
@@ -577,8 +623,11 @@ impl fmt::Display for SysError {
 }
 
 impl From<VMError> for SysError {
-    fn from(_: VMError) -> Self {
-        SysError::EFAULT
+    fn from(err: VMError) -> Self {
+        match err {
+            VMError::InvalidPtr => SysError::EFAULT,
+            VMError::NoMemory => SysError::ENOMEM,
+        }
     }
 }
 
@@ -1,6 +1,6 @@
 //! Syscalls for networking
 
-use super::fs::IoVecs;
+use super::fs::{IoVecs, MAX_CHUNK_SIZE};
 use super::*;
 use crate::drivers::SOCKET_ACTIVITY;
 use crate::fs::FileLike;
@@ -21,7 +21,7 @@ pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResu
         "socket: domain: {:?}, socket_type: {:?}, protocol: {}",
         domain, socket_type, protocol
     );
-    let mut proc = process();
+    let proc = process();
     let socket: Box<dyn Socket> = match domain {
         AddressFamily::Internet | AddressFamily::Unix => match socket_type {
             SocketType::Stream => Box::new(TcpSocketState::new()),
@@ -39,16 +39,14 @@ pub fn sys_socket(domain: usize, socket_type: usize, protocol: usize) -> SysResu
         },
         _ => return Err(SysError::EAFNOSUPPORT),
     };
-    let fd = proc.get_free_fd();
-    proc.files.insert(fd, FileLike::Socket(socket));
-    Ok(fd)
+    proc.add_file(FileLike::Socket(socket))
 }
 
 pub fn sys_setsockopt(
     fd: usize,
     level: usize,
     optname: usize,
-    optval: *const u8,
+    optval: UserInPtr<u8>,
     optlen: usize,
 ) -> SysResult {
     info!(
@@ -56,41 +54,32 @@ pub fn sys_setsockopt(
         fd, level, optname
     );
     let mut proc = process();
-    proc.vm.check_read_array(optval, optlen)?;
-    let data = unsafe { slice::from_raw_parts(optval, optlen) };
-    let socket = proc.get_socket(fd)?;
-    socket.setsockopt(level, optname, data)
+    let data = optval.read_array(optlen)?;
+    let mut socket = proc.get_socket(fd)?;
+    socket.setsockopt(level, optname, &data)
 }
 
 pub fn sys_getsockopt(
     fd: usize,
     level: usize,
     optname: usize,
-    optval: *mut u8,
-    optlen: *mut u32,
+    optval: UserOutPtr<u8>,
+    mut optlen: UserOutPtr<u32>,
 ) -> SysResult {
     info!(
         "getsockopt: fd: {}, level: {}, optname: {} optval: {:?} optlen: {:?}",
         fd, level, optname, optval, optlen
     );
-    let proc = process();
-    proc.vm.check_write_ptr(optlen)?;
     match level {
         SOL_SOCKET => match optname {
             SO_SNDBUF => {
-                proc.vm.check_write_array(optval, 4)?;
-                unsafe {
-                    *(optval as *mut u32) = crate::net::TCP_SENDBUF as u32;
-                    *optlen = 4;
-                }
+                optval.cast::<u32>().write(crate::net::TCP_SENDBUF as u32)?;
+                optlen.write(4)?;
                 Ok(0)
             }
             SO_RCVBUF => {
-                proc.vm.check_write_array(optval, 4)?;
-                unsafe {
-                    *(optval as *mut u32) = crate::net::TCP_RECVBUF as u32;
-                    *optlen = 4;
-                }
+                optval.cast::<u32>().write(crate::net::TCP_RECVBUF as u32)?;
+                optlen.write(4)?;
                 Ok(0)
             }
             _ => Err(SysError::ENOPROTOOPT),
@@ -103,25 +92,25 @@ pub fn sys_getsockopt(
     }
 }
 
-pub fn sys_connect(fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult {
+pub fn sys_connect(fd: usize, addr: UserInPtr<SockAddr>, addr_len: usize) -> SysResult {
     info!(
         "sys_connect: fd: {}, addr: {:?}, addr_len: {}",
         fd, addr, addr_len
     );
 
     let mut proc = process();
-    let endpoint = sockaddr_to_endpoint(&mut proc, addr, addr_len)?;
-    let socket = proc.get_socket(fd)?;
+    let endpoint = sockaddr_to_endpoint(addr, addr_len)?;
+    let mut socket = proc.get_socket(fd)?;
     socket.connect(endpoint)?;
     Ok(0)
 }
 
 pub fn sys_sendto(
     fd: usize,
-    base: *const u8,
+    base: UserInPtr<u8>,
     len: usize,
     _flags: usize,
-    addr: *const SockAddr,
+    addr: UserInPtr<SockAddr>,
     addr_len: usize,
 ) -> SysResult {
     info!(
@@ -130,27 +119,26 @@ pub fn sys_sendto(
     );
 
     let mut proc = process();
-    proc.vm.check_read_array(base, len)?;
-
-    let slice = unsafe { slice::from_raw_parts(base, len) };
+    // a datagram is never larger, and a stream socket may send a part
+    let data = base.read_array(len.min(MAX_CHUNK_SIZE))?;
     let endpoint = if addr.is_null() {
         None
     } else {
-        let endpoint = sockaddr_to_endpoint(&mut proc, addr, addr_len)?;
+        let endpoint = sockaddr_to_endpoint(addr, addr_len)?;
         info!("sys_sendto: sending to endpoint {:?}", endpoint);
         Some(endpoint)
     };
     let socket = proc.get_socket(fd)?;
-    socket.write(&slice, endpoint)
+    socket.write(&data, endpoint)
 }
 
 pub fn sys_recvfrom(
     fd: usize,
-    base: *mut u8,
+    mut base: UserOutPtr<u8>,
     len: usize,
     flags: usize,
-    addr: *mut SockAddr,
-    addr_len: *mut u32,
+    addr: UserOutPtr<SockAddr>,
+    addr_len: UserInOutPtr<u32>,
 ) -> SysResult {
     info!(
         "sys_recvfrom: fd: {} base: {:?} len: {} flags: {} addr: {:?} addr_len: {:?}",
@@ -158,52 +146,54 @@ pub fn sys_recvfrom(
     );
 
     let mut proc = process();
-    proc.vm.check_write_array(base, len)?;
-
+    // check the buffer first, so that no data received is lost
+    proc.vm.check_write_array(base.as_ptr(), len)?;
     let socket = proc.get_socket(fd)?;
-    let mut slice = unsafe { slice::from_raw_parts_mut(base, len) };
-    let (result, endpoint) = socket.read(&mut slice);
+    let mut buf = vec![0u8; len.min(MAX_CHUNK_SIZE)];
+    let (result, endpoint) = socket.read(&mut buf);
 
-    if result.is_ok() && !addr.is_null() {
-        let sockaddr_in = SockAddr::from(endpoint);
-        unsafe {
-            sockaddr_in.write_to(&mut proc, addr, addr_len)?;
+    if let Ok(len) = result {
+        base.write_array(&buf[..len])?;
+        if !addr.is_null() {
+            let sockaddr_in = SockAddr::from(endpoint);
+            sockaddr_in.write_to_with_len(addr, addr_len)?;
         }
     }
 
     result
 }
 
-pub fn sys_recvmsg(fd: usize, msg: *mut MsgHdr, flags: usize) -> SysResult {
+pub fn sys_recvmsg(fd: usize, mut msg: UserInOutPtr<MsgHdr>, flags: usize) -> SysResult {
     info!("recvmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
     let mut proc = process();
-    proc.vm.check_read_ptr(msg)?;
-    let hdr = unsafe { &mut *msg };
-    let mut iovs = IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &proc.vm, true)?;
+    let mut hdr = msg.read()?;
+    let mut iovs = IoVecs::new(hdr.msg_iov, hdr.msg_iovlen)?;
+    iovs.check_writable(&proc.vm)?;
 
-    let mut buf = iovs.new_buf(true);
+    let mut buf = iovs.new_buf();
     let socket = proc.get_socket(fd)?;
     let (result, endpoint) = socket.read(&mut buf);
 
     if let Ok(len) = result {
         // copy data to user
-        iovs.write_all_from_slice(&buf[..len]);
-        let sockaddr_in = SockAddr::from(endpoint);
-        unsafe {
-            sockaddr_in.write_to(&mut proc, hdr.msg_name, &mut hdr.msg_namelen as *mut u32)?;
+        iovs.write_all_from_slice(&buf[..len])?;
+        if !hdr.msg_name.is_null() {
+            let sockaddr_in = SockAddr::from(endpoint);
+            hdr.msg_namelen = sockaddr_in.write_to(hdr.msg_name, hdr.msg_namelen)?;
+            msg.write(hdr)?;
         }
     }
     result
 }
 
-pub fn sys_bind(fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult {
+pub fn sys_bind(fd: usize, addr: UserInPtr<SockAddr>, addr_len: usize) -> SysResult {
     info!("sys_bind: fd: {} addr: {:?} len: {}", fd, addr, addr_len);
     let mut proc = process();
 
-    let mut endpoint = sockaddr_to_endpoint(&mut proc, addr, addr_len)?;
+    let mut endpoint = sockaddr_to_endpoint(addr, addr_len)?;
     info!("sys_bind: fd: {} bind to {:?}", fd, endpoint);
 
-    let socket = proc.get_socket(fd)?;
+    let mut socket = proc.get_socket(fd)?;
     socket.bind(endpoint)
 }
 
@@ -213,7 +203,7 @@ pub fn sys_listen(fd: usize, backlog: usize) -> SysResult {
     // open multiple sockets for each connection
     let mut proc = process();
 
-    let socket = proc.get_socket(fd)?;
+    let mut socket = proc.get_socket(fd)?;
     socket.listen()
 }
 
@@ -225,7 +215,7 @@ pub fn sys_shutdown(fd: usize, how: usize) -> SysResult {
     socket.shutdown()
 }
 
-pub fn sys_accept(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResult {
+pub fn sys_accept(fd: usize, addr: UserOutPtr<SockAddr>, addr_len: UserInOutPtr<u32>) -> SysResult {
     info!(
         "sys_accept: fd: {} addr: {:?} addr_len: {:?}",
         fd, addr, addr_len
@@ -234,22 +224,21 @@ pub fn sys_accept(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResu
     // open multiple sockets for each connection
     let mut proc = process();
 
-    let socket = proc.get_socket(fd)?;
-    let (new_socket, remote_endpoint) = socket.accept()?;
-
-    let new_fd = proc.get_free_fd();
-    proc.files.insert(new_fd, FileLike::Socket(new_socket));
+    let (new_socket, remote_endpoint) = proc.get_socket(fd)?.accept()?;
+    let new_fd = proc.add_file(FileLike::Socket(new_socket))?;
 
     if !addr.is_null() {
         let sockaddr_in = SockAddr::from(remote_endpoint);
-        unsafe {
-            sockaddr_in.write_to(&mut proc, addr, addr_len)?;
-        }
+        sockaddr_in.write_to_with_len(addr, addr_len)?;
     }
     Ok(new_fd)
 }
 
-pub fn sys_getsockname(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResult {
+pub fn sys_getsockname(
+    fd: usize,
+    addr: UserOutPtr<SockAddr>,
+    addr_len: UserInOutPtr<u32>,
+) -> SysResult {
     info!(
         "sys_getsockname: fd: {} addr: {:?} addr_len: {:?}",
         fd, addr, addr_len
@@ -264,13 +253,15 @@ pub fn sys_getsockname(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> Sy
     let socket = proc.get_socket(fd)?;
     let endpoint = socket.endpoint().ok_or(SysError::EINVAL)?;
     let sockaddr_in = SockAddr::from(endpoint);
-    unsafe {
-        sockaddr_in.write_to(&mut proc, addr, addr_len)?;
-    }
+    sockaddr_in.write_to_with_len(addr, addr_len)?;
     Ok(0)
 }
 
-pub fn sys_getpeername(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResult {
+pub fn sys_getpeername(
+    fd: usize,
+    addr: UserOutPtr<SockAddr>,
+    addr_len: UserInOutPtr<u32>,
+) -> SysResult {
     info!(
         "sys_getpeername: fd: {} addr: {:?} addr_len: {:?}",
         fd, addr, addr_len
@@ -280,25 +271,23 @@ pub fn sys_getpeername(fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> Sy
     // open multiple sockets for each connection
     let mut proc = process();
 
-    if addr as usize == 0 {
+    if addr.is_null() {
         return Err(SysError::EINVAL);
     }
 
     let socket = proc.get_socket(fd)?;
     let remote_endpoint = socket.remote_endpoint().ok_or(SysError::EINVAL)?;
     let sockaddr_in = SockAddr::from(remote_endpoint);
-    unsafe {
-        sockaddr_in.write_to(&mut proc, addr, addr_len)?;
-    }
+    sockaddr_in.write_to_with_len(addr, addr_len)?;
     Ok(0)
 }
 
 impl Process {
-    fn get_socket(&mut self, fd: usize) -> Result<&mut Box<dyn Socket>, SysError> {
-        match self.get_file_like(fd)? {
-            FileLike::Socket(socket) => Ok(socket),
-            _ => Err(SysError::EBADF),
-        }
+    fn get_socket(&mut self, fd: usize) -> Result<FileGuard<Box<dyn Socket>>, SysError> {
+        self.get_file_like(fd)?.map(|file_like| match file_like {
+            FileLike::Socket(socket) => Some(socket),
+            _ => None,
+        })
     }
 }
 
@@ -400,24 +389,28 @@ impl From<Endpoint> for SockAddr {
 
 /// Convert sockaddr to endpoint
 // Check len is long enough
-fn sockaddr_to_endpoint(
-    proc: &mut Process,
-    addr: *const SockAddr,
-    len: usize,
-) -> Result<Endpoint, SysError> {
+fn sockaddr_to_endpoint(addr: UserInPtr<SockAddr>, len: usize) -> Result<Endpoint, SysError> {
     if len < size_of::<u16>() {
         return Err(SysError::EINVAL);
     }
-    proc.vm.check_read_array(addr as *const u8, len)?;
+    // only read what user provides, the rest stays zeroed
+    let bytes = addr
+        .cast::<u8>()
+        .read_array(min(len, size_of::<SockAddr>()))?;
+    let mut sockaddr: SockAddr = unsafe { core::mem::zeroed() };
+    unsafe {
+        slice::from_raw_parts_mut(&mut sockaddr as *mut SockAddr as *mut u8, bytes.len())
+            .copy_from_slice(&bytes);
+    }
     unsafe {
-        match AddressFamily::from((*addr).family) {
+        match AddressFamily::from(sockaddr.family) {
             AddressFamily::Internet => {
                 if len < size_of::<SockAddrIn>() {
                     return Err(SysError::EINVAL);
                 }
-                let port = u16::from_be((*addr).addr_in.sin_port);
+                let port = u16::from_be(sockaddr.addr_in.sin_port);
                 let addr = IpAddress::from(Ipv4Address::from_bytes(
-                    &u32::from_be((*addr).addr_in.sin_addr).to_be_bytes()[..],
+                    &u32::from_be(sockaddr.addr_in.sin_addr).to_be_bytes()[..],
                 ));
                 Ok(Endpoint::Ip((addr, port).into()))
             }
@@ -427,7 +420,7 @@ fn sockaddr_to_endpoint(
                     return Err(SysError::EINVAL);
                 }
                 Ok(Endpoint::LinkLevel(LinkLevelEndpoint::new(
-                    (*addr).addr_ll.sll_ifindex as usize,
+                    sockaddr.addr_ll.sll_ifindex as usize,
                 )))
             }
             AddressFamily::Netlink => {
@@ -435,8 +428,8 @@ fn sockaddr_to_endpoint(
                     return Err(SysError::EINVAL);
                 }
                 Ok(Endpoint::Netlink(NetlinkEndpoint::new(
-                    (*addr).addr_nl.nl_pid,
-                    (*addr).addr_nl.nl_groups,
+                    sockaddr.addr_nl.nl_pid,
+                    sockaddr.addr_nl.nl_groups,
                 )))
             }
             _ => Err(SysError::EINVAL),
@@ -445,22 +438,10 @@ fn sockaddr_to_endpoint(
 }
 
 impl SockAddr {
-    /// Write to user sockaddr
-    /// Check mutability for user
-    unsafe fn write_to(
-        self,
-        proc: &mut Process,
-        addr: *mut SockAddr,
-        addr_len: *mut u32,
-    ) -> SysResult {
-        // Ignore NULL
-        if addr.is_null() {
-            return Ok(0);
-        }
-
-        proc.vm.check_write_ptr(addr_len)?;
-        let max_addr_len = *addr_len as usize;
-        let full_len = match AddressFamily::from(self.family) {
+    /// Write at most `max_len` bytes to user sockaddr
+    /// Return the full length of this sockaddr
+    fn write_to(self, addr: UserOutPtr<SockAddr>, max_len: u32) -> Result<u32, SysError> {
+        let full_len = match AddressFamily::from(unsafe { self.family }) {
             AddressFamily::Internet => size_of::<SockAddrIn>(),
             AddressFamily::Packet => size_of::<SockAddrLl>(),
             AddressFamily::Netlink => size_of::<SockAddrNl>(),
@@ -468,24 +449,40 @@ impl SockAddr {
             _ => return Err(SysError::EINVAL),
         };
 
-        let written_len = min(max_addr_len, full_len);
+        let written_len = min(max_len as usize, full_len);
         if written_len > 0 {
-            proc.vm.check_write_array(addr as *mut u8, written_len)?;
-            let source = slice::from_raw_parts(&self as *const SockAddr as *const u8, written_len);
-            let target = slice::from_raw_parts_mut(addr as *mut u8, written_len);
-            target.copy_from_slice(source);
+            let source = unsafe {
+                slice::from_raw_parts(&self as *const SockAddr as *const u8, written_len)
+            };
+            addr.cast::<u8>().write_array(source)?;
         }
-        addr_len.write(full_len as u32);
-        return Ok(0);
+        Ok(full_len as u32)
+    }
+
+    /// Write to user sockaddr, and update its length at `addr_len`
+    fn write_to_with_len(
+        self,
+        addr: UserOutPtr<SockAddr>,
+        mut addr_len: UserInOutPtr<u32>,
+    ) -> SysResult {
+        // Ignore NULL
+        if addr.is_null() {
+            return Ok(0);
+        }
+
+        let max_addr_len = addr_len.read()?;
+        let full_len = self.write_to(addr, max_addr_len)?;
+        addr_len.write(full_len)?;
+        Ok(0)
     }
 }
 
 #[repr(C)]
 #[derive(Debug)]
 pub struct MsgHdr {
-    msg_name: *mut SockAddr,
+    msg_name: UserOutPtr<SockAddr>,
     msg_namelen: u32,
-    msg_iov: *mut IoVec,
+    msg_iov: UserInPtr<IoVec>,
     msg_iovlen: usize,
     msg_control: usize,
     msg_controllen: usize,
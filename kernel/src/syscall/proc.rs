@@ -1,70 +1,184 @@
 //! Syscalls for process
 
+use core::mem::size_of;
+
+use rcore_memory::PAGE_SIZE;
+
 use super::*;
 use crate::fs::INodeExt;
+use crate::process::rlimit::RLIMIT_NPROC;
+use crate::process::sched::{nice_to_priority, NICE_MAX, NICE_MIN};
+use crate::sync::SpinNoIrqLock as Mutex;
 
 /// Fork the current process. Return the child's PID.
 pub fn sys_fork(tf: &TrapFrame) -> SysResult {
-    let new_thread = current_thread().fork(tf);
-    let pid = processor().manager().add(new_thread);
-    info!("fork: {} -> {}", thread::current().id(), pid);
-    Ok(pid)
+    do_clone(CloneFlags::empty(), None, 0.into(), 0.into(), 0, tf)
 }
 
-/// Create a new thread in the current process.
-/// The new thread's stack pointer will be set to `newsp`,
-/// and thread pointer will be set to `newtls`.
-/// The child tid will be stored at both `parent_tid` and `child_tid`.
-/// This is partially implemented for musl only.
+/// Create a new process, or a new thread in the current process with `CLONE_THREAD`.
+/// The new thread's stack pointer will be set to `newsp` if not 0,
+/// and thread pointer will be set to `newtls` with `CLONE_SETTLS`.
+/// The exit signal in the low byte of `flags` is ignored, as there are no signals yet.
+/// Return the tid of the new thread.
 pub fn sys_clone(
     flags: usize,
     newsp: usize,
-    parent_tid: *mut u32,
-    child_tid: *mut u32,
+    parent_tid: UserOutPtr<u32>,
+    child_tid: UserOutPtr<u32>,
     newtls: usize,
     tf: &TrapFrame,
 ) -> SysResult {
-    let clone_flags = CloneFlags::from_bits_truncate(flags);
+    let clone_flags = CloneFlags::from_bits_truncate(flags & !CloneFlags::CSIGNAL.bits());
     info!(
         "clone: flags: {:?} == {:#x}, newsp: {:#x}, parent_tid: {:?}, child_tid: {:?}, newtls: {:#x}",
         clone_flags, flags, newsp, parent_tid, child_tid, newtls
     );
-    if flags == 0x4111 || flags == 0x11 {
-        warn!("sys_clone is calling sys_fork instead, ignoring other args");
-        return sys_fork(tf);
-    }
-    if (flags != 0x7d0f00) && (flags != 0x5d0f00) {
-        //0x5d0f00 is the args from gcc of alpine linux
-        //warn!("sys_clone only support musl pthread_create");
-        panic!(
-            "sys_clone only support sys_fork OR musl pthread_create without flags{:x}",
-            flags
-        );
-        //return Err(SysError::ENOSYS);
+    let stack = if newsp != 0 { Some(newsp) } else { None };
+    do_clone(clone_flags, stack, parent_tid, child_tid, newtls, tf)
+}
+
+/// `clone` with the arguments in a struct of `size` bytes, which may grow in later versions
+pub fn sys_clone3(args: UserInPtr<CloneArgs>, size: usize, tf: &TrapFrame) -> SysResult {
+    info!("clone3: args: {:?}, size: {}", args, size);
+    if size < size_of::<CloneArgs>() {
+        return Err(SysError::EINVAL);
+    }
+    if size > PAGE_SIZE {
+        return Err(SysError::E2BIG);
     }
+    // the fields of later versions are not supported, unless they are zero
+    let extra = args
+        .cast::<u8>()
+        .add(size_of::<CloneArgs>())
+        .read_array(size - size_of::<CloneArgs>())?;
+    if extra.iter().any(|&b| b != 0) {
+        return Err(SysError::E2BIG);
+    }
+    let args = args.read()?;
+    info!("clone3: {:x?}", args);
+    let flags = CloneFlags::from_bits(args.flags as usize)
+        .filter(|flags| !flags.intersects(CloneFlags::CSIGNAL))
+        .ok_or(SysError::EINVAL)?;
+    if args.flags as usize as u64 != args.flags || args.exit_signal >= 64 {
+        return Err(SysError::EINVAL);
+    }
+    // the stack is given by its lowest address and size
+    let stack = match (args.stack as usize, args.stack_size as usize) {
+        (0, 0) => None,
+        (0, _) | (_, 0) => return Err(SysError::EINVAL),
+        (stack, stack_size) => Some(stack + stack_size),
+    };
+    do_clone(
+        flags,
+        stack,
+        (args.parent_tid as usize).into(),
+        (args.child_tid as usize).into(),
+        args.tls as usize,
+        tf,
+    )
+}
+
+fn do_clone(
+    flags: CloneFlags,
+    stack: Option<usize>,
+    parent_tid: UserOutPtr<u32>,
+    child_tid: UserOutPtr<u32>,
+    tls: usize,
+    tf: &TrapFrame,
+) -> SysResult {
+    // the same combinations as Linux
+    if flags.contains(CloneFlags::SIGHAND) && !flags.contains(CloneFlags::VM)
+        || flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::SIGHAND)
+        || flags.contains(CloneFlags::FS | CloneFlags::NEWNS)
     {
-        let proc = process();
-        proc.vm.check_write_ptr(parent_tid)?;
-        proc.vm.check_write_ptr(child_tid)?;
+        return Err(SysError::EINVAL);
     }
-    let new_thread = current_thread().clone(tf, newsp, newtls, child_tid as usize);
-    // FIXME: parent pid
-    let tid = processor().manager().add(new_thread);
+    // a process has its own memory set, which is copied only for the child of vfork
+    if cfg!(not(feature = "nommu"))
+        && flags.contains(CloneFlags::VM)
+        && !flags.intersects(CloneFlags::THREAD | CloneFlags::VFORK)
+    {
+        return Err(SysError::EINVAL);
+    }
+    // namespaces and pidfd are not supported
+    if flags.intersects(
+        CloneFlags::PIDFD
+            | CloneFlags::NEWNS
+            | CloneFlags::NEWCGROUP
+            | CloneFlags::NEWUTS
+            | CloneFlags::NEWIPC
+            | CloneFlags::NEWUSER
+            | CloneFlags::NEWPID
+            | CloneFlags::NEWNET,
+    ) {
+        return Err(SysError::EINVAL);
+    }
+    if flags.contains(CloneFlags::THREAD) {
+        if !flags.contains(CloneFlags::FILES | CloneFlags::FS) {
+            warn!("clone: a thread always shares the files and cwd of its process");
+        }
+    } else {
+        let nproc = PROCESSES
+            .read()
+            .values()
+            .filter(|weak| weak.upgrade().is_some())
+            .count();
+        if process().rlimits.get(RLIMIT_NPROC).exceeded_by(nproc + 1) {
+            return Err(SysError::EAGAIN);
+        }
+    }
+
+    let child_tid = child_tid.as_ptr() as usize;
+    let new_thread = current_thread().clone(
+        tf,
+        flags,
+        stack,
+        if flags.contains(CloneFlags::SETTLS) {
+            Some(tls)
+        } else {
+            None
+        },
+        if flags.contains(CloneFlags::CHILD_SETTID) {
+            child_tid
+        } else {
+            0
+        },
+        if flags.contains(CloneFlags::PARENT_SETTID) {
+            parent_tid.as_ptr() as usize
+        } else {
+            0
+        },
+        if flags.contains(CloneFlags::CHILD_CLEARTID) {
+            child_tid
+        } else {
+            0
+        },
+    )?;
+    // the thread may be in a process still running in the memory of its vfork parent
+    let vfork_done = if flags.contains(CloneFlags::THREAD) {
+        None
+    } else {
+        new_thread.proc().lock().vfork_done.clone()
+    };
+    let tid = manager().add(new_thread);
     info!("clone: {} -> {}", thread::current().id(), tid);
-    unsafe {
-        parent_tid.write(tid as u32);
-        child_tid.write(tid as u32);
+    // the child runs in our memory with `CLONE_VFORK` or without MMU,
+    // wait until it execs or exits
+    if let Some(done) = vfork_done {
+        done.acquire();
     }
     Ok(tid)
 }
 
-/// Wait for the process exit.
+/// Wait for the process exit, or the stop of a tracee.
 /// Return the PID. Store exit code to `wstatus` if it's not null.
-pub fn sys_wait4(pid: isize, wstatus: *mut i32) -> SysResult {
-    info!("wait4: pid: {}, code: {:?}", pid, wstatus);
-    if !wstatus.is_null() {
-        process().vm.check_write_ptr(wstatus)?;
-    }
+/// Return 0 with `WNOHANG` if no process is done yet.
+pub fn sys_wait4(pid: isize, mut wstatus: UserOutPtr<i32>, options: usize) -> SysResult {
+    info!(
+        "wait4: pid: {}, code: {:?}, options: {:#x}",
+        pid, wstatus, options
+    );
+    const WNOHANG: usize = 1;
     #[derive(Debug)]
     enum WaitFor {
         AnyChild,
@@ -77,41 +191,51 @@ pub fn sys_wait4(pid: isize, wstatus: *mut i32) -> SysResult {
     };
     loop {
         let mut proc = process();
-        // check child_exit_code
-        let find = match target {
-            WaitFor::AnyChild => proc
-                .child_exit_code
-                .iter()
-                .next()
-                .map(|(&pid, &code)| (pid, code)),
-            WaitFor::Pid(pid) => proc.child_exit_code.get(&pid).map(|&code| (pid, code)),
+        // the tracees report their stops to the tracer, as if they were its children
+        let target_pid = match target {
+            WaitFor::AnyChild => None,
+            WaitFor::Pid(pid) => Some(pid),
         };
-        // if found, return
-        if let Some((pid, exit_code)) = find {
-            proc.child_exit_code.remove(&pid);
+        let (found_tracee, stopped) = crate::process::ptrace::wait_tracees(&mut proc, target_pid);
+        if let Some((pid, status)) = stopped {
             if !wstatus.is_null() {
-                unsafe {
-                    wstatus.write(exit_code as i32);
+                wstatus.write(status)?;
+            }
+            return Ok(pid);
+        }
+        // find a zombie among the children waited for
+        let mut found = found_tracee;
+        let mut zombie = None;
+        for (index, child) in proc.children.iter().enumerate() {
+            let child = child.lock();
+            let pid = child.pid.get();
+            if let WaitFor::Pid(target_pid) = target {
+                if pid != target_pid {
+                    continue;
                 }
             }
+            found = true;
+            if let ProcessState::Zombie(exit_code) = child.state {
+                zombie = Some((index, pid, exit_code));
+                break;
+            }
+        }
+        // if found, reap it
+        if let Some((index, pid, exit_code)) = zombie {
+            if !wstatus.is_null() {
+                wstatus.write(exit_code as i32)?;
+            }
+            let child = proc.children.remove(index);
+            drop(proc);
+            remove_process(pid, &child);
             return Ok(pid);
         }
-        // if not, check pid
-        let children: Vec<_> = proc
-            .children
-            .iter()
-            .filter_map(|weak| weak.upgrade())
-            .collect();
-        let invalid = match target {
-            WaitFor::AnyChild => children.len() == 0,
-            WaitFor::Pid(pid) => children
-                .iter()
-                .find(|p| p.lock().pid.get() == pid)
-                .is_none(),
-        };
-        if invalid {
+        if !found {
             return Err(SysError::ECHILD);
         }
+        if options & WNOHANG != 0 {
+            return Ok(0);
+        }
         info!(
             "wait: thread {} -> {:?}, sleep",
             thread::current().id(),
@@ -123,47 +247,41 @@ pub fn sys_wait4(pid: isize, wstatus: *mut i32) -> SysResult {
     }
 }
 
+/// Remove the reaped process `proc` from `PROCESSES`, unless its pid is reused
+fn remove_process(pid: usize, proc: &Arc<Mutex<Process>>) {
+    let mut processes = PROCESSES.write();
+    let same = processes
+        .get(&pid)
+        .and_then(|weak| weak.upgrade())
+        .map_or(true, |p| Arc::ptr_eq(&p, proc));
+    if same {
+        processes.remove(&pid);
+    }
+}
+
 pub fn sys_exec(
-    name: *const u8,
-    argv: *const *const u8,
-    envp: *const *const u8,
+    name: UserInPtr<u8>,
+    argv: UserInPtr<UserInPtr<u8>>,
+    envp: UserInPtr<UserInPtr<u8>>,
     tf: &mut TrapFrame,
 ) -> SysResult {
     info!("exec: name: {:?}, argv: {:?}, envp: {:?}", name, argv, envp);
-    let proc = process();
     let exec_name = if name.is_null() {
         String::from("")
     } else {
-        unsafe { proc.vm.check_and_clone_cstr(name)? }
+        name.read_cstring()?
     };
 
     if argv.is_null() {
         return Err(SysError::EINVAL);
     }
-    // Check and copy args to kernel
-    let mut args = Vec::new();
-    unsafe {
-        let mut current_argv = argv as *const *const u8;
-        proc.vm.check_read_ptr(current_argv)?;
-        while !(*current_argv).is_null() {
-            let arg = proc.vm.check_and_clone_cstr(*current_argv)?;
-            args.push(arg);
-            current_argv = current_argv.add(1);
-        }
-    }
-    // Check and copy envs to kernel
-    let mut envs = Vec::new();
-    unsafe {
-        let mut current_env = envp as *const *const u8;
-        if !current_env.is_null() {
-            proc.vm.check_read_ptr(current_env)?;
-            while !(*current_env).is_null() {
-                let env = proc.vm.check_and_clone_cstr(*current_env)?;
-                envs.push(env);
-                current_env = current_env.add(1);
-            }
-        }
-    }
+    // Copy args and envs to kernel
+    let args = argv.read_cstring_array()?;
+    let envs = if envp.is_null() {
+        Vec::new()
+    } else {
+        envp.read_cstring_array()?
+    };
 
     if args.is_empty() {
         return Err(SysError::EINVAL);
@@ -174,27 +292,66 @@ pub fn sys_exec(
         exec_name, args, envs
     );
 
-    // Read program file
+    // Read program file, without the process locked
     //let path = args[0].as_str();
     let exec_path = exec_name.as_str();
-    let inode = proc.lookup_inode(exec_path)?;
+    let (inode, rlimits) = {
+        let proc = process();
+        (proc.lookup_inode(exec_path)?, proc.rlimits)
+    };
     let buf = inode.read_as_vec()?;
 
-    // Make new Thread
-    let mut thread = Thread::new_user(buf.as_slice(), exec_path, args, envs);
-    thread.proc.lock().clone_for_exec(&proc);
+    // Make new Thread, and take its memory in place,
+    // so the process is still the one known by its parent and children
+    let mut thread = Thread::new_user(buf.as_slice(), exec_path, args, envs, rlimits)?;
+
+    // like Linux, the other threads are killed, and the pid is kept
+    let tid = thread::current().id();
+    let live_threads = {
+        let mut proc = process();
+        if !proc.threads.contains(&tid) {
+            // quit by `exec` of another thread, see `exit_if_killed`
+            return Err(SysError::EINTR);
+        }
+        proc.quit_threads_except(tid, 0);
+        proc.live_threads.clone()
+    };
+    // a quit thread may still run in the old memory until switched out for good,
+    // which also flushes the TLB of its CPU
+    live_threads.wait_until(1);
+
+    let mut proc = process();
+    if let ProcessState::Zombie(_) = proc.state {
+        // killed meanwhile, see `exit_if_killed`
+        return Err(SysError::EINTR);
+    }
+    proc.exec_from(&mut thread.proc().lock());
+    // `/proc/self/exe` is an absolute path
+    if !exec_path.starts_with('/') {
+        let cwd = proc.cwd.clone();
+        proc.exec_path = format!("{}/{}", cwd.lock().trim_end_matches('/'), exec_path);
+    }
+    // the parent waiting for `vfork` can go on, its memory is no longer used
+    if let Some(done) = proc.vfork_done.take() {
+        done.release();
+    }
 
     // Activate new page table
     unsafe {
-        thread.proc.lock().vm.activate();
+        proc.vm.activate();
     }
 
     // Modify the TrapFrame
     *tf = unsafe { thread.context.get_init_tf() };
 
-    // Swap Context but keep KStack
-    ::core::mem::swap(&mut current_thread().kstack, &mut thread.kstack);
-    ::core::mem::swap(current_thread(), &mut *thread);
+    // Swap Context but keep KStack and the others
+    ::core::mem::swap(&mut current_thread().context, &mut thread.context);
+    // the old memory is gone
+    current_thread().clear_child_tid = 0;
+    // a tracee stops before running the new program, for the tracer to set breakpoints
+    if let Some(tracee) = proc.ptrace.as_mut() {
+        tracee.pending_stop = Some(crate::process::ptrace::SIGTRAP);
+    }
 
     Ok(0)
 }
@@ -204,6 +361,9 @@ pub fn sys_yield() -> SysResult {
     Ok(0)
 }
 
+/// Kill signal, which a tracer can not stop
+const SIGKILL: usize = 9;
+
 /// Kill the process
 pub fn sys_kill(pid: usize, sig: usize) -> SysResult {
     info!(
@@ -212,30 +372,105 @@ pub fn sys_kill(pid: usize, sig: usize) -> SysResult {
         pid,
         sig
     );
+    // do not hold `PROCESSES` while killing, it may be removed from there
+    let proc_arc = PROCESSES.read().get(&pid).and_then(|weak| weak.upgrade());
+    let proc_arc = proc_arc.ok_or(SysError::EINVAL)?;
+    // signal 0 only checks the process exists
+    if sig == 0 {
+        return Ok(0);
+    }
+    // a tracee stops for its tracer instead, as the tracer sees signals first
+    if sig != SIGKILL {
+        if let Some(tracee) = proc_arc.lock().ptrace.as_mut() {
+            tracee.pending_stop = Some(sig);
+            return Ok(0);
+        }
+    }
     let current_pid = process().pid.get().clone();
     if current_pid == pid {
         // killing myself
         sys_exit_group(sig);
+    }
+    kill_process(&proc_arc, sig);
+    Ok(0)
+}
+
+/// Quit all threads of the process with exit code `sig`, and release its files right away
+/// and its memory once no thread may run in it. It is left as a zombie until its parent
+/// reaps it by `wait4`, its children are given to the init process, and its tracees
+/// are detached. Nothing is done if it is already a zombie.
+///
+/// The current thread, if it is one of the threads, is left to quit by `exit_if_killed`
+/// before returning to user mode, as it may hold locks here.
+pub fn kill_process(proc_arc: &Arc<Mutex<Process>>, sig: usize) {
+    let tid = thread::current().id();
+    let mut proc = proc_arc.lock();
+    if let ProcessState::Zombie(_) = proc.state {
+        return;
+    }
+    // only the threads not quit yet are kept in the threads
+    proc.quit_threads_except(tid, sig);
+    if let Some(done) = proc.vfork_done.take() {
+        done.release();
+    }
+    proc.state = ProcessState::Zombie(sig);
+    proc.files = Arc::new(Mutex::new(BTreeMap::new()));
+
+    // never lock the relatives while holding the process, avoid deadlock
+    let children = core::mem::replace(&mut proc.children, Vec::new());
+    let parent = proc.parent.as_ref().and_then(|weak| weak.upgrade());
+    let tracer = proc
+        .ptrace
+        .take()
+        .and_then(|tracee| tracee.tracer.upgrade());
+    let tracees = core::mem::replace(&mut proc.tracees, Vec::new());
+    let pid = proc.pid.get();
+    let live_threads = proc.live_threads.clone();
+    drop(proc);
+
+    // a quit thread may still run in the memory until switched out for good,
+    // which also flushes the TLB of its CPU
+    let me = current_thread().belongs_to(proc_arc) as usize;
+    live_threads.wait_until(me);
+    // the page table is kept as it may be the one in use
+    proc_arc.lock().vm.clear();
+
+    // notify parent, as SIGCHLD would do.
+    // There are no signal handlers, so waking `wait4` is all it does.
+    if let Some(parent) = parent {
+        parent.lock().child_exit.notify_all();
     } else {
-        if let Some(proc_arc) = PROCESSES.read().get(&pid).and_then(|weak| weak.upgrade()) {
-            let proc = proc_arc.lock();
-            // quit all threads
-            for tid in proc.threads.iter() {
-                processor().manager().exit(*tid, sig);
-            }
-            // notify parent and fill exit code
-            // avoid deadlock
-            let proc_parent = proc.parent.clone();
-            let pid = proc.pid.get();
-            drop(proc);
-            if let Some(parent) = proc_parent {
-                let mut parent = parent.lock();
-                parent.child_exit_code.insert(pid, sig);
-                parent.child_exit.notify_one();
-            }
-            Ok(0)
-        } else {
-            Err(SysError::EINVAL)
+        // nobody will reap it
+        PROCESSES.write().remove(&pid);
+    }
+    // the tracer is told as well, and the tracees go on untraced
+    if let Some(tracer) = tracer {
+        tracer.lock().child_exit.notify_all();
+    }
+    for tracee in tracees.iter().filter_map(|weak| weak.upgrade()) {
+        crate::process::ptrace::detach(&mut tracee.lock());
+    }
+
+    // give the children to init, unless init itself is exiting
+    let init = INIT_PROCESS
+        .read()
+        .upgrade()
+        .filter(|init| init.lock().pid.get() != pid);
+    for child in children {
+        let mut child_lock = child.lock();
+        child_lock.parent = init.as_ref().map(Arc::downgrade);
+        let child_pid = child_lock.pid.get();
+        let zombie = match child_lock.state {
+            ProcessState::Zombie(_) => true,
+            _ => false,
+        };
+        drop(child_lock);
+        if let Some(init) = &init {
+            let mut init = init.lock();
+            init.children.push(child);
+            init.child_exit.notify_all();
+        } else if zombie {
+            remove_process(child_pid, &child);
         }
     }
 }
@@ -255,7 +490,9 @@ pub fn sys_gettid() -> SysResult {
 
 /// Get the parent process id
 pub fn sys_getppid() -> SysResult {
-    if let Some(ref parent) = process().parent.as_ref() {
+    // do not lock the parent while holding current process
+    let parent = process().parent.clone();
+    if let Some(parent) = parent.and_then(|weak| weak.upgrade()) {
         Ok(parent.lock().pid.get())
     } else {
         Ok(0)
@@ -266,105 +503,165 @@ pub fn sys_getppid() -> SysResult {
 pub fn sys_exit(exit_code: usize) -> ! {
     let tid = thread::current().id();
     info!("exit: {}, code: {}", tid, exit_code);
-    let mut proc = process();
-    proc.threads.retain(|&id| id != tid);
-
-    // for last thread,
-    // notify parent and fill exit code
-    // avoid deadlock
-    let exit = proc.threads.len() == 0;
-    let proc_parent = proc.parent.clone();
-    let pid = proc.pid.get();
-    drop(proc);
-    if exit {
-        if let Some(parent) = proc_parent {
-            let mut parent = parent.lock();
-            parent.child_exit_code.insert(pid, exit_code);
-            parent.child_exit.notify_one();
-        }
-    }
 
     // perform futex wake 1
     // ref: http://man7.org/linux/man-pages/man2/set_tid_address.2.html
-    // FIXME: do it in all possible ways a thread can exit
-    //        it has memory access so we can't move it to Thread::drop?
+    // other ways a thread can exit quit the whole process,
+    // whose memory is then released, so nobody would see the write
     let clear_child_tid = current_thread().clear_child_tid;
     if clear_child_tid != 0 {
-        unsafe {
-            (clear_child_tid as *mut u32).write(0);
-        }
+        // nothing to do if user has unmapped it
+        UserOutPtr::<u32>::from(clear_child_tid).write(0).ok();
         let queue = process().get_futex(clear_child_tid);
         queue.notify_one();
     }
 
-    processor().manager().exit(tid, exit_code as usize);
+    let mut proc = process();
+    // quit already by a killer if not in the threads
+    let quit = !proc.threads.contains(&tid);
+    proc.threads.retain(|&id| id != tid);
+    let last = !quit && proc.threads.is_empty();
+    drop(proc);
+    if last {
+        // the last thread quits the process
+        kill_process(current_thread().proc(), exit_code);
+    }
+
+    if !quit {
+        manager().exit(tid, exit_code as usize);
+    }
     processor().yield_now();
     unreachable!();
 }
 
 /// Exit the current thread group (i.e. process)
 pub fn sys_exit_group(exit_code: usize) -> ! {
-    let proc = process();
-    info!("exit_group: {}, code: {}", proc.pid, exit_code);
-
-    // quit all threads
-    for tid in proc.threads.iter() {
-        processor().manager().exit(*tid, exit_code);
-    }
-
-    // notify parent and fill exit code
-    // avoid deadlock
-    let proc_parent = proc.parent.clone();
-    let pid = proc.pid.get();
-    drop(proc);
-    if let Some(parent) = proc_parent {
-        let mut parent = parent.lock();
-        parent.child_exit_code.insert(pid, exit_code);
-        parent.child_exit.notify_one();
-    }
-
-    processor().yield_now();
+    info!("exit_group: {}, code: {}", process().pid, exit_code);
+    kill_process(current_thread().proc(), exit_code);
+    crate::trap::exit_if_killed();
     unreachable!();
 }
 
-pub fn sys_nanosleep(req: *const TimeSpec) -> SysResult {
-    process().vm.check_read_ptr(req)?;
-    let time = unsafe { req.read() };
+pub fn sys_nanosleep(req: UserInPtr<TimeSpec>) -> SysResult {
+    let time = req.read()?;
     info!("nanosleep: time: {:#?}", time);
     // TODO: handle spurious wakeup
     thread::sleep(time.to_duration());
     Ok(0)
 }
 
-pub fn sys_set_priority(priority: usize) -> SysResult {
-    let pid = thread::current().id();
-    processor().manager().set_priority(pid, priority as u8);
+/// Set the nice value of the processes selected by `which` and `who` to `prio`,
+/// clamped into -20..=19.
+/// Every process runs as root, so there is no permission check.
+pub fn sys_setpriority(which: usize, who: usize, prio: usize) -> SysResult {
+    let nice = (prio as i32 as isize).max(NICE_MIN).min(NICE_MAX);
+    info!(
+        "setpriority: which: {}, who: {}, nice: {}",
+        which, who, nice
+    );
+    for proc in priority_targets(which, who)? {
+        let mut proc = proc.lock();
+        proc.nice = nice;
+        for &tid in proc.threads.iter() {
+            manager().set_priority(tid, nice_to_priority(nice));
+        }
+    }
     Ok(0)
 }
 
+/// Get the highest priority of the processes selected by `which` and `who`.
+/// Like the Linux syscall, return `20 - nice` to avoid negative values.
+pub fn sys_getpriority(which: usize, who: usize) -> SysResult {
+    info!("getpriority: which: {}, who: {}", which, who);
+    let nice = priority_targets(which, who)?
+        .iter()
+        .map(|proc| proc.lock().nice)
+        .min()
+        .unwrap();
+    Ok((20 - nice) as usize)
+}
+
+const PRIO_PROCESS: usize = 0;
+const PRIO_PGRP: usize = 1;
+const PRIO_USER: usize = 2;
+
+/// The processes selected by `which` and `who` of get/setpriority, never empty.
+/// `who` of 0 means the current process, group or user.
+fn priority_targets(which: usize, who: usize) -> Result<Vec<Arc<Mutex<Process>>>, SysError> {
+    let current = current_thread().proc().clone();
+    let current_pid = current.lock().pid.get();
+    match which {
+        // there are no process groups, each process is alone in its group
+        PRIO_PROCESS | PRIO_PGRP => {
+            if who == 0 || who == current_pid {
+                Ok(vec![current])
+            } else {
+                let proc = PROCESSES.read().get(&who).and_then(|weak| weak.upgrade());
+                proc.map(|proc| vec![proc]).ok_or(SysError::ESRCH)
+            }
+        }
+        // every process runs as root
+        PRIO_USER if who == 0 => Ok(PROCESSES
+            .read()
+            .values()
+            .filter_map(|weak| weak.upgrade())
+            .collect()),
+        PRIO_USER => Err(SysError::ESRCH),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
 bitflags! {
     pub struct CloneFlags: usize {
-        const CSIGNAL = 0x000000ff;
-        const VM = 0x0000100;
-        const FS = 0x0000200;
-        const FILES = 0x0000400;
-        const SIGHAND = 0x0000800;
-        const PTRACE = 0x0002000;
-        const VFORK = 0x0004000;
-        const PARENT = 0x0008000;
-        const SYSVSEM = 0x0008000;
-        const SETTLS = 0x0008000;
-        const PARENT_SETTID = 0x0010000;
-        const CHILD_CLEARTID = 0x0020000;
-        const DETACHED = 0x0040000;
-        const UNTRACED = 0x0080000;
-        const CHILD_SETTID = 0x0100000;
-        const NEWCGROUP = 0x0200000;
-        const NEWUTS = 0x0400000;
-        const NEWIPC = 0x0800000;
-        const NEWUSER = 0x1000000;
-        const NEWPID = 0x2000000;
-        const NEWNET = 0x4000000;
-        const IO = 0x8000000;
+        const CSIGNAL = 0x0000_00ff;
+        /// Share the memory, or copy it without
+        const VM = 0x0000_0100;
+        /// Share the working directory
+        const FS = 0x0000_0200;
+        /// Share the file table
+        const FILES = 0x0000_0400;
+        /// Share the signal handlers
+        const SIGHAND = 0x0000_0800;
+        const PIDFD = 0x0000_1000;
+        const PTRACE = 0x0000_2000;
+        /// The parent waits until the child execs or exits
+        const VFORK = 0x0000_4000;
+        /// The child has the same parent as the caller
+        const PARENT = 0x0000_8000;
+        /// A thread in the same process
+        const THREAD = 0x0001_0000;
+        const NEWNS = 0x0002_0000;
+        const SYSVSEM = 0x0004_0000;
+        /// Set the thread pointer of the child
+        const SETTLS = 0x0008_0000;
+        /// Write the tid of the child at `parent_tid`
+        const PARENT_SETTID = 0x0010_0000;
+        /// Clear `child_tid` and wake the futex there when the child exits
+        const CHILD_CLEARTID = 0x0020_0000;
+        const DETACHED = 0x0040_0000;
+        const UNTRACED = 0x0080_0000;
+        /// Write the tid of the child at `child_tid` in its memory
+        const CHILD_SETTID = 0x0100_0000;
+        const NEWCGROUP = 0x0200_0000;
+        const NEWUTS = 0x0400_0000;
+        const NEWIPC = 0x0800_0000;
+        const NEWUSER = 0x1000_0000;
+        const NEWPID = 0x2000_0000;
+        const NEWNET = 0x4000_0000;
+        const IO = 0x8000_0000;
     }
 }
+
+/// Arguments of `clone3`, the first version
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+}
@@ -0,0 +1,255 @@
+//! Syscall for tracing processes, see `process::ptrace`
+
+use super::*;
+use crate::process::ptrace::{detach, resume, Stop, Tracee, SIGSTOP, SIGTRAP};
+use crate::sync::SpinNoIrqLock as Mutex;
+use core::mem::size_of;
+
+const PTRACE_TRACEME: usize = 0;
+const PTRACE_PEEKTEXT: usize = 1;
+const PTRACE_PEEKDATA: usize = 2;
+const PTRACE_POKETEXT: usize = 4;
+const PTRACE_POKEDATA: usize = 5;
+const PTRACE_CONT: usize = 7;
+const PTRACE_KILL: usize = 8;
+const PTRACE_SINGLESTEP: usize = 9;
+const PTRACE_GETREGS: usize = 12;
+const PTRACE_SETREGS: usize = 13;
+const PTRACE_ATTACH: usize = 16;
+const PTRACE_DETACH: usize = 17;
+const PTRACE_SYSCALL: usize = 24;
+const PTRACE_SETOPTIONS: usize = 0x4200;
+const PTRACE_GETREGSET: usize = 0x4204;
+const PTRACE_SETREGSET: usize = 0x4205;
+
+/// Report syscall stops with `SIGTRAP | 0x80`, the only option supported
+const PTRACE_O_TRACESYSGOOD: usize = 1;
+/// The general registers in a register set
+const NT_PRSTATUS: usize = 1;
+const SIGKILL: usize = 9;
+
+/// Number of registers in `elf_gregset_t`
+#[cfg(target_arch = "x86_64")]
+const ELF_NGREG: usize = 27;
+#[cfg(target_arch = "aarch64")]
+const ELF_NGREG: usize = 34;
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+const ELF_NGREG: usize = 32;
+#[cfg(target_arch = "mips")]
+const ELF_NGREG: usize = 45;
+
+/// Index of `orig_rax` in `user_regs_struct`
+#[cfg(target_arch = "x86_64")]
+const ORIG_RAX: usize = 15;
+
+/// Trace process `pid`, or let the parent trace the current process by `PTRACE_TRACEME`.
+///
+/// Like the raw Linux syscall, `PTRACE_PEEKTEXT` and `PTRACE_PEEKDATA` store the word at `data`.
+/// The registers are in the layout of `elf_gregset_t`, which is `user_regs_struct` on x86_64.
+/// They are at `data` for `PTRACE_GETREGS` and `PTRACE_SETREGS`,
+/// or in the `iovec` at `data` for `PTRACE_GETREGSET` and `PTRACE_SETREGSET` with `NT_PRSTATUS`,
+/// which are used on aarch64 and riscv.
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> SysResult {
+    info!(
+        "ptrace: request: {:#x}, pid: {}, addr: {:#x}, data: {:#x}",
+        request, pid, addr, data
+    );
+    match request {
+        PTRACE_TRACEME => return trace_me(),
+        PTRACE_ATTACH => return attach(pid),
+        _ => {}
+    }
+    let tracee = find_tracee(pid)?;
+    // never touch the memory of the tracer while holding the tracee
+    match request {
+        PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+            let word = {
+                let mut tracee = tracee.lock();
+                stopped(&mut tracee)?;
+                tracee.read_user_word(addr).map_err(|_| SysError::EIO)?
+            };
+            UserOutPtr::<usize>::from(data).write(word)?;
+            Ok(0)
+        }
+        PTRACE_POKETEXT | PTRACE_POKEDATA => {
+            let mut tracee = tracee.lock();
+            stopped(&mut tracee)?;
+            tracee
+                .write_user_word(addr, data)
+                .map_err(|_| SysError::EIO)?;
+            Ok(0)
+        }
+        PTRACE_GETREGS => {
+            let regs = get_regs(&tracee)?;
+            UserOutPtr::<usize>::from(data).write_array(&regs)?;
+            Ok(0)
+        }
+        PTRACE_SETREGS => {
+            let values = UserInPtr::<usize>::from(data).read_array(ELF_NGREG)?;
+            set_regs(&tracee, &values)?;
+            Ok(0)
+        }
+        PTRACE_GETREGSET | PTRACE_SETREGSET => {
+            if addr != NT_PRSTATUS {
+                return Err(SysError::EINVAL);
+            }
+            let mut iov = UserInOutPtr::<[usize; 2]>::from(data);
+            let [base, len] = iov.read()?;
+            let size = ELF_NGREG * size_of::<usize>();
+            if len < size {
+                return Err(SysError::EINVAL);
+            }
+            if request == PTRACE_GETREGSET {
+                let regs = get_regs(&tracee)?;
+                UserOutPtr::<usize>::from(base).write_array(&regs)?;
+            } else {
+                let values = UserInPtr::<usize>::from(base).read_array(ELF_NGREG)?;
+                set_regs(&tracee, &values)?;
+            }
+            iov.write([base, size])?;
+            Ok(0)
+        }
+        PTRACE_SETOPTIONS => {
+            if data & !PTRACE_O_TRACESYSGOOD != 0 {
+                warn!("ptrace: unsupported options {:#x}", data);
+                return Err(SysError::EINVAL);
+            }
+            let mut tracee = tracee.lock();
+            stopped(&mut tracee)?;
+            if let Some(state) = tracee.ptrace.as_mut() {
+                state.syscall_good = data & PTRACE_O_TRACESYSGOOD != 0;
+            }
+            Ok(0)
+        }
+        PTRACE_CONT | PTRACE_SYSCALL | PTRACE_SINGLESTEP => {
+            // there are no signal handlers, a signal passed on kills like `kill`
+            if data != 0 && data != SIGSTOP && data != SIGTRAP {
+                kill_process(&tracee, data);
+                return Ok(0);
+            }
+            let syscall = request == PTRACE_SYSCALL;
+            let single_step = request == PTRACE_SINGLESTEP;
+            resume(&mut tracee.lock(), syscall, single_step)?;
+            Ok(0)
+        }
+        PTRACE_KILL => {
+            kill_process(&tracee, SIGKILL);
+            Ok(0)
+        }
+        PTRACE_DETACH => {
+            current_thread()
+                .proc()
+                .lock()
+                .tracees
+                .retain(|weak| weak.upgrade().map_or(false, |t| !Arc::ptr_eq(&t, &tracee)));
+            detach(&mut tracee.lock());
+            Ok(0)
+        }
+        _ => {
+            warn!("ptrace: unsupported request {:#x}", request);
+            Err(SysError::EIO)
+        }
+    }
+}
+
+/// Let the parent trace the current process
+fn trace_me() -> SysResult {
+    let current = current_thread().proc().clone();
+    let parent = current.lock().parent.clone();
+    let parent = parent
+        .and_then(|weak| weak.upgrade())
+        .ok_or(SysError::EPERM)?;
+    attach_to(&parent, &current, None)
+}
+
+/// Trace process `pid`, which stops at its next trap from user mode
+fn attach(pid: usize) -> SysResult {
+    let current = current_thread().proc().clone();
+    let tracee = PROCESSES
+        .read()
+        .get(&pid)
+        .and_then(|weak| weak.upgrade())
+        .ok_or(SysError::ESRCH)?;
+    // no loops, as the tracer locks the tracee
+    let tracer_of_current = current
+        .lock()
+        .ptrace
+        .as_ref()
+        .and_then(|state| state.tracer.upgrade());
+    if Arc::ptr_eq(&tracee, &current)
+        || tracer_of_current.map_or(false, |tracer| Arc::ptr_eq(&tracer, &tracee))
+    {
+        return Err(SysError::EPERM);
+    }
+    attach_to(&current, &tracee, Some(SIGSTOP))
+}
+
+fn attach_to(
+    tracer: &Arc<Mutex<Process>>,
+    tracee: &Arc<Mutex<Process>>,
+    pending_stop: Option<usize>,
+) -> SysResult {
+    let mut tracer_lock = tracer.lock();
+    let mut tracee_lock = tracee.lock();
+    if tracee_lock.ptrace.is_some() {
+        return Err(SysError::EPERM);
+    }
+    if let ProcessState::Zombie(_) = tracee_lock.state {
+        return Err(SysError::ESRCH);
+    }
+    let mut state = Tracee::new(Arc::downgrade(tracer));
+    state.pending_stop = pending_stop;
+    tracee_lock.ptrace = Some(state);
+    tracer_lock.tracees.push(Arc::downgrade(tracee));
+    info!("ptrace: {} traces {}", tracer_lock.pid, tracee_lock.pid);
+    Ok(0)
+}
+
+/// The process `pid` traced by the current process
+fn find_tracee(pid: usize) -> Result<Arc<Mutex<Process>>, SysError> {
+    process()
+        .tracees
+        .iter()
+        .filter_map(|weak| weak.upgrade())
+        .find(|tracee| {
+            let tracee = tracee.lock();
+            tracee.pid.get() == pid && tracee.ptrace.is_some()
+        })
+        .ok_or(SysError::ESRCH)
+}
+
+/// The stopped thread of `tracee`, which must be stopped
+fn stopped(tracee: &mut Process) -> Result<&mut Stop, SysError> {
+    if tracee.state != ProcessState::Stopped {
+        return Err(SysError::ESRCH);
+    }
+    tracee
+        .ptrace
+        .as_mut()
+        .and_then(|state| state.stopped.as_mut())
+        .ok_or(SysError::ESRCH)
+}
+
+fn get_regs(tracee: &Arc<Mutex<Process>>) -> Result<[usize; ELF_NGREG], SysError> {
+    let mut tracee = tracee.lock();
+    let stop = stopped(&mut tracee)?;
+    #[allow(unused_mut)]
+    let mut regs = stop.tf().elf_gregs();
+    // the number of the syscall at a syscall stop, like Linux
+    #[cfg(target_arch = "x86_64")]
+    {
+        regs[ORIG_RAX] = stop.syscall.unwrap_or(!0);
+    }
+    Ok(regs)
+}
+
+fn set_regs(tracee: &Arc<Mutex<Process>>, values: &[usize]) -> Result<(), SysError> {
+    let mut regs = [0; ELF_NGREG];
+    regs.copy_from_slice(values);
+    let mut tracee = tracee.lock();
+    let stop = stopped(&mut tracee)?;
+    if !stop.tf().set_elf_gregs(&regs) {
+        return Err(SysError::EIO);
+    }
+    Ok(())
+}
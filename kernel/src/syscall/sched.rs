@@ -0,0 +1,139 @@
+//! Syscalls for scheduling policies and CPU affinity
+
+use super::*;
+use crate::consts::{MAX_CPU_NUM, USEC_PER_TICK};
+use crate::process::sched::*;
+use core::mem::size_of;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SchedParam {
+    sched_priority: i32,
+}
+
+/// Set the policy and real-time priority of thread `pid`, or current thread if 0
+pub fn sys_sched_setscheduler(
+    pid: usize,
+    policy: usize,
+    param: UserInPtr<SchedParam>,
+) -> SysResult {
+    let param = param.read()?;
+    info!(
+        "sched_setscheduler: pid: {}, policy: {}, param: {:?}",
+        pid, policy, param
+    );
+    let tid = thread_of(pid)?;
+    let policy = Policy::new(policy, param.sched_priority as usize).ok_or(SysError::EINVAL)?;
+    SCHEDULER.set_policy(tid, policy);
+    Ok(0)
+}
+
+pub fn sys_sched_getscheduler(pid: usize) -> SysResult {
+    info!("sched_getscheduler: pid: {}", pid);
+    let tid = thread_of(pid)?;
+    Ok(SCHEDULER.policy(tid).policy())
+}
+
+/// Set the real-time priority of thread `pid`, keeping its policy
+pub fn sys_sched_setparam(pid: usize, param: UserInPtr<SchedParam>) -> SysResult {
+    let param = param.read()?;
+    info!("sched_setparam: pid: {}, param: {:?}", pid, param);
+    let tid = thread_of(pid)?;
+    let policy = SCHEDULER.policy(tid).policy();
+    let policy = Policy::new(policy, param.sched_priority as usize).ok_or(SysError::EINVAL)?;
+    SCHEDULER.set_policy(tid, policy);
+    Ok(0)
+}
+
+pub fn sys_sched_getparam(pid: usize, mut param: UserOutPtr<SchedParam>) -> SysResult {
+    info!("sched_getparam: pid: {}", pid);
+    let tid = thread_of(pid)?;
+    param.write(SchedParam {
+        sched_priority: SCHEDULER.policy(tid).rt_priority() as i32,
+    })?;
+    Ok(0)
+}
+
+pub fn sys_sched_get_priority_max(policy: usize) -> SysResult {
+    match policy {
+        SCHED_FIFO | SCHED_RR => Ok(RT_PRIO_MAX),
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE => Ok(0),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+pub fn sys_sched_get_priority_min(policy: usize) -> SysResult {
+    match policy {
+        SCHED_FIFO | SCHED_RR => Ok(RT_PRIO_MIN),
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE => Ok(0),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+/// Get the time slice of thread `pid`, which is only limited for `SCHED_RR`
+pub fn sys_sched_rr_get_interval(pid: usize, mut interval: UserOutPtr<TimeSpec>) -> SysResult {
+    info!("sched_rr_get_interval: pid: {}", pid);
+    let tid = thread_of(pid)?;
+    let ticks = match SCHEDULER.policy(tid) {
+        Policy::RoundRobin(_) => RR_TIMESLICE,
+        _ => 0,
+    };
+    interval.write(TimeSpec::from_usec((ticks * USEC_PER_TICK) as u64))?;
+    Ok(0)
+}
+
+/// Bytes of the CPU mask in `sched_getaffinity` and `sched_setaffinity`
+const CPU_MASK_SIZE: usize = size_of::<CpuMask>();
+
+/// Get the CPUs thread `pid` may run on, in a mask of `size` bytes.
+/// Return the size of the mask written.
+pub fn sys_sched_getaffinity(pid: usize, size: usize, mut mask: UserOutPtr<u8>) -> SysResult {
+    info!("sched_getaffinity: pid: {}, size: {}", pid, size);
+    if size * 8 < MAX_CPU_NUM || size % size_of::<usize>() != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let tid = thread_of(pid)?;
+    let affinity = SCHEDULER.affinity(tid) & SCHEDULER.online_cpus();
+    mask.write_array(&affinity.to_le_bytes())?;
+    Ok(CPU_MASK_SIZE)
+}
+
+/// Set the CPUs thread `pid` may run on, from a mask of `size` bytes.
+/// At least one of them must be online.
+pub fn sys_sched_setaffinity(pid: usize, size: usize, mask: UserInPtr<u8>) -> SysResult {
+    info!("sched_setaffinity: pid: {}, size: {}", pid, size);
+    let tid = thread_of(pid)?;
+    let mut bytes = [0u8; CPU_MASK_SIZE];
+    let len = size.min(CPU_MASK_SIZE);
+    bytes[..len].copy_from_slice(&mask.read_array(len)?);
+    let affinity = CpuMask::from_le_bytes(bytes) & CPU_MASK_ALL;
+    if affinity & SCHEDULER.online_cpus() == 0 {
+        return Err(SysError::EINVAL);
+    }
+    SCHEDULER.set_affinity(tid, affinity);
+    if tid == thread::current().id() && affinity & (1 << cpu::id()) == 0 {
+        // move to an allowed CPU now
+        thread::yield_now();
+    }
+    Ok(0)
+}
+
+/// The thread `tid`, or current thread if 0
+fn thread_of(tid: usize) -> Result<Tid, SysError> {
+    if (tid as isize) < 0 {
+        return Err(SysError::EINVAL);
+    }
+    if tid == 0 {
+        return Ok(thread::current().id());
+    }
+    let procs: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    if procs.iter().any(|proc| proc.lock().threads.contains(&tid)) {
+        Ok(tid)
+    } else {
+        Err(SysError::ESRCH)
+    }
+}
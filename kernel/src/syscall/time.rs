@@ -67,13 +67,16 @@ impl TimeSpec {
         Duration::new(self.sec as u64, self.nsec as u32)
     }
 
-    pub fn get_epoch() -> Self {
-        let usec = get_epoch_usec();
+    pub fn from_usec(usec: u64) -> Self {
         TimeSpec {
             sec: (usec / USEC_PER_SEC) as usize,
             nsec: (usec % USEC_PER_SEC * NSEC_PER_USEC) as usize,
         }
     }
+
+    pub fn get_epoch() -> Self {
+        TimeSpec::from_usec(get_epoch_usec())
+    }
 }
 
 pub fn sys_gettimeofday(tv: *mut TimeVal, tz: *const u8) -> SysResult {
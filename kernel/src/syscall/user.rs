@@ -0,0 +1,187 @@
+//! Typed pointers to user space
+//!
+//! Every access goes through `copy_from_user` / `copy_to_user`,
+//! so a bad pointer from user results in `EFAULT` instead of a kernel crash.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{self, size_of};
+
+use crate::memory::{check_user_readable, copy_from_user, copy_to_user};
+use rcore_memory::PAGE_SIZE;
+
+use super::*;
+
+/// Max length of a string read from user, including the terminating nul
+const MAX_CSTRING_LEN: usize = 128 * 1024;
+
+pub trait Policy {}
+pub trait Read: Policy {}
+pub trait Write: Policy {}
+
+/// The pointed data is read by kernel
+pub enum In {}
+/// The pointed data is written by kernel
+pub enum Out {}
+/// The pointed data is both read and written by kernel
+pub enum InOut {}
+
+impl Policy for In {}
+impl Policy for Out {}
+impl Policy for InOut {}
+impl Read for In {}
+impl Write for Out {}
+impl Read for InOut {}
+impl Write for InOut {}
+
+/// A pointer to `T` in user space, with access policy `P`
+#[repr(transparent)]
+pub struct UserPtr<T, P: Policy> {
+    ptr: *mut T,
+    mark: PhantomData<P>,
+}
+
+pub type UserInPtr<T> = UserPtr<T, In>;
+pub type UserOutPtr<T> = UserPtr<T, Out>;
+pub type UserInOutPtr<T> = UserPtr<T, InOut>;
+
+impl<T, P: Policy> UserPtr<T, P> {
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+
+    /// The pointer `count` elements after this one
+    pub fn add(&self, count: usize) -> Self {
+        UserPtr {
+            ptr: self.ptr.wrapping_add(count),
+            mark: PhantomData,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr
+    }
+
+    /// Reinterpret as a pointer to `U`
+    pub fn cast<U>(&self) -> UserPtr<U, P> {
+        UserPtr {
+            ptr: self.ptr as *mut U,
+            mark: PhantomData,
+        }
+    }
+}
+
+impl<T, P: Read> UserPtr<T, P> {
+    pub fn read(&self) -> Result<T, SysError> {
+        let mut value: T = unsafe { mem::uninitialized() };
+        let dst =
+            unsafe { slice::from_raw_parts_mut(&mut value as *mut T as *mut u8, size_of::<T>()) };
+        match copy_from_user(dst, self.ptr as *const u8) {
+            Ok(()) => Ok(value),
+            Err(_) => {
+                mem::forget(value);
+                Err(SysError::EFAULT)
+            }
+        }
+    }
+
+    /// Read `len` elements. As `len` is given by user, the range is checked before allocating,
+    /// and failing to allocate is `ENOMEM` instead of a kernel panic.
+    pub fn read_array(&self, len: usize) -> Result<Vec<T>, SysError> {
+        let size = len.checked_mul(size_of::<T>()).ok_or(SysError::EFAULT)?;
+        check_user_readable(self.ptr as *const u8, size)?;
+        let mut vec = Vec::new();
+        vec.try_reserve_exact(len).map_err(|_| SysError::ENOMEM)?;
+        let dst = unsafe { slice::from_raw_parts_mut(vec.as_mut_ptr() as *mut u8, size) };
+        copy_from_user(dst, self.ptr as *const u8)?;
+        unsafe {
+            vec.set_len(len);
+        }
+        Ok(vec)
+    }
+}
+
+impl<P: Read> UserPtr<u8, P> {
+    /// Read a nul-terminated string
+    pub fn read_cstring(&self) -> Result<String, SysError> {
+        let mut bytes = Vec::new();
+        let mut ptr = self.ptr as usize;
+        loop {
+            // never read across a page boundary past the nul
+            let chunk_len = PAGE_SIZE - ptr % PAGE_SIZE;
+            let chunk = UserInPtr::<u8>::from(ptr).read_array(chunk_len)?;
+            if let Some(pos) = chunk.iter().position(|&c| c == 0) {
+                bytes.extend_from_slice(&chunk[..pos]);
+                break;
+            }
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() >= MAX_CSTRING_LEN {
+                return Err(SysError::ENAMETOOLONG);
+            }
+            ptr += chunk_len;
+        }
+        String::from_utf8(bytes).map_err(|_| SysError::EINVAL)
+    }
+}
+
+impl<P: Read> UserPtr<UserInPtr<u8>, P> {
+    /// Read a null-terminated array of strings, e.g. `argv` and `envp`
+    pub fn read_cstring_array(&self) -> Result<Vec<String>, SysError> {
+        let mut strings = Vec::new();
+        let mut ptr = *self;
+        loop {
+            let string_ptr = ptr.read()?;
+            if string_ptr.is_null() {
+                break;
+            }
+            strings.push(string_ptr.read_cstring()?);
+            ptr = ptr.add(1);
+        }
+        Ok(strings)
+    }
+}
+
+impl<T, P: Write> UserPtr<T, P> {
+    pub fn write(&mut self, value: T) -> Result<(), SysError> {
+        self.write_array(&[value])
+    }
+
+    pub fn write_array(&mut self, values: &[T]) -> Result<(), SysError> {
+        let src = unsafe {
+            slice::from_raw_parts(values.as_ptr() as *const u8, values.len() * size_of::<T>())
+        };
+        copy_to_user(self.ptr as *mut u8, src)?;
+        Ok(())
+    }
+}
+
+impl<P: Write> UserPtr<u8, P> {
+    /// Write `s` followed by a nul
+    pub fn write_cstring(&mut self, s: &str) -> Result<(), SysError> {
+        self.write_array(s.as_bytes())?;
+        self.add(s.len()).write(0)
+    }
+}
+
+impl<T, P: Policy> From<usize> for UserPtr<T, P> {
+    fn from(addr: usize) -> Self {
+        UserPtr {
+            ptr: addr as *mut T,
+            mark: PhantomData,
+        }
+    }
+}
+
+impl<T, P: Policy> Clone for UserPtr<T, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, P: Policy> Copy for UserPtr<T, P> {}
+
+impl<T, P: Policy> fmt::Debug for UserPtr<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.ptr)
+    }
+}
@@ -0,0 +1,23 @@
+//! `rcore_thread::std_thread`, not preempted while using the thread manager
+//!
+//! The manager holds spin locks of `rcore_thread`, which are not counted by
+//! `sync::preempt`. `yield_now` and `park_action` switch with interrupts disabled,
+//! the others are wrapped here.
+
+use crate::sync::preempt::PreemptGuard;
+use core::time::Duration;
+pub use rcore_thread::std_thread::*;
+
+pub fn sleep(dur: Duration) {
+    let _preempt = PreemptGuard::new();
+    rcore_thread::std_thread::sleep(dur)
+}
+
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: Send + 'static + FnOnce() -> T,
+    T: Send + 'static,
+{
+    let _preempt = PreemptGuard::new();
+    rcore_thread::std_thread::spawn(f)
+}
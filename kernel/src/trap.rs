@@ -1,6 +1,9 @@
 use crate::arch::cpu;
 use crate::arch::interrupt::TrapFrame;
+use crate::consts::USEC_PER_TICK;
+use crate::process::rlimit::RLIMIT_CPU;
 use crate::process::*;
+use crate::sync::preempt::preemptible;
 use log::*;
 
 pub static mut TICK: usize = 0;
@@ -9,21 +12,123 @@ pub fn uptime_msec() -> usize {
     unsafe { crate::trap::TICK / crate::consts::USEC_PER_TICK / 1000 }
 }
 
-pub fn timer() {
+pub fn timer(tf: &mut TrapFrame) {
     if cpu::id() == 0 {
         unsafe {
             TICK += 1;
         }
     }
-    processor().tick();
+    if tf.is_user() {
+        current_thread().enter_from_user(tf);
+        exit_if_killed();
+        // without guards, check the stack left by the last syscall
+        #[cfg(any(target_arch = "mips", feature = "nommu"))]
+        current_thread().kstack.check_canary();
+        // charge the tick to current process
+        let exceeded = {
+            let mut proc = process();
+            proc.cpu_ticks += 1;
+            let cpu_secs = proc.cpu_ticks * USEC_PER_TICK / 1_000_000;
+            proc.rlimits.get(RLIMIT_CPU).exceeded_by(cpu_secs)
+        };
+        if exceeded {
+            warn!("{} exceeded RLIMIT_CPU, kill it", process().pid);
+            crate::syscall::sys_exit_group(SIGXCPU);
+        }
+        // a tracee attached while running stops here, or waits for a stopped thread
+        ptrace::stop_if_pending(tf);
+    }
+    // a thread in kernel mode is switched out only if it holds no spin lock
+    if tf.is_user() || preemptible() {
+        processor().tick();
+    }
+}
+
+/// Illegal instruction
+const SIGILL: usize = 4;
+/// Invalid memory reference
+const SIGSEGV: usize = 11;
+/// CPU time limit exceeded
+const SIGXCPU: usize = 24;
+/// Set in the exit status of a process killed with its core dumped
+const CORE_DUMPED: usize = 0x80;
+
+/// A breakpoint or single step trap. A tracee stops for its tracer,
+/// other processes from user mode are killed by SIGTRAP.
+pub fn breakpoint(tf: &mut TrapFrame) {
+    if !tf.is_user() {
+        error!("breakpoint in kernel @ {:#x}", tf.pc());
+        return;
+    }
+    current_thread().enter_from_user(tf);
+    if process().ptrace.is_none() {
+        warn!("{} breakpoint @ {:#x}", process().pid, tf.pc());
+        fatal_user_trap(tf, ptrace::SIGTRAP);
+    }
+    ptrace::stop(tf, ptrace::SIGTRAP, None);
 }
 
+/// Unhandled trap. Kill the current process by SIGILL if it comes from user mode,
+/// as the usual one is an illegal instruction.
 pub fn error(tf: &TrapFrame) -> ! {
+    if tf.is_user() {
+        warn!("{} unhandled trap @ {:#x}", process().pid, tf.pc());
+        fatal_user_trap(tf, SIGILL);
+    }
     error!("{:#x?}", tf);
     let tid = processor().tid();
     error!("On CPU{} Thread {}", cpu::id(), tid);
 
-    processor().manager().exit(tid, 0x100);
+    manager().exit(tid, 0x100);
+    processor().yield_now();
+    unreachable!();
+}
+
+/// Unhandled page fault at `addr`.
+/// Kill the current process by SIGSEGV if it comes from user mode.
+/// A kernel fault inside a user access routine resumes at its fixup.
+pub fn page_fault_error(tf: &mut TrapFrame, addr: usize) {
+    if tf.is_user() {
+        warn!("{} segmentation fault @ {:#x}", process().pid, addr);
+        fatal_user_trap(tf, SIGSEGV);
+    }
+    #[cfg(not(any(target_arch = "mips", feature = "nommu")))]
+    crate::memory::check_kernel_stack_overflow(addr);
+    if let Some(fixup) = crate::memory::exception_fixup(tf.pc()) {
+        debug!("bad user access @ {:#x}, fixup to {:#x}", addr, fixup);
+        tf.set_pc(fixup);
+        return;
+    }
+    error(tf)
+}
+
+/// Kill the current process by `sig`, after dumping its core if RLIMIT_CORE allows
+fn fatal_user_trap(tf: &TrapFrame, sig: usize) -> ! {
+    // a killed process faults on its released memory, there is nothing to dump
+    exit_if_killed();
+    if coredump::dump(tf, sig) {
+        crate::syscall::sys_exit_group(sig | CORE_DUMPED);
+    }
+    crate::syscall::sys_exit_group(sig);
+}
+
+/// Quit the current user thread if it is quit by a killer, or its process is killed
+/// while it was running. Checked before returning to user mode.
+///
+/// A killer leaves only the threads not quit yet in `Process::threads`,
+/// the current one among them quits here.
+pub fn exit_if_killed() {
+    let tid = processor().tid();
+    let mut proc = process();
+    if !proc.threads.contains(&tid) {
+        // switched out for good at the yield
+    } else if let ProcessState::Zombie(code) = proc.state {
+        proc.threads.retain(|&other| other != tid);
+        manager().exit(tid, code);
+    } else {
+        return;
+    }
+    drop(proc);
     processor().yield_now();
     unreachable!();
 }